@@ -0,0 +1,6 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // The environment server's gRPC code is generated from its protobuf definition
+    #[cfg(feature = "env-server")]
+    tonic_build::compile_protos("proto/env.proto")?;
+    Ok(())
+}
@@ -0,0 +1,101 @@
+//! Headless episodes, run the same way `webgame_rust.GameWrapper` runs them.
+
+use bevy::prelude::*;
+use webgame_game::{
+    configs::LibCfgPlugin,
+    gridworld::{Agent, LevelLayout, NextAction, PlayerAgent, PursuerAgent, ShouldRun},
+    net::{action_dir, policy_grid, TOGGLE_ACTION},
+    observer::Observer,
+    world_objs::{GameOutcome, LevelComplete},
+};
+
+/// A single headless episode.
+pub struct Env {
+    pub app: App,
+}
+
+impl Env {
+    /// Starts an episode on this level.
+    pub fn new(level: LevelLayout) -> Self {
+        let mut app = App::new();
+        app.add_plugins(LibCfgPlugin).insert_resource(level);
+        app.finish();
+        app.cleanup();
+        app.update();
+        Self { app }
+    }
+
+    /// Applies both agents' actions and advances the game by one step.
+    pub fn step(&mut self, player_action: usize, pursuer_action: usize) {
+        self.set_action::<PlayerAgent>(player_action);
+        self.set_action::<PursuerAgent>(pursuer_action);
+        self.app.update();
+    }
+
+    /// Returns the position of the agent with the given marker.
+    pub fn agent_pos<T: Component>(&mut self) -> Vec2 {
+        let world = &mut self.app.world;
+        let xform = world
+            .query_filtered::<&GlobalTransform, With<T>>()
+            .single(world);
+        xform.translation().xy()
+    }
+
+    /// Returns true if the agent with marker `T` can see the agent with marker `O`.
+    pub fn sees<T: Component, O: Component>(&mut self) -> bool {
+        let world = &mut self.app.world;
+        let other_e = world.query_filtered::<Entity, With<O>>().single(world);
+        let observer = world.query_filtered::<&Observer, With<T>>().single(world);
+        observer.observing.contains(&other_e)
+    }
+
+    /// Returns how the episode ended, or `None` if it's still going.
+    pub fn outcome(&self) -> Option<GameOutcome> {
+        self.app
+            .world
+            .get_resource::<LevelComplete>()
+            .map(|complete| complete.0)
+    }
+
+    /// Returns true if the player has escaped, ending the episode.
+    pub fn player_escaped(&self) -> bool {
+        matches!(self.outcome(), Some(GameOutcome::PlayerEscaped { .. }))
+    }
+
+    /// Returns true if a pursuer has caught the player, ending the episode.
+    pub fn player_caught(&self) -> bool {
+        matches!(self.outcome(), Some(GameOutcome::PlayerCaught { .. }))
+    }
+
+    /// Ends the episode for running out of steps, unless it's already over.
+    pub fn time_out(&mut self) {
+        let world = &mut self.app.world;
+        if world.contains_resource::<LevelComplete>() {
+            return;
+        }
+        world.insert_resource(LevelComplete(GameOutcome::TimedOut));
+        world.remove_resource::<ShouldRun>();
+        world.send_event(GameOutcome::TimedOut);
+    }
+
+    /// Builds the grid observation that `PolicyNet` expects for the agent with marker `T`, whose opponent has
+    /// marker `O`. Matches `GameEnv` in `webgame/envs.py` when no filter is used, so the belief channel is empty.
+    pub fn policy_input<T: Component, O: Component>(&mut self) -> Vec<f32> {
+        let pos = self.agent_pos::<T>();
+        let other_pos = self.agent_pos::<O>();
+        let sees_other = self.sees::<T, O>();
+        let world = &mut self.app.world;
+        let dir = world.query_filtered::<&Agent, With<T>>().single(world).dir;
+        let level = world.resource::<LevelLayout>();
+        policy_grid(level, pos, dir, sees_other.then_some(other_pos))
+    }
+
+    fn set_action<T: Component>(&mut self, action: usize) {
+        let world = &mut self.app.world;
+        let mut next_action = world
+            .query_filtered::<&mut NextAction, With<T>>()
+            .single_mut(world);
+        next_action.dir = action_dir(action);
+        next_action.toggle_objs = action == TOGGLE_ACTION;
+    }
+}
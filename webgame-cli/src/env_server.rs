@@ -0,0 +1,246 @@
+//! Serves headless episodes over gRPC, so training frameworks in other languages or on other machines can use the
+//! environment without the Python wheel. Only built with the `env-server` feature. See `proto/env.proto` for the API.
+//!
+//! Bevy apps have to stay on the thread that created them, so every environment runs on a single thread, and requests
+//! are passed to it over a channel.
+
+use std::{
+    collections::HashMap,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+};
+
+use bevy::prelude::*;
+use rand::{rngs::StdRng, SeedableRng};
+use tokio::sync::oneshot;
+use tonic::{transport::Server, Request, Response, Status};
+use webgame_game::{
+    gridworld::{LevelLayout, LoadedLevelData, PlayerAgent, PursuerAgent},
+    net::{ACTION_COUNT, POLICY_CHANNELS},
+};
+
+use crate::{env::Env, next_level, CliError};
+
+pub mod proto {
+    tonic::include_proto!("pursuer.env");
+}
+
+use proto::{
+    env_server::{self, EnvServer},
+    AgentObs, CloseReply, CloseRequest, GetObsRequest, Observation, ResetRequest, StepRequest,
+};
+
+/// Something for the environment thread to do to one environment.
+enum EnvCommand {
+    Reset {
+        level: Option<LevelLayout>,
+        seed: Option<u64>,
+    },
+    Step {
+        player_action: usize,
+        pursuer_action: usize,
+    },
+    GetObs,
+    Close,
+}
+
+/// A command, and where to send what the environment observes afterwards. Closed environments don't observe anything.
+struct EnvRequest {
+    env_id: String,
+    command: EnvCommand,
+    reply: oneshot::Sender<Result<Option<Observation>, Status>>,
+}
+
+/// An environment and its episode so far.
+struct ServedEnv {
+    env: Env,
+    /// Generates the environment's random levels.
+    rng: StdRng,
+    step: u64,
+}
+
+/// Answers requests by passing them to the environment thread.
+struct EnvService {
+    requests: mpsc::Sender<EnvRequest>,
+}
+
+impl EnvService {
+    async fn send(&self, env_id: String, command: EnvCommand) -> Result<Observation, Status> {
+        let stopped = || Status::unavailable("The environment thread stopped");
+        let (reply, rx) = oneshot::channel();
+        self.requests
+            .send(EnvRequest {
+                env_id,
+                command,
+                reply,
+            })
+            .map_err(|_| stopped())?;
+        let obs = rx.await.map_err(|_| stopped())??;
+        Ok(obs.unwrap_or_default())
+    }
+}
+
+#[tonic::async_trait]
+impl env_server::Env for EnvService {
+    async fn reset(&self, request: Request<ResetRequest>) -> Result<Response<Observation>, Status> {
+        let ResetRequest {
+            env_id,
+            level_json,
+            seed,
+        } = request.into_inner();
+        let level = level_json
+            .map(|json| {
+                LoadedLevelData::from_json(&json)
+                    .map(|data| LevelLayout::from_data(&data))
+                    .map_err(|err| Status::invalid_argument(format!("Invalid level: {err}")))
+            })
+            .transpose()?;
+        let obs = self.send(env_id, EnvCommand::Reset { level, seed }).await?;
+        Ok(Response::new(obs))
+    }
+
+    async fn step(&self, request: Request<StepRequest>) -> Result<Response<Observation>, Status> {
+        let StepRequest {
+            env_id,
+            player_action,
+            pursuer_action,
+        } = request.into_inner();
+        let (player_action, pursuer_action) = (player_action as usize, pursuer_action as usize);
+        if player_action >= ACTION_COUNT || pursuer_action >= ACTION_COUNT {
+            return Err(Status::invalid_argument(format!(
+                "Actions must be less than {ACTION_COUNT}"
+            )));
+        }
+        let command = EnvCommand::Step {
+            player_action,
+            pursuer_action,
+        };
+        Ok(Response::new(self.send(env_id, command).await?))
+    }
+
+    async fn get_obs(
+        &self,
+        request: Request<GetObsRequest>,
+    ) -> Result<Response<Observation>, Status> {
+        let env_id = request.into_inner().env_id;
+        Ok(Response::new(self.send(env_id, EnvCommand::GetObs).await?))
+    }
+
+    async fn close(&self, request: Request<CloseRequest>) -> Result<Response<CloseReply>, Status> {
+        let env_id = request.into_inner().env_id;
+        self.send(env_id, EnvCommand::Close).await?;
+        Ok(Response::new(CloseReply {}))
+    }
+}
+
+/// Serves environments on `addr` until the process is stopped. Episodes play `level` if it's given, and random levels
+/// otherwise, unless a level is sent when they're reset.
+pub fn run_env_server(addr: &str, level: Option<PathBuf>) -> Result<(), CliError> {
+    let listen_err = |source| CliError::Listen {
+        addr: addr.into(),
+        source,
+    };
+    let socket_addr = addr
+        .parse()
+        .map_err(|err| listen_err(io::Error::new(ErrorKind::InvalidInput, err)))?;
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .map_err(listen_err)?;
+
+    let (requests, rx) = mpsc::channel();
+    thread::spawn(move || run_envs(level.as_deref(), rx));
+    println!("Serving environments on {addr}");
+    runtime.block_on(
+        Server::builder()
+            .add_service(EnvServer::new(EnvService { requests }))
+            .serve(socket_addr),
+    )?;
+    Ok(())
+}
+
+/// Runs commands on environments until the server stops.
+fn run_envs(level: Option<&Path>, requests: mpsc::Receiver<EnvRequest>) {
+    let mut envs = HashMap::new();
+    for request in requests {
+        let result = run_command(&mut envs, level, request.env_id, request.command);
+        let _ = request.reply.send(result);
+    }
+}
+
+fn run_command(
+    envs: &mut HashMap<String, ServedEnv>,
+    default_level: Option<&Path>,
+    env_id: String,
+    command: EnvCommand,
+) -> Result<Option<Observation>, Status> {
+    let action = match command {
+        EnvCommand::Reset { level, seed } => {
+            // Environments keep generating levels from the same RNG, so seeded ones stay reproducible
+            let mut rng = match envs.remove(&env_id) {
+                Some(served) => served.rng,
+                None => seed.map_or_else(StdRng::from_entropy, StdRng::seed_from_u64),
+            };
+            let level = match level {
+                Some(level) => level,
+                None => next_level(default_level, &mut rng)
+                    .map_err(|err| Status::failed_precondition(err.to_string()))?,
+            };
+            let served = ServedEnv {
+                env: Env::new(level),
+                rng,
+                step: 0,
+            };
+            envs.insert(env_id.clone(), served);
+            None
+        }
+        EnvCommand::Step {
+            player_action,
+            pursuer_action,
+        } => Some((player_action, pursuer_action)),
+        EnvCommand::GetObs => None,
+        EnvCommand::Close => {
+            envs.remove(&env_id);
+            return Ok(None);
+        }
+    };
+
+    let served = envs.get_mut(&env_id).ok_or_else(|| {
+        Status::not_found(format!("No environment called {env_id:?}, reset it first"))
+    })?;
+    if let Some((player_action, pursuer_action)) = action {
+        served.env.step(player_action, pursuer_action);
+        served.step += 1;
+    }
+    Ok(Some(observe(served)))
+}
+
+/// Describes what both agents observe.
+fn observe(served: &mut ServedEnv) -> Observation {
+    let env = &mut served.env;
+    let level = env.app.world.resource::<LevelLayout>();
+    let (width, height) = (level.width as u32, level.height as u32);
+    Observation {
+        width,
+        height,
+        channels: POLICY_CHANNELS as u32,
+        player: Some(agent_obs::<PlayerAgent, PursuerAgent>(env)),
+        pursuer: Some(agent_obs::<PursuerAgent, PlayerAgent>(env)),
+        player_escaped: env.player_escaped(),
+        step: served.step,
+        player_caught: env.player_caught(),
+    }
+}
+
+/// Describes what the agent with marker `T` observes, where its opponent has marker `O`.
+fn agent_obs<T: Component, O: Component>(env: &mut Env) -> AgentObs {
+    let pos = env.agent_pos::<T>();
+    AgentObs {
+        grid: env.policy_input::<T, O>(),
+        x: pos.x,
+        y: pos.y,
+        sees_other: env.sees::<T, O>(),
+    }
+}
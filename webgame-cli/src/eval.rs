@@ -0,0 +1,239 @@
+//! Round-robin tournaments between checkpoints, run headless at simulation speed.
+
+use std::path::{Path, PathBuf};
+
+use rand::{rngs::StdRng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use webgame_game::{
+    gridworld::{LevelLayout, PlayerAgent, PursuerAgent},
+    net::ComputeDevice,
+    world_objs::GameOutcome,
+};
+
+use crate::{env::Env, load_level_data, policy::Policy, CliError};
+
+/// How many steps episodes last if neither the config nor the level sets a limit.
+pub const DEFAULT_MAX_STEPS: usize = 200;
+
+/// Describes a tournament, read from a JSON file. Every player policy plays every pursuer policy on every level.
+///
+/// ```json
+/// {
+///     "player_policies": ["checkpoints/player_100.safetensors", null],
+///     "pursuer_policies": ["checkpoints/pursuer_100.safetensors"],
+///     "levels": ["levels/test.json"],
+///     "episodes": 20
+/// }
+/// ```
+#[derive(Deserialize)]
+pub struct EvalConfig {
+    /// Safetensors checkpoints of the player's policy. `null` takes random actions.
+    pub player_policies: Vec<Option<PathBuf>>,
+    /// Safetensors checkpoints of the pursuer's policy. `null` takes random actions.
+    pub pursuer_policies: Vec<Option<PathBuf>>,
+    pub levels: Vec<PathBuf>,
+    /// How many episodes each matchup plays on each level.
+    #[serde(default = "default_episodes")]
+    pub episodes: usize,
+    /// How many steps an episode lasts before it times out. Defaults to the level's `max_steps`, then
+    /// `DEFAULT_MAX_STEPS`.
+    #[serde(default)]
+    pub max_steps: Option<usize>,
+    #[serde(default)]
+    pub seed: u64,
+}
+
+fn default_episodes() -> usize {
+    10
+}
+
+impl EvalConfig {
+    /// Reads a config from a JSON file.
+    pub fn load(path: &Path) -> Result<Self, CliError> {
+        let json = std::fs::read_to_string(path).map_err(|source| CliError::Io {
+            path: path.into(),
+            source,
+        })?;
+        serde_json::from_str(&json).map_err(|source| CliError::Config {
+            path: path.into(),
+            source,
+        })
+    }
+}
+
+/// How a player policy fared against a pursuer policy on a level.
+#[derive(Serialize)]
+pub struct MatchupResult {
+    pub player_policy: String,
+    pub pursuer_policy: String,
+    pub level: String,
+    pub episodes: usize,
+    #[serde(flatten)]
+    pub stats: MatchupStats,
+}
+
+/// Aggregate statistics over a matchup's episodes.
+#[derive(Serialize)]
+pub struct MatchupStats {
+    /// The fraction of episodes a pursuer caught the player in.
+    pub catch_rate: f32,
+    /// The fraction of episodes that ran out of steps before the player escaped or was caught.
+    pub timeout_rate: f32,
+    pub mean_episode_length: f32,
+    /// The fraction of steps the pursuer could see the player on.
+    pub sighted_rate: f32,
+}
+
+/// Plays every matchup in the config, returning results in the order player policy, pursuer policy, then level.
+pub fn run_tournament(
+    config: &EvalConfig,
+    device: ComputeDevice,
+) -> Result<Vec<MatchupResult>, CliError> {
+    let load_policies = |paths: &[Option<PathBuf>]| {
+        paths
+            .iter()
+            .map(|path| {
+                Ok((
+                    policy_name(path.as_deref()),
+                    Policy::load(path.as_deref(), device)?,
+                ))
+            })
+            .collect::<Result<Vec<_>, CliError>>()
+    };
+    let player_policies = load_policies(&config.player_policies)?;
+    let pursuer_policies = load_policies(&config.pursuer_policies)?;
+    let levels = config
+        .levels
+        .iter()
+        .map(|path| Ok((path, LevelLayout::from_data(&load_level_data(path)?))))
+        .collect::<Result<Vec<_>, CliError>>()?;
+
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    let mut results = Vec::new();
+    for (player_name, player_policy) in &player_policies {
+        for (pursuer_name, pursuer_policy) in &pursuer_policies {
+            for (level_path, level) in &levels {
+                let max_steps = config
+                    .max_steps
+                    .or(level.meta.max_steps.map(|steps| steps as usize))
+                    .unwrap_or(DEFAULT_MAX_STEPS);
+                let stats = play_matchup(
+                    player_policy,
+                    pursuer_policy,
+                    level,
+                    config.episodes,
+                    max_steps,
+                    &mut rng,
+                )?;
+                println!(
+                    "{player_name} vs {pursuer_name} on {level_path:?}: caught {:.0}% of the time, timed out {:.0}%",
+                    stats.catch_rate * 100.,
+                    stats.timeout_rate * 100.,
+                );
+                results.push(MatchupResult {
+                    player_policy: player_name.clone(),
+                    pursuer_policy: pursuer_name.clone(),
+                    level: level_path.display().to_string(),
+                    episodes: config.episodes,
+                    stats,
+                });
+            }
+        }
+    }
+    Ok(results)
+}
+
+/// Plays `episodes` episodes side by side, so each policy runs once per step for all of them.
+fn play_matchup(
+    player_policy: &Policy,
+    pursuer_policy: &Policy,
+    level: &LevelLayout,
+    episodes: usize,
+    max_steps: usize,
+    rng: &mut StdRng,
+) -> Result<MatchupStats, CliError> {
+    let mut envs: Vec<_> = (0..episodes).map(|_| Env::new(level.clone())).collect();
+    let (mut total_length, mut total_steps, mut sighted_steps) = (0, 0, 0);
+    let (mut caught, mut timed_out) = (0, 0);
+    for step in 1..=max_steps {
+        if envs.is_empty() {
+            break;
+        }
+        let player_actions =
+            player_policy.act_batch::<PlayerAgent, PursuerAgent>(&mut envs, rng)?;
+        let pursuer_actions =
+            pursuer_policy.act_batch::<PursuerAgent, PlayerAgent>(&mut envs, rng)?;
+        for (env, (player_action, pursuer_action)) in envs
+            .iter_mut()
+            .zip(player_actions.into_iter().zip(pursuer_actions))
+        {
+            env.step(player_action, pursuer_action);
+            sighted_steps += env.sees::<PursuerAgent, PlayerAgent>() as usize;
+        }
+        total_steps += envs.len();
+
+        if step == max_steps {
+            envs.iter_mut().for_each(Env::time_out);
+        }
+        for env in envs.iter().filter(|env| env.outcome().is_some()) {
+            total_length += step;
+            caught += env.player_caught() as usize;
+            timed_out += matches!(env.outcome(), Some(GameOutcome::TimedOut)) as usize;
+        }
+        envs.retain(|env| env.outcome().is_none());
+    }
+
+    let episodes_f = episodes.max(1) as f32;
+    Ok(MatchupStats {
+        catch_rate: caught as f32 / episodes_f,
+        timeout_rate: timed_out as f32 / episodes_f,
+        mean_episode_length: total_length as f32 / episodes_f,
+        sighted_rate: sighted_steps as f32 / total_steps.max(1) as f32,
+    })
+}
+
+/// Names a policy in results by its path.
+fn policy_name(path: Option<&Path>) -> String {
+    match path {
+        Some(path) => path.display().to_string(),
+        None => "random".into(),
+    }
+}
+
+/// Writes results to a file, as JSON if its extension is `.json` and as CSV otherwise.
+pub fn write_results(path: &Path, results: &[MatchupResult]) -> Result<(), CliError> {
+    let contents = if path.extension().is_some_and(|ext| ext == "json") {
+        serde_json::to_string_pretty(results).expect("results should always serialize")
+    } else {
+        let mut csv = String::from(
+            "player_policy,pursuer_policy,level,episodes,catch_rate,timeout_rate,mean_episode_length,sighted_rate\n",
+        );
+        for result in results {
+            csv += &format!(
+                "{},{},{},{},{},{},{},{}\n",
+                csv_field(&result.player_policy),
+                csv_field(&result.pursuer_policy),
+                csv_field(&result.level),
+                result.episodes,
+                result.stats.catch_rate,
+                result.stats.timeout_rate,
+                result.stats.mean_episode_length,
+                result.stats.sighted_rate,
+            );
+        }
+        csv
+    };
+    std::fs::write(path, contents).map_err(|source| CliError::Io {
+        path: path.into(),
+        source,
+    })
+}
+
+/// Quotes a CSV field if it contains characters that would break the row.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.into()
+    }
+}
@@ -0,0 +1,656 @@
+//! Runs and inspects the game from the command line, without the Python wheel.
+
+use std::{
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::Instant,
+};
+
+use bevy::prelude::*;
+use clap::{Parser, Subcommand};
+use rand::{rngs::StdRng, SeedableRng};
+use thiserror::Error;
+use webgame_game::{
+    authoritative::AuthoritativeClientPlugin,
+    configs::{CoreGamePlugin, PlayablePlugin},
+    gridworld::{
+        LevelDataError, LevelLayout, LevelLoader, LoadedLevelData, PlayerAgent, PursuerAgent,
+        DEFAULT_LEVEL_SIZE,
+    },
+    level_gen::{ObstacleParams, Symmetry},
+    level_mutation::is_playable,
+    multiplayer::{
+        LobbyRole, MultiplayerPlugin, MultiplayerSession, DEFAULT_INPUT_DELAY, DEFAULT_SERVER_URL,
+    },
+    net::{ComputeDevice, PolicyAgent, PolicyOverlayPlugin, PolicyRunnerPlugin},
+    replay::ReplayPlaybackPlugin,
+    spectator::{SpectatorPlugin, SpectatorServer},
+    viewer::ViewerPlugin,
+};
+
+use crate::{
+    env::Env,
+    eval::{run_tournament, write_results, EvalConfig},
+    match_server::run_match_server,
+    policy::Policy,
+    relay::run_relay,
+    trajectory::{write_trajectory, TrajectoryStep},
+};
+
+mod env;
+#[cfg(feature = "env-server")]
+mod env_server;
+mod eval;
+mod match_server;
+mod policy;
+mod relay;
+mod trajectory;
+
+/// The probability of a cell containing a wall in random levels. Matches the default in `GameEnv`.
+const DEFAULT_WALL_PROB: f64 = 0.1;
+
+#[derive(Parser)]
+#[command(about = "Runs and inspects Pursuer environments")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Opens a window to play a level.
+    Play {
+        /// The level file to play. Defaults to the game's test level.
+        #[arg(long)]
+        level: Option<PathBuf>,
+        /// The folder containing the game's `assets` folder.
+        #[arg(long, default_value = concat!(env!("CARGO_MANIFEST_DIR"), "/../webgame-game"))]
+        assets: PathBuf,
+        /// Asset path of a checkpoint to play as the pursuer.
+        #[arg(long)]
+        policy: Option<String>,
+        /// Asset path of a checkpoint to play as the player instead of the keyboard. Set both policies to watch them
+        /// play each other.
+        #[arg(long)]
+        player_policy: Option<String>,
+        /// Draw the action probabilities of policy-driven agents over the level.
+        #[arg(long)]
+        policy_overlay: bool,
+        /// Host an online match against someone else, as "player", "pursuer" or "spectator". The relay server replies
+        /// with a room code for them to join with.
+        #[arg(long, conflicts_with_all = ["join", "lobby", "policy", "player_policy"])]
+        host: Option<LobbyRole>,
+        /// Join someone else's online match by its room code. The host's level is played instead of `--level`.
+        #[arg(long, conflicts_with_all = ["level", "lobby", "policy", "player_policy"])]
+        join: Option<String>,
+        /// Open the lobby to host or join online matches from inside the game. Press F4 to toggle it.
+        #[arg(long, conflicts_with_all = ["policy", "player_policy"])]
+        lobby: bool,
+        /// The relay server online matches go through. See `relay`.
+        #[arg(long, default_value = DEFAULT_SERVER_URL)]
+        server: String,
+        /// How many ticks after being pressed inputs take effect in online matches. Raise this if the game keeps
+        /// pausing to wait for the other person's inputs.
+        #[arg(long, default_value_t = DEFAULT_INPUT_DELAY)]
+        input_delay: u32,
+        /// Stream the game to spectators over WebSocket on this address, e.g. `127.0.0.1:9200`.
+        #[arg(long)]
+        spectate: Option<String>,
+    },
+    /// Plays back a replay saved by pressing F6 during play.
+    ///
+    /// Press Space to pause, Right to step forward while paused, and Up or Down to change the speed.
+    Replay {
+        /// Asset path of the replay, e.g. `replays/replay_0.replay`.
+        path: String,
+        /// The folder containing the game's `assets` folder.
+        #[arg(long, default_value = concat!(env!("CARGO_MANIFEST_DIR"), "/../webgame-game"))]
+        assets: PathBuf,
+    },
+    /// Opens a window showing a game streamed by a spectator server, e.g. one started by `play --spectate` or by
+    /// `GameWrapper` during training. Levels are shown as they start, and agents as they move.
+    View {
+        /// The spectator server's address.
+        #[arg(long, default_value = "ws://127.0.0.1:9200")]
+        server: String,
+        /// The folder containing the game's `assets` folder.
+        #[arg(long, default_value = concat!(env!("CARGO_MANIFEST_DIR"), "/../webgame-game"))]
+        assets: PathBuf,
+    },
+    /// Runs headless episodes and writes every step to a Parquet file.
+    ///
+    /// A new episode starts whenever the player escapes. Agents without a policy take random actions.
+    Rollout {
+        /// Safetensors checkpoint of the pursuer's policy.
+        #[arg(long)]
+        policy: Option<PathBuf>,
+        /// Safetensors checkpoint of the player's policy.
+        #[arg(long)]
+        player_policy: Option<PathBuf>,
+        /// The level file to play. Random levels are used if not provided.
+        #[arg(long)]
+        level: Option<PathBuf>,
+        /// The number of steps each environment runs for.
+        #[arg(long, default_value_t = 100)]
+        steps: usize,
+        /// The number of environments to run side by side. Each policy picks actions for every environment in a single
+        /// batch.
+        #[arg(long, default_value_t = 1)]
+        envs: usize,
+        #[arg(long)]
+        out: PathBuf,
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+        /// Where policies run: "auto", "cpu", "cuda", "cuda:<index>", "metal", or "metal:<index>". Falls back to the CPU
+        /// if the device isn't available.
+        #[arg(long, default_value = "auto")]
+        device: ComputeDevice,
+    },
+    /// Plays every player policy against every pursuer policy on every level in a JSON config, and writes each
+    /// matchup's catch rate and mean episode length to a CSV or JSON file.
+    Eval {
+        config: PathBuf,
+        /// Where to write results. Written as JSON if the extension is `.json`, otherwise as CSV.
+        #[arg(long)]
+        out: PathBuf,
+        /// Where policies run. See `rollout`.
+        #[arg(long, default_value = "auto")]
+        device: ComputeDevice,
+    },
+    /// Checks that level files parse and are playable. Exits with an error if any aren't.
+    ValidateLevel {
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Generates random levels and writes them to a folder as `level_<i>.json`.
+    GenLevels {
+        #[arg(long, default_value_t = 10)]
+        count: usize,
+        #[arg(long)]
+        out_dir: PathBuf,
+        #[arg(long, default_value_t = DEFAULT_LEVEL_SIZE)]
+        size: usize,
+        #[arg(long, default_value_t = DEFAULT_WALL_PROB)]
+        wall_prob: f64,
+        /// Add random movable objects.
+        #[arg(long)]
+        objects: bool,
+        /// Build walls from this many obstacle shapes instead of using `--wall-prob`.
+        #[arg(long)]
+        obstacles: Option<usize>,
+        /// Make levels symmetric, with spawns on opposite sides: "mirror_x", "mirror_y", or "rotate_180".
+        #[arg(long)]
+        symmetry: Option<Symmetry>,
+        /// Keep generating until `count` playable levels are found.
+        #[arg(long)]
+        playable: bool,
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+    },
+    /// Runs a relay server for online matches, which pairs up games and passes their inputs between them.
+    Relay {
+        /// The address to listen on.
+        #[arg(long, default_value = "127.0.0.1:9000")]
+        addr: String,
+    },
+    /// Runs online matches on this machine, so cheating games can't change what happens. Connect to it with `connect`.
+    MatchServer {
+        /// The address to listen on.
+        #[arg(long, default_value = "127.0.0.1:9400")]
+        addr: String,
+        /// The level file to play. Random levels are used if not provided.
+        #[arg(long)]
+        level: Option<PathBuf>,
+        /// Save a replay of every level to this folder.
+        #[arg(long)]
+        record: Option<PathBuf>,
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+    },
+    /// Opens a window to play a match run by `match-server`.
+    Connect {
+        /// The match server's address.
+        #[arg(long, default_value = "ws://127.0.0.1:9400")]
+        server: String,
+        /// What to play as: "player", "pursuer" or "spectator".
+        #[arg(long, default_value = "player")]
+        role: LobbyRole,
+        /// The folder containing the game's `assets` folder.
+        #[arg(long, default_value = concat!(env!("CARGO_MANIFEST_DIR"), "/../webgame-game"))]
+        assets: PathBuf,
+    },
+    /// Serves headless environments over gRPC, for training frameworks that can't use the Python wheel. See
+    /// `proto/env.proto` for the API.
+    #[cfg(feature = "env-server")]
+    EnvServer {
+        /// The address to listen on.
+        #[arg(long, default_value = "127.0.0.1:9300")]
+        addr: String,
+        /// The level file to play. Random levels are used if not provided, unless clients send their own.
+        #[arg(long)]
+        level: Option<PathBuf>,
+    },
+    /// Measures how many steps per second headless episodes run at.
+    Bench {
+        /// The level file to play. A random level is used if not provided.
+        #[arg(long)]
+        level: Option<PathBuf>,
+        #[arg(long, default_value_t = 1000)]
+        steps: usize,
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+    },
+}
+
+/// Errors that can occur when running commands.
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum CliError {
+    #[error("Could not access {path:?}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Invalid level {path:?}: {source}")]
+    Level {
+        path: PathBuf,
+        source: LevelDataError,
+    },
+    #[error("Could not load policy {path:?}: {source}")]
+    Policy {
+        path: PathBuf,
+        source: candle_core::Error,
+    },
+    #[error("Invalid config {path:?}: {source}")]
+    Config {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    #[error("Could not run policy: {0}")]
+    Candle(#[from] candle_core::Error),
+    #[error("Policy gave invalid action probabilities: {0}")]
+    ActionProbs(#[from] rand::distributions::WeightedError),
+    #[error("Could not write trajectory: {0}")]
+    Parquet(#[from] parquet::errors::ParquetError),
+    #[error("{0} level(s) failed validation")]
+    InvalidLevels(usize),
+    #[error("Could not listen on {addr}: {source}")]
+    Listen {
+        addr: String,
+        source: std::io::Error,
+    },
+    #[cfg(feature = "env-server")]
+    #[error("Environment server failed: {0}")]
+    EnvServer(#[from] tonic::transport::Error),
+}
+
+fn main() -> ExitCode {
+    let result = match Cli::parse().command {
+        Command::Play {
+            level,
+            assets,
+            policy,
+            player_policy,
+            policy_overlay,
+            host,
+            join,
+            lobby,
+            server,
+            input_delay,
+            spectate,
+        } => {
+            let session = host
+                .map(MultiplayerSession::Host)
+                .or(join.map(MultiplayerSession::Join))
+                .or(lobby.then_some(MultiplayerSession::Lobby));
+            let multiplayer = session.map(|session| MultiplayerPlugin {
+                server_url: server,
+                session,
+                input_delay,
+            });
+            play(
+                level.as_deref(),
+                &assets,
+                policy,
+                player_policy,
+                policy_overlay,
+                multiplayer,
+                spectate,
+            )
+        }
+        Command::Replay { path, assets } => replay(path, &assets),
+        Command::View { server, assets } => view(server, &assets),
+        Command::Rollout {
+            policy,
+            player_policy,
+            level,
+            steps,
+            envs,
+            out,
+            seed,
+            device,
+        } => rollout(
+            policy.as_deref(),
+            player_policy.as_deref(),
+            level.as_deref(),
+            steps,
+            envs,
+            &out,
+            seed,
+            device,
+        ),
+        Command::Eval {
+            config,
+            out,
+            device,
+        } => eval(&config, &out, device),
+        Command::ValidateLevel { paths } => validate_levels(&paths),
+        Command::GenLevels {
+            count,
+            out_dir,
+            size,
+            wall_prob,
+            objects,
+            obstacles,
+            symmetry,
+            playable,
+            seed,
+        } => gen_levels(
+            count, &out_dir, size, wall_prob, objects, obstacles, symmetry, playable, seed,
+        ),
+        Command::Relay { addr } => run_relay(&addr),
+        Command::MatchServer {
+            addr,
+            level,
+            record,
+            seed,
+        } => run_match_server(&addr, level.as_deref(), record.as_deref(), seed),
+        Command::Connect {
+            server,
+            role,
+            assets,
+        } => connect(server, role, &assets),
+        #[cfg(feature = "env-server")]
+        Command::EnvServer { addr, level } => env_server::run_env_server(&addr, level),
+        Command::Bench { level, steps, seed } => bench(level.as_deref(), steps, seed),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Reads and parses a level file.
+fn load_level_data(path: &Path) -> Result<LoadedLevelData, CliError> {
+    let json = std::fs::read_to_string(path).map_err(|source| CliError::Io {
+        path: path.into(),
+        source,
+    })?;
+    LoadedLevelData::from_json(&json).map_err(|source| CliError::Level {
+        path: path.into(),
+        source,
+    })
+}
+
+/// Loads the level file if one was given, otherwise generates a random level.
+fn next_level(path: Option<&Path>, rng: &mut StdRng) -> Result<LevelLayout, CliError> {
+    match path {
+        Some(path) => Ok(LevelLayout::from_data(&load_level_data(path)?)),
+        None => Ok(LevelLayout::random(
+            DEFAULT_LEVEL_SIZE,
+            DEFAULT_LEVEL_SIZE,
+            DEFAULT_WALL_PROB,
+            0,
+            rng,
+        )),
+    }
+}
+
+fn play(
+    level: Option<&Path>,
+    assets: &Path,
+    policy: Option<String>,
+    player_policy: Option<String>,
+    policy_overlay: bool,
+    multiplayer: Option<MultiplayerPlugin>,
+    spectate: Option<String>,
+) -> Result<(), CliError> {
+    // Bevy looks for the `assets` folder here, since this binary lives outside the game's crate
+    std::env::set_var("BEVY_ASSET_ROOT", assets);
+    let mut app = App::new();
+    app.add_plugins((PlayablePlugin, CoreGamePlugin));
+    if let Some(path) = policy {
+        app.add_plugins(PolicyRunnerPlugin { path, ..default() });
+    }
+    if let Some(path) = player_policy {
+        app.add_plugins(PolicyRunnerPlugin {
+            path,
+            agent: PolicyAgent::Player,
+            ..default()
+        });
+    }
+    if policy_overlay {
+        app.add_plugins(PolicyOverlayPlugin);
+    }
+    // Guests play whichever levels the host sends
+    let is_guest = multiplayer
+        .as_ref()
+        .is_some_and(|multiplayer| !multiplayer.is_host());
+    if let Some(multiplayer) = multiplayer {
+        app.add_plugins(multiplayer);
+    }
+    if let Some(addr) = spectate {
+        let server =
+            SpectatorServer::bind(&addr).map_err(|source| CliError::Listen { addr, source })?;
+        app.add_plugins(SpectatorPlugin { server });
+    }
+    match level {
+        _ if is_guest => (),
+        Some(path) => {
+            app.insert_resource(LevelLayout::from_data(&load_level_data(path)?));
+        }
+        None => {
+            app.insert_resource(LevelLoader::Path("levels/test.json".into()));
+        }
+    }
+    app.run();
+    Ok(())
+}
+
+fn replay(path: String, assets: &Path) -> Result<(), CliError> {
+    std::env::set_var("BEVY_ASSET_ROOT", assets);
+    App::new()
+        .add_plugins((
+            PlayablePlugin,
+            CoreGamePlugin,
+            ReplayPlaybackPlugin { path },
+        ))
+        .run();
+    Ok(())
+}
+
+/// Plays a match run by the match server at `server_url`.
+fn connect(server_url: String, role: LobbyRole, assets: &Path) -> Result<(), CliError> {
+    std::env::set_var("BEVY_ASSET_ROOT", assets);
+    App::new()
+        .add_plugins((
+            PlayablePlugin,
+            CoreGamePlugin,
+            AuthoritativeClientPlugin { server_url, role },
+        ))
+        .run();
+    Ok(())
+}
+
+/// Shows the game streamed by the spectator server at `server_url`.
+fn view(server_url: String, assets: &Path) -> Result<(), CliError> {
+    std::env::set_var("BEVY_ASSET_ROOT", assets);
+    App::new()
+        .add_plugins((PlayablePlugin, CoreGamePlugin, ViewerPlugin { server_url }))
+        .run();
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn rollout(
+    policy: Option<&Path>,
+    player_policy: Option<&Path>,
+    level: Option<&Path>,
+    steps: usize,
+    env_count: usize,
+    out: &Path,
+    seed: u64,
+    device: ComputeDevice,
+) -> Result<(), CliError> {
+    let pursuer_policy = Policy::load(policy, device)?;
+    let player_policy = Policy::load(player_policy, device)?;
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut envs = (0..env_count)
+        .map(|_| Ok(Env::new(next_level(level, &mut rng)?)))
+        .collect::<Result<Vec<_>, CliError>>()?;
+
+    // Each environment tracks its current episode and step. Episodes are numbered in the order they start.
+    let mut episodes: Vec<(i32, i32)> = (0..env_count as i32).map(|i| (i, 0)).collect();
+    let mut next_episode = env_count as i32;
+    let mut trajectory = Vec::with_capacity(steps * env_count);
+    for _ in 0..steps {
+        let player_actions =
+            player_policy.act_batch::<PlayerAgent, PursuerAgent>(&mut envs, &mut rng)?;
+        let pursuer_actions =
+            pursuer_policy.act_batch::<PursuerAgent, PlayerAgent>(&mut envs, &mut rng)?;
+        for (i, env) in envs.iter_mut().enumerate() {
+            let (player_action, pursuer_action) = (player_actions[i], pursuer_actions[i]);
+            env.step(player_action, pursuer_action);
+
+            let player_pos = env.agent_pos::<PlayerAgent>();
+            let pursuer_pos = env.agent_pos::<PursuerAgent>();
+            let player_escaped = env.player_escaped();
+            let player_caught = env.player_caught();
+            let (episode, episode_step) = &mut episodes[i];
+            trajectory.push(TrajectoryStep {
+                episode: *episode,
+                step: *episode_step,
+                player_action: player_action as i32,
+                pursuer_action: pursuer_action as i32,
+                player_x: player_pos.x,
+                player_y: player_pos.y,
+                pursuer_x: pursuer_pos.x,
+                pursuer_y: pursuer_pos.y,
+                pursuer_sees_player: env.sees::<PursuerAgent, PlayerAgent>(),
+                player_escaped,
+                player_caught,
+            });
+
+            *episode_step += 1;
+            if env.outcome().is_some() {
+                *env = Env::new(next_level(level, &mut rng)?);
+                *episode = next_episode;
+                *episode_step = 0;
+                next_episode += 1;
+            }
+        }
+    }
+
+    write_trajectory(out, &trajectory)?;
+    println!(
+        "Wrote {} steps from {next_episode} episode(s) to {out:?}.",
+        trajectory.len(),
+    );
+    Ok(())
+}
+
+fn eval(config: &Path, out: &Path, device: ComputeDevice) -> Result<(), CliError> {
+    let results = run_tournament(&EvalConfig::load(config)?, device)?;
+    write_results(out, &results)?;
+    println!("Wrote {} matchup(s) to {out:?}.", results.len());
+    Ok(())
+}
+
+fn validate_levels(paths: &[PathBuf]) -> Result<(), CliError> {
+    let mut invalid = 0;
+    for path in paths {
+        match load_level_data(path) {
+            Ok(level) if is_playable(&level) => println!("{path:?}: ok"),
+            Ok(_) => {
+                println!("{path:?}: not playable (blocked markers or disconnected areas)");
+                invalid += 1;
+            }
+            Err(err) => {
+                println!("{err}");
+                invalid += 1;
+            }
+        }
+    }
+    match invalid {
+        0 => Ok(()),
+        _ => Err(CliError::InvalidLevels(invalid)),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn gen_levels(
+    count: usize,
+    out_dir: &Path,
+    size: usize,
+    wall_prob: f64,
+    objects: bool,
+    obstacles: Option<usize>,
+    symmetry: Option<Symmetry>,
+    playable: bool,
+    seed: u64,
+) -> Result<(), CliError> {
+    let io_err = |source| CliError::Io {
+        path: out_dir.into(),
+        source,
+    };
+    std::fs::create_dir_all(out_dir).map_err(io_err)?;
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut written = 0;
+    while written < count {
+        let max_items = if objects { size } else { 0 };
+        let mut level = match obstacles {
+            Some(count) => {
+                let params = ObstacleParams { count, ..default() };
+                LevelLayout::random_obstacles(size, size, &params, max_items, &mut rng)
+            }
+            None => LevelLayout::random(size, size, wall_prob, max_items, &mut rng),
+        };
+        if let Some(symmetry) = symmetry {
+            level.make_symmetric(symmetry, &mut rng);
+        }
+        let level = level.to_data();
+        if playable && !is_playable(&level) {
+            continue;
+        }
+        let json = serde_json::to_string_pretty(&level).expect("levels should always serialize");
+        std::fs::write(out_dir.join(format!("level_{written}.json")), json).map_err(io_err)?;
+        written += 1;
+    }
+    println!("Wrote {count} level(s) to {out_dir:?}.");
+    Ok(())
+}
+
+fn bench(level: Option<&Path>, steps: usize, seed: u64) -> Result<(), CliError> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut env = Env::new(next_level(level, &mut rng)?);
+    let policy = Policy::Random;
+
+    let start = Instant::now();
+    for _ in 0..steps {
+        let player_action = policy.act::<PlayerAgent, PursuerAgent>(&mut env, &mut rng)?;
+        let pursuer_action = policy.act::<PursuerAgent, PlayerAgent>(&mut env, &mut rng)?;
+        env.step(player_action, pursuer_action);
+        if env.outcome().is_some() {
+            env = Env::new(next_level(level, &mut rng)?);
+        }
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    println!(
+        "Ran {steps} steps in {elapsed:.2}s ({:.1} steps/s).",
+        steps as f64 / elapsed
+    );
+    Ok(())
+}
@@ -0,0 +1,422 @@
+//! A server that runs online matches itself, instead of trusting the players' games. See `webgame_game::authoritative`
+//! for how games play against it.
+//!
+//! The game runs headless on this thread, one fixed tick at a time, at the rate games play them. Games connect over
+//! WebSocket, and are read from and written to between ticks without blocking, like spectators. Handshakes happen on
+//! their own threads, so games that are slow to connect don't hold up the match.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    io::ErrorKind,
+    net::{TcpListener, TcpStream},
+    path::Path,
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+    time::{Duration, Instant},
+};
+
+use bevy::{prelude::*, time::TimeUpdateStrategy};
+use bevy_rapier2d::prelude::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use tungstenite::{Message, WebSocket};
+use webgame_game::{
+    authoritative::{AgentPose, ClientMessage, MatchState, ServerMessage},
+    configs::LibCfgPlugin,
+    filter::FilterRng,
+    gridworld::{Agent, LevelLayout, LevelObject, NextAction, PlayerAgent, PursuerAgent},
+    multiplayer::{LobbyRole, Role, LOCKSTEP_TS},
+    observer::DetectionRng,
+    replay::{RecordedAction, Replay, ReplayTick},
+    spectator::HANDSHAKE_TIMEOUT,
+    world_objs::{GameOutcome, LevelComplete},
+};
+
+use crate::{next_level, CliError};
+
+/// The most inputs kept waiting for each agent. Games that send inputs faster than ticks are played lose the oldest
+/// ones, so they can't move faster than anyone else.
+const MAX_PENDING_INPUTS: usize = 8;
+/// How many ticks the end of a level is shown for before the next one starts.
+const LEVEL_END_TICKS: u64 = 120;
+
+/// A connected game.
+struct Client {
+    socket: WebSocket<TcpStream>,
+    /// Set once the game has joined.
+    role: Option<LobbyRole>,
+}
+
+/// Inputs sent for an agent.
+#[derive(Default)]
+struct AgentInputs {
+    /// Inputs waiting to be applied, by the tick the sender gave them.
+    pending: VecDeque<(u64, RecordedAction)>,
+    /// The last input applied.
+    last: Option<(u64, RecordedAction)>,
+}
+
+impl AgentInputs {
+    /// Queues an input, unless it's older than one already sent.
+    fn push(&mut self, tick: u64, action: RecordedAction) {
+        let newest = self.pending.back().or(self.last.as_ref());
+        if newest.is_some_and(|&(newest, _)| tick <= newest) {
+            return;
+        }
+        // Only the server decides how fast agents move, so anything longer than a unit direction is shortened
+        let dir = Vec2::from(action.dir);
+        let dir = if dir.is_finite() {
+            dir.clamp_length_max(1.)
+        } else {
+            Vec2::ZERO
+        };
+        self.pending.push_back((
+            tick,
+            RecordedAction {
+                dir: dir.into(),
+                ..action
+            },
+        ));
+        if self.pending.len() > MAX_PENDING_INPUTS {
+            self.pending.pop_front();
+        }
+    }
+
+    /// Returns the input for the next tick. If none has arrived, the agent keeps moving the way it was, but doesn't
+    /// repeat one-off actions.
+    fn next(&mut self) -> RecordedAction {
+        match self.pending.pop_front() {
+            Some(input) => {
+                self.last = Some(input);
+                input.1
+            }
+            None => self
+                .last
+                .map(|(_, action)| RecordedAction {
+                    toggle_objs: false,
+                    gadget: None,
+                    ..action
+                })
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// The level being played.
+struct Match {
+    app: App,
+    /// A recording of the level, with spawns set to where the agents started.
+    replay: Replay,
+    /// When the level ended, if it has.
+    ended_at: Option<u64>,
+}
+
+impl Match {
+    /// Sets up a level, fixing where the agents spawn so games can be told.
+    fn start(mut level: LevelLayout, rng: &mut StdRng) -> Self {
+        level.fix_spawns(rng);
+        let seed = rng.gen();
+        let mut app = App::new();
+        app.add_plugins(LibCfgPlugin)
+            .insert_resource(level.clone())
+            // Play ticks the way games in online matches do, so they can predict them
+            .insert_resource(TimeUpdateStrategy::ManualDuration(tick_duration()))
+            .insert_resource(RapierConfiguration {
+                gravity: Vec2::ZERO,
+                timestep_mode: TimestepMode::Fixed {
+                    dt: LOCKSTEP_TS,
+                    substeps: 1,
+                },
+                ..default()
+            })
+            .insert_resource(DetectionRng(StdRng::seed_from_u64(seed)))
+            .insert_resource(FilterRng(StdRng::seed_from_u64(seed)));
+        app.finish();
+        app.cleanup();
+        app.update();
+        Self {
+            app,
+            replay: Replay {
+                level: level.to_data(),
+                seed,
+                ticks: Vec::new(),
+                checksums: Vec::new(),
+            },
+            ended_at: None,
+        }
+    }
+
+    /// The ticks played so far.
+    fn tick(&self) -> u64 {
+        self.replay.ticks.len() as u64
+    }
+
+    /// Plays a tick with the agents' next inputs.
+    fn step(&mut self, inputs: &mut HashMap<Role, AgentInputs>) {
+        let player = inputs.entry(Role::Player).or_default().next();
+        let pursuer = inputs.entry(Role::Pursuer).or_default().next();
+        let world = &mut self.app.world;
+        set_action::<PlayerAgent>(world, &player);
+        set_action::<PursuerAgent>(world, &pursuer);
+        self.app.update();
+        self.replay.ticks.push(ReplayTick {
+            delta: tick_duration(),
+            player,
+            pursuer,
+        });
+        if self.ended_at.is_none() && self.app.world.contains_resource::<LevelComplete>() {
+            self.ended_at = Some(self.tick());
+        }
+    }
+
+    /// Describes where everything is. `acked` is left for each recipient to fill in.
+    fn state(&mut self) -> MatchState {
+        let tick = self.tick();
+        let world = &mut self.app.world;
+        MatchState {
+            tick,
+            acked: None,
+            player: agent_pose::<PlayerAgent>(world),
+            pursuer: agent_pose::<PursuerAgent>(world),
+            objects: world
+                .query::<(&LevelObject, &GlobalTransform)>()
+                .iter(world)
+                .map(|(obj, xform)| (obj.0, xform.translation().xy().into()))
+                .collect(),
+            escaped: matches!(
+                world.get_resource::<LevelComplete>(),
+                Some(LevelComplete(GameOutcome::PlayerEscaped { .. }))
+            ),
+        }
+    }
+}
+
+fn tick_duration() -> Duration {
+    Duration::from_secs_f32(LOCKSTEP_TS)
+}
+
+fn set_action<T: Component>(world: &mut World, action: &RecordedAction) {
+    let mut query = world.query_filtered::<&mut NextAction, With<T>>();
+    if let Ok(mut next_action) = query.get_single_mut(world) {
+        action.apply(&mut next_action);
+    }
+}
+
+fn agent_pose<T: Component>(world: &mut World) -> Option<AgentPose> {
+    let mut query = world.query_filtered::<(&Agent, &GlobalTransform), With<T>>();
+    let (agent, xform) = query.get_single(world).ok()?;
+    Some(AgentPose {
+        pos: xform.translation().xy().into(),
+        dir: agent.dir.into(),
+    })
+}
+
+/// Runs matches on `addr` until the process is stopped, playing `level` if it's given and random levels otherwise.
+/// Each level waits until both agents have someone controlling them. If `record_dir` is set, a replay of every level
+/// is saved there.
+pub fn run_match_server(
+    addr: &str,
+    level: Option<&Path>,
+    record_dir: Option<&Path>,
+    seed: u64,
+) -> Result<(), CliError> {
+    let listen_err = |source| CliError::Listen {
+        addr: addr.into(),
+        source,
+    };
+    let listener = TcpListener::bind(addr).map_err(listen_err)?;
+    if let Some(dir) = record_dir {
+        std::fs::create_dir_all(dir).map_err(|source| CliError::Io {
+            path: dir.into(),
+            source,
+        })?;
+    }
+    println!("Running matches on ws://{addr}");
+
+    let (socket_tx, socket_rx) = mpsc::channel();
+    thread::spawn(move || accept_clients(listener, socket_tx));
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut clients = Vec::new();
+    let mut inputs = HashMap::new();
+    let mut game = Match::start(next_level(level, &mut rng)?, &mut rng);
+    let mut next_tick = Instant::now();
+    loop {
+        add_clients(&socket_rx, &mut clients);
+        read_clients(&mut clients, &mut inputs, &game);
+
+        let agents_taken = Role::ALL.into_iter().all(|role| {
+            clients
+                .iter()
+                .any(|client| client.role.and_then(|role| role.agent()) == Some(role))
+        });
+        if agents_taken {
+            game.step(&mut inputs);
+        }
+        let state = game.state();
+        clients.retain_mut(|client| {
+            let Some(role) = client.role else {
+                return true;
+            };
+            let acked = role
+                .agent()
+                .and_then(|agent| inputs.get(&agent)?.last)
+                .map(|(tick, _)| tick);
+            let state = MatchState {
+                acked,
+                ..state.clone()
+            };
+            send(&mut client.socket, &ServerMessage::State(state)).is_ok()
+        });
+
+        if game
+            .ended_at
+            .is_some_and(|ended_at| game.tick() >= ended_at + LEVEL_END_TICKS)
+        {
+            if let Some(dir) = record_dir {
+                let path = (0..)
+                    .map(|i| dir.join(format!("match_{i}.replay")))
+                    .find(|path| !path.exists())
+                    .unwrap();
+                match std::fs::write(&path, game.replay.to_bytes()) {
+                    Ok(()) => println!("Saved replay to {path:?}"),
+                    Err(err) => eprintln!("Could not save replay to {path:?}: {err}"),
+                }
+            }
+            game = Match::start(next_level(level, &mut rng)?, &mut rng);
+            for agent_inputs in inputs.values_mut() {
+                agent_inputs.pending.clear();
+            }
+            let msg = ServerMessage::Level {
+                level: game.replay.level.clone(),
+            };
+            clients.retain_mut(|client| {
+                client.role.is_none() || send(&mut client.socket, &msg).is_ok()
+            });
+        }
+
+        // Play ticks at the rate they cover game time, without trying to catch up if the server falls behind
+        next_tick += tick_duration();
+        let now = Instant::now();
+        match next_tick.checked_duration_since(now) {
+            Some(wait) => thread::sleep(wait),
+            None => next_tick = now,
+        }
+    }
+}
+
+/// Accepts games as they connect, completing each handshake on its own thread and passing the socket on to the match
+/// once it's done.
+fn accept_clients(listener: TcpListener, sockets: Sender<WebSocket<TcpStream>>) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                eprintln!("Could not accept connection: {err}");
+                continue;
+            }
+        };
+        let sockets = sockets.clone();
+        thread::spawn(move || match handshake(stream) {
+            Ok(socket) => {
+                let _ = sockets.send(socket);
+            }
+            Err(err) => eprintln!("Could not connect: {err}"),
+        });
+    }
+}
+
+/// Adds games that have finished connecting since the last tick.
+fn add_clients(sockets: &Receiver<WebSocket<TcpStream>>, clients: &mut Vec<Client>) {
+    clients.extend(
+        sockets
+            .try_iter()
+            .map(|socket| Client { socket, role: None }),
+    );
+}
+
+/// Completes a WebSocket handshake, then makes the socket non-blocking, so slow games don't hold up the match.
+fn handshake(stream: TcpStream) -> Result<WebSocket<TcpStream>, String> {
+    stream
+        .set_read_timeout(Some(HANDSHAKE_TIMEOUT))
+        .map_err(|err| err.to_string())?;
+    let socket = tungstenite::accept(stream).map_err(|err| err.to_string())?;
+    socket
+        .get_ref()
+        .set_nonblocking(true)
+        .map_err(|err| err.to_string())?;
+    Ok(socket)
+}
+
+/// Handles every message games have sent since the last tick, dropping games that have disconnected.
+fn read_clients(clients: &mut Vec<Client>, inputs: &mut HashMap<Role, AgentInputs>, game: &Match) {
+    let mut idx = 0;
+    while idx < clients.len() {
+        match read_client(clients, idx, inputs, game) {
+            Ok(()) => idx += 1,
+            Err(err) => {
+                let client = clients.remove(idx);
+                if let Some(agent) = client.role.and_then(|role| role.agent()) {
+                    println!("The {agent:?} left: {err}");
+                    inputs.remove(&agent);
+                }
+            }
+        }
+    }
+}
+
+fn read_client(
+    clients: &mut [Client],
+    idx: usize,
+    inputs: &mut HashMap<Role, AgentInputs>,
+    game: &Match,
+) -> tungstenite::Result<()> {
+    loop {
+        let text = match clients[idx].socket.read() {
+            Ok(Message::Text(text)) => text,
+            Ok(Message::Close(_)) => return Err(tungstenite::Error::ConnectionClosed),
+            Ok(_) => continue,
+            Err(tungstenite::Error::Io(err)) if err.kind() == ErrorKind::WouldBlock => {
+                return Ok(())
+            }
+            Err(err) => return Err(err),
+        };
+        let reply = match (serde_json::from_str(&text), clients[idx].role) {
+            (Ok(ClientMessage::Join { role }), None) => {
+                let taken = role.agent().is_some()
+                    && clients.iter().any(|client| client.role == Some(role));
+                if taken {
+                    Some(format!("Someone is already playing as the {role:?}"))
+                } else {
+                    let client = &mut clients[idx];
+                    client.role = Some(role);
+                    send(&mut client.socket, &ServerMessage::Joined { role })?;
+                    let level = game.replay.level.clone();
+                    send(&mut client.socket, &ServerMessage::Level { level })?;
+                    None
+                }
+            }
+            (Ok(ClientMessage::Join { .. }), Some(_)) => Some("Already joined".into()),
+            (Ok(ClientMessage::Input { tick, action }), Some(role)) => {
+                // Games can only move the agent they control
+                if let Some(agent) = role.agent() {
+                    inputs.entry(agent).or_default().push(tick, action);
+                }
+                None
+            }
+            (Ok(ClientMessage::Input { .. }), None) => Some("Join before sending inputs".into()),
+            (Err(err), _) => Some(format!("Invalid message: {err}")),
+        };
+        if let Some(message) = reply {
+            send(&mut clients[idx].socket, &ServerMessage::Error { message })?;
+        }
+    }
+}
+
+/// Sends a message, or queues it to be sent later if the game isn't ready for it yet.
+fn send(socket: &mut WebSocket<TcpStream>, msg: &ServerMessage) -> tungstenite::Result<()> {
+    let text = serde_json::to_string(msg).expect("server messages should always serialize");
+    match socket.send(Message::Text(text)) {
+        Err(tungstenite::Error::Io(err)) if err.kind() == ErrorKind::WouldBlock => Ok(()),
+        result => result,
+    }
+}
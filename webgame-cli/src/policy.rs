@@ -0,0 +1,108 @@
+//! Chooses actions for agents.
+
+use std::path::Path;
+
+use bevy::prelude::*;
+use candle_core::{DType, Device, Tensor};
+use candle_nn as nn;
+use rand::{distributions::WeightedIndex, prelude::Distribution, Rng};
+use webgame_game::{
+    gridworld::LevelLayout,
+    net::{ComputeDevice, LoadableNN, PolicyNet, ACTION_COUNT, POLICY_CHANNELS},
+};
+
+use crate::{env::Env, CliError};
+
+/// Controls an agent.
+pub enum Policy {
+    /// Picks uniformly random actions.
+    Random,
+    /// Samples actions from a trained policy network, running on the device it was loaded onto.
+    Net(PolicyNet, Device),
+}
+
+impl Policy {
+    /// Loads a policy network from a safetensors checkpoint onto a device, or uses random actions if no path is given.
+    pub fn load(path: Option<&Path>, device: ComputeDevice) -> Result<Self, CliError> {
+        let Some(path) = path else {
+            return Ok(Self::Random);
+        };
+        let weights = std::fs::read(path).map_err(|source| CliError::Io {
+            path: path.into(),
+            source,
+        })?;
+        let device = device.device();
+        let load = || {
+            let vb = nn::VarBuilder::from_buffered_safetensors(weights, DType::F32, &device)?;
+            PolicyNet::load(vb)
+        };
+        load()
+            .map(|net| Self::Net(net, device))
+            .map_err(|source| CliError::Policy {
+                path: path.into(),
+                source,
+            })
+    }
+
+    /// Chooses an action for the agent with marker `T`, whose opponent has marker `O`.
+    pub fn act<T: Component, O: Component>(
+        &self,
+        env: &mut Env,
+        rng: &mut impl Rng,
+    ) -> Result<usize, CliError> {
+        Ok(self.act_batch::<T, O>(std::slice::from_mut(env), rng)?[0])
+    }
+
+    /// Chooses an action for the agent with marker `T` in each environment, whose opponent has marker `O`.
+    ///
+    /// Observations from environments with the same level size are stacked and run through the network in a single
+    /// forward pass, which is much faster than running each environment on its own.
+    pub fn act_batch<T: Component, O: Component>(
+        &self,
+        envs: &mut [Env],
+        rng: &mut impl Rng,
+    ) -> Result<Vec<usize>, CliError> {
+        let (net, device) = match self {
+            Self::Random => {
+                return Ok(envs
+                    .iter()
+                    .map(|_| rng.gen_range(0..ACTION_COUNT))
+                    .collect())
+            }
+            Self::Net(net, device) => (net, device),
+        };
+
+        // Group environments by level size, since each batch must have a single shape
+        let mut batches: Vec<((usize, usize), Vec<usize>)> = Vec::new();
+        for (i, env) in envs.iter().enumerate() {
+            let level = env.app.world.resource::<LevelLayout>();
+            let size = (level.width, level.height);
+            match batches
+                .iter_mut()
+                .find(|(batch_size, _)| *batch_size == size)
+            {
+                Some((_, indices)) => indices.push(i),
+                None => batches.push((size, vec![i])),
+            }
+        }
+
+        let mut actions = vec![0; envs.len()];
+        for ((width, height), indices) in batches {
+            let input: Vec<f32> = indices
+                .iter()
+                .flat_map(|&i| envs[i].policy_input::<T, O>())
+                .collect();
+            let grid = Tensor::from_vec(
+                input,
+                (indices.len(), POLICY_CHANNELS, height, width),
+                device,
+            )?;
+            let probs: Vec<Vec<f32>> =
+                nn::ops::softmax_last_dim(&net.forward(&grid)?)?.to_vec2()?;
+            for (i, probs) in indices.into_iter().zip(probs) {
+                actions[i] = WeightedIndex::new(&probs)?.sample(rng);
+            }
+        }
+        Ok(actions)
+    }
+}
@@ -0,0 +1,352 @@
+//! A WebSocket server that gathers games into rooms for online matches, and passes messages between them.
+//!
+//! Each connection runs on its own thread. Rooms are shared between threads, and every member of a room has a channel
+//! its connection forwards to its game, so connections can message each other without the server understanding what
+//! they're saying.
+
+use std::{
+    collections::HashMap,
+    io::ErrorKind,
+    net::{TcpListener, TcpStream},
+    sync::{
+        mpsc::{self, Sender},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    thread,
+    time::Duration,
+};
+
+use rand::Rng;
+use tungstenite::Message;
+use webgame_game::multiplayer::{
+    LobbyInfo, LobbyMember, LobbyRole, RelayMessage, Role, ROOM_CODE_LEN,
+};
+
+use crate::CliError;
+
+/// How long connections wait for a message from their game before checking for ones to pass on.
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Sent to members who act on a room that closed before they heard about it.
+const ROOM_GONE: &str = "The room has closed";
+
+/// Everyone in a room.
+struct Room {
+    members: Vec<Member>,
+    /// Whether the match has started. Rooms can't be joined once it has.
+    started: bool,
+}
+
+struct Member {
+    /// Identifies the member's connection, since members move around as others leave.
+    id: usize,
+    role: LobbyRole,
+    ready: bool,
+    host: bool,
+    /// Messages to pass on to the member's game.
+    sender: Sender<String>,
+}
+
+type Rooms = Arc<Mutex<HashMap<String, Room>>>;
+
+impl Room {
+    /// Returns the first agent no one is playing as, or `Spectator` if both are taken.
+    fn free_role(&self) -> LobbyRole {
+        Role::ALL
+            .into_iter()
+            .map(LobbyRole::from)
+            .find(|&role| !self.is_taken(role, None))
+            .unwrap_or(LobbyRole::Spectator)
+    }
+
+    /// Returns whether someone other than `except` is playing as this agent. Any number of people can spectate.
+    fn is_taken(&self, role: LobbyRole, except: Option<usize>) -> bool {
+        role.agent().is_some()
+            && self
+                .members
+                .iter()
+                .any(|member| member.role == role && Some(member.id) != except)
+    }
+
+    fn member_mut(&mut self, id: usize) -> &mut Member {
+        self.members
+            .iter_mut()
+            .find(|member| member.id == id)
+            .expect("connections should only act on rooms they're in")
+    }
+
+    /// Sends everyone the state of the room.
+    fn send_lobby(&self, room: &str) {
+        let members: Vec<_> = self
+            .members
+            .iter()
+            .map(|member| LobbyMember {
+                role: member.role,
+                ready: member.ready,
+                host: member.host,
+            })
+            .collect();
+        for (you, member) in self.members.iter().enumerate() {
+            let _ = member.sender.send(to_text(&RelayMessage::Lobby(LobbyInfo {
+                room: room.into(),
+                members: members.clone(),
+                you,
+            })));
+        }
+    }
+
+    /// Starts the match once both agents have someone playing as them and everyone is ready.
+    fn try_start(&mut self) {
+        let agents_taken = Role::ALL
+            .into_iter()
+            .all(|role| self.is_taken(role.into(), None));
+        if self.started || !agents_taken || !self.members.iter().all(|member| member.ready) {
+            return;
+        }
+        self.started = true;
+        for member in &self.members {
+            let _ = member
+                .sender
+                .send(to_text(&RelayMessage::Started { role: member.role }));
+        }
+    }
+
+    /// Passes a message on to everyone but its sender.
+    fn relay(&self, from: usize, text: &str) {
+        for member in self.members.iter().filter(|member| member.id != from) {
+            let _ = member.sender.send(text.into());
+        }
+    }
+}
+
+/// Accepts connections on `addr` until the process is stopped.
+pub fn run_relay(addr: &str) -> Result<(), CliError> {
+    let listener = TcpListener::bind(addr).map_err(|source| CliError::Listen {
+        addr: addr.into(),
+        source,
+    })?;
+    println!("Relaying matches on ws://{addr}");
+    let rooms = Rooms::default();
+    for (id, stream) in listener.incoming().enumerate() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                eprintln!("Could not accept connection: {err}");
+                continue;
+            }
+        };
+        let rooms = rooms.clone();
+        thread::spawn(move || {
+            if let Err(err) = handle_connection(stream, id, &rooms) {
+                eprintln!("Connection closed: {err}");
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Hosts and joins rooms for a game, and passes messages between it and the rest of its room.
+fn handle_connection(
+    stream: TcpStream,
+    id: usize,
+    rooms: &Rooms,
+) -> Result<(), tungstenite::Error> {
+    let mut socket = tungstenite::accept(stream).map_err(|err| match err {
+        tungstenite::HandshakeError::Failure(err) => err,
+        tungstenite::HandshakeError::Interrupted(_) => {
+            tungstenite::Error::Io(ErrorKind::WouldBlock.into())
+        }
+    })?;
+    // Time out reads, so messages from the rest of the room are passed on even while this game is quiet
+    socket.get_ref().set_read_timeout(Some(POLL_INTERVAL))?;
+
+    let (tx, rx) = mpsc::channel();
+    let mut in_room: Option<String> = None;
+    let room_closed = to_text(&RelayMessage::RoomClosed);
+    let result = loop {
+        for text in rx.try_iter() {
+            if text == room_closed {
+                in_room = None;
+            }
+            socket.send(Message::Text(text))?;
+        }
+
+        let text = match socket.read() {
+            Ok(Message::Text(text)) => text,
+            Ok(Message::Close(_)) => break Ok(()),
+            Ok(_) => continue,
+            Err(tungstenite::Error::Io(err))
+                if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+            {
+                continue
+            }
+            Err(err) => break Err(err),
+        };
+        let msg = match serde_json::from_str(&text) {
+            Ok(msg) => msg,
+            Err(err) => {
+                socket.send(to_error(format!("Invalid message: {err}")))?;
+                continue;
+            }
+        };
+        let mut rooms = lock_rooms(rooms);
+        let reply = match (msg, in_room.clone()) {
+            (RelayMessage::Host { role }, None) => {
+                let code = new_room_code(&rooms);
+                let room = Room {
+                    members: vec![Member {
+                        id,
+                        role,
+                        ready: false,
+                        host: true,
+                        sender: tx.clone(),
+                    }],
+                    started: false,
+                };
+                room.send_lobby(&code);
+                rooms.insert(code.clone(), room);
+                in_room = Some(code);
+                None
+            }
+            (RelayMessage::Join { room: code }, None) => match rooms.get_mut(&code) {
+                Some(room) if !room.started => {
+                    let role = room.free_role();
+                    room.members.push(Member {
+                        id,
+                        role,
+                        ready: false,
+                        host: false,
+                        sender: tx.clone(),
+                    });
+                    room.send_lobby(&code);
+                    in_room = Some(code);
+                    None
+                }
+                Some(_) => Some(format!("The match in room {code} has already started")),
+                None => Some(format!("No open room with the code {code}")),
+            },
+            (RelayMessage::Host { .. } | RelayMessage::Join { .. }, Some(_)) => {
+                Some("Already in a room".into())
+            }
+            (RelayMessage::SelectRole { role }, Some(code)) => {
+                match joined_room(&mut rooms, &code, id) {
+                    Some(room) if room.started => Some("The match has already started".into()),
+                    Some(room) if room.is_taken(role, Some(id)) => {
+                        Some(format!("Someone is already playing as the {role:?}"))
+                    }
+                    Some(room) => {
+                        let member = room.member_mut(id);
+                        member.role = role;
+                        member.ready = false;
+                        room.send_lobby(&code);
+                        None
+                    }
+                    None => {
+                        in_room = None;
+                        Some(ROOM_GONE.into())
+                    }
+                }
+            }
+            (RelayMessage::Ready { ready }, Some(code)) => match joined_room(&mut rooms, &code, id)
+            {
+                Some(room) => {
+                    if !room.started {
+                        room.member_mut(id).ready = ready;
+                        room.send_lobby(&code);
+                        room.try_start();
+                    }
+                    None
+                }
+                None => {
+                    in_room = None;
+                    Some(ROOM_GONE.into())
+                }
+            },
+            (
+                RelayMessage::Input(_) | RelayMessage::Reset { .. } | RelayMessage::Checksum { .. },
+                Some(code),
+            ) => match joined_room(&mut rooms, &code, id) {
+                Some(room) => {
+                    room.relay(id, &text);
+                    None
+                }
+                None => {
+                    in_room = None;
+                    Some(ROOM_GONE.into())
+                }
+            },
+            (RelayMessage::SelectRole { .. } | RelayMessage::Ready { .. }, None) => {
+                Some("Not in a room".into())
+            }
+            _ => None,
+        };
+        drop(rooms);
+        if let Some(message) = reply {
+            socket.send(to_error(message))?;
+        }
+    };
+
+    if let Some(code) = in_room {
+        leave_room(&mut lock_rooms(rooms), &code, id);
+    }
+    result
+}
+
+/// Locks the rooms, even if a connection panicked while holding the lock, so one bad connection can't take down the
+/// relay.
+fn lock_rooms(rooms: &Rooms) -> MutexGuard<HashMap<String, Room>> {
+    rooms.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Returns the room with this code if the connection is still in it, or `None` if it has closed.
+fn joined_room<'a>(
+    rooms: &'a mut HashMap<String, Room>,
+    code: &str,
+    id: usize,
+) -> Option<&'a mut Room> {
+    rooms
+        .get_mut(code)
+        .filter(|room| room.members.iter().any(|member| member.id == id))
+}
+
+/// Removes a member from their room. The room closes if it's empty or the host left, since no one else can pick levels.
+/// Everyone left is told it closed, so their connections stop acting on it.
+fn leave_room(rooms: &mut HashMap<String, Room>, code: &str, id: usize) {
+    let Some(room) = rooms.get_mut(code) else {
+        return;
+    };
+    let Some(idx) = room.members.iter().position(|member| member.id == id) else {
+        return;
+    };
+    let member = room.members.remove(idx);
+    if member.host || room.members.is_empty() {
+        room.relay(id, &to_text(&RelayMessage::RoomClosed));
+        rooms.remove(code);
+        return;
+    }
+    if room.started && member.role.agent().is_some() {
+        room.relay(id, &to_text(&RelayMessage::PeerLeft));
+    }
+    room.send_lobby(code);
+}
+
+/// Returns a random room code that isn't in use.
+fn new_room_code(rooms: &HashMap<String, Room>) -> String {
+    let mut rng = rand::thread_rng();
+    loop {
+        let room: String = (0..ROOM_CODE_LEN)
+            .map(|_| rng.gen_range(b'A'..=b'Z') as char)
+            .collect();
+        if !rooms.contains_key(&room) {
+            return room;
+        }
+    }
+}
+
+fn to_text(msg: &RelayMessage) -> String {
+    serde_json::to_string(msg).expect("relay messages should always serialize")
+}
+
+fn to_error(message: String) -> Message {
+    Message::Text(to_text(&RelayMessage::Error { message }))
+}
@@ -0,0 +1,103 @@
+//! Records rollouts and writes them to Parquet files.
+
+use std::{fs::File, path::Path, sync::Arc};
+
+use parquet::{
+    data_type::{BoolType, FloatType, Int32Type},
+    errors::ParquetError,
+    file::{properties::WriterProperties, writer::SerializedFileWriter},
+    schema::parser::parse_message_type,
+};
+
+use crate::CliError;
+
+/// A single step of a rollout, recorded after both agents acted.
+pub struct TrajectoryStep {
+    pub episode: i32,
+    pub step: i32,
+    pub player_action: i32,
+    pub pursuer_action: i32,
+    pub player_x: f32,
+    pub player_y: f32,
+    pub pursuer_x: f32,
+    pub pursuer_y: f32,
+    pub pursuer_sees_player: bool,
+    pub player_escaped: bool,
+    pub player_caught: bool,
+}
+
+/// A column of values, along with its name in the file.
+enum Column {
+    Int(&'static str, Vec<i32>),
+    Float(&'static str, Vec<f32>),
+    Bool(&'static str, Vec<bool>),
+}
+
+/// Writes steps to a Parquet file, with one row per step.
+pub fn write_trajectory(path: &Path, steps: &[TrajectoryStep]) -> Result<(), CliError> {
+    let ints =
+        |name, f: fn(&TrajectoryStep) -> i32| Column::Int(name, steps.iter().map(f).collect());
+    let floats =
+        |name, f: fn(&TrajectoryStep) -> f32| Column::Float(name, steps.iter().map(f).collect());
+    let bools =
+        |name, f: fn(&TrajectoryStep) -> bool| Column::Bool(name, steps.iter().map(f).collect());
+    let columns = [
+        ints("episode", |s| s.episode),
+        ints("step", |s| s.step),
+        ints("player_action", |s| s.player_action),
+        ints("pursuer_action", |s| s.pursuer_action),
+        floats("player_x", |s| s.player_x),
+        floats("player_y", |s| s.player_y),
+        floats("pursuer_x", |s| s.pursuer_x),
+        floats("pursuer_y", |s| s.pursuer_y),
+        bools("pursuer_sees_player", |s| s.pursuer_sees_player),
+        bools("player_escaped", |s| s.player_escaped),
+        bools("player_caught", |s| s.player_caught),
+    ];
+
+    let fields: String = columns
+        .iter()
+        .map(|column| match column {
+            Column::Int(name, _) => format!("REQUIRED INT32 {name}; "),
+            Column::Float(name, _) => format!("REQUIRED FLOAT {name}; "),
+            Column::Bool(name, _) => format!("REQUIRED BOOLEAN {name}; "),
+        })
+        .collect();
+    let schema = Arc::new(parse_message_type(&format!(
+        "message trajectory {{ {fields}}}"
+    ))?);
+
+    let file = File::create(path).map_err(|source| CliError::Io {
+        path: path.into(),
+        source,
+    })?;
+    let mut writer =
+        SerializedFileWriter::new(file, schema, Arc::new(WriterProperties::builder().build()))?;
+    let mut row_group = writer.next_row_group()?;
+    for column in &columns {
+        let mut col_writer = row_group
+            .next_column()?
+            .ok_or_else(|| ParquetError::General("Schema has too few columns".into()))?;
+        match column {
+            Column::Int(_, values) => {
+                col_writer
+                    .typed::<Int32Type>()
+                    .write_batch(values, None, None)?;
+            }
+            Column::Float(_, values) => {
+                col_writer
+                    .typed::<FloatType>()
+                    .write_batch(values, None, None)?;
+            }
+            Column::Bool(_, values) => {
+                col_writer
+                    .typed::<BoolType>()
+                    .write_batch(values, None, None)?;
+            }
+        }
+        col_writer.close()?;
+    }
+    row_group.close()?;
+    writer.close()?;
+    Ok(())
+}
@@ -0,0 +1,174 @@
+//! Tracks the pursuer's belief over where the player is with a simple grid filter, printing it every few steps.
+//!
+//! Run with `cargo run --example belief_visualization -- [seed]`.
+
+use bevy::prelude::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use webgame_game::{
+    bitgrid::BitGrid,
+    configs::LibCfgPlugin,
+    gridworld::{LevelLayout, NextAction, PlayerAgent, PursuerAgent, DEFAULT_LEVEL_SIZE},
+    observer::Observer,
+};
+
+const MAX_STEPS: usize = 40;
+const PRINT_EVERY: usize = 10;
+const WALL_PROB: f64 = 0.1;
+
+fn main() {
+    let seed = std::env::args()
+        .nth(1)
+        .map(|seed| seed.parse().expect("seed should be an integer"))
+        .unwrap_or(0);
+    run(seed);
+}
+
+/// Runs the example with the given seed.
+fn run(seed: u64) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let level = LevelLayout::random(
+        DEFAULT_LEVEL_SIZE,
+        DEFAULT_LEVEL_SIZE,
+        WALL_PROB,
+        DEFAULT_LEVEL_SIZE,
+        &mut rng,
+    );
+    let (width, height) = (level.width, level.height);
+    let grid = level.grid();
+
+    let mut app = App::new();
+    app.add_plugins(LibCfgPlugin).insert_resource(level.clone());
+    app.finish();
+    app.cleanup();
+    app.update();
+
+    // Start with a uniform belief over all empty cells
+    let mut belief: Vec<f32> = level
+        .walls
+        .iter()
+        .map(|wall| if wall { 0. } else { 1. })
+        .collect();
+    normalize(&mut belief);
+
+    for step in 0..MAX_STEPS {
+        let world = &mut app.world;
+        let (player_e, _) = agent_pos::<PlayerAgent>(world);
+        let (pursuer_e, _) = agent_pos::<PursuerAgent>(world);
+        for e in [player_e, pursuer_e] {
+            world.get_mut::<NextAction>(e).unwrap().dir =
+                Vec2::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0)).normalize_or_zero();
+        }
+        app.update();
+
+        // Predict: the player may have moved into any neighboring empty cell
+        belief = diffuse(&belief, &level.walls, width, height);
+
+        // Update: either collapse onto the player, or rule out every cell the pursuer can see
+        let world = &mut app.world;
+        let (_, player_pos) = agent_pos::<PlayerAgent>(world);
+        let observer = world.get::<Observer>(pursuer_e).unwrap();
+        if observer.observing.contains(&player_e) {
+            belief.fill(0.);
+            let (x, y) = grid.world_to_cell_clamped(player_pos);
+            belief[y * width + x] = 1.;
+        } else {
+            for (i, prob) in belief.iter_mut().enumerate() {
+                let center = grid.cell_to_world(grid.idx_cell(i));
+                if observer.vis_mesh.iter().any(|tri| in_triangle(center, tri)) {
+                    *prob = 0.;
+                }
+            }
+            normalize(&mut belief);
+        }
+
+        if (step + 1) % PRINT_EVERY == 0 {
+            println!("Step {}:", step + 1);
+            println!("{}\n", draw_belief(&belief, &level.walls, width));
+        }
+    }
+}
+
+/// Spreads each cell's probability evenly between itself and its empty neighbors.
+fn diffuse(belief: &[f32], walls: &BitGrid, width: usize, height: usize) -> Vec<f32> {
+    let mut next = vec![0.; belief.len()];
+    for (i, &prob) in belief.iter().enumerate() {
+        if walls[i] {
+            continue;
+        }
+        let (x, y) = (i % width, i / width);
+        let mut targets = vec![i];
+        if x > 0 {
+            targets.push(i - 1);
+        }
+        if x + 1 < width {
+            targets.push(i + 1);
+        }
+        if y > 0 {
+            targets.push(i - width);
+        }
+        if y + 1 < height {
+            targets.push(i + width);
+        }
+        targets.retain(|&j| !walls[j]);
+        for &j in &targets {
+            next[j] += prob / targets.len() as f32;
+        }
+    }
+    next
+}
+
+/// Scales the belief so it sums to 1, if it has any mass.
+fn normalize(belief: &mut [f32]) {
+    let total: f32 = belief.iter().sum();
+    if total > 0. {
+        belief.iter_mut().for_each(|prob| *prob /= total);
+    }
+}
+
+/// Draws the belief as deciles (`0`-`9`), with walls as `#` and the top row first.
+fn draw_belief(belief: &[f32], walls: &BitGrid, width: usize) -> String {
+    let max = belief.iter().cloned().fold(0., f32::max).max(f32::EPSILON);
+    let cells: Vec<char> = belief
+        .iter()
+        .zip(walls.iter())
+        .map(|(&prob, wall)| {
+            if wall {
+                '#'
+            } else {
+                char::from_digit(((prob / max) * 9.).round() as u32, 10).unwrap()
+            }
+        })
+        .collect();
+    cells
+        .chunks(width)
+        .rev()
+        .map(|row| row.iter().collect::<String>())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Returns true if the point lies inside the triangle.
+fn in_triangle(p: Vec2, tri: &[Vec2; 3]) -> bool {
+    let d1 = (p - tri[1]).perp_dot(tri[0] - tri[1]);
+    let d2 = (p - tri[2]).perp_dot(tri[1] - tri[2]);
+    let d3 = (p - tri[0]).perp_dot(tri[2] - tri[0]);
+    let has_neg = d1 < 0. || d2 < 0. || d3 < 0.;
+    let has_pos = d1 > 0. || d2 > 0. || d3 > 0.;
+    !(has_neg && has_pos)
+}
+
+/// Returns the entity and position of the agent with the given marker.
+fn agent_pos<T: Component>(world: &mut World) -> (Entity, Vec2) {
+    let (e, xform) = world
+        .query_filtered::<(Entity, &GlobalTransform), With<T>>()
+        .single(world);
+    (e, xform.translation().xy())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn runs_with_default_seed() {
+        super::run(0);
+    }
+}
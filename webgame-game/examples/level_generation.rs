@@ -0,0 +1,66 @@
+//! Generates random levels and prints them, along with the JSON for the first one.
+//!
+//! Run with `cargo run --example level_generation -- [seed]`.
+
+use rand::{rngs::StdRng, SeedableRng};
+use webgame_game::gridworld::{CellTopology, LevelLayout, DEFAULT_LEVEL_SIZE};
+
+const LEVEL_COUNT: usize = 3;
+const WALL_PROB: f64 = 0.1;
+
+fn main() {
+    let seed = std::env::args()
+        .nth(1)
+        .map(|seed| seed.parse().expect("seed should be an integer"))
+        .unwrap_or(0);
+    run(seed);
+}
+
+/// Runs the example with the given seed.
+fn run(seed: u64) {
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    for i in 0..LEVEL_COUNT {
+        let level = LevelLayout::random(
+            DEFAULT_LEVEL_SIZE,
+            DEFAULT_LEVEL_SIZE,
+            WALL_PROB,
+            DEFAULT_LEVEL_SIZE,
+            &mut rng,
+        );
+        println!("Level {i}:");
+        println!("{}\n", draw_level(&level));
+        if i == 0 {
+            println!("{}\n", level.to_data().to_json());
+        }
+    }
+}
+
+/// Draws each cell by its topology, with the top row first.
+fn draw_level(level: &LevelLayout) -> String {
+    let cells: Vec<char> = level
+        .topology()
+        .into_iter()
+        .map(|cell| match cell {
+            CellTopology::Wall => '#',
+            CellTopology::DeadEnd => 'x',
+            CellTopology::Corridor => '=',
+            CellTopology::Corner => '+',
+            CellTopology::Open => '.',
+        })
+        .collect();
+    cells
+        .chunks(level.width)
+        .rev()
+        .map(|row| row.iter().collect::<String>())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn runs_with_default_seed() {
+        super::run(0);
+    }
+}
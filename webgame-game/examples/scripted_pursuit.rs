@@ -0,0 +1,80 @@
+//! Runs a headless episode where a scripted pursuer chases the player, reporting when the player is seen.
+//!
+//! Run with `cargo run --example scripted_pursuit -- [seed]`.
+
+use bevy::prelude::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use webgame_game::{
+    configs::LibCfgPlugin,
+    gridworld::{LevelLayout, NextAction, PlayerAgent, PursuerAgent, DEFAULT_LEVEL_SIZE},
+    observer::Observer,
+};
+
+const MAX_STEPS: usize = 100;
+const WALL_PROB: f64 = 0.1;
+
+fn main() {
+    let seed = std::env::args()
+        .nth(1)
+        .map(|seed| seed.parse().expect("seed should be an integer"))
+        .unwrap_or(0);
+    run(seed);
+}
+
+/// Runs the example with the given seed.
+fn run(seed: u64) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let level = LevelLayout::random(
+        DEFAULT_LEVEL_SIZE,
+        DEFAULT_LEVEL_SIZE,
+        WALL_PROB,
+        DEFAULT_LEVEL_SIZE,
+        &mut rng,
+    );
+
+    let mut app = App::new();
+    app.add_plugins(LibCfgPlugin).insert_resource(level);
+    app.finish();
+    app.cleanup();
+    app.update();
+
+    let mut times_seen = 0;
+    for step in 0..MAX_STEPS {
+        let world = &mut app.world;
+        let (player_e, player_pos) = agent_pos::<PlayerAgent>(world);
+        let (pursuer_e, pursuer_pos) = agent_pos::<PursuerAgent>(world);
+
+        // The pursuer heads straight for the player, while the player wanders randomly
+        world.get_mut::<NextAction>(pursuer_e).unwrap().dir =
+            (player_pos - pursuer_pos).normalize_or_zero();
+        world.get_mut::<NextAction>(player_e).unwrap().dir =
+            Vec2::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0)).normalize_or_zero();
+
+        app.update();
+
+        let world = &mut app.world;
+        let observer = world.get::<Observer>(pursuer_e).unwrap();
+        if observer.observing.contains(&player_e) {
+            times_seen += 1;
+            let (_, player_pos) = agent_pos::<PlayerAgent>(world);
+            println!("Step {step}: pursuer sees player at {player_pos}");
+        }
+    }
+    println!("Player was seen on {times_seen} of {MAX_STEPS} steps.");
+}
+
+/// Returns the entity and position of the agent with the given marker.
+fn agent_pos<T: Component>(world: &mut World) -> (Entity, Vec2) {
+    let (e, xform) = world
+        .query_filtered::<(Entity, &GlobalTransform), With<T>>()
+        .single(world);
+    (e, xform.translation().xy())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn runs_with_default_seed() {
+        super::run(0);
+    }
+}
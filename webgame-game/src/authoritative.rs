@@ -0,0 +1,398 @@
+//! Matches run by a server (`webgame-cli match-server`) instead of by the players' games. Only built with the
+//! `multiplayer` feature.
+//!
+//! Only the server's simulation counts, so a modified game can't teleport its agent or open doors it can't reach, and
+//! the server's recording of each level is the one true record of what happened. Games send the server their agent's
+//! input for each tick and predict what it does by simulating the level themselves, so controls still feel immediate.
+//! The server applies at most one input per tick, and replies with where everything is and the last input it applied.
+//! If the game predicted that input differently, its agent is moved by the difference.
+
+use std::{collections::VecDeque, time::Duration};
+
+use bevy::{prelude::*, time::TimeUpdateStrategy, utils::Instant};
+use bevy_rapier2d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    gridworld::{
+        move_agents, reset_level, set_keyboard_action, Agent, LevelLayout, LevelObject,
+        LoadedLevelData, NextAction, PlayerAgent, PursuerAgent, RemoteControlled, ResetEvent,
+        ShouldRun,
+    },
+    multiplayer::{use_fixed_timestep, LobbyRole, Role, LOCKSTEP_TS},
+    replay::RecordedAction,
+    transport::{self, Transport, TransportEvent},
+};
+
+/// How far a predicted agent can be from where the server says it was before it's corrected. Small differences come
+/// from floating point error and aren't worth a visible nudge.
+pub const CORRECTION_THRESHOLD: f32 = 0.5;
+/// The most ticks of predictions kept waiting for the server to catch up.
+pub const MAX_PREDICTIONS: usize = 120;
+/// The most real time games try to catch up on if they fall behind.
+const MAX_CATCH_UP: Duration = Duration::from_millis(250);
+
+/// Sent from games to the match server, as JSON.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    /// Asks to control an agent, or to watch.
+    Join { role: LobbyRole },
+    /// The sender's agent's input for one of its ticks. Ticks count up from 0 from when the game joined.
+    Input { tick: u64, action: RecordedAction },
+}
+
+/// Sent from the match server to games, as JSON.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// The join was accepted.
+    Joined { role: LobbyRole },
+    /// The level being played, sent when a game joins and whenever a new one starts. Spawn points are always set.
+    Level { level: LoadedLevelData },
+    /// Where everything is after one of the server's ticks.
+    State(MatchState),
+    /// Sent when the server can't do what was asked.
+    Error { message: String },
+}
+
+/// The state of the level after one of the server's ticks, as seen by one game.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MatchState {
+    /// The server's ticks since the level started.
+    pub tick: u64,
+    /// The last of the recipient's inputs the server applied, if the recipient controls an agent and has sent any.
+    pub acked: Option<u64>,
+    pub player: Option<AgentPose>,
+    pub pursuer: Option<AgentPose>,
+    /// Where each of the level's objects is, by its index in the level's `objects`.
+    pub objects: Vec<(usize, [f32; 2])>,
+    /// Whether the player has escaped.
+    pub escaped: bool,
+}
+
+/// Where an agent is, in world coordinates, and the direction it's looking in.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct AgentPose {
+    pub pos: [f32; 2],
+    pub dir: [f32; 2],
+}
+
+/// Plays a match run by the match server at `server_url`, e.g. `ws://localhost:9400`.
+#[derive(Clone)]
+pub struct AuthoritativeClientPlugin {
+    pub server_url: String,
+    pub role: LobbyRole,
+}
+
+impl Plugin for AuthoritativeClientPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_non_send_resource(ServerConnection::default())
+            .insert_resource(ClientState {
+                server_url: self.server_url.clone(),
+                requested_role: self.role,
+                role: None,
+                tick: 0,
+                predictions: VecDeque::new(),
+                latest: None,
+                ticking: false,
+            })
+            .init_resource::<TickPacer>()
+            .add_systems(Startup, (use_fixed_timestep, connect_to_server))
+            .add_systems(
+                Update,
+                (
+                    receive_server_messages.before(reset_level),
+                    mark_remote_agents.after(receive_server_messages),
+                    set_keyboard_action::<PursuerAgent>.run_if(
+                        resource_exists::<ShouldRun>.and_then(|state: Res<ClientState>| {
+                            state.agent() == Some(Role::Pursuer)
+                        }),
+                    ),
+                    send_input
+                        .after(mark_remote_agents)
+                        .after(set_keyboard_action::<PlayerAgent>)
+                        .after(set_keyboard_action::<PursuerAgent>)
+                        .before(move_agents),
+                    apply_server_state
+                        .after(receive_server_messages)
+                        .after(move_agents),
+                ),
+            )
+            .add_systems(Last, (record_prediction, pace_ticks).chain());
+    }
+}
+
+/// The connection to the match server, if one has been opened.
+/// This isn't `Send` in browsers, so it's stored as a non-send resource.
+#[derive(Default)]
+struct ServerConnection {
+    socket: Option<Box<dyn Transport>>,
+}
+
+impl ServerConnection {
+    fn send(&mut self, msg: &ClientMessage) {
+        let json = serde_json::to_string(msg).expect("client messages should always serialize");
+        if let Some(socket) = &mut self.socket {
+            socket.send(json);
+        }
+    }
+}
+
+/// The state of the match, as this game sees it.
+#[derive(Resource)]
+pub struct ClientState {
+    pub server_url: String,
+    requested_role: LobbyRole,
+    /// Set once the server accepts the join.
+    pub role: Option<LobbyRole>,
+    /// The tick this game is on. Also the tick of the next input it sends.
+    pub tick: u64,
+    /// Where this game predicted its agent would be after each tick the server hasn't replied about yet.
+    predictions: VecDeque<(u64, Vec2)>,
+    /// The latest state the server sent, until it's been applied.
+    latest: Option<MatchState>,
+    /// Whether the game is playing a tick this frame.
+    ticking: bool,
+}
+
+impl ClientState {
+    pub fn agent(&self) -> Option<Role> {
+        self.role.and_then(|role| role.agent())
+    }
+}
+
+/// Holds the game back, so it plays ticks at the same rate as the server.
+#[derive(Resource, Default)]
+struct TickPacer {
+    /// Real time that hasn't been played yet.
+    budget: Duration,
+    last_frame: Option<Instant>,
+    /// Whether the game is being held back by removing `ShouldRun`.
+    frozen: bool,
+}
+
+fn connect_to_server(mut conn: NonSendMut<ServerConnection>, state: Res<ClientState>) {
+    match transport::connect(&state.server_url) {
+        Ok(socket) => conn.socket = Some(socket),
+        Err(err) => error!("Couldn't connect to {}: {err}", state.server_url),
+    }
+}
+
+/// Joins once connected, starts the levels the server sends, and keeps the latest state.
+fn receive_server_messages(
+    mut conn: NonSendMut<ServerConnection>,
+    mut state: ResMut<ClientState>,
+    mut ev_reset: EventWriter<ResetEvent>,
+) {
+    while let Some(ev) = conn.socket.as_mut().and_then(|socket| socket.try_recv()) {
+        let msg = match ev {
+            TransportEvent::Opened => {
+                let role = state.requested_role;
+                conn.send(&ClientMessage::Join { role });
+                continue;
+            }
+            TransportEvent::Message(text) => match serde_json::from_str(&text) {
+                Ok(msg) => msg,
+                Err(err) => {
+                    warn!("Ignoring invalid message from the match server: {err}");
+                    continue;
+                }
+            },
+            TransportEvent::Error(err) => {
+                error!("Match server connection error: {err}");
+                continue;
+            }
+            TransportEvent::Closed => {
+                warn!("Lost connection to the match server");
+                conn.socket = None;
+                state.role = None;
+                break;
+            }
+        };
+        match msg {
+            ServerMessage::Joined { role } => {
+                info!("Joined the match as the {role:?}");
+                state.role = Some(role);
+            }
+            ServerMessage::Level { level } => {
+                // Predictions about the last level don't apply to this one
+                state.predictions.clear();
+                state.latest = None;
+                ev_reset.send(ResetEvent {
+                    level: Some(LevelLayout::from_data(&level)),
+                });
+            }
+            ServerMessage::State(match_state) => state.latest = Some(match_state),
+            ServerMessage::Error { message } => error!("Match server error: {message}"),
+        }
+    }
+}
+
+/// Marks the agents this game doesn't control, so they only move where the server says.
+fn mark_remote_agents(
+    mut commands: Commands,
+    state: Res<ClientState>,
+    player_query: Query<Entity, (With<PlayerAgent>, Without<RemoteControlled>)>,
+    pursuer_query: Query<Entity, (With<PursuerAgent>, Without<RemoteControlled>)>,
+) {
+    for role in Role::ALL
+        .into_iter()
+        .filter(|&role| Some(role) != state.agent())
+    {
+        let remote = match role {
+            Role::Player => player_query.get_single(),
+            Role::Pursuer => pursuer_query.get_single(),
+        };
+        if let Ok(e) = remote {
+            commands.entity(e).insert(RemoteControlled);
+        }
+    }
+}
+
+/// Sends the server this game's agent's input for this tick. The game predicts what it does by applying it right away.
+fn send_input(
+    mut conn: NonSendMut<ServerConnection>,
+    mut state: ResMut<ClientState>,
+    should_run: Option<Res<ShouldRun>>,
+    player_query: Query<&NextAction, With<PlayerAgent>>,
+    pursuer_query: Query<&NextAction, With<PursuerAgent>>,
+) {
+    state.ticking = should_run.is_some() && state.role.is_some();
+    if !state.ticking {
+        return;
+    }
+    let next_action = match state.agent() {
+        Some(Role::Player) => player_query.get_single().ok(),
+        Some(Role::Pursuer) => pursuer_query.get_single().ok(),
+        None => None,
+    };
+    if let Some(next_action) = next_action {
+        conn.send(&ClientMessage::Input {
+            tick: state.tick,
+            action: RecordedAction::from(next_action),
+        });
+    }
+}
+
+/// Remembers where this game's agent ended up after this tick, to compare with where the server says it was.
+fn record_prediction(
+    mut state: ResMut<ClientState>,
+    player_query: Query<&GlobalTransform, With<PlayerAgent>>,
+    pursuer_query: Query<&GlobalTransform, With<PursuerAgent>>,
+) {
+    if !state.ticking {
+        return;
+    }
+    let xform = match state.agent() {
+        Some(Role::Player) => player_query.get_single().ok(),
+        Some(Role::Pursuer) => pursuer_query.get_single().ok(),
+        None => None,
+    };
+    if let Some(xform) = xform {
+        let tick = state.tick;
+        state
+            .predictions
+            .push_back((tick, xform.translation().xy()));
+        if state.predictions.len() > MAX_PREDICTIONS {
+            state.predictions.pop_front();
+        }
+    }
+    state.tick += 1;
+}
+
+/// Moves everything to where the server says it is, except this game's agent, which is only corrected if it was
+/// predicted wrong.
+fn apply_server_state(
+    mut state: ResMut<ClientState>,
+    mut player_query: Query<(&mut Agent, &mut Transform), With<PlayerAgent>>,
+    mut pursuer_query: Query<
+        (&mut Agent, &mut Transform),
+        (With<PursuerAgent>, Without<PlayerAgent>),
+    >,
+    mut obj_query: Query<(&LevelObject, &mut Transform), Without<Agent>>,
+) {
+    let Some(match_state) = state.latest.take() else {
+        return;
+    };
+    let local = state.agent();
+    for role in Role::ALL {
+        let (agent, pose) = match role {
+            Role::Player => (player_query.get_single_mut(), match_state.player),
+            Role::Pursuer => (pursuer_query.get_single_mut(), match_state.pursuer),
+        };
+        let (Ok((mut agent, mut xform)), Some(pose)) = (agent, pose) else {
+            continue;
+        };
+        let server_pos = Vec2::from(pose.pos);
+        if Some(role) != local {
+            agent.dir = pose.dir.into();
+            xform.translation = server_pos.extend(xform.translation.z);
+            continue;
+        }
+
+        // Compare the server's result for the last input it applied with what was predicted for it
+        let Some(acked) = match_state.acked else {
+            continue;
+        };
+        while state
+            .predictions
+            .front()
+            .is_some_and(|&(tick, _)| tick < acked)
+        {
+            state.predictions.pop_front();
+        }
+        let Some(&(tick, predicted)) = state.predictions.front() else {
+            continue;
+        };
+        let error = server_pos - predicted;
+        if tick != acked || error.length() <= CORRECTION_THRESHOLD {
+            continue;
+        }
+        // Later predictions started from the wrong place too, so they move with the agent
+        xform.translation += error.extend(0.);
+        for (_, pos) in state.predictions.iter_mut() {
+            *pos += error;
+        }
+    }
+
+    for (obj, mut xform) in obj_query.iter_mut() {
+        if let Some((_, pos)) = match_state.objects.iter().find(|(idx, _)| *idx == obj.0) {
+            xform.translation = Vec2::from(*pos).extend(xform.translation.z);
+        }
+    }
+}
+
+/// Decides whether the next frame plays a tick, and if not, holds the game back until it should, so ticks are played
+/// at the rate they cover game time.
+fn pace_ticks(
+    mut commands: Commands,
+    mut pacer: ResMut<TickPacer>,
+    should_run: Option<Res<ShouldRun>>,
+    mut time_strategy: ResMut<TimeUpdateStrategy>,
+    mut rapier_config: ResMut<RapierConfiguration>,
+) {
+    let now = Instant::now();
+    let elapsed = pacer.last_frame.map(|last| now - last).unwrap_or_default();
+    let tick_duration = Duration::from_secs_f32(LOCKSTEP_TS);
+
+    let pacer = pacer.as_mut();
+    pacer.last_frame = Some(now);
+    pacer.budget = (pacer.budget + elapsed).min(MAX_CATCH_UP);
+    if pacer.budget >= tick_duration {
+        pacer.budget -= tick_duration;
+        *time_strategy = TimeUpdateStrategy::ManualDuration(tick_duration);
+        if pacer.frozen {
+            commands.insert_resource(ShouldRun);
+            rapier_config.physics_pipeline_active = true;
+            pacer.frozen = false;
+        }
+    } else {
+        *time_strategy = TimeUpdateStrategy::ManualDuration(Duration::ZERO);
+        if !pacer.frozen && should_run.is_some() {
+            commands.remove_resource::<ShouldRun>();
+            rapier_config.physics_pipeline_active = false;
+            pacer.frozen = true;
+        }
+    }
+}
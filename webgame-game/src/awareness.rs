@@ -0,0 +1,164 @@
+//! Tracks how aware agents are of the player, so glimpses and noises build suspicion over time instead of detection
+//! being all or nothing.
+
+use bevy::prelude::*;
+
+use crate::{
+    gridworld::{LevelLayout, PlayerAgent, PursuerAgent, ShouldRun},
+    observer::{update_observers, Observer},
+    world_objs::{audible_noises, HearingConfig, NoiseSource},
+};
+
+/// Plugin for awareness.
+pub struct AwarenessPlugin;
+
+impl Plugin for AwarenessPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<AwarenessConfig>().add_systems(
+            Update,
+            update_awareness
+                .after(update_observers)
+                .run_if(resource_exists::<ShouldRun>),
+        );
+    }
+}
+
+/// How much an agent suspects the player is nearby.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AwarenessState {
+    /// The agent hasn't noticed anything.
+    #[default]
+    Unaware,
+    /// The agent has caught glimpses or heard noises, but hasn't seen the player clearly.
+    Suspicious,
+    /// The agent knows where the player is.
+    Alert,
+}
+
+/// Configures how quickly agents become aware of the player, and how awareness changes how they move.
+#[derive(Resource, Clone, Copy)]
+pub struct AwarenessConfig {
+    /// How much awareness rises per second, multiplied by the chance the agent had of noticing the player.
+    pub detection_gain: f32,
+    /// How much awareness rises per second, multiplied by how loud a noise the player made is.
+    pub noise_gain: f32,
+    /// How much awareness falls per second when nothing raises it.
+    pub decay: f32,
+    /// The awareness level at which agents become suspicious. Agents become alert at 1.
+    pub suspicious_threshold: f32,
+    /// How fast agents move in each state, as a fraction of their usual speed.
+    /// Indexed by unaware, suspicious, then alert.
+    pub speed_scales: [f32; 3],
+}
+
+impl Default for AwarenessConfig {
+    fn default() -> Self {
+        Self {
+            detection_gain: 2.,
+            noise_gain: 1.,
+            decay: 0.1,
+            suspicious_threshold: 0.3,
+            speed_scales: [1.; 3],
+        }
+    }
+}
+
+impl AwarenessConfig {
+    /// Returns true if the settings are in range: none are negative, the suspicious threshold is between 0 and 1, and
+    /// every speed scale is positive.
+    pub fn is_valid(&self) -> bool {
+        self.detection_gain >= 0.
+            && self.noise_gain >= 0.
+            && self.decay >= 0.
+            && (0. ..=1.).contains(&self.suspicious_threshold)
+            && self.speed_scales.iter().all(|&scale| scale > 0.)
+    }
+
+    /// Returns which state an agent is in at an awareness level.
+    pub fn state(&self, level: f32) -> AwarenessState {
+        if level >= 1. {
+            AwarenessState::Alert
+        } else if level >= self.suspicious_threshold {
+            AwarenessState::Suspicious
+        } else {
+            AwarenessState::Unaware
+        }
+    }
+
+    /// Returns how fast an agent in a state moves, as a fraction of its usual speed.
+    pub fn speed_scale(&self, state: AwarenessState) -> f32 {
+        self.speed_scales[state as usize]
+    }
+}
+
+/// How aware an agent is of the player.
+/// Agents that actually notice the player become alert straight away.
+#[derive(Component, Clone, Copy)]
+pub struct Awareness {
+    /// How aware the agent is, from 0 to 1.
+    pub level: f32,
+    pub state: AwarenessState,
+    /// How fast the agent moves in its current state, as a fraction of its usual speed.
+    pub speed_scale: f32,
+}
+
+impl Default for Awareness {
+    fn default() -> Self {
+        Self {
+            level: 0.,
+            state: AwarenessState::Unaware,
+            speed_scale: 1.,
+        }
+    }
+}
+
+/// Raises awareness when agents partially detect the player or hear noises the player made, and lets it fall off
+/// otherwise.
+fn update_awareness(
+    mut agent_query: Query<(
+        &mut Awareness,
+        &Observer,
+        &GlobalTransform,
+        Has<PursuerAgent>,
+    )>,
+    player_query: Query<Entity, With<PlayerAgent>>,
+    noise_query: Query<(&GlobalTransform, &NoiseSource)>,
+    level: Res<LevelLayout>,
+    (config, hearing_config): (Res<AwarenessConfig>, Res<HearingConfig>),
+    time: Res<Time>,
+) {
+    let delta = time.delta_seconds();
+    for (mut awareness, observer, xform, is_pursuer) in agent_query.iter_mut() {
+        let pos = xform.translation().xy();
+        let range_scale = hearing_config.range_scale(is_pursuer);
+        let mut gain = 0.;
+        let mut noticed = false;
+        for player_e in player_query.iter() {
+            noticed |= observer.observing.contains(&player_e);
+            let prob = observer
+                .detection_probs
+                .get(&player_e)
+                .copied()
+                .unwrap_or(0.);
+            gain += config.detection_gain * prob;
+
+            let player_noises = noise_query
+                .iter()
+                .filter(|(_, noise_src)| noise_src.activated_by == Some(player_e))
+                .map(|(noise_xform, noise_src)| ((), noise_xform.translation().xy(), noise_src));
+            for (_, loudness) in audible_noises(&level, pos, range_scale, player_noises) {
+                gain += config.noise_gain * loudness;
+            }
+        }
+
+        awareness.level = if noticed {
+            1.
+        } else if gain > 0. {
+            (awareness.level + gain * delta).min(1.)
+        } else {
+            (awareness.level - config.decay * delta).max(0.)
+        };
+        awareness.state = config.state(awareness.level);
+        awareness.speed_scale = config.speed_scale(awareness.state);
+    }
+}
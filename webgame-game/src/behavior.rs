@@ -0,0 +1,141 @@
+//! A small behavior tree framework for scripted agents.
+//!
+//! Trees are built from composite and decorator nodes wrapped around leaves, which each kind of agent defines for
+//! itself. Trees can be loaded from RON files, so behavior can be tweaked without recompiling. For example:
+//!
+//! ```ron
+//! Selector([
+//!     Sequence([Leaf(SeesPlayer), Leaf(Chase)]),
+//!     Leaf(Patrol),
+//! ])
+//! ```
+
+use std::marker::PhantomData;
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
+    prelude::*,
+    utils::BoxedFuture,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use thiserror::Error;
+
+/// The result of ticking a node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    Success,
+    Failure,
+    /// The node hasn't finished yet, and should be ticked again next update.
+    Running,
+}
+
+/// A leaf of a behavior tree, which checks or acts on the context `C`.
+pub trait Leaf<C> {
+    fn tick(&self, ctx: &mut C) -> Status;
+}
+
+/// A node of a behavior tree.
+///
+/// Trees don't remember which node was running, and are ticked from the root every update. Anything that needs to
+/// carry over between updates should be kept in the context instead.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub enum Node<L> {
+    /// Ticks children in order until one doesn't succeed, and returns its status. Succeeds if all of them do.
+    Sequence(Vec<Node<L>>),
+    /// Ticks children in order until one doesn't fail, and returns its status. Fails if all of them do.
+    Selector(Vec<Node<L>>),
+    /// Swaps success and failure.
+    Invert(Box<Node<L>>),
+    /// Succeeds even if the child fails.
+    Succeed(Box<Node<L>>),
+    Leaf(L),
+}
+
+impl<L> Node<L> {
+    /// Runs the tree rooted at this node for one update.
+    pub fn tick<C>(&self, ctx: &mut C) -> Status
+    where
+        L: Leaf<C>,
+    {
+        match self {
+            Self::Sequence(children) => children
+                .iter()
+                .map(|child| child.tick(ctx))
+                .find(|&status| status != Status::Success)
+                .unwrap_or(Status::Success),
+            Self::Selector(children) => children
+                .iter()
+                .map(|child| child.tick(ctx))
+                .find(|&status| status != Status::Failure)
+                .unwrap_or(Status::Failure),
+            Self::Invert(child) => match child.tick(ctx) {
+                Status::Success => Status::Failure,
+                Status::Failure => Status::Success,
+                Status::Running => Status::Running,
+            },
+            Self::Succeed(child) => match child.tick(ctx) {
+                Status::Running => Status::Running,
+                _ => Status::Success,
+            },
+            Self::Leaf(leaf) => leaf.tick(ctx),
+        }
+    }
+
+    /// Parses a tree from RON.
+    pub fn from_ron(ron: &str) -> Result<Self, ron::error::SpannedError>
+    where
+        L: DeserializeOwned,
+    {
+        ron::from_str(ron)
+    }
+}
+
+/// A behavior tree loaded from a RON file.
+#[derive(Asset, TypePath, Debug, Clone)]
+pub struct BehaviorTree<L: TypePath + Send + Sync>(pub Node<L>);
+
+/// Loads behavior trees with leaves of type `L` from RON files with a given extension, e.g. `pursuer.ron`.
+pub struct BehaviorTreeLoader<L> {
+    extensions: [&'static str; 1],
+    _leaf: PhantomData<fn() -> L>,
+}
+
+impl<L> BehaviorTreeLoader<L> {
+    pub fn new(extension: &'static str) -> Self {
+        Self {
+            extensions: [extension],
+            _leaf: PhantomData,
+        }
+    }
+}
+
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum BehaviorTreeLoaderError {
+    #[error("Could not load asset: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid behavior tree: {0}")]
+    Ron(#[from] ron::error::SpannedError),
+}
+
+impl<L: TypePath + DeserializeOwned + Send + Sync + 'static> AssetLoader for BehaviorTreeLoader<L> {
+    type Asset = BehaviorTree<L>;
+    type Settings = ();
+    type Error = BehaviorTreeLoaderError;
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<Self::Asset, Self::Error>> {
+        Box::pin(async move {
+            let mut buf = String::new();
+            reader.read_to_string(&mut buf).await?;
+            Ok(BehaviorTree(Node::from_ron(&buf)?))
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &self.extensions
+    }
+}
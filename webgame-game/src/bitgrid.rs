@@ -0,0 +1,200 @@
+//! Compact storage for per-cell flags, such as walls, and line of sight checks against them.
+
+use std::ops::Index;
+
+use bevy::prelude::*;
+
+const WORD_BITS: usize = u64::BITS as usize;
+
+/// A grid of flags, stored as one bit per cell.
+///
+/// Cells are indexed row by row, the same way as `LevelLayout::walls`. Indexing returns a `bool`, so reads look the
+/// same as with a `Vec<bool>`, but writes go through `set`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct BitGrid {
+    width: usize,
+    height: usize,
+    words: Vec<u64>,
+}
+
+impl BitGrid {
+    /// Creates a grid with every flag cleared.
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            words: vec![0; (width * height).div_ceil(WORD_BITS)],
+        }
+    }
+
+    /// Creates a grid from flags given row by row.
+    /// Missing flags are cleared, and extra ones are ignored.
+    pub fn from_cells(width: usize, height: usize, cells: impl IntoIterator<Item = bool>) -> Self {
+        let mut grid = Self::new(width, height);
+        for (i, value) in cells.into_iter().take(width * height).enumerate() {
+            grid.set(i, value);
+        }
+        grid
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the number of cells in the grid.
+    pub fn len(&self) -> usize {
+        self.width * self.height
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the flag at this index.
+    ///
+    /// Panics if the index is out of bounds.
+    pub fn get(&self, idx: usize) -> bool {
+        assert!(
+            idx < self.len(),
+            "index {idx} is out of bounds for a grid of {} cells",
+            self.len()
+        );
+        self.words[idx / WORD_BITS] & (1 << (idx % WORD_BITS)) != 0
+    }
+
+    /// Sets the flag at this index.
+    ///
+    /// Panics if the index is out of bounds.
+    pub fn set(&mut self, idx: usize, value: bool) {
+        assert!(
+            idx < self.len(),
+            "index {idx} is out of bounds for a grid of {} cells",
+            self.len()
+        );
+        let mask = 1 << (idx % WORD_BITS);
+        if value {
+            self.words[idx / WORD_BITS] |= mask;
+        } else {
+            self.words[idx / WORD_BITS] &= !mask;
+        }
+    }
+
+    /// Returns the flag of a cell, or `false` if it's outside the grid.
+    pub fn get_cell(&self, cell: IVec2) -> bool {
+        cell.x >= 0
+            && cell.y >= 0
+            && (cell.x as usize) < self.width
+            && (cell.y as usize) < self.height
+            && self.get(cell.y as usize * self.width + cell.x as usize)
+    }
+
+    /// Iterates over every flag, in index order.
+    pub fn iter(&self) -> impl Iterator<Item = bool> + '_ {
+        (0..self.len()).map(|i| self.get(i))
+    }
+
+    /// Iterates over the indices of set flags, in order.
+    /// Runs of cleared flags are skipped 64 at a time, so this is fast on sparse grids.
+    pub fn iter_ones(&self) -> impl Iterator<Item = usize> + '_ {
+        self.words
+            .iter()
+            .enumerate()
+            .filter(|(_, &word)| word != 0)
+            .flat_map(|(i, &word)| {
+                (0..WORD_BITS)
+                    .filter(move |bit| word & (1 << bit) != 0)
+                    .map(move |bit| i * WORD_BITS + bit)
+            })
+    }
+
+    /// Returns the number of set flags.
+    pub fn count_ones(&self) -> usize {
+        self.words
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
+    }
+
+    pub fn to_vec(&self) -> Vec<bool> {
+        self.iter().collect()
+    }
+
+    /// Sets every flag that's set in `other`, a word at a time. Both grids must be the same size.
+    pub fn union_with(&mut self, other: &BitGrid) {
+        assert_eq!(
+            (self.width, self.height),
+            (other.width, other.height),
+            "grids must be the same size"
+        );
+        for (word, &other_word) in self.words.iter_mut().zip(&other.words) {
+            *word |= other_word;
+        }
+    }
+
+    /// Walks the cells crossed by the segment between two points, and returns the first one with its flag set.
+    ///
+    /// Points are in cell units, with cell `(x, y)` centered on `(x, y)` like in world space. Cells are visited in
+    /// order using a DDA, so this only touches cells along the segment. Cells outside the grid never block.
+    pub fn raycast(&self, from: Vec2, to: Vec2) -> Option<(usize, usize)> {
+        // Shift points so cell (x, y) covers [x, x + 1) on each axis
+        let (from, to) = (from + 0.5, to + 0.5);
+        let mut cell = from.floor().as_ivec2();
+        let end = to.floor().as_ivec2();
+        let dir = to - from;
+        let step = IVec2::new(
+            if dir.x < 0. { -1 } else { 1 },
+            if dir.y < 0. { -1 } else { 1 },
+        );
+        // How far along the segment (from 0 to 1) the next crossing on each axis is
+        let first_crossing = |pos: f32, cell: i32, dir: f32| {
+            if dir > 0. {
+                (cell as f32 + 1. - pos) / dir
+            } else if dir < 0. {
+                (pos - cell as f32) / -dir
+            } else {
+                f32::INFINITY
+            }
+        };
+        let mut t_max = Vec2::new(
+            first_crossing(from.x, cell.x, dir.x),
+            first_crossing(from.y, cell.y, dir.y),
+        );
+        let t_delta = Vec2::new(1. / dir.x.abs(), 1. / dir.y.abs());
+
+        let cell_count = (end.x - cell.x).abs() + (end.y - cell.y).abs() + 1;
+        for _ in 0..cell_count {
+            if self.get_cell(cell) {
+                return Some((cell.x as usize, cell.y as usize));
+            }
+            if t_max.x < t_max.y {
+                cell.x += step.x;
+                t_max.x += t_delta.x;
+            } else {
+                cell.y += step.y;
+                t_max.y += t_delta.y;
+            }
+        }
+        None
+    }
+
+    /// Returns true if no set cells lie between the two points, which are given in cell units.
+    pub fn line_of_sight(&self, from: Vec2, to: Vec2) -> bool {
+        self.raycast(from, to).is_none()
+    }
+}
+
+impl Index<usize> for BitGrid {
+    type Output = bool;
+
+    fn index(&self, idx: usize) -> &bool {
+        if self.get(idx) {
+            &true
+        } else {
+            &false
+        }
+    }
+}
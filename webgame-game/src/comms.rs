@@ -0,0 +1,109 @@
+//! Communication between agents on the same team.
+
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+
+use crate::{
+    gridworld::{PlayerAgent, ShouldRun},
+    observer::{update_observers, Observer},
+};
+
+/// Plugin for passing sightings between teammates over radio.
+pub struct CommsPlugin;
+
+impl Plugin for CommsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<RadioConfig>()
+            .init_resource::<RadioChannel>()
+            .add_systems(
+                Update,
+                (send_alerts, deliver_alerts)
+                    .chain()
+                    .after(update_observers)
+                    .run_if(resource_exists::<ShouldRun>),
+            );
+    }
+}
+
+/// Configures how alerts propagate between teammates.
+#[derive(Resource, Clone, Copy)]
+pub struct RadioConfig {
+    /// How many ticks pass between a sighting and teammates receiving it.
+    pub delay_ticks: u64,
+}
+
+impl Default for RadioConfig {
+    fn default() -> Self {
+        Self { delay_ticks: 2 }
+    }
+}
+
+/// A sighting of the player sent by an agent.
+#[derive(Clone, Copy, Debug)]
+pub struct RadioAlert {
+    /// The agent that saw the player.
+    pub sender: Entity,
+    /// Where the player was seen.
+    pub pos: Vec2,
+    /// The tick the player was seen on.
+    pub sent_at: u64,
+}
+
+/// Allows an agent or fixed camera to alert teammates when it sees the player, and to receive their alerts.
+///
+/// Alerts are only delivered to other radios, so this has no effect until a team has multiple members.
+#[derive(Component, Default)]
+pub struct Radio {
+    /// The most recent alert received from a teammate.
+    pub last_alert: Option<RadioAlert>,
+}
+
+/// Holds alerts that have been sent, but not yet received.
+#[derive(Resource, Default)]
+pub struct RadioChannel {
+    /// The number of ticks since the level started.
+    pub tick: u64,
+    in_flight: VecDeque<RadioAlert>,
+}
+
+/// Queues an alert for every agent that can currently see the player.
+fn send_alerts(
+    radio_query: Query<(Entity, &Observer), With<Radio>>,
+    player_query: Query<(Entity, &GlobalTransform), With<PlayerAgent>>,
+    mut channel: ResMut<RadioChannel>,
+) {
+    channel.tick += 1;
+    let Ok((player_e, player_xform)) = player_query.get_single() else {
+        return;
+    };
+    for (radio_e, observer) in radio_query.iter() {
+        if observer.observing.contains(&player_e) {
+            let alert = RadioAlert {
+                sender: radio_e,
+                pos: player_xform.translation().xy(),
+                sent_at: channel.tick,
+            };
+            channel.in_flight.push_back(alert);
+        }
+    }
+}
+
+/// Delivers alerts to every teammate of the sender once the configured delay has passed.
+fn deliver_alerts(
+    mut radio_query: Query<(Entity, &mut Radio)>,
+    mut channel: ResMut<RadioChannel>,
+    config: Res<RadioConfig>,
+) {
+    while let Some(alert) = channel.in_flight.front().copied() {
+        if channel.tick < alert.sent_at + config.delay_ticks {
+            break;
+        }
+        channel.in_flight.pop_front();
+        for (radio_e, mut radio) in radio_query.iter_mut() {
+            if radio_e != alert.sender {
+                radio.last_alert = Some(alert);
+            }
+        }
+    }
+}
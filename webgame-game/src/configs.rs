@@ -12,10 +12,26 @@ use bevy::{
 use bevy_rapier2d::prelude::*;
 
 use crate::{
+    awareness::AwarenessPlugin,
+    comms::CommsPlugin,
+    difficulty::{Difficulty, DifficultyPlayPlugin, DifficultyPlugin},
+    editor::LevelEditorPlugin,
+    filter::{FilterPlayPlugin, FilterPlugin},
+    gadgets::GadgetPlugin,
     gridworld::{GridworldPlayPlugin, GridworldPlugin, LevelLayout, LevelLoader, DEFAULT_LEVEL_SIZE},
-    net::NetPlugin,
+    lighting::LightingPlugin,
+    main_menu::{open_main_menu, MainMenuPlugin},
+    net::{NetPlugin, PolicyRunnerPlugin},
     observer::{ObserverPlayPlugin, ObserverPlugin},
-    world_objs::WorldObjPlugin,
+    pathfinding::PathfindingPlugin,
+    pause_menu::PauseMenuPlugin,
+    pursuer_ai::ScriptedPursuerPlugin,
+    replay::ReplayRecorderPlugin,
+    screens::ScreenState,
+    sensors::SensorPlugin,
+    settings::SettingsPlugin,
+    visibility::VisibilityPlugin,
+    world_objs::{WorldObjPlayPlugin, WorldObjPlugin},
 };
 
 /// Handles core functionality for our game (i.e. gameplay logic).
@@ -24,7 +40,21 @@ pub struct CoreGamePlugin;
 impl Plugin for CoreGamePlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(100.0))
-            .add_plugins((NetPlugin, GridworldPlugin, ObserverPlugin, WorldObjPlugin))
+            .add_plugins((
+                NetPlugin,
+                GridworldPlugin,
+                ObserverPlugin,
+                LightingPlugin,
+                VisibilityPlugin,
+                WorldObjPlugin,
+                CommsPlugin,
+                SensorPlugin,
+                GadgetPlugin,
+                PathfindingPlugin,
+                AwarenessPlugin,
+                FilterPlugin,
+                DifficultyPlugin,
+            ))
             .insert_resource(RapierConfiguration {
                 gravity: Vec2::ZERO,
                 ..default()
@@ -53,7 +83,19 @@ impl Plugin for PlayablePlugin {
                 ..default()
             }))
             // .add_plugins(RapierDebugRenderPlugin::default())
-            .add_plugins((GridworldPlayPlugin, ObserverPlayPlugin));
+            .init_state::<ScreenState>()
+            .add_plugins((
+                GridworldPlayPlugin,
+                ObserverPlayPlugin,
+                WorldObjPlayPlugin,
+                FilterPlayPlugin,
+                LevelEditorPlugin,
+                PauseMenuPlugin,
+                MainMenuPlugin,
+                SettingsPlugin,
+                DifficultyPlayPlugin,
+                ReplayRecorderPlugin,
+            ));
     }
 }
 
@@ -62,8 +104,57 @@ pub struct ReleaseCfgPlugin;
 
 impl Plugin for ReleaseCfgPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins((PlayablePlugin, CoreGamePlugin))
-            .insert_resource(LevelLoader::Path("levels/test.json".into()));
+        // Builds with online matches open on the lobby. Open the page with `?host=player` or `?host=pursuer` to host a
+        // match straight away, or `?join=<room>` to join one
+        #[cfg(all(feature = "multiplayer", target_arch = "wasm32"))]
+        if let Some(multiplayer) = crate::multiplayer::MultiplayerPlugin::from_location() {
+            app.add_plugins(MultiplayerCfgPlugin(multiplayer));
+            return;
+        }
+        app.add_plugins((
+            PlayablePlugin,
+            CoreGamePlugin,
+            // Put a checkpoint saved by `webgame/train_agents.py` here to have the pursuer play with it. Without one, the
+            // pursuer follows a script instead. Add another with `agent: PolicyAgent::Player` for an AI vs AI match
+            PolicyRunnerPlugin {
+                path: "policies/pursuer.safetensors".into(),
+                ..default()
+            },
+            // The scripted pursuer's behavior tree is loaded from here, so it can be changed without recompiling
+            ScriptedPursuerPlugin {
+                behavior_path: Some("behaviors/default.pursuer.ron".into()),
+            },
+        ))
+        // Pick a level from `assets/levels` to start
+        .add_systems(Startup, open_main_menu)
+        // Press F3 to change the difficulty
+        .insert_resource(Difficulty::Normal);
+        // Browsers only run networks on the CPU, so halve the size of the weights to keep up with the frame rate
+        #[cfg(target_arch = "wasm32")]
+        app.insert_resource(crate::net::NetPrecision::F16);
+        // Builds with telemetry report how players do to the URL in `TELEMETRY_URL` at compile time, if it's set
+        #[cfg(feature = "telemetry")]
+        if let Some(endpoint) = option_env!("TELEMETRY_URL") {
+            app.add_plugins(crate::telemetry::TelemetryPlugin {
+                endpoint: endpoint.into(),
+                ..default()
+            });
+        }
+    }
+}
+
+/// The configuration for online matches. Only games that can host load levels, and guests play
+/// whichever ones the host sends.
+#[cfg(feature = "multiplayer")]
+pub struct MultiplayerCfgPlugin(pub crate::multiplayer::MultiplayerPlugin);
+
+#[cfg(feature = "multiplayer")]
+impl Plugin for MultiplayerCfgPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins((PlayablePlugin, CoreGamePlugin, self.0.clone()));
+        if self.0.is_host() {
+            app.insert_resource(LevelLoader::Path("levels/test.json".into()));
+        }
     }
 }
 
@@ -124,6 +215,8 @@ impl Plugin for VisualizerPlugin {
             }
             let rec = rec.spawn().unwrap();
             revy::RerunPlugin { rec }
-        });
+        })
+        // Log agents' beliefs along with the rest of the scene
+        .add_plugins(crate::filter::BeliefHeatmapPlugin);
     }
 }
@@ -0,0 +1,180 @@
+//! Difficulty presets, which tune how capable the pursuer is all at once.
+
+use std::str::FromStr;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    filter::{FilterConfig, MotionModel},
+    gadgets::GadgetConfig,
+    gridworld::SpeedConfig,
+    net::{ActionSelection, PolicyRunnerConfig},
+    observer::VisionConfig,
+    world_objs::HearingConfig,
+};
+
+/// Plugin for difficulty presets.
+pub struct DifficultyPlugin;
+
+impl Plugin for DifficultyPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Difficulty>().add_systems(
+            PreUpdate,
+            apply_difficulty.run_if(resource_changed::<Difficulty>),
+        );
+    }
+}
+
+/// Adds playable functionality for `DifficultyPlugin`.
+pub struct DifficultyPlayPlugin;
+
+impl Plugin for DifficultyPlayPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, cycle_difficulty);
+    }
+}
+
+/// How hard the pursuer is to escape.
+///
+/// Every preset but `Custom` overwrites the pursuer's speed, field of view, and hearing range, how quickly gadget
+/// energy regenerates and what gadgets cost, how well agents' filters track each other, and how greedily policies pick
+/// actions, whenever the difficulty changes. `Custom` leaves
+/// them alone, so they can be set individually. Field of view changes take effect from the next level.
+#[derive(Resource, Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Difficulty {
+    Easy,
+    Normal,
+    Hard,
+    /// Use whatever settings were configured, without applying a preset.
+    #[default]
+    Custom,
+}
+
+#[derive(Debug, Error)]
+#[error("Unknown difficulty \"{0}\", expected \"easy\", \"normal\", \"hard\", or \"custom\"")]
+pub struct UnknownDifficultyError(pub String);
+
+impl FromStr for Difficulty {
+    type Err = UnknownDifficultyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "easy" => Ok(Self::Easy),
+            "normal" => Ok(Self::Normal),
+            "hard" => Ok(Self::Hard),
+            "custom" => Ok(Self::Custom),
+            _ => Err(UnknownDifficultyError(s.into())),
+        }
+    }
+}
+
+/// The settings a difficulty applies.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DifficultyPreset {
+    /// How fast the pursuer walks, as a multiple of the usual speed.
+    pub pursuer_speed: f32,
+    pub pursuer_fov_degrees: f32,
+    /// How far the pursuer can hear noises, as a multiple of each noise's radius.
+    pub pursuer_hearing: f32,
+    /// How much gadget energy is regained per second.
+    pub gadget_regen_rate: f32,
+    /// How much energy sprinting uses per second.
+    pub sprint_cost: f32,
+    /// How much energy each ping uses.
+    pub ping_cost: f32,
+    /// How many particles the particle backend uses.
+    pub particle_count: usize,
+    pub motion_model: MotionModel,
+    /// The temperature policies sample actions at. Lower is greedier.
+    pub policy_temperature: f32,
+}
+
+impl Difficulty {
+    /// Returns the settings this difficulty applies, or `None` for `Custom`.
+    pub fn preset(&self) -> Option<DifficultyPreset> {
+        match self {
+            Self::Easy => Some(DifficultyPreset {
+                pursuer_speed: 0.8,
+                pursuer_fov_degrees: 45.,
+                pursuer_hearing: 0.5,
+                gadget_regen_rate: 0.5,
+                sprint_cost: 6.,
+                ping_cost: 10.,
+                particle_count: 250,
+                motion_model: MotionModel::RandomWalk,
+                policy_temperature: 2.,
+            }),
+            Self::Normal => Some(DifficultyPreset {
+                pursuer_speed: 1.,
+                pursuer_fov_degrees: 60.,
+                pursuer_hearing: 1.,
+                gadget_regen_rate: 1.,
+                sprint_cost: 4.,
+                ping_cost: 8.,
+                particle_count: 1000,
+                motion_model: MotionModel::RandomWalk,
+                policy_temperature: 1.,
+            }),
+            Self::Hard => Some(DifficultyPreset {
+                pursuer_speed: 1.2,
+                pursuer_fov_degrees: 90.,
+                pursuer_hearing: 1.5,
+                gadget_regen_rate: 1.5,
+                sprint_cost: 3.,
+                ping_cost: 6.,
+                particle_count: 4000,
+                motion_model: MotionModel::GoalDirected,
+                policy_temperature: 0.5,
+            }),
+            Self::Custom => None,
+        }
+    }
+
+    /// Returns the next difficulty, from easy to hard and back. `Custom` goes to `Easy`.
+    pub fn next(&self) -> Self {
+        match self {
+            Self::Easy => Self::Normal,
+            Self::Normal => Self::Hard,
+            Self::Hard | Self::Custom => Self::Easy,
+        }
+    }
+}
+
+/// Applies the difficulty's preset to the settings it covers.
+fn apply_difficulty(
+    difficulty: Res<Difficulty>,
+    mut speed_config: ResMut<SpeedConfig>,
+    mut vision_config: ResMut<VisionConfig>,
+    mut hearing_config: ResMut<HearingConfig>,
+    mut gadget_config: ResMut<GadgetConfig>,
+    filter_config: Option<ResMut<FilterConfig>>,
+    policy_config: Option<ResMut<PolicyRunnerConfig>>,
+) {
+    let Some(preset) = difficulty.preset() else {
+        return;
+    };
+    speed_config.pursuer = preset.pursuer_speed;
+    vision_config.pursuer.fov_degrees = preset.pursuer_fov_degrees;
+    hearing_config.pursuer = preset.pursuer_hearing;
+    gadget_config.regen_rate = preset.gadget_regen_rate;
+    gadget_config.sprint_cost = preset.sprint_cost;
+    gadget_config.ping_cost = preset.ping_cost;
+    if let Some(mut filter_config) = filter_config {
+        filter_config.particle_count = preset.particle_count;
+        filter_config.motion_model = preset.motion_model;
+    }
+    if let Some(mut policy_config) = policy_config {
+        policy_config.selection = ActionSelection::Temperature(preset.policy_temperature);
+    }
+}
+
+/// Cycles through the difficulties with F3.
+fn cycle_difficulty(inpt: Res<ButtonInput<KeyCode>>, mut difficulty: ResMut<Difficulty>) {
+    if inpt.just_pressed(KeyCode::F3) {
+        *difficulty = difficulty.next();
+        info!("Difficulty set to {:?}", *difficulty);
+    }
+}
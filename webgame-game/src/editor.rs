@@ -0,0 +1,362 @@
+//! A level editor for the playable version of the game.
+
+use bevy::prelude::*;
+use thiserror::Error;
+
+use crate::{
+    gridworld::{
+        teardown_level, LevelDataError, LevelLayout, LevelLoader, LoadedLevelData, LoadedObjData,
+        DEFAULT_LEVEL_SIZE,
+    },
+    screens::ScreenState,
+};
+
+/// Adds the level editor screen, toggled with F2.
+pub struct LevelEditorPlugin;
+
+impl Plugin for LevelEditorPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<EditorTool>()
+            .add_systems(
+                OnEnter(ScreenState::Editor),
+                (setup_editor, teardown_level).chain(),
+            )
+            .add_systems(
+                OnExit(ScreenState::Editor),
+                (cleanup_editor, start_edited_level),
+            )
+            .add_systems(
+                Update,
+                (
+                    toggle_editor,
+                    (
+                        select_tool,
+                        paint_cells,
+                        save_load_level,
+                        update_cells.run_if(resource_exists_and_changed::<EditorLevel>),
+                        update_status.run_if(resource_changed::<EditorTool>),
+                    )
+                        .run_if(in_state(ScreenState::Editor)),
+                ),
+            );
+    }
+}
+
+/// Where the editor saves and loads levels, relative to the working directory.
+const EDITOR_LEVEL_PATH: &str = "assets/levels/editor.json";
+
+/// The model used for noise sources placed by the editor.
+const EDITOR_OBJ_NAME: &str = "cardboardBoxClosed";
+
+/// The width and height of a cell in the editor, in pixels.
+const EDITOR_CELL_PX: f32 = 32.;
+
+const EDITOR_HELP: &str = "Click to paint. 1: Wall, 2: Noise source, 3: Player spawn, 4: Pursuer spawn, 5: Key, 6: Door\nCtrl+S: Save, Ctrl+L: Load, F2: Play";
+
+/// The level being edited, in the same format as level files.
+#[derive(Resource)]
+pub struct EditorLevel(pub LoadedLevelData);
+
+impl EditorLevel {
+    /// Applies a tool to the cell at `pos`.
+    pub fn apply(&mut self, tool: EditorTool, pos: (usize, usize)) {
+        let level = &mut self.0;
+        let idx = pos.1 * level.width + pos.0;
+        match tool {
+            EditorTool::Wall => {
+                level.walls[idx] = (level.walls[idx] == 0) as u8;
+                if level.walls[idx] != 0 {
+                    self.clear_cell(pos);
+                }
+                return;
+            }
+            EditorTool::NoiseSource => {
+                if let Some(i) = level.objects.iter().position(|obj| obj.pos == pos) {
+                    level.objects.remove(i);
+                } else {
+                    level.objects.push(LoadedObjData {
+                        name: EDITOR_OBJ_NAME.into(),
+                        pos,
+                        dir: None,
+                        movable: true,
+                    });
+                }
+            }
+            EditorTool::PlayerSpawn => level.player_spawn = Some(pos),
+            EditorTool::PursuerSpawn => level.pursuer_spawn = Some(pos),
+            EditorTool::Key => level.key_pos = Some(pos),
+            EditorTool::Door => level.door_pos = Some(pos),
+        }
+        level.walls[idx] = 0;
+    }
+
+    /// Removes everything but walls from a cell.
+    fn clear_cell(&mut self, pos: (usize, usize)) {
+        let level = &mut self.0;
+        level.objects.retain(|obj| obj.pos != pos);
+        for marker in [
+            &mut level.player_spawn,
+            &mut level.pursuer_spawn,
+            &mut level.key_pos,
+            &mut level.door_pos,
+        ] {
+            if *marker == Some(pos) {
+                *marker = None;
+            }
+        }
+    }
+
+    /// Returns the color a cell should be drawn with.
+    fn cell_color(&self, pos: (usize, usize)) -> Color {
+        let level = &self.0;
+        if level.player_spawn == Some(pos) {
+            Color::GREEN
+        } else if level.pursuer_spawn == Some(pos) {
+            Color::RED
+        } else if level.key_pos == Some(pos) {
+            Color::YELLOW
+        } else if level.door_pos == Some(pos) {
+            Color::MAROON
+        } else if level.objects.iter().any(|obj| obj.pos == pos) {
+            Color::BLUE
+        } else if level.walls[pos.1 * level.width + pos.0] != 0 {
+            Color::BLACK
+        } else {
+            Color::GRAY
+        }
+    }
+}
+
+/// What clicking on a cell does.
+#[derive(Resource, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditorTool {
+    #[default]
+    Wall,
+    NoiseSource,
+    PlayerSpawn,
+    PursuerSpawn,
+    Key,
+    Door,
+}
+
+/// Errors that can occur when saving or loading levels in the editor.
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum EditorFileError {
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+    #[error("{0}")]
+    Json(#[from] serde_json::error::Error),
+    #[error("{0}")]
+    LevelData(#[from] LevelDataError),
+}
+
+/// Marks entities that make up the editor's UI.
+#[derive(Component)]
+struct EditorUi;
+
+/// A clickable cell in the editor's grid.
+#[derive(Component)]
+struct EditorCell {
+    pos: (usize, usize),
+}
+
+/// The text showing the current tool and the result of the last save or load.
+#[derive(Component)]
+struct EditorStatusText;
+
+/// Switches between the game and the editor.
+fn toggle_editor(
+    inpt: Res<ButtonInput<KeyCode>>,
+    state: Res<State<ScreenState>>,
+    mut next_state: ResMut<NextState<ScreenState>>,
+) {
+    if inpt.just_pressed(KeyCode::F2) {
+        next_state.set(match state.get() {
+            ScreenState::Editor => ScreenState::Game,
+            _ => ScreenState::Editor,
+        });
+    }
+}
+
+/// Starts editing the current level, or an empty level if none has been loaded.
+fn setup_editor(mut commands: Commands, level: Option<Res<LevelLayout>>, tool: Res<EditorTool>) {
+    commands.remove_resource::<LevelLoader>();
+    let editor_level = EditorLevel(match level {
+        Some(level) => level.to_data(),
+        None => LoadedLevelData::empty(DEFAULT_LEVEL_SIZE, DEFAULT_LEVEL_SIZE),
+    });
+    commands.spawn((EditorUi, Camera2dBundle::default()));
+    spawn_editor_grid(&mut commands, &editor_level, *tool);
+    commands.insert_resource(editor_level);
+}
+
+/// Spawns the grid of cells and the status text.
+fn spawn_editor_grid(commands: &mut Commands, level: &EditorLevel, tool: EditorTool) {
+    let text_style = TextStyle {
+        font_size: 14.,
+        color: Color::WHITE,
+        ..default()
+    };
+    commands
+        .spawn((
+            EditorUi,
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.),
+                    height: Val::Percent(100.),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                ..default()
+            },
+        ))
+        .with_children(|p| {
+            p.spawn((
+                EditorStatusText,
+                TextBundle::from_sections([
+                    TextSection::new(format!("Tool: {tool:?}\n"), text_style.clone()),
+                    TextSection::new("", text_style.clone()),
+                ]),
+            ));
+            for y in 0..level.0.height {
+                p.spawn(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Row,
+                        ..default()
+                    },
+                    ..default()
+                })
+                .with_children(|p| {
+                    for x in 0..level.0.width {
+                        p.spawn((
+                            EditorCell { pos: (x, y) },
+                            ButtonBundle {
+                                style: Style {
+                                    width: Val::Px(EDITOR_CELL_PX),
+                                    height: Val::Px(EDITOR_CELL_PX),
+                                    margin: UiRect::all(Val::Px(1.)),
+                                    ..default()
+                                },
+                                background_color: level.cell_color((x, y)).into(),
+                                ..default()
+                            },
+                        ));
+                    }
+                });
+            }
+            p.spawn(TextBundle::from_section(EDITOR_HELP, text_style));
+        });
+}
+
+/// Selects the current tool with the number keys.
+fn select_tool(inpt: Res<ButtonInput<KeyCode>>, mut tool: ResMut<EditorTool>) {
+    let tools = [
+        (KeyCode::Digit1, EditorTool::Wall),
+        (KeyCode::Digit2, EditorTool::NoiseSource),
+        (KeyCode::Digit3, EditorTool::PlayerSpawn),
+        (KeyCode::Digit4, EditorTool::PursuerSpawn),
+        (KeyCode::Digit5, EditorTool::Key),
+        (KeyCode::Digit6, EditorTool::Door),
+    ];
+    for (key, new_tool) in tools {
+        if inpt.just_pressed(key) {
+            *tool = new_tool;
+        }
+    }
+}
+
+/// Applies the current tool to clicked cells.
+fn paint_cells(
+    cell_query: Query<(&Interaction, &EditorCell), Changed<Interaction>>,
+    tool: Res<EditorTool>,
+    mut level: ResMut<EditorLevel>,
+) {
+    for (interaction, cell) in cell_query.iter() {
+        if *interaction == Interaction::Pressed {
+            level.apply(*tool, cell.pos);
+        }
+    }
+}
+
+/// Recolors cells when the level changes.
+fn update_cells(
+    level: Res<EditorLevel>,
+    mut cell_query: Query<(&EditorCell, &mut BackgroundColor)>,
+) {
+    for (cell, mut color) in cell_query.iter_mut() {
+        *color = level.cell_color(cell.pos).into();
+    }
+}
+
+/// Shows the current tool.
+fn update_status(
+    tool: Res<EditorTool>,
+    mut status_query: Query<&mut Text, With<EditorStatusText>>,
+) {
+    for mut text in status_query.iter_mut() {
+        text.sections[0].value = format!("Tool: {:?}\n", *tool);
+    }
+}
+
+/// Saves the level with Ctrl+S and loads it with Ctrl+L.
+fn save_load_level(
+    mut commands: Commands,
+    inpt: Res<ButtonInput<KeyCode>>,
+    mut level: ResMut<EditorLevel>,
+    tool: Res<EditorTool>,
+    ui_query: Query<Entity, (With<EditorUi>, With<Node>)>,
+    mut status_query: Query<&mut Text, With<EditorStatusText>>,
+) {
+    if !inpt.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) {
+        return;
+    }
+    let message = if inpt.just_pressed(KeyCode::KeyS) {
+        match save_editor_level(&level.0) {
+            Ok(()) => format!("Saved to {EDITOR_LEVEL_PATH}"),
+            Err(e) => format!("Could not save level: {e}"),
+        }
+    } else if inpt.just_pressed(KeyCode::KeyL) {
+        match load_editor_level() {
+            Ok(data) => {
+                // The level might be a different size, so the grid is rebuilt
+                level.0 = data;
+                for e in ui_query.iter() {
+                    commands.entity(e).despawn_recursive();
+                }
+                spawn_editor_grid(&mut commands, &level, *tool);
+                return;
+            }
+            Err(e) => format!("Could not load level: {e}"),
+        }
+    } else {
+        return;
+    };
+    for mut text in status_query.iter_mut() {
+        text.sections[1].value = message.clone();
+    }
+}
+
+fn save_editor_level(level: &LoadedLevelData) -> Result<(), EditorFileError> {
+    std::fs::write(EDITOR_LEVEL_PATH, serde_json::to_string_pretty(level)?)?;
+    Ok(())
+}
+
+fn load_editor_level() -> Result<LoadedLevelData, EditorFileError> {
+    let json = std::fs::read_to_string(EDITOR_LEVEL_PATH)?;
+    Ok(LoadedLevelData::from_json(&json)?)
+}
+
+/// Removes the editor's UI.
+fn cleanup_editor(mut commands: Commands, ui_query: Query<Entity, With<EditorUi>>) {
+    for e in ui_query.iter() {
+        commands.entity(e).despawn_recursive();
+    }
+}
+
+/// Starts the game with the edited level.
+fn start_edited_level(mut commands: Commands, level: Res<EditorLevel>) {
+    commands.insert_resource(LevelLayout::from_data(&level.0));
+}
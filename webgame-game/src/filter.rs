@@ -0,0 +1,1625 @@
+//! Bayes filters that track where agents think the agents they're chasing or fleeing are, mirroring `BayesFilter`
+//! in `webgame/filter.py`.
+//! Pursuers track every player, and players track every pursuer, with a separate belief for each.
+//!
+//! The grid backend keeps a probability for every cell, the sparse backend only keeps cells above a probability floor,
+//! the particle backend keeps a cloud of weighted guesses, and the Gaussian backend keeps a small mixture of Gaussians
+//! in world space. All of them are rasterized to the same grid and updated from the same evidence.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    str::FromStr,
+};
+
+use bevy::{
+    prelude::*,
+    render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+        texture::ImageSampler,
+    },
+};
+use rand::{
+    distributions::{Distribution, WeightedIndex},
+    rngs::StdRng,
+    Rng, SeedableRng,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    gadgets::{quadrant_of, PingResult},
+    gridworld::{
+        Agent, GameId, GridTransform, LevelEntity, LevelLayout, PlayerAgent, PursuerAgent,
+        ShouldRun, GRID_CELL_SIZE,
+    },
+    net::{load_weights_into_net, policy_grid, MeasureNet, NNWrapper},
+    observer::{update_observers, update_vm_data, Observer},
+    pathfinding::{open_neighbors, update_distance_field, DistanceField},
+    visibility::mesh_coverage,
+    world_objs::{audible_noises, HearingConfig, NoiseSource},
+};
+
+/// Plugin for agents' beliefs about where the agents they're tracking are.
+pub struct FilterPlugin;
+
+impl Plugin for FilterPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ExternalSighting>()
+            .init_resource::<FilterConfig>()
+            .init_resource::<FilterRng>()
+            .add_systems(Update, load_weights_into_net::<MeasureNet>)
+            .add_systems(
+                Update,
+                (
+                    update_beliefs::<PursuerAgent, PlayerAgent>,
+                    update_beliefs::<PlayerAgent, PursuerAgent>,
+                )
+                    .after(load_weights_into_net::<MeasureNet>)
+                    .after(update_observers)
+                    .after(update_vm_data)
+                    .after(update_distance_field)
+                    .run_if(resource_exists::<ShouldRun>),
+            );
+    }
+}
+
+/// Adds playable functionality for `FilterPlugin`.
+pub struct FilterPlayPlugin;
+
+impl Plugin for FilterPlayPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(BeliefHeatmapPlugin);
+    }
+}
+
+/// Replaces the hand-written likelihood in pursuers' filters with a `MeasureNet` loaded from a safetensors asset, such
+/// as one trained by `webgame/train_filter.py`. Players keep the hand-written one, since the model is only trained on
+/// what pursuers observe.
+pub struct LearnedMeasurementPlugin {
+    /// The asset path of the weights.
+    pub path: String,
+}
+
+impl Plugin for LearnedMeasurementPlugin {
+    fn build(&self, app: &mut App) {
+        let path = self.path.clone();
+        app.add_systems(
+            Startup,
+            move |mut commands: Commands, asset_server: Res<AssetServer>| {
+                commands.spawn(NNWrapper::<MeasureNet>::with_sftensors(
+                    asset_server.load(path.clone()),
+                ));
+            },
+        );
+    }
+}
+
+/// Shows agents' beliefs as heatmaps over the level. Used by the playable build, and by library builds that log to
+/// Rerun, which picks the heatmaps up along with the rest of the scene.
+pub struct BeliefHeatmapPlugin;
+
+impl Plugin for BeliefHeatmapPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ShowBeliefHeatmaps>().add_systems(
+            Update,
+            (
+                add_belief_heatmaps,
+                update_belief_heatmaps,
+                show_belief_heatmaps,
+            )
+                .chain()
+                .after(update_beliefs::<PursuerAgent, PlayerAgent>)
+                .after(update_beliefs::<PlayerAgent, PursuerAgent>),
+        );
+    }
+}
+
+/// Which kind of filter tracks agents' beliefs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FilterBackend {
+    /// Keeps a probability for every cell.
+    #[default]
+    Grid,
+    /// Keeps a fixed number of weighted particles, which scales better to large levels.
+    Particles,
+    /// Keeps a small mixture of Gaussians in continuous coordinates, for agents that don't move cell by cell.
+    Gaussians,
+    /// Like `Grid`, but only keeps cells above a probability floor, so predicting only visits cells the tracked agent
+    /// could plausibly be in. Suited to large levels.
+    Sparse,
+}
+
+#[derive(Debug, Error)]
+#[error(
+    "Unknown filter backend \"{0}\", expected \"grid\", \"particles\", \"gaussians\", or \"sparse\""
+)]
+pub struct UnknownFilterBackendError(pub String);
+
+impl FromStr for FilterBackend {
+    type Err = UnknownFilterBackendError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "grid" => Ok(Self::Grid),
+            "particles" => Ok(Self::Particles),
+            "gaussians" => Ok(Self::Gaussians),
+            "sparse" => Ok(Self::Sparse),
+            _ => Err(UnknownFilterBackendError(s.into())),
+        }
+    }
+}
+
+/// How the particle backend picks which particles survive each update.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Resampling {
+    /// Draws every particle independently. Simple, but the noisiest.
+    Multinomial,
+    /// Draws one random offset and picks particles at evenly spaced points from it.
+    #[default]
+    Systematic,
+    /// Draws a random point within each of a set of evenly sized strata.
+    Stratified,
+}
+
+#[derive(Debug, Error)]
+#[error("Unknown resampling strategy \"{0}\", expected \"multinomial\", \"systematic\", or \"stratified\"")]
+pub struct UnknownResamplingError(pub String);
+
+impl FromStr for Resampling {
+    type Err = UnknownResamplingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "multinomial" => Ok(Self::Multinomial),
+            "systematic" => Ok(Self::Systematic),
+            "stratified" => Ok(Self::Stratified),
+            _ => Err(UnknownResamplingError(s.into())),
+        }
+    }
+}
+
+/// How tracked agents are assumed to move between updates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MotionModel {
+    /// Agents stay where they are.
+    Stationary,
+    /// Agents are equally likely to stay put or step to any open neighbor.
+    #[default]
+    RandomWalk,
+    /// Agents head for the nearest exit or door some of the time, and take a random walk step otherwise.
+    GoalDirected,
+}
+
+#[derive(Debug, Error)]
+#[error(
+    "Unknown motion model \"{0}\", expected \"stationary\", \"random_walk\", or \"goal_directed\""
+)]
+pub struct UnknownMotionModelError(pub String);
+
+impl FromStr for MotionModel {
+    type Err = UnknownMotionModelError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stationary" => Ok(Self::Stationary),
+            "random_walk" => Ok(Self::RandomWalk),
+            "goal_directed" => Ok(Self::GoalDirected),
+            _ => Err(UnknownMotionModelError(s.into())),
+        }
+    }
+}
+
+/// Configures how agents' beliefs are tracked.
+#[derive(Resource, Clone, Copy)]
+pub struct FilterConfig {
+    pub backend: FilterBackend,
+    /// How many particles the particle backend uses.
+    pub particle_count: usize,
+    pub resampling: Resampling,
+    /// The furthest, in cells, particles can drift along each axis on every update. With the Gaussian backend, the
+    /// standard deviation, in cells, added to each component on every update.
+    pub motion_noise: f32,
+    pub motion_model: MotionModel,
+    /// With the goal directed motion model, the chance of a tracked agent stepping towards the nearest exit instead of
+    /// randomly.
+    pub goal_bias: f32,
+    /// How many components the Gaussian backend's mixture has.
+    pub gaussian_count: usize,
+    /// The sparse backend drops cells less likely than this after every update.
+    pub sparse_floor: f32,
+}
+
+impl Default for FilterConfig {
+    fn default() -> Self {
+        Self {
+            backend: FilterBackend::Grid,
+            particle_count: 1000,
+            resampling: Resampling::Systematic,
+            motion_noise: 1.,
+            motion_model: MotionModel::RandomWalk,
+            goal_bias: 0.5,
+            gaussian_count: 4,
+            sparse_floor: 1e-4,
+        }
+    }
+}
+
+impl FilterConfig {
+    /// Returns true if there's at least one particle and Gaussian, the motion noise isn't negative, and the goal bias
+    /// and sparse floor are between 0 and 1.
+    pub fn is_valid(&self) -> bool {
+        self.particle_count > 0
+            && self.gaussian_count > 0
+            && self.motion_noise >= 0.
+            && (0. ..=1.).contains(&self.goal_bias)
+            && (0. ..=1.).contains(&self.sparse_floor)
+    }
+}
+
+/// The random numbers used by the particle and Gaussian backends. Insert a seeded one for reproducible runs.
+#[derive(Resource)]
+pub struct FilterRng(pub StdRng);
+
+impl Default for FilterRng {
+    fn default() -> Self {
+        Self(StdRng::from_entropy())
+    }
+}
+
+/// How likely evidence is to show up when the tracked agent isn't where it points.
+/// Keeps a single wrong observation from ruling out cells for good.
+pub const EVIDENCE_FLOOR: f32 = 0.01;
+
+/// How close, in world units, an agent has to be to a visual marker to have moved it.
+pub const MARKER_PUSH_RADIUS: f32 = GRID_CELL_SIZE * 1.5;
+
+/// A sighting of a tracked agent reported from outside the filter, such as by a camera or alarm. Applied on the next
+/// update, when beliefs are corrected.
+#[derive(Event, Clone, Copy, Debug)]
+pub struct ExternalSighting {
+    /// The agent that was seen.
+    pub target: Entity,
+    /// If set, only this agent's beliefs are corrected. Otherwise, every agent tracking `target` takes it into account.
+    pub tracker: Option<Entity>,
+    /// Where `target` was seen, in world space.
+    pub pos: Vec2,
+    /// How close, in world units, `target` was to `pos`.
+    pub radius: f32,
+    /// How sure the source is, from 0 to 1. A fully confident sighting is as strong as any other evidence.
+    pub confidence: f32,
+}
+
+impl ExternalSighting {
+    /// Returns a likelihood that's 1 for cells whose centers are within `radius` of `pos`, and lower elsewhere the
+    /// more confident the sighting is.
+    fn likelihood(&self, level: &LevelLayout) -> impl Fn(usize) -> f32 {
+        let near = near_likelihood(level, self.pos, self.radius);
+        let far = (1. - self.confidence).max(EVIDENCE_FLOOR);
+        move |idx| if near(idx) < 1. { far } else { 1. }
+    }
+}
+
+/// What an agent believes about where each agent it's tracking is.
+#[derive(Component, Clone, Default)]
+pub struct Beliefs {
+    /// Keyed by the tracked agent. Entries are added as agents show up and removed once they're gone.
+    pub targets: HashMap<Entity, TargetBelief>,
+    /// Set when beliefs are restored from a snapshot, so they're kept even if the level was just loaded.
+    restored: bool,
+}
+
+/// An agent's belief about where one other agent is.
+#[derive(Clone, Default)]
+pub struct TargetBelief {
+    pub belief: Belief,
+    /// Only kept up to date with the particle backend.
+    pub particles: Particles,
+    /// Only kept up to date with the Gaussian backend.
+    pub gaussians: Gaussians,
+    /// Only kept up to date with the sparse backend.
+    pub sparse: SparseBelief,
+}
+
+impl TargetBelief {
+    /// Moves belief forward a step, starting over first if `level_changed` is set or it hasn't been started yet.
+    fn predict(
+        &mut self,
+        level: &LevelLayout,
+        level_changed: bool,
+        config: &FilterConfig,
+        dist_field: &DistanceField,
+        rng: &mut StdRng,
+    ) {
+        let reset = level_changed || self.belief.probs.len() != level.walls.len();
+        if reset {
+            self.belief.reset(level);
+        }
+        match config.backend {
+            FilterBackend::Particles => {
+                if reset || self.particles.positions.len() != config.particle_count {
+                    let probs = self.belief.probs.clone();
+                    self.particles
+                        .scatter(level.grid(), &probs, config.particle_count, rng);
+                }
+                self.particles.predict(level, config, dist_field, rng);
+            }
+            FilterBackend::Gaussians => {
+                if reset || self.gaussians.components.len() != config.gaussian_count {
+                    let probs = self.belief.probs.clone();
+                    self.gaussians
+                        .seed(level.grid(), &probs, config.gaussian_count, rng);
+                }
+                self.gaussians.predict(level, config, dist_field);
+            }
+            FilterBackend::Sparse => {
+                if reset || self.sparse.cells.is_empty() {
+                    self.sparse.fill(&self.belief.probs, config.sparse_floor);
+                }
+                self.sparse.predict(level, config, dist_field);
+            }
+            FilterBackend::Grid => self.belief.predict(level, config, dist_field),
+        }
+    }
+
+    /// Returns the belief after `predict`, before it's been corrected.
+    fn predicted_probs(&self, grid: GridTransform, backend: FilterBackend) -> Vec<f32> {
+        match backend {
+            FilterBackend::Particles => self.particles.rasterize(grid),
+            FilterBackend::Gaussians => self.gaussians.rasterize(grid),
+            FilterBackend::Sparse => self.sparse.rasterize(grid),
+            FilterBackend::Grid => self.belief.probs.clone(),
+        }
+    }
+
+    /// Weighs the predicted belief by the likelihood of this step's evidence.
+    fn correct(
+        &mut self,
+        grid: GridTransform,
+        config: &FilterConfig,
+        lkhd: Vec<f32>,
+        rng: &mut StdRng,
+    ) {
+        match config.backend {
+            FilterBackend::Particles => {
+                // If the evidence rules out every particle, start over from it alone
+                if self.particles.correct(grid, &lkhd) {
+                    self.particles.resample(config.resampling, rng);
+                } else {
+                    self.particles
+                        .scatter(grid, &lkhd, config.particle_count, rng);
+                }
+                self.belief.probs = self.particles.rasterize(grid);
+            }
+            FilterBackend::Gaussians => {
+                // Refit the mixture to the corrected grid, since the evidence isn't Gaussian itself
+                let mut probs = self
+                    .gaussians
+                    .rasterize(grid)
+                    .into_iter()
+                    .zip(&lkhd)
+                    .map(|(prob, cell_lkhd)| prob * cell_lkhd)
+                    .collect::<Vec<_>>();
+                if probs.iter().sum::<f32>() <= 0. {
+                    self.gaussians.seed(grid, &lkhd, config.gaussian_count, rng);
+                    probs = lkhd.clone();
+                }
+                normalize(&mut probs);
+                self.gaussians.fit(grid, &probs);
+                // Walls and cells the evidence rules out stay empty, even if the mixture spills into them
+                let mut probs = self
+                    .gaussians
+                    .rasterize(grid)
+                    .into_iter()
+                    .zip(&lkhd)
+                    .map(|(prob, &cell_lkhd)| if cell_lkhd > 0. { prob } else { 0. })
+                    .collect::<Vec<_>>();
+                normalize(&mut probs);
+                self.belief.probs = probs;
+            }
+            FilterBackend::Sparse => {
+                // If the evidence rules out every cell that's kept, start over from it alone
+                if !self.sparse.correct(&lkhd, config.sparse_floor) {
+                    let mut probs = lkhd;
+                    normalize(&mut probs);
+                    self.sparse.fill(&probs, config.sparse_floor);
+                }
+                self.belief.probs = self.sparse.rasterize(grid);
+            }
+            FilterBackend::Grid => {
+                // If the evidence rules out everywhere the agent thought its target could be, start over from it
+                // alone
+                let mut probs = self
+                    .belief
+                    .probs
+                    .iter()
+                    .zip(&lkhd)
+                    .map(|(prob, cell_lkhd)| prob * cell_lkhd)
+                    .collect::<Vec<_>>();
+                if probs.iter().sum::<f32>() <= 0. {
+                    probs = lkhd;
+                }
+                normalize(&mut probs);
+                self.belief.probs = probs;
+            }
+        }
+    }
+}
+
+/// A saved copy of every agent's beliefs, with agents identified by `GameId` so it can be restored into another app
+/// playing the same level. Doesn't include `FilterRng`.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Default)]
+pub struct FilterSnapshot {
+    pub trackers: Vec<TrackerSnapshot>,
+}
+
+/// A saved copy of one agent's beliefs. See `FilterSnapshot`.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct TrackerSnapshot {
+    /// The `GameId` of the agent holding these beliefs.
+    pub tracker: u64,
+    pub targets: Vec<TargetSnapshot>,
+}
+
+/// A saved copy of one agent's belief about another. See `FilterSnapshot`.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct TargetSnapshot {
+    /// The `GameId` of the tracked agent.
+    pub target: u64,
+    /// Indexed the same way as `LevelLayout::walls`.
+    pub probs: Vec<f32>,
+    /// Each particle's world space position and weight, as `[x, y, weight]`. Empty unless the particle backend is
+    /// used.
+    pub particles: Vec<[f32; 3]>,
+    /// Each Gaussian's weight, world space mean, and variance, as `[weight, x, y, variance]`. Empty unless the
+    /// Gaussian backend is used.
+    #[serde(default)]
+    pub gaussians: Vec<[f32; 4]>,
+}
+
+impl FilterSnapshot {
+    /// Saves the beliefs of every tracker. Agents without a game ID are left out.
+    pub fn capture<'a>(
+        trackers: impl IntoIterator<Item = (&'a GameId, &'a Beliefs)>,
+        game_ids: &HashMap<Entity, u64>,
+    ) -> Self {
+        let mut trackers = trackers
+            .into_iter()
+            .map(|(tracker_id, beliefs)| {
+                let mut targets = beliefs
+                    .targets
+                    .iter()
+                    .filter_map(|(target_e, target_belief)| {
+                        let particles = &target_belief.particles;
+                        Some(TargetSnapshot {
+                            target: *game_ids.get(target_e)?,
+                            probs: target_belief.belief.probs.clone(),
+                            particles: particles
+                                .positions
+                                .iter()
+                                .zip(&particles.weights)
+                                .map(|(pos, &weight)| [pos.x, pos.y, weight])
+                                .collect(),
+                            gaussians: target_belief
+                                .gaussians
+                                .components
+                                .iter()
+                                .map(|g| [g.weight, g.mean.x, g.mean.y, g.variance])
+                                .collect(),
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                targets.sort_unstable_by_key(|target| target.target);
+                TrackerSnapshot {
+                    tracker: tracker_id.0,
+                    targets,
+                }
+            })
+            .collect::<Vec<_>>();
+        trackers.sort_unstable_by_key(|tracker| tracker.tracker);
+        Self { trackers }
+    }
+
+    /// Replaces the beliefs of every tracker in the snapshot with the saved ones. Trackers and targets that can't be
+    /// found in `entities` are skipped, and beliefs saved on a level of a different size start over on the next
+    /// update.
+    pub fn restore<'a>(
+        &self,
+        trackers: impl IntoIterator<Item = (&'a GameId, Mut<'a, Beliefs>)>,
+        entities: &HashMap<u64, Entity>,
+    ) {
+        for (tracker_id, mut beliefs) in trackers {
+            let Some(saved) = self
+                .trackers
+                .iter()
+                .find(|saved| saved.tracker == tracker_id.0)
+            else {
+                continue;
+            };
+            beliefs.targets = saved
+                .targets
+                .iter()
+                .filter_map(|target| {
+                    let target_belief = TargetBelief {
+                        belief: Belief {
+                            probs: target.probs.clone(),
+                        },
+                        particles: Particles {
+                            positions: target
+                                .particles
+                                .iter()
+                                .map(|&[x, y, _]| Vec2::new(x, y))
+                                .collect(),
+                            weights: target
+                                .particles
+                                .iter()
+                                .map(|&[_, _, weight]| weight)
+                                .collect(),
+                        },
+                        gaussians: Gaussians {
+                            components: target
+                                .gaussians
+                                .iter()
+                                .map(|&[weight, x, y, variance]| Gaussian {
+                                    weight,
+                                    mean: Vec2::new(x, y),
+                                    variance,
+                                })
+                                .collect(),
+                        },
+                        // Filled from `belief` on the next update
+                        sparse: SparseBelief::default(),
+                    };
+                    Some((*entities.get(&target.target)?, target_belief))
+                })
+                .collect();
+            beliefs.restored = true;
+        }
+    }
+
+    /// Parses a snapshot from JSON.
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    /// Serializes the snapshot as JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("filter snapshots should always serialize")
+    }
+}
+
+/// The chance of a tracked agent being in each cell, indexed the same way as `LevelLayout::walls`.
+///
+/// Starts out empty, and is spread evenly over open cells on the first update or whenever the level changes. With the
+/// sparse, particle, and Gaussian backends, this is rasterized from `SparseBelief`, `Particles`, or `Gaussians` after
+/// every update.
+#[derive(Clone, Default)]
+pub struct Belief {
+    pub probs: Vec<f32>,
+}
+
+impl Belief {
+    /// Spreads belief evenly over the cells that aren't walls.
+    fn reset(&mut self, level: &LevelLayout) {
+        self.probs = level.walls.iter().map(|wall| !wall as u8 as f32).collect();
+        normalize(&mut self.probs);
+    }
+
+    /// Moves belief to neighboring cells according to the configured motion model.
+    fn predict(&mut self, level: &LevelLayout, config: &FilterConfig, dist_field: &DistanceField) {
+        if config.motion_model == MotionModel::Stationary {
+            return;
+        }
+        let mut predicted = vec![0.; self.probs.len()];
+        for (i, &prob) in self.probs.iter().enumerate() {
+            if prob <= 0. {
+                continue;
+            }
+            for (idx, share) in transitions(level, config, dist_field, i) {
+                predicted[idx] += prob * share;
+            }
+        }
+        self.probs = predicted;
+    }
+
+    /// Returns how spread out the belief is, in nats. Zero means the agent is certain which cell the tracked agent is
+    /// in, and it's highest when belief is spread evenly.
+    pub fn entropy(&self) -> f32 {
+        -self
+            .probs
+            .iter()
+            .filter(|&&prob| prob > 0.)
+            .map(|&prob| prob * prob.ln())
+            .sum::<f32>()
+    }
+
+    /// Returns the index of the cell the tracked agent is most likely to be in, or `None` if the belief is empty.
+    /// Ties go to the lowest index.
+    pub fn map_cell(&self) -> Option<usize> {
+        self.top_k(1).first().map(|&(idx, _)| idx)
+    }
+
+    /// Returns the `k` cells the tracked agent is most likely to be in, as indices paired with their probabilities,
+    /// most likely first. Ties go to the lowest index. Returns fewer than `k` if there aren't enough cells.
+    pub fn top_k(&self, k: usize) -> Vec<(usize, f32)> {
+        let mut modes = self.probs.iter().copied().enumerate().collect::<Vec<_>>();
+        modes.sort_by(|(idx_a, prob_a), (idx_b, prob_b)| {
+            prob_b.total_cmp(prob_a).then(idx_a.cmp(idx_b))
+        });
+        modes.truncate(k);
+        modes
+    }
+}
+
+/// Returns the cells a tracked agent in the cell at `idx` could move to in one step under the configured motion model,
+/// paired with the chance of it moving there. Indices are the same as `LevelLayout::walls`.
+fn transitions(
+    level: &LevelLayout,
+    config: &FilterConfig,
+    dist_field: &DistanceField,
+    idx: usize,
+) -> Vec<(usize, f32)> {
+    let grid = level.grid();
+    let (x, y) = grid.idx_cell(idx);
+    let targets = [(0, 0), (-1, 0), (1, 0), (0, -1), (0, 1)]
+        .into_iter()
+        .filter_map(|(dx, dy)| {
+            let cell = (x.checked_add_signed(dx)?, y.checked_add_signed(dy)?);
+            grid.cell_idx(cell).filter(|&idx| !level.walls.get(idx))
+        })
+        .collect::<Vec<_>>();
+    let goal_targets = match config.motion_model {
+        MotionModel::GoalDirected => goal_steps(level, dist_field, (x, y))
+            .into_iter()
+            .filter_map(|cell| grid.cell_idx(cell))
+            .collect(),
+        _ => Vec::new(),
+    };
+    let goal_share = if goal_targets.is_empty() {
+        0.
+    } else {
+        config.goal_bias
+    };
+    let mut transitions = targets
+        .iter()
+        .map(|&idx| (idx, (1. - goal_share) / targets.len() as f32))
+        .collect::<Vec<_>>();
+    transitions.extend(
+        goal_targets
+            .iter()
+            .map(|&idx| (idx, goal_share / goal_targets.len() as f32)),
+    );
+    transitions
+}
+
+/// Only the cells a tracked agent is likely enough to be in, used by the sparse backend.
+///
+/// Starts out empty, and is filled from `Belief` on the first update or whenever the level changes.
+#[derive(Clone, Default)]
+pub struct SparseBelief {
+    /// The chance of the tracked agent being in each kept cell, keyed by the same indices as `LevelLayout::walls`.
+    /// Sums to 1.
+    pub cells: BTreeMap<usize, f32>,
+}
+
+impl SparseBelief {
+    /// Keeps the cells of `probs` that are at least `floor`.
+    fn fill(&mut self, probs: &[f32], floor: f32) {
+        self.cells = probs
+            .iter()
+            .enumerate()
+            .filter(|&(_, &prob)| prob > 0.)
+            .map(|(idx, &prob)| (idx, prob))
+            .collect();
+        self.prune(floor);
+    }
+
+    /// Moves belief to neighboring cells according to the configured motion model. Only visits kept cells.
+    fn predict(&mut self, level: &LevelLayout, config: &FilterConfig, dist_field: &DistanceField) {
+        if config.motion_model == MotionModel::Stationary {
+            return;
+        }
+        let mut predicted = BTreeMap::new();
+        for (&i, &prob) in &self.cells {
+            for (idx, share) in transitions(level, config, dist_field, i) {
+                *predicted.entry(idx).or_insert(0.) += prob * share;
+            }
+        }
+        self.cells = predicted;
+    }
+
+    /// Weighs each kept cell by its likelihood, then drops cells below `floor`. Returns false, leaving the belief
+    /// empty, if every cell was ruled out.
+    fn correct(&mut self, lkhd: &[f32], floor: f32) -> bool {
+        for (&idx, prob) in &mut self.cells {
+            *prob *= lkhd[idx];
+        }
+        self.cells.retain(|_, prob| *prob > 0.);
+        if self.cells.is_empty() {
+            return false;
+        }
+        self.prune(floor);
+        true
+    }
+
+    /// Drops cells less likely than `floor`, unless that would drop all of them, then renormalizes.
+    fn prune(&mut self, floor: f32) {
+        let total = self.cells.values().sum::<f32>();
+        if total <= 0. {
+            return;
+        }
+        if self.cells.values().any(|&prob| prob / total >= floor) {
+            self.cells.retain(|_, prob| *prob / total >= floor);
+        }
+        let total = self.cells.values().sum::<f32>();
+        for prob in self.cells.values_mut() {
+            *prob /= total;
+        }
+    }
+
+    /// Returns the chance of the tracked agent being in each cell, indexed the same way as `LevelLayout::walls`.
+    pub fn rasterize(&self, grid: GridTransform) -> Vec<f32> {
+        let mut probs = vec![0.; grid.width * grid.height];
+        for (&idx, &prob) in &self.cells {
+            probs[idx] = prob;
+        }
+        probs
+    }
+}
+
+/// Returns the cells an agent heading for the nearest exit would step to from `cell`: whichever of it and its open
+/// neighbors are closest to an exit. Empty if none of them can reach one.
+fn goal_steps(
+    level: &LevelLayout,
+    dist_field: &DistanceField,
+    cell: (usize, usize),
+) -> Vec<(usize, usize)> {
+    let candidates = std::iter::once(cell)
+        .chain(open_neighbors(level, cell))
+        .filter_map(|cell| Some((cell, dist_field.to_exit(level, cell)?)))
+        .collect::<Vec<_>>();
+    let Some(best) = candidates.iter().map(|&(_, dist)| dist).min() else {
+        return Vec::new();
+    };
+    candidates
+        .into_iter()
+        .filter(|&(_, dist)| dist == best)
+        .map(|(cell, _)| cell)
+        .collect()
+}
+
+/// Scales probabilities so they sum to 1. Leaves them alone if they're all zero.
+fn normalize(probs: &mut [f32]) {
+    let total = probs.iter().sum::<f32>();
+    if total > 0. {
+        for prob in probs {
+            *prob /= total;
+        }
+    }
+}
+
+/// A cloud of weighted guesses at where a tracked agent is, used by the particle backend.
+///
+/// Starts out empty, and is scattered over open cells on the first update or whenever the level changes.
+#[derive(Clone, Default)]
+pub struct Particles {
+    /// Where each particle is, in world space.
+    pub positions: Vec<Vec2>,
+    /// How likely each particle is. Sums to 1.
+    pub weights: Vec<f32>,
+}
+
+impl Particles {
+    /// Scatters `count` evenly weighted particles over cells in proportion to `cell_weights`, each at a random spot in
+    /// its cell. Clears the particles if no cell has any weight.
+    fn scatter(
+        &mut self,
+        grid: GridTransform,
+        cell_weights: &[f32],
+        count: usize,
+        rng: &mut StdRng,
+    ) {
+        let Ok(cells) = WeightedIndex::new(cell_weights) else {
+            self.positions.clear();
+            self.weights.clear();
+            return;
+        };
+        let half = GRID_CELL_SIZE / 2.;
+        self.positions = (0..count)
+            .map(|_| {
+                let center = grid.cell_to_world(grid.idx_cell(cells.sample(rng)));
+                center + Vec2::new(rng.gen_range(-half..half), rng.gen_range(-half..half))
+            })
+            .collect();
+        self.weights = vec![1. / count as f32; count];
+    }
+
+    /// Moves each particle according to the configured motion model, then by a random offset of up to the configured
+    /// motion noise along each axis. Particles that would end up in a wall or outside the level stay put.
+    fn predict(
+        &mut self,
+        level: &LevelLayout,
+        config: &FilterConfig,
+        dist_field: &DistanceField,
+        rng: &mut StdRng,
+    ) {
+        let motion_noise = config.motion_noise * GRID_CELL_SIZE;
+        if config.motion_model == MotionModel::Stationary
+            || (config.motion_model == MotionModel::RandomWalk && motion_noise <= 0.)
+        {
+            return;
+        }
+        let grid = level.grid();
+        for pos in &mut self.positions {
+            let mut new_pos = *pos;
+            if config.motion_model == MotionModel::GoalDirected
+                && rng.gen_bool(config.goal_bias as f64)
+            {
+                if let Some(cell) = grid.world_to_cell(*pos) {
+                    let steps = goal_steps(level, dist_field, cell);
+                    if !steps.is_empty() {
+                        let step = steps[rng.gen_range(0..steps.len())];
+                        new_pos += grid.cell_to_world(step) - grid.cell_to_world(cell);
+                    }
+                }
+            }
+            if motion_noise > 0. {
+                new_pos += Vec2::new(
+                    rng.gen_range(-motion_noise..=motion_noise),
+                    rng.gen_range(-motion_noise..=motion_noise),
+                );
+            }
+            let open = grid
+                .world_to_cell(new_pos)
+                .and_then(|cell| grid.cell_idx(cell))
+                .is_some_and(|idx| !level.walls.get(idx));
+            if open {
+                *pos = new_pos;
+            }
+        }
+    }
+
+    /// Weighs each particle by the likelihood of the cell it's in. Returns false if every particle was ruled out.
+    fn correct(&mut self, grid: GridTransform, lkhd: &[f32]) -> bool {
+        for (pos, weight) in self.positions.iter().zip(&mut self.weights) {
+            *weight *= grid
+                .world_to_cell(*pos)
+                .and_then(|cell| grid.cell_idx(cell))
+                .map_or(0., |idx| lkhd[idx]);
+        }
+        if self.weights.iter().sum::<f32>() <= 0. {
+            return false;
+        }
+        normalize(&mut self.weights);
+        true
+    }
+
+    /// Replaces the particles with an evenly weighted set, where each old particle is copied in proportion to its
+    /// weight.
+    fn resample(&mut self, resampling: Resampling, rng: &mut StdRng) {
+        let count = self.positions.len();
+        if count == 0 {
+            return;
+        }
+        let indices: Vec<usize> = match resampling {
+            Resampling::Multinomial => {
+                let Ok(particles) = WeightedIndex::new(&self.weights) else {
+                    return;
+                };
+                (0..count).map(|_| particles.sample(rng)).collect()
+            }
+            Resampling::Systematic => {
+                let offset = rng.gen::<f32>();
+                pick_sorted(
+                    &self.weights,
+                    (0..count).map(|i| (i as f32 + offset) / count as f32),
+                )
+            }
+            Resampling::Stratified => pick_sorted(
+                &self.weights,
+                (0..count).map(|i| (i as f32 + rng.gen::<f32>()) / count as f32),
+            ),
+        };
+        let positions = indices.iter().map(|&i| self.positions[i]).collect();
+        self.positions = positions;
+        self.weights = vec![1. / count as f32; count];
+    }
+
+    /// Returns the chance of the tracked agent being in each cell, indexed the same way as `LevelLayout::walls`, by
+    /// adding up the weights of the particles in each cell.
+    pub fn rasterize(&self, grid: GridTransform) -> Vec<f32> {
+        let mut probs = vec![0.; grid.width * grid.height];
+        for (pos, weight) in self.positions.iter().zip(&self.weights) {
+            if let Some(idx) = grid
+                .world_to_cell(*pos)
+                .and_then(|cell| grid.cell_idx(cell))
+            {
+                probs[idx] += weight;
+            }
+        }
+        normalize(&mut probs);
+        probs
+    }
+}
+
+/// One component of `Gaussians`, spread evenly along both axes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Gaussian {
+    /// How much of the mixture this component makes up. Sums to 1 over all components.
+    pub weight: f32,
+    /// The component's center, in world space.
+    pub mean: Vec2,
+    /// The component's variance along each axis, in world units squared.
+    pub variance: f32,
+}
+
+impl Gaussian {
+    /// Returns the component's unweighted density at `pos`.
+    fn density(&self, pos: Vec2) -> f32 {
+        (-pos.distance_squared(self.mean) / (2. * self.variance)).exp()
+            / (2. * std::f32::consts::PI * self.variance)
+    }
+}
+
+/// The smallest variance a component can shrink to, so it always covers at least about one cell.
+const MIN_GAUSSIAN_VARIANCE: f32 = (GRID_CELL_SIZE / 2.) * (GRID_CELL_SIZE / 2.);
+
+/// A mixture of Gaussians describing where a tracked agent is in continuous coordinates, used by the Gaussian backend.
+///
+/// Starts out empty, and is seeded over open cells on the first update or whenever the level changes.
+#[derive(Clone, Default)]
+pub struct Gaussians {
+    pub components: Vec<Gaussian>,
+}
+
+impl Gaussians {
+    /// Places `count` evenly weighted components at cells drawn in proportion to `cell_weights`, then fits them to
+    /// those weights. Clears the mixture if no cell has any weight.
+    fn seed(&mut self, grid: GridTransform, cell_weights: &[f32], count: usize, rng: &mut StdRng) {
+        let Ok(cells) = WeightedIndex::new(cell_weights) else {
+            self.components.clear();
+            return;
+        };
+        let size = Vec2::new(grid.width as f32, grid.height as f32) * GRID_CELL_SIZE;
+        let variance = (size.length_squared() / count as f32).max(MIN_GAUSSIAN_VARIANCE);
+        self.components = (0..count)
+            .map(|_| Gaussian {
+                weight: 1. / count as f32,
+                mean: grid.cell_to_world(grid.idx_cell(cells.sample(rng))),
+                variance,
+            })
+            .collect();
+        let mut probs = cell_weights.to_vec();
+        normalize(&mut probs);
+        self.fit(grid, &probs);
+    }
+
+    /// Shifts each component according to the configured motion model, then widens it by the configured motion
+    /// noise. Under the goal directed motion model, a goal bias share of each component's mean steps towards the
+    /// nearest exit.
+    fn predict(&mut self, level: &LevelLayout, config: &FilterConfig, dist_field: &DistanceField) {
+        if config.motion_model == MotionModel::Stationary {
+            return;
+        }
+        let grid = level.grid();
+        let motion_noise = config.motion_noise * GRID_CELL_SIZE;
+        for component in &mut self.components {
+            if config.motion_model == MotionModel::GoalDirected {
+                if let Some(cell) = grid.world_to_cell(component.mean) {
+                    let steps = goal_steps(level, dist_field, cell);
+                    if !steps.is_empty() {
+                        let step = steps
+                            .iter()
+                            .map(|&step| grid.cell_to_world(step) - grid.cell_to_world(cell))
+                            .sum::<Vec2>()
+                            / steps.len() as f32;
+                        component.mean += step * config.goal_bias;
+                    }
+                }
+            }
+            component.variance += motion_noise * motion_noise;
+        }
+    }
+
+    /// Refits the mixture to `probs` with a round of expectation maximization, treating each cell as a point at its
+    /// center. Components left without any probability are moved to the most likely cell.
+    fn fit(&mut self, grid: GridTransform, probs: &[f32]) {
+        if self.components.is_empty() {
+            return;
+        }
+        let centers = (0..probs.len())
+            .map(|idx| grid.cell_to_world(grid.idx_cell(idx)))
+            .collect::<Vec<_>>();
+        let count = self.components.len();
+        let mut mass = vec![0.; count];
+        let mut sums = vec![Vec2::ZERO; count];
+        let mut sq_sums = vec![0.; count];
+        for (&prob, &center) in probs.iter().zip(&centers) {
+            if prob <= 0. {
+                continue;
+            }
+            let mut resps = self
+                .components
+                .iter()
+                .map(|component| component.weight * component.density(center))
+                .collect::<Vec<_>>();
+            let total = resps.iter().sum::<f32>();
+            if total > 0. {
+                resps.iter_mut().for_each(|resp| *resp /= total);
+            } else {
+                // Too far from every component to register, so give it to the closest one
+                let closest = self
+                    .components
+                    .iter()
+                    .map(|component| component.mean.distance_squared(center))
+                    .enumerate()
+                    .min_by(|(_, a), (_, b)| a.total_cmp(b))
+                    .map_or(0, |(i, _)| i);
+                resps.iter_mut().for_each(|resp| *resp = 0.);
+                resps[closest] = 1.;
+            }
+            for (i, resp) in resps.into_iter().enumerate() {
+                mass[i] += prob * resp;
+                sums[i] += center * prob * resp;
+                sq_sums[i] += center.length_squared() * prob * resp;
+            }
+        }
+        let most_likely = probs
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map_or(0, |(idx, _)| idx);
+        for (i, component) in self.components.iter_mut().enumerate() {
+            if mass[i] <= f32::EPSILON {
+                // Kept with a sliver of weight so it can pick up probability again on the next fit
+                *component = Gaussian {
+                    weight: EVIDENCE_FLOOR / count as f32,
+                    mean: centers[most_likely],
+                    variance: MIN_GAUSSIAN_VARIANCE,
+                };
+                continue;
+            }
+            let mean = sums[i] / mass[i];
+            component.weight = mass[i];
+            component.mean = mean;
+            component.variance =
+                ((sq_sums[i] / mass[i] - mean.length_squared()) / 2.).max(MIN_GAUSSIAN_VARIANCE);
+        }
+        let total = self.components.iter().map(|c| c.weight).sum::<f32>();
+        for component in &mut self.components {
+            component.weight /= total;
+        }
+    }
+
+    /// Returns the chance of the tracked agent being in each cell, indexed the same way as `LevelLayout::walls`, from
+    /// the mixture's density at each cell's center. Cells outside the level aren't counted.
+    pub fn rasterize(&self, grid: GridTransform) -> Vec<f32> {
+        let mut probs = (0..grid.width * grid.height)
+            .map(|idx| {
+                let center = grid.cell_to_world(grid.idx_cell(idx));
+                self.components
+                    .iter()
+                    .map(|component| component.weight * component.density(center))
+                    .sum::<f32>()
+            })
+            .collect::<Vec<_>>();
+        normalize(&mut probs);
+        probs
+    }
+}
+
+/// Returns which particle each of `points` lands on, if the particles' weights were laid end to end from 0 to 1.
+/// `points` must be in increasing order.
+fn pick_sorted(weights: &[f32], points: impl Iterator<Item = f32>) -> Vec<usize> {
+    let mut i = 0;
+    let mut cumulative = weights[0];
+    points
+        .map(|point| {
+            while point >= cumulative && i + 1 < weights.len() {
+                i += 1;
+                cumulative += weights[i];
+            }
+            i
+        })
+        .collect()
+}
+
+/// Returns a likelihood that's 1 for cells whose centers are within `radius` of `pos`, and `EVIDENCE_FLOOR` elsewhere.
+fn near_likelihood(level: &LevelLayout, pos: Vec2, radius: f32) -> impl Fn(usize) -> f32 {
+    let grid = level.grid();
+    move |idx| {
+        if grid.cell_to_world(grid.idx_cell(idx)).distance(pos) <= radius {
+            1.
+        } else {
+            EVIDENCE_FLOOR
+        }
+    }
+}
+
+/// Returns noises set off by any of `targets` that the agent can hear `range_scale` times as far as usual, and visual
+/// markers it sees move this step, as positions paired with how close a target has to be to them to be responsible.
+///
+/// The agent can't tell which target caused each of these, so they're assigned with `associate_evidence`. Markers
+/// close enough to the agent that it might have moved them itself are ignored.
+fn anonymous_evidence<'a>(
+    level: &LevelLayout,
+    observer: &Observer,
+    listener_pos: Vec2,
+    range_scale: f32,
+    target_noises: impl IntoIterator<Item = (Vec2, &'a NoiseSource)>,
+    now: f32,
+) -> Vec<(Vec2, f32)> {
+    let target_noises = target_noises
+        .into_iter()
+        .map(|(pos, noise_src)| ((pos, noise_src.active_radius), pos, noise_src));
+    let mut evidence = audible_noises(level, listener_pos, range_scale, target_noises)
+        .into_iter()
+        .map(|((pos, active_radius), _)| (pos, active_radius + GRID_CELL_SIZE / 2.))
+        .collect::<Vec<_>>();
+    evidence.extend(
+        observer
+            .seen_markers
+            .values()
+            .filter(|vm_data| {
+                vm_data.displacement != Vec2::ZERO
+                    && vm_data.displaced_at == now
+                    && vm_data.pos.distance(listener_pos) > MARKER_PUSH_RADIUS
+            })
+            .map(|vm_data| (vm_data.pos, MARKER_PUSH_RADIUS)),
+    );
+    evidence
+}
+
+/// Returns which target each piece of `evidence` is assigned to, as an index into `predicted`, which holds each
+/// target's predicted belief.
+///
+/// Each piece goes to the target the agent thinks is most likely to be close enough to have caused it. Ties go to the
+/// lowest index.
+fn associate_evidence(
+    level: &LevelLayout,
+    evidence: &[(Vec2, f32)],
+    predicted: &[Vec<f32>],
+) -> Vec<usize> {
+    evidence
+        .iter()
+        .map(|&(pos, radius)| {
+            let near = near_likelihood(level, pos, radius);
+            let mut best = (0, f32::NEG_INFINITY);
+            for (target_idx, probs) in predicted.iter().enumerate() {
+                let mass = probs
+                    .iter()
+                    .enumerate()
+                    .map(|(i, prob)| prob * near(i))
+                    .sum::<f32>();
+                if mass > best.1 {
+                    best = (target_idx, mass);
+                }
+            }
+            best.0
+        })
+        .collect()
+}
+
+/// Returns the likelihood of an agent's evidence this step if a target it's tracking were in each cell, indexed the
+/// same way as `LevelLayout::walls`.
+///
+/// Cells the agent can see are ruled out in proportion to `coverage`, unless it notices the target. `evidence`
+/// assigned to the target points towards where it is. Walls are always ruled out.
+fn observation_likelihood(
+    level: &LevelLayout,
+    observer: &Observer,
+    coverage: &[f32],
+    (target_e, target_pos): (Entity, Vec2),
+    evidence: impl IntoIterator<Item = (Vec2, f32)>,
+) -> Vec<f32> {
+    let grid = level.grid();
+    let mut lkhd = level
+        .walls
+        .iter()
+        .map(|wall| !wall as u8 as f32)
+        .collect::<Vec<_>>();
+    if observer.observing.contains(&target_e) {
+        let target_idx = grid
+            .world_to_cell(target_pos)
+            .and_then(|cell| grid.cell_idx(cell));
+        for (i, cell_lkhd) in lkhd.iter_mut().enumerate() {
+            if Some(i) != target_idx {
+                *cell_lkhd *= EVIDENCE_FLOOR;
+            }
+        }
+    } else {
+        for (cell_lkhd, covered) in lkhd.iter_mut().zip(coverage) {
+            *cell_lkhd *= 1. - covered;
+        }
+    }
+
+    for (pos, radius) in evidence {
+        weigh(&mut lkhd, near_likelihood(level, pos, radius));
+    }
+    lkhd
+}
+
+/// Returns a likelihood that's 1 for cells in the quadrant a ping found the target in, and `EVIDENCE_FLOOR`
+/// elsewhere.
+fn ping_likelihood(level: &LevelLayout, quadrant: u8) -> impl Fn(usize) -> f32 + '_ {
+    let grid = level.grid();
+    move |idx| {
+        if quadrant_of(level, grid.cell_to_world(grid.idx_cell(idx))) == quadrant {
+            1.
+        } else {
+            EVIDENCE_FLOOR
+        }
+    }
+}
+
+/// Multiplies every cell's likelihood by another likelihood.
+fn weigh(lkhd: &mut [f32], other: impl Fn(usize) -> f32) {
+    for (i, cell_lkhd) in lkhd.iter_mut().enumerate() {
+        *cell_lkhd *= other(i);
+    }
+}
+
+/// Returns the likelihood of an agent's observations if a target it's tracking were in each cell, according to a
+/// learned measurement model. `target_pos` is only used if the agent sees the target. Returns `None`, with a warning,
+/// if the model fails to run.
+fn learned_likelihood(
+    net: &MeasureNet,
+    level: &LevelLayout,
+    observer: &Observer,
+    (pos, dir): (Vec2, Vec2),
+    (target_e, target_pos): (Entity, Vec2),
+) -> Option<Vec<f32>> {
+    let seen = observer.observing.contains(&target_e).then_some(target_pos);
+    net.likelihood(level, policy_grid(level, pos, dir, seen))
+        .map_err(|err| warn!("Learned measurement model failed, using the hand-written one: {err}"))
+        .ok()
+}
+
+/// Updates the beliefs of agents tagged with `Tracker` about where each agent tagged with `Target` is, using what they
+/// saw, heard, and pinged this step and the configured backend.
+///
+/// Noises and moved markers don't say which target caused them, so each is assigned to the target the tracker thinks
+/// most likely to be responsible before beliefs are corrected. `ExternalSighting`s do, so they're applied directly.
+///
+/// If a `MeasureNet` has been loaded, it replaces the hand-written likelihood of what pursuers saw and heard. Walls are
+/// still ruled out, and pings and external sightings are still applied on top.
+fn update_beliefs<Tracker: Component, Target: Component>(
+    mut tracker_query: Query<
+        (
+            Entity,
+            &mut Beliefs,
+            &Agent,
+            &Observer,
+            &GlobalTransform,
+            Option<&PingResult>,
+            Has<PursuerAgent>,
+        ),
+        With<Tracker>,
+    >,
+    target_query: Query<(Entity, &GlobalTransform), With<Target>>,
+    noise_query: Query<(&GlobalTransform, &NoiseSource)>,
+    (level, dist_field): (Res<LevelLayout>, Res<DistanceField>),
+    (time, mut sighting_events): (Res<Time>, EventReader<ExternalSighting>),
+    (config, hearing_config, measure_query): (
+        Res<FilterConfig>,
+        Res<HearingConfig>,
+        Query<&NNWrapper<MeasureNet>>,
+    ),
+    mut rng: ResMut<FilterRng>,
+) {
+    let grid = level.grid();
+    let measure_net = measure_query
+        .iter()
+        .find_map(|wrapper| wrapper.net.as_ref());
+    let sightings = sighting_events.read().copied().collect::<Vec<_>>();
+    let mut targets = target_query
+        .iter()
+        .map(|(target_e, target_xform)| (target_e, target_xform.translation().xy()))
+        .collect::<Vec<_>>();
+    targets.sort_unstable_by_key(|&(target_e, _)| target_e);
+    for (tracker_e, mut beliefs, agent, observer, xform, ping_result, is_pursuer) in
+        tracker_query.iter_mut()
+    {
+        let rng = &mut rng.0;
+        beliefs
+            .targets
+            .retain(|target_e, _| targets.iter().any(|&(e, _)| e == *target_e));
+        // Restored beliefs are kept even if the level was only just loaded
+        let level_changed = level.is_changed() && !beliefs.restored;
+        beliefs.restored = false;
+        for &(target_e, _) in &targets {
+            let target_belief = beliefs.targets.entry(target_e).or_default();
+            target_belief.predict(&level, level_changed, &config, &dist_field, rng);
+        }
+
+        let evidence = anonymous_evidence(
+            &level,
+            observer,
+            xform.translation().xy(),
+            hearing_config.range_scale(is_pursuer),
+            noise_query
+                .iter()
+                .filter(|(_, noise_src)| {
+                    targets
+                        .iter()
+                        .any(|&(target_e, _)| noise_src.activated_by == Some(target_e))
+                })
+                .map(|(noise_xform, noise_src)| (noise_xform.translation().xy(), noise_src)),
+            time.elapsed_seconds_wrapped(),
+        );
+        // Only work out which target caused what when there's a choice
+        let assigned = if targets.len() > 1 && !evidence.is_empty() {
+            let predicted = targets
+                .iter()
+                .map(|(target_e, _)| {
+                    beliefs.targets[target_e].predicted_probs(grid, config.backend)
+                })
+                .collect::<Vec<_>>();
+            associate_evidence(&level, &evidence, &predicted)
+        } else {
+            vec![0; evidence.len()]
+        };
+
+        let coverage = mesh_coverage(grid, &observer.vis_mesh);
+        for (target_idx, &target) in targets.iter().enumerate() {
+            let learned = measure_net.filter(|_| is_pursuer).and_then(|net| {
+                let pose = (xform.translation().xy(), agent.dir);
+                let mut lkhd = learned_likelihood(net, &level, observer, pose, target)?;
+                weigh(&mut lkhd, |i| !level.walls[i] as u8 as f32);
+                Some(lkhd)
+            });
+            let mut lkhd = learned.unwrap_or_else(|| {
+                observation_likelihood(
+                    &level,
+                    observer,
+                    &coverage,
+                    target,
+                    evidence
+                        .iter()
+                        .zip(&assigned)
+                        .filter(|(_, &assigned_idx)| assigned_idx == target_idx)
+                        .map(|(&evidence, _)| evidence),
+                )
+            });
+            if let Some(ping_result) = ping_result.filter(|ping_result| ping_result.fresh) {
+                weigh(&mut lkhd, ping_likelihood(&level, ping_result.quadrant));
+            }
+            for sighting in sightings.iter().filter(|sighting| {
+                sighting.target == target.0 && sighting.tracker.unwrap_or(tracker_e) == tracker_e
+            }) {
+                weigh(&mut lkhd, sighting.likelihood(&level));
+            }
+            let target_belief = beliefs.targets.get_mut(&target.0).unwrap();
+            target_belief.correct(grid, &config, lkhd, rng);
+        }
+    }
+}
+
+/// How opaque a heatmap is in cells where an agent thinks its targets are most likely to be.
+const HEATMAP_ALPHA: f32 = 0.6;
+
+/// A translucent overlay of an agent's belief, with more opaque cells where the agent thinks the agents it's tracking
+/// are more likely to be. Pursuers' beliefs are shaded in fuchsia, and players' in cyan.
+#[derive(Component)]
+pub struct BeliefHeatmap {
+    /// The agent whose belief is shown.
+    pub tracker: Entity,
+    image: Handle<Image>,
+}
+
+/// Whether `BeliefHeatmap`s are drawn.
+#[derive(Resource)]
+pub struct ShowBeliefHeatmaps(pub bool);
+
+impl Default for ShowBeliefHeatmaps {
+    fn default() -> Self {
+        Self(true)
+    }
+}
+
+/// Hides or shows heatmaps, depending on `ShowBeliefHeatmaps`.
+fn show_belief_heatmaps(
+    show: Res<ShowBeliefHeatmaps>,
+    mut heatmap_query: Query<&mut Visibility, With<BeliefHeatmap>>,
+) {
+    let visibility = if show.0 {
+        Visibility::Inherited
+    } else {
+        Visibility::Hidden
+    };
+    for mut vis in heatmap_query.iter_mut() {
+        vis.set_if_neq(visibility);
+    }
+}
+
+/// Adds a heatmap for every agent that starts tracking beliefs.
+fn add_belief_heatmaps(
+    mut commands: Commands,
+    tracker_query: Query<(Entity, Has<PlayerAgent>), Added<Beliefs>>,
+    mut images: ResMut<Assets<Image>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    for (tracker, is_player) in tracker_query.iter() {
+        let color = if is_player {
+            Color::CYAN
+        } else {
+            Color::FUCHSIA
+        };
+        let image = images.add(heatmap_image(1, 1));
+        commands.spawn((
+            LevelEntity,
+            BeliefHeatmap {
+                tracker,
+                image: image.clone(),
+            },
+            PbrBundle {
+                mesh: meshes.add(Rectangle::new(1., 1.)),
+                material: materials.add(StandardMaterial {
+                    base_color: color,
+                    base_color_texture: Some(image),
+                    alpha_mode: AlphaMode::Blend,
+                    unlit: true,
+                    ..default()
+                }),
+                ..default()
+            },
+        ));
+    }
+}
+
+/// Returns a blank heatmap texture with one pixel per cell.
+fn heatmap_image(width: usize, height: usize) -> Image {
+    let mut image = Image::new_fill(
+        Extent3d {
+            width: width as u32,
+            height: height as u32,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[255, 255, 255, 0],
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::default(),
+    );
+    image.sampler = ImageSampler::nearest();
+    image
+}
+
+/// Redraws heatmaps from their agents' current beliefs, stretched over the level. Heatmaps of agents that are gone are
+/// removed.
+fn update_belief_heatmaps(
+    mut commands: Commands,
+    mut heatmap_query: Query<(Entity, &BeliefHeatmap, &mut Transform)>,
+    beliefs_query: Query<&Beliefs>,
+    level: Option<Res<LevelLayout>>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    let Some(level) = level else {
+        return;
+    };
+    let grid = level.grid();
+    for (heatmap_e, heatmap, mut xform) in heatmap_query.iter_mut() {
+        let Ok(beliefs) = beliefs_query.get(heatmap.tracker) else {
+            commands.entity(heatmap_e).despawn_recursive();
+            continue;
+        };
+        let Some(image) = images.get_mut(&heatmap.image) else {
+            continue;
+        };
+        if image.width() as usize != level.width || image.height() as usize != level.height {
+            *image = heatmap_image(level.width, level.height);
+        }
+
+        let mut probs = vec![0.; level.walls.len()];
+        for target_belief in beliefs.targets.values() {
+            for (prob, &target_prob) in probs.iter_mut().zip(&target_belief.belief.probs) {
+                *prob += target_prob;
+            }
+        }
+        let max = probs.iter().copied().fold(f32::EPSILON, f32::max);
+        for (i, prob) in probs.into_iter().enumerate() {
+            // Images start from the top row, but cells start from the bottom
+            let (x, y) = grid.idx_cell(i);
+            let pixel = ((level.height - 1 - y) * level.width + x) * 4;
+            image.data[pixel + 3] = (prob / max * HEATMAP_ALPHA * 255.).round() as u8;
+        }
+
+        let first_center = grid.cell_to_world((0, 0));
+        let last_center = grid.cell_to_world((level.width - 1, level.height - 1));
+        *xform =
+            Transform::from_translation(((first_center + last_center) / 2.).extend(GRID_CELL_SIZE))
+                .with_scale(Vec3::new(
+                    level.width as f32 * GRID_CELL_SIZE,
+                    level.height as f32 * GRID_CELL_SIZE,
+                    1.,
+                ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gridworld::LoadedLevelData;
+
+    const STEPS: usize = 200;
+
+    /// A level with a wall down the middle, with gaps at either end.
+    fn level() -> LevelLayout {
+        let mut data = LoadedLevelData::empty(10, 8);
+        for y in 1..7 {
+            data.walls[y * data.width + 4] = 1;
+        }
+        LevelLayout::from_data(&data)
+    }
+
+    /// Tracks a target wandering the level with `backend`, seeing roughly where it is every step. Returns the mean
+    /// probability given to the target's true cell.
+    fn track(backend: FilterBackend, seed: u64) -> f32 {
+        let level = level();
+        let grid = level.grid();
+        let config = FilterConfig {
+            backend,
+            ..default()
+        };
+        let dist_field = DistanceField::default();
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut target = (1, 1);
+        let mut belief = TargetBelief::default();
+        let mut total = 0.;
+        for step in 0..STEPS {
+            let moves = open_neighbors(&level, target).collect::<Vec<_>>();
+            if !moves.is_empty() && rng.gen_bool(0.8) {
+                target = moves[rng.gen_range(0..moves.len())];
+            }
+            belief.predict(&level, step == 0, &config, &dist_field, &mut rng);
+            let near = near_likelihood(&level, grid.cell_to_world(target), GRID_CELL_SIZE * 1.5);
+            let lkhd = level
+                .walls
+                .iter()
+                .enumerate()
+                .map(|(i, wall)| !wall as u8 as f32 * near(i))
+                .collect();
+            belief.correct(grid, &config, lkhd, &mut rng);
+            total += belief.belief.probs[grid.cell_idx(target).unwrap()];
+        }
+        total / STEPS as f32
+    }
+
+    #[test]
+    fn backends_are_reproducible() {
+        for backend in [
+            FilterBackend::Grid,
+            FilterBackend::Particles,
+            FilterBackend::Gaussians,
+            FilterBackend::Sparse,
+        ] {
+            assert_eq!(track(backend, 7), track(backend, 7), "{backend:?}");
+        }
+    }
+
+    #[test]
+    fn backends_track_the_target() {
+        let level = level();
+        let uniform = 1. / level.walls.iter().filter(|wall| !wall).count() as f32;
+        for seed in 0..3 {
+            let grid = track(FilterBackend::Grid, seed);
+            let particles = track(FilterBackend::Particles, seed);
+            let gaussians = track(FilterBackend::Gaussians, seed);
+            let sparse = track(FilterBackend::Sparse, seed);
+            assert!(grid > uniform * 5., "grid: {grid}, uniform: {uniform}");
+            // Sparse only drops cells that are almost ruled out, so it should match the grid closely
+            assert!(
+                (sparse - grid).abs() < 0.02,
+                "sparse: {sparse}, grid: {grid}"
+            );
+            assert!(
+                particles > grid * 0.5,
+                "particles: {particles}, grid: {grid}"
+            );
+            assert!(
+                gaussians > uniform * 3.,
+                "gaussians: {gaussians}, uniform: {uniform}"
+            );
+        }
+    }
+}
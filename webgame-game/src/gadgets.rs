@@ -0,0 +1,152 @@
+//! Abilities agents can use by spending energy.
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::gridworld::{move_agents, LevelLayout, NextAction, PlayerAgent, ShouldRun};
+
+/// Plugin for gadgets and the energy that powers them.
+pub struct GadgetPlugin;
+
+impl Plugin for GadgetPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GadgetConfig>().add_systems(
+            Update,
+            (regen_energy, use_gadgets)
+                .chain()
+                .before(move_agents)
+                .run_if(resource_exists::<ShouldRun>),
+        );
+    }
+}
+
+/// An ability that costs energy to use.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Gadget {
+    /// Moves the agent faster, at a cost per second.
+    Sprint,
+    /// Reveals which quadrant of the level the player is in, at a cost per use.
+    Ping,
+}
+
+/// Configures how much energy agents have and what gadgets cost.
+/// Harder difficulties can give agents more energy, or make gadgets cheaper.
+#[derive(Resource, Clone, Copy)]
+pub struct GadgetConfig {
+    /// The most energy an agent can store. Agents start with this much.
+    pub max_energy: f32,
+    /// How much energy is regained per second.
+    pub regen_rate: f32,
+    /// How much energy sprinting uses per second.
+    pub sprint_cost: f32,
+    /// How much energy each ping uses.
+    pub ping_cost: f32,
+}
+
+impl Default for GadgetConfig {
+    fn default() -> Self {
+        Self {
+            max_energy: 10.,
+            regen_rate: 1.,
+            sprint_cost: 4.,
+            ping_cost: 8.,
+        }
+    }
+}
+
+impl GadgetConfig {
+    /// Returns how much energy the gadget uses over `delta` seconds of use.
+    pub fn cost(&self, gadget: Gadget, delta: f32) -> f32 {
+        match gadget {
+            Gadget::Sprint => self.sprint_cost * delta,
+            Gadget::Ping => self.ping_cost,
+        }
+    }
+}
+
+/// Energy an agent can spend on gadgets. Agents without this can't use gadgets.
+#[derive(Component)]
+pub struct GadgetEnergy {
+    pub current: f32,
+}
+
+/// Indicates that an agent is sprinting this frame.
+#[derive(Component)]
+pub struct Sprinting;
+
+/// Stores the result of an agent's most recent ping.
+#[derive(Component)]
+pub struct PingResult {
+    /// The quadrant the player was in, as returned by `quadrant_of`.
+    pub quadrant: u8,
+    /// Whether the ping happened this frame.
+    pub fresh: bool,
+}
+
+/// Returns which quadrant of the level a position is in.
+/// 0 is bottom left, 1 is bottom right, 2 is top left, and 3 is top right.
+pub fn quadrant_of(level: &LevelLayout, pos: Vec2) -> u8 {
+    let (x, y) = level.grid().world_to_cell_clamped(pos);
+    let right = x * 2 >= level.width;
+    let top = y * 2 >= level.height;
+    right as u8 + 2 * top as u8
+}
+
+/// How much faster agents move while sprinting.
+pub const SPRINT_SPEED_SCALE: f32 = 1.5;
+
+/// Regenerates energy over time.
+fn regen_energy(
+    mut energy_query: Query<&mut GadgetEnergy>,
+    config: Res<GadgetConfig>,
+    time: Res<Time>,
+) {
+    for mut energy in energy_query.iter_mut() {
+        energy.current =
+            (energy.current + config.regen_rate * time.delta_seconds()).min(config.max_energy);
+    }
+}
+
+/// Activates the gadgets agents ask for, as long as they have enough energy.
+fn use_gadgets(
+    mut commands: Commands,
+    mut agent_query: Query<(
+        Entity,
+        &NextAction,
+        Option<&mut GadgetEnergy>,
+        Option<&mut PingResult>,
+    )>,
+    player_query: Query<&GlobalTransform, With<PlayerAgent>>,
+    level: Res<LevelLayout>,
+    config: Res<GadgetConfig>,
+    time: Res<Time>,
+) {
+    for (agent_e, next_action, energy, ping_result) in agent_query.iter_mut() {
+        let mut used = None;
+        if let (Some(gadget), Some(mut energy)) = (next_action.gadget, energy) {
+            let cost = config.cost(gadget, time.delta_seconds());
+            if energy.current >= cost {
+                energy.current -= cost;
+                used = Some(gadget);
+            }
+        }
+
+        if let Some(mut ping_result) = ping_result {
+            ping_result.fresh = false;
+        }
+        if used == Some(Gadget::Ping) {
+            if let Ok(player_xform) = player_query.get_single() {
+                commands.entity(agent_e).insert(PingResult {
+                    quadrant: quadrant_of(&level, player_xform.translation().xy()),
+                    fresh: true,
+                });
+            }
+        }
+
+        if used == Some(Gadget::Sprint) {
+            commands.entity(agent_e).insert(Sprinting);
+        } else {
+            commands.entity(agent_e).remove::<Sprinting>();
+        }
+    }
+}
@@ -9,16 +9,32 @@ use bevy::{
 use bevy_rapier2d::{
     control::KinematicCharacterController,
     dynamics::{Damping, LockedAxes, RigidBody},
-    geometry::Collider,
+    geometry::{Collider, Sensor},
 };
-use rand::{seq::IteratorRandom, Rng};
-use serde::Deserialize;
+use rand::{rngs::StdRng, seq::IteratorRandom, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 use crate::{
+    awareness::Awareness,
+    bitgrid::BitGrid,
+    comms::{Radio, RadioChannel},
     configs::IsPlayable,
-    observer::{DebugObserver, Observable, Observer, Wall},
-    world_objs::{NoiseSource, VisualMarker},
+    filter::Beliefs,
+    gadgets::{Gadget, GadgetConfig, GadgetEnergy, Sprinting, SPRINT_SPEED_SCALE},
+    level_gen::{obstacle_walls, ObstacleParams, Symmetry},
+    level_set::{LevelSampling, LevelSet},
+    lighting::LightSource,
+    net::PolicyRunner,
+    observer::{
+        Cover, DebugObserver, Fog, Observable, Observer, OccludesVision, VisionConfig,
+        VisionParams, Wall,
+    },
+    pathfinding::distance_field,
+    world_objs::{
+        CameraFeed, DynamicWall, Exit, ExitDoor, InVent, Key, LevelComplete, NoiseSource, Patrol,
+        StationaryCamera, Vent, VisualMarker,
+    },
 };
 
 /// Plugin for basic game features, such as moving around and not going through walls.
@@ -26,18 +42,24 @@ pub struct GridworldPlugin;
 
 impl Plugin for GridworldPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(
-            Update,
-            (
-                setup_entities.run_if(resource_added::<LevelLayout>),
+        app.add_event::<ResetEvent>()
+            .init_resource::<SpeedConfig>()
+            .init_resource::<KeyBindings>()
+            .init_resource::<SpawnRng>()
+            .add_systems(
+                Update,
                 (
-                    move_agents,
-                    visualize_agent::<PursuerAgent>(Color::RED),
-                    visualize_agent::<PlayerAgent>(Color::GREEN),
-                )
-                    .run_if(resource_exists::<ShouldRun>),
-            ),
-        );
+                    reset_level.before(setup_entities),
+                    setup_entities.run_if(resource_added::<LevelLayout>),
+                    update_topology.run_if(resource_changed::<LevelLayout>),
+                    (
+                        move_agents,
+                        visualize_agent::<PursuerAgent>(Color::RED),
+                        visualize_agent::<PlayerAgent>(Color::GREEN),
+                    )
+                        .run_if(resource_exists::<ShouldRun>),
+                ),
+            );
     }
 }
 
@@ -52,41 +74,619 @@ impl Plugin for GridworldPlayPlugin {
                 Update,
                 (
                     load_level,
-                    set_player_action.run_if(resource_exists::<ShouldRun>),
+                    set_keyboard_action::<PlayerAgent>.run_if(resource_exists::<ShouldRun>),
+                    save_level_hotkey.run_if(resource_exists::<LevelLayout>),
                 ),
             );
     }
 }
 
-/// The width and height of the level by default.
+/// The width and height of randomly generated levels.
 pub const DEFAULT_LEVEL_SIZE: usize = 8;
 /// The probability of a door spawning in an empty cell.
 pub const DOOR_PROB: f64 = 0.05;
 
+/// The directions objects in levels can face.
+pub const OBJECT_DIRS: [&str; 4] = ["left", "up", "right", "down"];
+
 /// Data for objects in levels.
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
 pub struct LoadedObjData {
     pub name: String,
     pub pos: (usize, usize),
+    /// Which way the object faces. One of `OBJECT_DIRS`, or "left" if not set.
     #[serde(default)]
     pub dir: Option<String>,
     #[serde(default)]
     pub movable: bool,
 }
 
+/// A neutral obstacle that walks a loop of waypoints, blocking movement and vision.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct PatrolData {
+    /// Cells to walk to in order, in the same coordinates as `objects`. After the last one, the patrol returns to the
+    /// first. Patrols walk in straight lines, so the path between consecutive waypoints should be clear.
+    pub waypoints: Vec<(usize, usize)>,
+    /// How fast the patrol walks, in cells per second.
+    #[serde(default = "default_patrol_speed")]
+    pub speed: f32,
+}
+
+fn default_patrol_speed() -> f32 {
+    1.
+}
+
+/// A direction along the grid, in level file coordinates, so `Up` points towards the first row.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum GridDir {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl GridDir {
+    /// Returns the neighbor of a cell in this direction, if it's inside the grid.
+    pub fn step(self, (x, y): (usize, usize), grid: GridTransform) -> Option<(usize, usize)> {
+        let next = match self {
+            Self::Up => (x, y.checked_sub(1)?),
+            Self::Down => (x, y + 1),
+            Self::Left => (x.checked_sub(1)?, y),
+            Self::Right => (x + 1, y),
+        };
+        grid.in_bounds(next).then_some(next)
+    }
+
+    /// Returns the direction from one cell to a neighboring one, if they are neighbors.
+    pub fn between(from: (usize, usize), to: (usize, usize)) -> Option<Self> {
+        match (
+            to.0 as isize - from.0 as isize,
+            to.1 as isize - from.1 as isize,
+        ) {
+            (0, -1) => Some(Self::Up),
+            (0, 1) => Some(Self::Down),
+            (-1, 0) => Some(Self::Left),
+            (1, 0) => Some(Self::Right),
+            _ => None,
+        }
+    }
+
+    /// Returns this direction as a unit vector in world space, where up is `+Y`.
+    pub fn to_world(self) -> Vec2 {
+        match self {
+            Self::Up => Vec2::Y,
+            Self::Down => -Vec2::Y,
+            Self::Left => -Vec2::X,
+            Self::Right => Vec2::X,
+        }
+    }
+}
+
+/// A cell edge that agents can only cross in one direction, like a turnstile or a ledge.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct OneWayEdge {
+    /// The cell on the side agents can cross from, in the same coordinates as `objects`.
+    pub cell: (usize, usize),
+    /// Which side of `cell` the edge is on. Agents can move from `cell` into its neighbor in this direction, but not
+    /// back.
+    pub dir: GridDir,
+}
+
+impl OneWayEdge {
+    /// Returns the cell agents can cross into, if it's inside the grid.
+    pub fn target(&self, grid: GridTransform) -> Option<(usize, usize)> {
+        self.dir.step(self.cell, grid)
+    }
+
+    /// Returns true if this edge stops agents from stepping from `from` to `to`, both in level file coordinates.
+    pub fn blocks(&self, from: (usize, usize), to: (usize, usize), grid: GridTransform) -> bool {
+        self.cell == to && self.target(grid) == Some(from)
+    }
+}
+
+/// A rectangle of cells that an agent can spawn in, including both corners.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpawnZone {
+    pub min: (usize, usize),
+    pub max: (usize, usize),
+}
+
+impl SpawnZone {
+    /// Returns true if the cell lies inside the zone.
+    pub fn contains(&self, (x, y): (usize, usize)) -> bool {
+        (self.min.0..=self.max.0).contains(&x) && (self.min.1..=self.max.1).contains(&y)
+    }
+}
+
+/// A rectangle of cells, including both corners, where fog limits how far observers can see.
+/// Observers inside fog, or looking through it, only see `view_dist` cells into it.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct FogData {
+    pub min: (usize, usize),
+    pub max: (usize, usize),
+    /// How far observers can see into the fog, in cells.
+    #[serde(default = "default_fog_view_dist")]
+    pub view_dist: f32,
+}
+
+fn default_fog_view_dist() -> f32 {
+    2.
+}
+
+impl FogData {
+    /// Returns the area covered by the fog in world space, given corners in level file coordinates.
+    pub fn world_rect(&self, grid: GridTransform) -> Rect {
+        Rect::from_corners(
+            grid.file_cell_to_world(self.min),
+            grid.file_cell_to_world(self.max),
+        )
+        .inflate(GRID_CELL_SIZE / 2.)
+    }
+}
+
+/// A fixed camera that sees up to a range, either in every direction or within a cone. What it sees is shared with
+/// the pursuer.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct CameraData {
+    /// The cell the camera is in, in the same coordinates as `objects`.
+    pub pos: (usize, usize),
+    /// How far the camera can see, in cells.
+    #[serde(default = "default_camera_range")]
+    pub range: f32,
+    /// If set, the camera only sees within this cone. Otherwise, it sees in every direction.
+    #[serde(default)]
+    pub cone: Option<CameraCone>,
+}
+
+fn default_camera_range() -> f32 {
+    4.
+}
+
+/// The vision cone of a camera that only looks one way.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct CameraCone {
+    /// Which way the camera looks.
+    pub dir: GridDir,
+    /// How wide the cone is, in degrees.
+    #[serde(default = "default_camera_fov_degrees")]
+    pub fov_degrees: f32,
+}
+
+fn default_camera_fov_degrees() -> f32 {
+    90.
+}
+
+/// A light that brightens the cells around it. Agents notice things in bright cells from further away.
+/// Light falls off linearly with distance, and walls cast shadows.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct LightData {
+    /// The cell the light is in, in the same coordinates as `objects`.
+    pub pos: (usize, usize),
+    /// How far the light reaches, in cells.
+    #[serde(default = "default_light_radius")]
+    pub radius: f32,
+    /// How much the light brightens its own cell, from 0 to 1.
+    #[serde(default = "default_light_intensity")]
+    pub intensity: f32,
+}
+
+fn default_light_radius() -> f32 {
+    4.
+}
+
+fn default_light_intensity() -> f32 {
+    1.
+}
+
+fn default_ambient_light() -> f32 {
+    1.
+}
+
+/// Overrides for how agents see on a level. Agents without an override use the `VisionConfig` resource.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct LevelVision {
+    #[serde(default)]
+    pub pursuer: Option<VisionParams>,
+    #[serde(default)]
+    pub player: Option<VisionParams>,
+}
+
+/// Descriptive information about a level, used to organize levels into suites.
+/// Every field is optional.
+#[derive(Resource, Deserialize, Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct LevelMeta {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub author: Option<String>,
+    /// How hard the level is. Higher is harder, and the scale is up to whoever organizes the suite.
+    #[serde(default)]
+    pub difficulty: Option<u32>,
+    /// How many steps an episode on this level should last before it's truncated.
+    #[serde(default)]
+    pub max_steps: Option<u32>,
+    /// The asset path of a policy to play as the pursuer on this level, instead of the one given to
+    /// `PolicyRunnerPlugin`. Only used if a policy is already controlling the pursuer.
+    #[serde(default)]
+    pub pursuer_policy: Option<String>,
+    /// Like `pursuer_policy`, but for the player.
+    #[serde(default)]
+    pub player_policy: Option<String>,
+}
+
+/// The current version of the level file format.
+/// Bump this and add a step to `migrate_level` whenever the format changes.
+pub const LEVEL_FORMAT_VERSION: u32 = 2;
+
 /// Data for loaded levels.
-#[derive(Deserialize, Asset, TypePath)]
+///
+/// Like `objects`, all positions are stored with the first row at the top of the level.
+#[derive(Deserialize, Serialize, Asset, TypePath, Clone)]
 pub struct LoadedLevelData {
-    pub size: usize,
+    pub version: u32,
+    pub width: usize,
+    pub height: usize,
+    /// Stores `height` rows of `width` cells each.
     pub walls: Vec<u8>,
     pub objects: Vec<LoadedObjData>,
+    /// Where the player starts. If not provided, a random empty cell is used.
+    #[serde(default)]
+    pub player_spawn: Option<(usize, usize)>,
+    /// Where the pursuer starts. If not provided, a random empty cell is used.
+    #[serde(default)]
+    pub pursuer_spawn: Option<(usize, usize)>,
+    /// If `player_spawn` isn't provided, the player starts in a random empty cell in this zone.
+    #[serde(default)]
+    pub player_spawn_zone: Option<SpawnZone>,
+    /// If `pursuer_spawn` isn't provided, the pursuer starts in a random empty cell in this zone.
+    #[serde(default)]
+    pub pursuer_spawn_zone: Option<SpawnZone>,
+    /// How many cells apart randomly chosen spawns should be.
+    /// If no cells are far enough apart, the player spawns as far from the pursuer as possible.
+    #[serde(default)]
+    pub min_spawn_separation: f32,
+    #[serde(default)]
+    pub key_pos: Option<(usize, usize)>,
+    #[serde(default)]
+    pub door_pos: Option<(usize, usize)>,
+    /// Open exits. The player escapes by reaching any of these, or the unlocked door.
+    #[serde(default)]
+    pub exits: Vec<(usize, usize)>,
+    /// Whether the player has to be carrying the key to escape through `exits`.
+    #[serde(default)]
+    pub exits_need_key: bool,
+    /// Walls that agents can open and close. These cells should be empty in `walls`.
+    #[serde(default)]
+    pub dynamic_walls: Vec<(usize, usize)>,
+    /// Pairs of connected vents. Agents can travel through vents in either direction.
+    #[serde(default)]
+    pub vents: Vec<[(usize, usize); 2]>,
+    /// Cells with cover (e.g. bushes), which block vision but not movement.
+    #[serde(default)]
+    pub cover: Vec<(usize, usize)>,
+    /// Cells with terrain other than normal ground.
+    #[serde(default)]
+    pub terrain: Vec<((usize, usize), Terrain)>,
+    /// Neutral obstacles that walk loops of waypoints.
+    #[serde(default)]
+    pub patrols: Vec<PatrolData>,
+    /// Cell edges that agents can only cross in one direction.
+    #[serde(default)]
+    pub one_way: Vec<OneWayEdge>,
+    /// Cells that push agents standing on them in a fixed direction.
+    #[serde(default)]
+    pub conveyors: Vec<((usize, usize), GridDir)>,
+    /// Regions where fog limits how far observers can see, creating hiding spots without walls.
+    #[serde(default)]
+    pub fog: Vec<FogData>,
+    /// Fixed cameras that watch the area around them for the pursuer.
+    #[serde(default)]
+    pub cameras: Vec<CameraData>,
+    /// Lights that let agents notice things from further away in dark levels.
+    #[serde(default)]
+    pub lights: Vec<LightData>,
+    /// How brightly lit every cell is without any lights, from 0 to 1. Fully lit levels don't need lights.
+    #[serde(default = "default_ambient_light")]
+    pub ambient_light: f32,
+    #[serde(default)]
+    pub vision: LevelVision,
+    #[serde(default)]
+    pub meta: LevelMeta,
+}
+
+impl LoadedLevelData {
+    /// Creates a level of the given size with no walls or objects.
+    pub fn empty(width: usize, height: usize) -> Self {
+        Self {
+            version: LEVEL_FORMAT_VERSION,
+            width,
+            height,
+            walls: vec![0; width * height],
+            objects: Vec::new(),
+            player_spawn: None,
+            pursuer_spawn: None,
+            player_spawn_zone: None,
+            pursuer_spawn_zone: None,
+            min_spawn_separation: 0.,
+            key_pos: None,
+            door_pos: None,
+            exits: Vec::new(),
+            exits_need_key: false,
+            dynamic_walls: Vec::new(),
+            vents: Vec::new(),
+            cover: Vec::new(),
+            terrain: Vec::new(),
+            patrols: Vec::new(),
+            one_way: Vec::new(),
+            conveyors: Vec::new(),
+            fog: Vec::new(),
+            cameras: Vec::new(),
+            lights: Vec::new(),
+            ambient_light: default_ambient_light(),
+            vision: LevelVision::default(),
+            meta: LevelMeta::default(),
+        }
+    }
+
+    /// Returns the coordinate conversions for this level's grid.
+    pub fn grid(&self) -> GridTransform {
+        GridTransform::new(self.width, self.height)
+    }
+
+    /// Parses level data from JSON, migrating older versions of the format to the current one.
+    pub fn from_json(json: &str) -> Result<Self, LevelDataError> {
+        let mut value: serde_json::Value = serde_json::from_str(json)?;
+        // Files written before versioning was added don't have a version field
+        let version = value
+            .get("version")
+            .map(|v| {
+                v.as_u64()
+                    .map(|v| v as u32)
+                    .ok_or(LevelDataError::InvalidVersion)
+            })
+            .transpose()?
+            .unwrap_or(0);
+        if version > LEVEL_FORMAT_VERSION {
+            return Err(LevelDataError::UnsupportedVersion(version));
+        }
+        for from_version in version..LEVEL_FORMAT_VERSION {
+            migrate_level(&mut value, from_version);
+        }
+        let data: Self = serde_json::from_value(value)?;
+        data.validate()?;
+        Ok(data)
+    }
+
+    /// Serializes the level data as JSON in the current format.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("level data should always serialize")
+    }
+
+    /// Checks that the level data is internally consistent.
+    pub fn validate(&self) -> Result<(), LevelDataError> {
+        if self.width == 0 || self.height == 0 {
+            return Err(LevelDataError::EmptyLevel {
+                width: self.width,
+                height: self.height,
+            });
+        }
+        if self.walls.len() != self.width * self.height {
+            return Err(LevelDataError::WallCount {
+                expected: self.width * self.height,
+                found: self.walls.len(),
+            });
+        }
+        let named_positions = self
+            .objects
+            .iter()
+            .map(|obj| (obj.name.as_str(), Some(obj.pos)))
+            .chain([
+                ("player_spawn", self.player_spawn),
+                ("pursuer_spawn", self.pursuer_spawn),
+                ("key_pos", self.key_pos),
+                ("door_pos", self.door_pos),
+            ])
+            .chain(
+                self.dynamic_walls
+                    .iter()
+                    .map(|&pos| ("dynamic_wall", Some(pos))),
+            )
+            .chain(self.exits.iter().map(|&pos| ("exit", Some(pos))))
+            .chain(self.vents.iter().flatten().map(|&pos| ("vent", Some(pos))))
+            .chain(self.cover.iter().map(|&pos| ("cover", Some(pos))))
+            .chain(self.terrain.iter().map(|&(pos, _)| ("terrain", Some(pos))))
+            .chain(
+                self.patrols
+                    .iter()
+                    .flat_map(|patrol| &patrol.waypoints)
+                    .map(|&pos| ("patrol", Some(pos))),
+            )
+            .chain(self.one_way.iter().map(|edge| ("one_way", Some(edge.cell))))
+            .chain(
+                self.conveyors
+                    .iter()
+                    .map(|&(pos, _)| ("conveyor", Some(pos))),
+            )
+            .chain(
+                self.fog
+                    .iter()
+                    .flat_map(|fog| [("fog", Some(fog.min)), ("fog", Some(fog.max))]),
+            )
+            .chain(
+                self.cameras
+                    .iter()
+                    .map(|camera| ("camera", Some(camera.pos))),
+            )
+            .chain(self.lights.iter().map(|light| ("light", Some(light.pos))))
+            .chain(
+                [
+                    ("player_spawn_zone", self.player_spawn_zone),
+                    ("pursuer_spawn_zone", self.pursuer_spawn_zone),
+                ]
+                .into_iter()
+                .flat_map(|(name, zone)| {
+                    zone.into_iter()
+                        .flat_map(move |zone| [(name, Some(zone.min)), (name, Some(zone.max))])
+                }),
+            );
+        // Agents need somewhere to spawn
+        let has_empty = (0..self.walls.len()).any(|i| {
+            self.walls[i] == 0
+                && !self
+                    .dynamic_walls
+                    .contains(&(i % self.width, i / self.width))
+        });
+        if !has_empty {
+            return Err(LevelDataError::NoEmptyCells);
+        }
+        for obj in &self.objects {
+            if let Some(dir) = obj
+                .dir
+                .as_ref()
+                .filter(|dir| !OBJECT_DIRS.contains(&dir.as_str()))
+            {
+                return Err(LevelDataError::InvalidObjectDir {
+                    name: obj.name.clone(),
+                    dir: dir.clone(),
+                });
+            }
+        }
+        for (name, pos) in named_positions {
+            if let Some(pos) = pos {
+                if pos.0 >= self.width || pos.1 >= self.height {
+                    return Err(LevelDataError::ObjectOutOfBounds {
+                        name: name.into(),
+                        pos,
+                    });
+                }
+            }
+        }
+        for zone in [self.player_spawn_zone, self.pursuer_spawn_zone]
+            .into_iter()
+            .flatten()
+        {
+            if zone.min.0 > zone.max.0 || zone.min.1 > zone.max.1 {
+                return Err(LevelDataError::InvalidSpawnZone(zone));
+            }
+        }
+        if let Some(i) = self
+            .patrols
+            .iter()
+            .position(|patrol| patrol.waypoints.is_empty())
+        {
+            return Err(LevelDataError::EmptyPatrol(i));
+        }
+        let grid = self.grid();
+        if let Some(i) = self
+            .one_way
+            .iter()
+            .position(|edge| edge.target(grid).is_none())
+        {
+            return Err(LevelDataError::OneWayOutOfBounds(i));
+        }
+        if let Some(i) = self
+            .fog
+            .iter()
+            .position(|fog| fog.min.0 > fog.max.0 || fog.min.1 > fog.max.1 || fog.view_dist < 0.)
+        {
+            return Err(LevelDataError::InvalidFog(i));
+        }
+        if let Some(i) = self.cameras.iter().position(|camera| {
+            let fov = camera.cone.map_or(360., |cone| cone.fov_degrees);
+            camera.range < 0. || fov <= 0. || fov > 360.
+        }) {
+            return Err(LevelDataError::InvalidCamera(i));
+        }
+        if let Some(i) = self
+            .lights
+            .iter()
+            .position(|light| light.radius < 0. || !(0. ..=1.).contains(&light.intensity))
+        {
+            return Err(LevelDataError::InvalidLight(i));
+        }
+        if !(0. ..=1.).contains(&self.ambient_light) {
+            return Err(LevelDataError::InvalidAmbientLight(self.ambient_light));
+        }
+        let vision = [self.vision.pursuer, self.vision.player];
+        if let Some(vision) = vision.into_iter().flatten().find(|v| !v.is_valid()) {
+            return Err(LevelDataError::InvalidVision(vision));
+        }
+        Ok(())
+    }
+}
+
+/// Upgrades raw level JSON from `from_version` to the next version.
+fn migrate_level(value: &mut serde_json::Value, from_version: u32) {
+    match from_version {
+        // Version 1 only adds the version field
+        0 => {
+            value["version"] = 1.into();
+        }
+        // Version 2 replaces `size` with `width` and `height`
+        1 => {
+            if let Some(level) = value.as_object_mut() {
+                if let Some(size) = level.remove("size") {
+                    level.insert("width".into(), size.clone());
+                    level.insert("height".into(), size);
+                }
+                level.insert("version".into(), 2.into());
+            }
+        }
+        _ => unreachable!("no migration from level version {from_version}"),
+    }
+}
+
+/// Errors that can occur when reading level data.
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum LevelDataError {
+    #[error("Could not parse JSON: {0}")]
+    Json(#[from] serde_json::error::Error),
+    #[error("Level version must be a non-negative integer")]
+    InvalidVersion,
+    #[error("Level version {0} is newer than this build supports")]
+    UnsupportedVersion(u32),
+    #[error("Level must be at least 1x1, found {width}x{height}")]
+    EmptyLevel { width: usize, height: usize },
+    #[error("Expected {expected} wall cells, found {found}")]
+    WallCount { expected: usize, found: usize },
+    #[error("Object \"{name}\" at {pos:?} is outside the level")]
+    ObjectOutOfBounds { name: String, pos: (usize, usize) },
+    #[error("Object \"{name}\" faces \"{dir}\", which isn't one of left, up, right, or down")]
+    InvalidObjectDir { name: String, dir: String },
+    #[error("Level has no empty cells for agents to spawn in")]
+    NoEmptyCells,
+    #[error("Spawn zone {0:?} has a minimum corner past its maximum corner")]
+    InvalidSpawnZone(SpawnZone),
+    #[error("Patrol {0} has no waypoints")]
+    EmptyPatrol(usize),
+    #[error("One-way edge {0} leads outside the level")]
+    OneWayOutOfBounds(usize),
+    #[error("Fog zone {0} has a negative view distance or a minimum corner past its maximum")]
+    InvalidFog(usize),
+    #[error("Camera {0} has a negative range or a field of view outside 0 to 360 degrees")]
+    InvalidCamera(usize),
+    #[error("Light {0} has a negative radius or an intensity outside 0 to 1")]
+    InvalidLight(usize),
+    #[error("Ambient light {0} is outside 0 to 1")]
+    InvalidAmbientLight(f32),
+    #[error("Vision {0:?} needs a field of view from 0 to 360 degrees and a non-negative range")]
+    InvalidVision(VisionParams),
 }
 
 /// Indicates that a level should be loaded.
+///
+/// Once loaded, a single level file keeps being watched, and the level is reset whenever the file changes.
+/// This requires the `bevy/file_watcher` feature.
 #[derive(Resource)]
 pub enum LevelLoader {
     Path(String),
+    /// Loads every level into a `LevelSet`, which picks a new level on each `ResetEvent`.
+    Paths(Vec<String>, LevelSampling),
     Asset(Handle<LoadedLevelData>),
+    SetAssets(Vec<Handle<LoadedLevelData>>, LevelSampling),
+    Watching(Handle<LoadedLevelData>),
 }
 
 #[derive(Default)]
@@ -97,8 +697,8 @@ struct LoadedLevelDataLoader;
 pub enum LoadedLevelDataLoaderError {
     #[error("Could not load asset: {0}")]
     Io(#[from] std::io::Error),
-    #[error("Could not parse JSON: {0}")]
-    RonSpannedError(#[from] serde_json::error::Error),
+    #[error("Invalid level data: {0}")]
+    LevelData(#[from] LevelDataError),
 }
 
 impl AssetLoader for LoadedLevelDataLoader {
@@ -114,7 +714,7 @@ impl AssetLoader for LoadedLevelDataLoader {
         Box::pin(async move {
             let mut buf = String::new();
             reader.read_to_string(&mut buf).await?;
-            let data: LoadedLevelData = serde_json::from_str(&buf)?;
+            let data = LoadedLevelData::from_json(&buf)?;
             Ok(data)
         })
     }
@@ -124,96 +724,682 @@ impl AssetLoader for LoadedLevelDataLoader {
     }
 }
 
-/// Loads levels.
+/// Loads levels, and reloads them when their files change.
 fn load_level(
     level: Option<Res<LevelLoader>>,
     level_data: Res<Assets<LoadedLevelData>>,
     asset_server: Res<AssetServer>,
+    mut asset_events: EventReader<AssetEvent<LoadedLevelData>>,
+    mut ev_reset: EventWriter<ResetEvent>,
     mut commands: Commands,
 ) {
-    if let Some(level) = level {
-        match level.as_ref() {
-            LevelLoader::Path(path) => {
-                commands.insert_resource(LevelLoader::Asset(asset_server.load(path)));
-            }
-            LevelLoader::Asset(handle) => {
-                if let Some(level) = level_data.get(handle.clone()) {
-                    let mut walls = Vec::new();
-                    for y in 0..level.size {
-                        for x in 0..level.size {
-                            walls.push(level.walls[(level.size - y - 1) * level.size + x] != 0);
-                        }
+    let Some(level) = level else {
+        asset_events.clear();
+        return;
+    };
+    match level.as_ref() {
+        LevelLoader::Path(path) => {
+            commands.insert_resource(LevelLoader::Asset(asset_server.load(path)));
+        }
+        LevelLoader::Paths(paths, sampling) => {
+            let handles = paths.iter().map(|path| asset_server.load(path)).collect();
+            commands.insert_resource(LevelLoader::SetAssets(handles, sampling.clone()));
+        }
+        LevelLoader::SetAssets(handles, sampling) => {
+            let loaded: Option<Vec<_>> = handles
+                .iter()
+                .map(|handle| level_data.get(handle).map(LevelLayout::from_data))
+                .collect();
+            if let Some(levels) = loaded {
+                match LevelSet::new(levels, sampling.clone()) {
+                    Ok(mut level_set) => {
+                        commands.insert_resource(level_set.next_level(&mut rand::thread_rng()));
+                        commands.insert_resource(level_set);
                     }
-                    commands.insert_resource(LevelLayout {
-                        walls,
-                        size: level.size,
-                        objects: level.objects.clone(),
-                    });
-                    commands.remove_resource::<LevelLoader>();
+                    Err(err) => error!("Could not create level set: {err}"),
                 }
+                commands.remove_resource::<LevelLoader>();
+            }
+            asset_events.clear();
+        }
+        LevelLoader::Asset(handle) => {
+            if let Some(level) = level_data.get(handle.clone()) {
+                commands.insert_resource(LevelLayout::from_data(level));
+                commands.insert_resource(LevelLoader::Watching(handle.clone()));
+            }
+            asset_events.clear();
+        }
+        LevelLoader::Watching(handle) => {
+            let modified = asset_events.read().any(|ev| ev.is_modified(handle.id()));
+            if let Some(level) = level_data.get(handle.clone()).filter(|_| modified) {
+                ev_reset.send(ResetEvent {
+                    level: Some(LevelLayout::from_data(level)),
+                });
             }
         }
     }
 }
 
+/// Tells the game to replace the current level with a new one.
+#[derive(Event)]
+pub struct ResetEvent {
+    /// The level to play. If not set, the next level from the `LevelSet` is used, or the current level is restarted
+    /// if there isn't one.
+    pub level: Option<LevelLayout>,
+}
+
+/// Tears down the current level and sets up the one in the latest `ResetEvent`.
+pub fn reset_level(
+    mut commands: Commands,
+    mut ev_reset: EventReader<ResetEvent>,
+    level_query: Query<Entity, With<LevelEntity>>,
+    current_level: Option<Res<LevelLayout>>,
+    level_set: Option<ResMut<LevelSet>>,
+) {
+    let Some(ev) = ev_reset.read().last() else {
+        return;
+    };
+    let next_level = ev
+        .level
+        .clone()
+        .or_else(|| Some(level_set?.next_level(&mut rand::thread_rng())))
+        .or_else(|| current_level.map(|level| level.clone()));
+    let Some(next_level) = next_level else {
+        return;
+    };
+    for e in level_query.iter() {
+        commands.entity(e).despawn_recursive();
+    }
+    commands.remove_resource::<ShouldRun>();
+    // Removing the old layout first means the new one counts as added, so `setup_entities` runs again
+    commands.remove_resource::<LevelLayout>();
+    commands.insert_resource(next_level);
+}
+
 /// Indicates that the game should begin running.
 #[derive(Resource)]
 pub struct ShouldRun;
 
-/// Stores the layout of the level.
+/// The random numbers used to pick where agents spawn. Insert a seeded one for reproducible runs.
 #[derive(Resource)]
+pub struct SpawnRng(pub StdRng);
+
+impl Default for SpawnRng {
+    fn default() -> Self {
+        Self(StdRng::from_entropy())
+    }
+}
+
+/// Stores the layout of the level.
+#[derive(Resource, Clone)]
 pub struct LevelLayout {
-    /// Stores `true` if a wall exists, `false` for empty spaces. The first element is the top right corner.
-    pub walls: Vec<bool>,
-    pub size: usize,
+    /// Stores `true` if a wall exists, `false` for empty spaces. The first element is the bottom left corner.
+    pub walls: BitGrid,
+    pub width: usize,
+    pub height: usize,
     pub objects: Vec<LoadedObjData>,
+    /// Where the player starts, in the same coordinates as `objects`.
+    pub player_spawn: Option<(usize, usize)>,
+    /// Where the pursuer starts, in the same coordinates as `objects`.
+    pub pursuer_spawn: Option<(usize, usize)>,
+    /// Where the player can start if `player_spawn` isn't set, in the same coordinates as `objects`.
+    pub player_spawn_zone: Option<SpawnZone>,
+    /// Where the pursuer can start if `pursuer_spawn` isn't set, in the same coordinates as `objects`.
+    pub pursuer_spawn_zone: Option<SpawnZone>,
+    /// How many cells apart randomly chosen spawns should be.
+    pub min_spawn_separation: f32,
+    pub key_pos: Option<(usize, usize)>,
+    pub door_pos: Option<(usize, usize)>,
+    /// Open exits, in the same coordinates as `objects`.
+    pub exits: Vec<(usize, usize)>,
+    /// Whether the player has to be carrying the key to escape through `exits`.
+    pub exits_need_key: bool,
+    /// Walls that can be opened and closed, in the same coordinates as `objects`.
+    /// Their current state is reflected in `walls`.
+    pub dynamic_walls: Vec<(usize, usize)>,
+    /// Pairs of connected vents, in the same coordinates as `objects`.
+    pub vents: Vec<[(usize, usize); 2]>,
+    /// Stores `true` for cells with cover, indexed the same way as `walls`.
+    pub cover: Vec<bool>,
+    /// The terrain of each cell, indexed the same way as `walls`.
+    pub terrain: Vec<Terrain>,
+    /// Patrols, with waypoints in the same coordinates as `objects`.
+    pub patrols: Vec<PatrolData>,
+    /// Cell edges that agents can only cross in one direction, in the same coordinates as `objects`.
+    pub one_way: Vec<OneWayEdge>,
+    /// The direction each cell's conveyor pushes agents, if it has one. Indexed the same way as `walls`.
+    pub conveyors: Vec<Option<GridDir>>,
+    /// Fog zones, in the same coordinates as `objects`.
+    pub fog: Vec<FogData>,
+    /// Fixed cameras, in the same coordinates as `objects`.
+    pub cameras: Vec<CameraData>,
+    /// Lights, in the same coordinates as `objects`.
+    pub lights: Vec<LightData>,
+    /// How brightly lit every cell is without any lights.
+    pub ambient_light: f32,
+    /// Overrides for how agents see on this level.
+    pub vision: LevelVision,
+    pub meta: LevelMeta,
 }
 
 impl LevelLayout {
-    /// Generates a randomized level.
-    pub fn random(size: usize, wall_prob: f64, max_items: usize) -> Self {
-        let mut rng = rand::thread_rng();
+    /// Returns the coordinate conversions for this level's grid.
+    pub fn grid(&self) -> GridTransform {
+        GridTransform::new(self.width, self.height)
+    }
+
+    /// Creates a layout from loaded level data.
+    /// Level files store rows from top to bottom, so rows are flipped here.
+    pub fn from_data(level: &LoadedLevelData) -> Self {
+        let mut walls = Vec::new();
+        let mut cover = Vec::new();
+        let grid = level.grid();
+        let mut terrain = vec![Terrain::default(); level.width * level.height];
+        for &(cell, cell_terrain) in &level.terrain {
+            if let Some(idx) = grid.file_cell_idx(cell) {
+                terrain[idx] = cell_terrain;
+            }
+        }
+        let mut conveyors = vec![None; level.width * level.height];
+        for &(cell, dir) in &level.conveyors {
+            if let Some(idx) = grid.file_cell_idx(cell) {
+                conveyors[idx] = Some(dir);
+            }
+        }
+        for y in 0..level.height {
+            for x in 0..level.width {
+                let file_cell = grid.flip_y((x, y));
+                walls.push(
+                    level.walls[file_cell.1 * level.width + x] != 0
+                        || level.dynamic_walls.contains(&file_cell),
+                );
+                cover.push(level.cover.contains(&file_cell));
+            }
+        }
+        Self {
+            walls: BitGrid::from_cells(level.width, level.height, walls),
+            width: level.width,
+            height: level.height,
+            objects: level.objects.clone(),
+            player_spawn: level.player_spawn,
+            pursuer_spawn: level.pursuer_spawn,
+            player_spawn_zone: level.player_spawn_zone,
+            pursuer_spawn_zone: level.pursuer_spawn_zone,
+            min_spawn_separation: level.min_spawn_separation,
+            key_pos: level.key_pos,
+            door_pos: level.door_pos,
+            exits: level.exits.clone(),
+            exits_need_key: level.exits_need_key,
+            dynamic_walls: level.dynamic_walls.clone(),
+            vents: level.vents.clone(),
+            cover,
+            terrain,
+            patrols: level.patrols.clone(),
+            one_way: level.one_way.clone(),
+            conveyors,
+            fog: level.fog.clone(),
+            cameras: level.cameras.clone(),
+            lights: level.lights.clone(),
+            ambient_light: level.ambient_light,
+            vision: level.vision,
+            meta: level.meta.clone(),
+        }
+    }
+
+    /// Converts this layout back into the format used by level files.
+    pub fn to_data(&self) -> LoadedLevelData {
+        let mut walls = Vec::new();
+        let mut cover = Vec::new();
+        let mut terrain = Vec::new();
+        let mut conveyors = Vec::new();
+        let grid = self.grid();
+        for y in (0..self.height).rev() {
+            for x in 0..self.width {
+                let file_cell = grid.flip_y((x, y));
+                let idx = y * self.width + x;
+                let is_dynamic = self.dynamic_walls.contains(&file_cell);
+                walls.push((self.walls[idx] && !is_dynamic) as u8);
+                if self.cover[idx] {
+                    cover.push(file_cell);
+                }
+                let cell_terrain = self.terrain[idx];
+                if cell_terrain != Terrain::Normal {
+                    terrain.push((file_cell, cell_terrain));
+                }
+                if let Some(dir) = self.conveyors[idx] {
+                    conveyors.push((file_cell, dir));
+                }
+            }
+        }
+        LoadedLevelData {
+            version: LEVEL_FORMAT_VERSION,
+            width: self.width,
+            height: self.height,
+            walls,
+            objects: self.objects.clone(),
+            player_spawn: self.player_spawn,
+            pursuer_spawn: self.pursuer_spawn,
+            player_spawn_zone: self.player_spawn_zone,
+            pursuer_spawn_zone: self.pursuer_spawn_zone,
+            min_spawn_separation: self.min_spawn_separation,
+            key_pos: self.key_pos,
+            door_pos: self.door_pos,
+            exits: self.exits.clone(),
+            exits_need_key: self.exits_need_key,
+            dynamic_walls: self.dynamic_walls.clone(),
+            vents: self.vents.clone(),
+            cover,
+            terrain,
+            patrols: self.patrols.clone(),
+            one_way: self.one_way.clone(),
+            conveyors,
+            fog: self.fog.clone(),
+            cameras: self.cameras.clone(),
+            lights: self.lights.clone(),
+            ambient_light: self.ambient_light,
+            vision: self.vision,
+            meta: self.meta.clone(),
+        }
+    }
+
+    /// Generates a randomized level, drawing from the provided RNG.
+    pub fn random(
+        width: usize,
+        height: usize,
+        wall_prob: f64,
+        max_items: usize,
+        rng: &mut impl Rng,
+    ) -> Self {
+        let walls = (0..(width * height))
+            .map(|_| rng.gen_bool(wall_prob))
+            .collect();
+        Self::random_with_walls(width, height, walls, max_items, rng)
+    }
+
+    /// Generates a randomized level with walls made of obstacle shapes, drawing from the provided RNG.
+    pub fn random_obstacles(
+        width: usize,
+        height: usize,
+        params: &ObstacleParams,
+        max_items: usize,
+        rng: &mut impl Rng,
+    ) -> Self {
+        let walls = obstacle_walls(width, height, params, rng);
+        Self::random_with_walls(width, height, walls, max_items, rng)
+    }
+
+    /// Makes the level symmetric by copying half of it onto the other half, and places the agents' spawns on
+    /// opposite sides. Spawns are only placed if the two cells are connected.
+    pub fn make_symmetric(&mut self, symmetry: Symmetry, rng: &mut impl Rng) {
+        let (width, height) = (self.width, self.height);
+        let mut walls = self.walls.to_vec();
+        symmetry.apply(&mut walls, width, height);
+        self.walls = BitGrid::from_cells(width, height, walls);
+        symmetry.apply(&mut self.cover, width, height);
+        symmetry.apply(&mut self.terrain, width, height);
+
+        // Objects in the source half are kept and copied, and the rest are dropped
+        let mut objects = Vec::new();
+        for obj in &self.objects {
+            if !symmetry.is_source(obj.pos, width, height) {
+                continue;
+            }
+            objects.push(obj.clone());
+            let image_pos = symmetry.image(obj.pos, width, height);
+            if image_pos != obj.pos {
+                objects.push(LoadedObjData {
+                    pos: image_pos,
+                    dir: obj.dir.as_deref().map(|dir| symmetry.image_dir(dir)),
+                    ..obj.clone()
+                });
+            }
+        }
+        self.objects = objects;
+
+        let spawn = (0..self.walls.len())
+            .map(|i| (i % width, i / width))
+            .filter(|&cell| {
+                let image = symmetry.image(cell, width, height);
+                !self.walls[cell.1 * width + cell.0] && image != cell
+            })
+            .filter(|&cell| {
+                let (img_x, img_y) = symmetry.image(cell, width, height);
+                distance_field(self, cell)[img_y * width + img_x].is_some()
+            })
+            .choose(rng);
+        let grid = self.grid();
+        self.pursuer_spawn = spawn.map(|cell| grid.flip_y(cell));
+        self.player_spawn = spawn.map(|cell| grid.flip_y(symmetry.image(cell, width, height)));
+    }
+
+    /// Creates a level with these walls, and places up to `max_items` objects in random empty cells.
+    fn random_with_walls(
+        width: usize,
+        height: usize,
+        walls: Vec<bool>,
+        max_items: usize,
+        rng: &mut impl Rng,
+    ) -> Self {
         let orig = Self {
-            walls: (0..(size * size))
-                .map(|_| rng.gen_bool(wall_prob))
-                .collect(),
-            size,
+            walls: BitGrid::from_cells(width, height, walls),
+            width,
+            height,
             objects: Vec::new(),
+            player_spawn: None,
+            pursuer_spawn: None,
+            player_spawn_zone: None,
+            pursuer_spawn_zone: None,
+            min_spawn_separation: 0.,
+            key_pos: None,
+            door_pos: None,
+            exits: Vec::new(),
+            exits_need_key: false,
+            dynamic_walls: Vec::new(),
+            vents: Vec::new(),
+            cover: vec![false; width * height],
+            terrain: vec![Terrain::default(); width * height],
+            patrols: Vec::new(),
+            one_way: Vec::new(),
+            conveyors: vec![None; width * height],
+            fog: Vec::new(),
+            cameras: Vec::new(),
+            lights: Vec::new(),
+            ambient_light: default_ambient_light(),
+            vision: LevelVision::default(),
+            meta: LevelMeta::default(),
         };
+        let grid = orig.grid();
         let mut objects = Vec::new();
-        for _ in 0..rng.gen_range(0..max_items) {
-            let tile_idx = orig.get_empty();
-            let y = tile_idx / size;
-            let x = tile_idx % size;
+        let item_count = if max_items > 0 {
+            rng.gen_range(0..max_items)
+        } else {
+            0
+        };
+        for _ in 0..item_count {
+            // Objects are stored in level file coordinates, so flip the empty cell's row
+            let tile_idx = orig.get_empty_with(rng);
             objects.push(LoadedObjData {
                 name: "".into(),
-                pos: (x, y),
+                pos: grid.flip_y(grid.idx_cell(tile_idx)),
                 dir: Some("left".into()),
                 movable: true,
             });
         }
         Self {
             walls: orig.walls,
-            size,
             objects,
+            ..orig
+        }
+    }
+
+    /// Converts this layout into the format used by level files, with objects moved to the cells they're currently
+    /// in. This captures levels as they are during play, such as random levels worth keeping.
+    pub fn snapshot<'a>(
+        &self,
+        objects: impl IntoIterator<Item = (&'a LevelObject, &'a GlobalTransform)>,
+    ) -> LoadedLevelData {
+        let mut data = self.to_data();
+        let grid = self.grid();
+        for (obj, xform) in objects {
+            let cell = grid.world_to_cell(xform.translation().xy());
+            if let (Some(obj_data), Some(cell)) = (data.objects.get_mut(obj.0), cell) {
+                obj_data.pos = grid.flip_y(cell);
+            }
         }
+        data
+    }
+
+    /// Returns true if agents can step between these neighboring cells, which are indexed the same way as `walls`.
+    ///
+    /// Steps are blocked by one-way edges, and by conveyors in either cell pushing back against the step, since
+    /// conveyors push as fast as agents walk. Walls aren't checked here.
+    pub fn can_cross(&self, from: (usize, usize), to: (usize, usize)) -> bool {
+        let grid = self.grid();
+        let conveyor_at = |cell| grid.cell_idx(cell).and_then(|idx| self.conveyors[idx]);
+        let (from_conveyor, to_conveyor) = (conveyor_at(from), conveyor_at(to));
+        // Directions are in level file coordinates, so flip cells before comparing them
+        let (from, to) = (grid.flip_y(from), grid.flip_y(to));
+        let backwards = GridDir::between(to, from);
+        let against_conveyor =
+            backwards.is_some() && (from_conveyor == backwards || to_conveyor == backwards);
+        !against_conveyor && !self.one_way.iter().any(|edge| edge.blocks(from, to, grid))
+    }
+
+    /// Returns the only direction each one-way edge can be crossed in, as `(from, to)` pairs of cells indexed the
+    /// same way as `walls`.
+    pub fn one_way_moves(&self) -> impl Iterator<Item = ((usize, usize), (usize, usize))> + '_ {
+        let grid = self.grid();
+        self.one_way.iter().filter_map(move |edge| {
+            let target = edge.target(grid)?;
+            Some((grid.flip_y(edge.cell), grid.flip_y(target)))
+        })
+    }
+
+    /// Returns the terrain of the cell containing this world position.
+    /// Positions outside the level count as normal ground.
+    pub fn terrain_at(&self, pos: Vec2) -> Terrain {
+        let grid = self.grid();
+        grid.world_to_cell(pos)
+            .and_then(|cell| grid.cell_idx(cell))
+            .map_or(Terrain::Normal, |idx| self.terrain[idx])
+    }
+
+    /// Returns the direction the conveyor under this world position pushes agents, if there is one.
+    pub fn conveyor_at(&self, pos: Vec2) -> Option<GridDir> {
+        let grid = self.grid();
+        grid.world_to_cell(pos)
+            .and_then(|cell| grid.cell_idx(cell))
+            .and_then(|idx| self.conveyors[idx])
+    }
+
+    /// Returns how many cells into the fog observers can see for each cell, indexed the same way as `walls`.
+    /// Cells covered by several fog zones use the shortest distance, and cells without fog are `None`.
+    pub fn fog_view_dists(&self) -> Vec<Option<f32>> {
+        let grid = self.grid();
+        let mut view_dists: Vec<Option<f32>> = vec![None; self.width * self.height];
+        for fog in &self.fog {
+            for y in fog.min.1..=fog.max.1 {
+                for x in fog.min.0..=fog.max.0 {
+                    if let Some(idx) = grid.file_cell_idx((x, y)) {
+                        let dist = view_dists[idx].map_or(fog.view_dist, |d| d.min(fog.view_dist));
+                        view_dists[idx] = Some(dist);
+                    }
+                }
+            }
+        }
+        view_dists
     }
 
     /// Returns a random empty tile index.
     pub fn get_empty(&self) -> usize {
-        let mut rng = rand::thread_rng();
-        let tile_idx = self
-            .walls
+        self.get_empty_with(&mut rand::thread_rng())
+    }
+
+    /// Returns the tile indices the pursuer and player should spawn at, in that order.
+    pub fn spawn_tiles(&self) -> (usize, usize) {
+        self.spawn_tiles_with(&mut rand::thread_rng())
+    }
+
+    /// Picks where the agents spawn if they aren't fixed yet, so every game the level is sent to spawns them in the same
+    /// place. Pass a seeded RNG for the same spawns every time.
+    pub fn fix_spawns(&mut self, rng: &mut impl Rng) {
+        let grid = self.grid();
+        let (pursuer_idx, player_idx) = self.spawn_tiles_with(rng);
+        self.pursuer_spawn = Some(grid.flip_y(grid.idx_cell(pursuer_idx)));
+        self.player_spawn = Some(grid.flip_y(grid.idx_cell(player_idx)));
+    }
+
+    /// Returns the tile indices the pursuer and player should spawn at, drawing from the provided RNG.
+    ///
+    /// Fixed spawn points are used if set. Otherwise, agents spawn in a random empty cell in their spawn zone, or
+    /// anywhere in the level if they don't have one. The player is kept `min_spawn_separation` cells away from the
+    /// pursuer if possible. If every cell is a wall, agents spawn in one anyway.
+    pub fn spawn_tiles_with(&self, rng: &mut impl Rng) -> (usize, usize) {
+        // Candidates are only empty if the level has no cells, which `validate` rejects
+        let pursuer_idx = self
+            .spawn_candidates(self.pursuer_spawn, self.pursuer_spawn_zone)
+            .into_iter()
+            .choose(rng)
+            .unwrap_or_default();
+
+        let player_candidates = self.spawn_candidates(self.player_spawn, self.player_spawn_zone);
+        let cell_dist = |idx: usize| {
+            let to_cell =
+                |idx: usize| Vec2::new((idx % self.width) as f32, (idx / self.width) as f32);
+            to_cell(idx).distance(to_cell(pursuer_idx))
+        };
+        let player_idx = player_candidates
             .iter()
-            .enumerate()
-            .filter(|(_, x)| !**x)
-            .map(|(x, _)| x)
-            .choose(&mut rng)
-            .unwrap();
-        tile_idx
+            .copied()
+            .filter(|&idx| cell_dist(idx) >= self.min_spawn_separation)
+            .choose(rng)
+            .or_else(|| {
+                player_candidates
+                    .iter()
+                    .copied()
+                    .max_by(|&idx1, &idx2| cell_dist(idx1).total_cmp(&cell_dist(idx2)))
+            })
+            .unwrap_or(pursuer_idx);
+        (pursuer_idx, player_idx)
+    }
+
+    /// Returns the tile indices an agent can spawn at, given an optional spawn point and spawn zone.
+    /// If the zone has no empty cells, any empty cell can be used, and if there are none of those, any cell.
+    fn spawn_candidates(
+        &self,
+        spawn: Option<(usize, usize)>,
+        zone: Option<SpawnZone>,
+    ) -> Vec<usize> {
+        let grid = self.grid();
+        if let Some(idx) = spawn.and_then(|cell| grid.file_cell_idx(cell)) {
+            return vec![idx];
+        }
+        let empty = self.empty_or_any_cells();
+        let in_zone: Vec<usize> = match zone {
+            Some(zone) => empty
+                .iter()
+                .copied()
+                .filter(|&i| zone.contains(grid.flip_y(grid.idx_cell(i))))
+                .collect(),
+            None => Vec::new(),
+        };
+        if in_zone.is_empty() {
+            empty
+        } else {
+            in_zone
+        }
+    }
+
+    /// Returns the indices of cells that aren't walls, or of every cell if they all are, so levels that are walled in
+    /// by the editor or mutations can still be played.
+    fn empty_or_any_cells(&self) -> Vec<usize> {
+        let empty: Vec<usize> = (0..self.walls.len()).filter(|&i| !self.walls[i]).collect();
+        if empty.is_empty() {
+            (0..self.walls.len()).collect()
+        } else {
+            empty
+        }
+    }
+
+    /// Returns a random empty tile index, drawing from the provided RNG. If every cell is a wall, returns any cell.
+    pub fn get_empty_with(&self, rng: &mut impl Rng) -> usize {
+        self.empty_or_any_cells()
+            .into_iter()
+            .choose(rng)
+            .unwrap_or_default()
+    }
+
+    /// Classifies each cell by its open neighbors. The sides of the level count as walls.
+    pub fn topology(&self) -> Vec<CellTopology> {
+        let is_open = |x: isize, y: isize| {
+            x >= 0
+                && y >= 0
+                && (x as usize) < self.width
+                && (y as usize) < self.height
+                && !self.walls[y as usize * self.width + x as usize]
+        };
+        (0..self.walls.len())
+            .map(|i| {
+                if self.walls[i] {
+                    return CellTopology::Wall;
+                }
+                let (x, y) = ((i % self.width) as isize, (i / self.width) as isize);
+                let [left, right, down, up] = [
+                    is_open(x - 1, y),
+                    is_open(x + 1, y),
+                    is_open(x, y - 1),
+                    is_open(x, y + 1),
+                ];
+                match [left, right, down, up].iter().filter(|open| **open).count() {
+                    0 | 1 => CellTopology::DeadEnd,
+                    2 if (left && right) || (down && up) => CellTopology::Corridor,
+                    2 => CellTopology::Corner,
+                    _ => CellTopology::Open,
+                }
+            })
+            .collect()
     }
 }
 
+/// The ground in a cell, which affects how agents move and how much noise they make.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+#[repr(u8)]
+pub enum Terrain {
+    #[default]
+    Normal = 0,
+    /// Slows agents down.
+    Mud = 1,
+    /// Makes noise when agents walk over it.
+    Gravel = 2,
+    /// Stops agents from setting off noise sources.
+    Carpet = 3,
+}
+
+impl Terrain {
+    /// Multiplier for how fast agents move on this terrain.
+    pub fn speed_scale(self) -> f32 {
+        match self {
+            Self::Mud => 0.5,
+            _ => 1.,
+        }
+    }
+
+    /// The color used to draw this terrain.
+    pub fn color(self) -> Color {
+        match self {
+            Self::Normal => Color::WHITE,
+            Self::Mud => Color::rgb(0.4, 0.26, 0.13),
+            Self::Gravel => Color::GRAY,
+            Self::Carpet => Color::rgb(0.5, 0.1, 0.2),
+        }
+    }
+}
+
+/// The shape of the space around a cell, based on which of its four neighbors are walls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum CellTopology {
+    Wall = 0,
+    /// Zero or one open neighbors.
+    DeadEnd = 1,
+    /// Two open neighbors on opposite sides.
+    Corridor = 2,
+    /// Two open neighbors on adjacent sides.
+    Corner = 3,
+    /// Three or more open neighbors.
+    Open = 4,
+}
+
+/// Stores the topology of every cell in the level, indexed the same way as `LevelLayout::walls`.
+#[derive(Resource, Clone)]
+pub struct LevelTopology(pub Vec<CellTopology>);
+
+/// Recomputes cell topology whenever the level's walls change.
+fn update_topology(mut commands: Commands, level: Res<LevelLayout>) {
+    commands.insert_resource(LevelTopology(level.topology()));
+}
+
 /// State used by all agents.
 #[derive(Component, Clone, Copy)]
 pub struct Agent {
@@ -235,62 +1421,116 @@ pub struct PursuerAgent;
 #[derive(Component)]
 pub struct PlayerAgent;
 
+/// Indicates an agent controlled from somewhere else, like another player's game, so the keyboard doesn't move it.
+#[derive(Component)]
+pub struct RemoteControlled;
+
 /// The child of an `Agent` that contains its visuals.
 #[derive(Component)]
 pub struct AgentVisuals;
 
+/// A stable ID for an entity in the level.
+///
+/// Unlike `Entity`, these don't depend on what was spawned before the level was set up, so they're the same every
+/// time a level is played.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct GameId(pub u64);
+
+/// Marks top level entities that belong to the current level, so they can be torn down.
+#[derive(Component)]
+pub struct LevelEntity;
+
+/// Marks objects spawned from the level, storing their index in `LevelLayout::objects`.
+#[derive(Component)]
+pub struct LevelObject(pub usize);
+
+/// Despawns the current level and stops the game, so a new `LevelLayout` can be inserted.
+pub fn teardown_level(mut commands: Commands, level_query: Query<Entity, With<LevelEntity>>) {
+    for e in level_query.iter() {
+        commands.entity(e).despawn_recursive();
+    }
+    commands.remove_resource::<LevelLayout>();
+    commands.remove_resource::<LevelMeta>();
+    commands.remove_resource::<ShouldRun>();
+}
+
 /// Sets up all entities in the game.
-fn setup_entities(
+pub fn setup_entities(
     mut commands: Commands,
     level: Res<LevelLayout>,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
     asset_server: Res<AssetServer>,
     is_playable: Option<Res<IsPlayable>>,
+    gadget_config: Res<GadgetConfig>,
+    vision_config: Res<VisionConfig>,
+    mut spawn_rng: ResMut<SpawnRng>,
 ) {
+    let grid = level.grid();
+    let pursuer_vision = level.vision.pursuer.unwrap_or(vision_config.pursuer);
+    let player_vision = level.vision.player.unwrap_or(vision_config.player);
+
+    // IDs are assigned in spawn order, so they're the same every time this level is set up
+    let mut next_game_id = 0;
+    let mut game_id = || {
+        next_game_id += 1;
+        GameId(next_game_id - 1)
+    };
+
     // Add camera + light
-    commands.spawn(Camera3dBundle {
-        transform: Transform::from_translation(Vec3::new(
-            GRID_CELL_SIZE * (((level.size + 1) / 2) as f32),
-            -300.,
-            700.,
-        ))
-        .with_rotation(Quat::from_rotation_x(0.5)),
-        projection: Projection::Perspective(PerspectiveProjection {
-            fov: 0.4,
+    commands.spawn((
+        LevelEntity,
+        Camera3dBundle {
+            transform: Transform::from_translation(Vec3::new(
+                GRID_CELL_SIZE * (((level.width + 1) / 2) as f32),
+                -300.,
+                700.,
+            ))
+            .with_rotation(Quat::from_rotation_x(0.5)),
+            projection: Projection::Perspective(PerspectiveProjection {
+                fov: 0.4,
+                ..default()
+            }),
             ..default()
-        }),
-        ..default()
-    });
-    commands.spawn(DirectionalLightBundle {
-        directional_light: DirectionalLight {
-            illuminance: 2000.,
+        },
+    ));
+    commands.spawn((
+        LevelEntity,
+        DirectionalLightBundle {
+            directional_light: DirectionalLight {
+                illuminance: 2000.,
+                ..default()
+            },
+            transform: Transform::from_rotation(Quat::from_rotation_x(PI / 4.)),
             ..default()
         },
-        transform: Transform::from_rotation(Quat::from_rotation_x(PI / 4.)),
-        ..default()
-    });
+    ));
 
-    let pursuer_tile_idx = level.get_empty();
+    let (pursuer_tile_idx, player_tile_idx) = level.spawn_tiles_with(&mut spawn_rng.0);
     commands
         .spawn((
+            LevelEntity,
+            game_id(),
             PursuerAgent,
             Agent::default(),
             NextAction::default(),
-            Collider::ball(GRID_CELL_SIZE * 0.25),
+            Collider::ball(AGENT_RADIUS),
             RigidBody::KinematicPositionBased,
             KinematicCharacterController::default(),
             TransformBundle::from_transform(Transform::from_translation(
-                Vec3::new(
-                    (pursuer_tile_idx % level.size) as f32,
-                    (pursuer_tile_idx / level.size) as f32,
-                    0.,
-                ) * GRID_CELL_SIZE,
+                grid.cell_to_world(grid.idx_cell(pursuer_tile_idx))
+                    .extend(0.),
             )),
-            Observer::default(),
+            Observer::new(pursuer_vision),
             Observable,
             DebugObserver,
+            Radio::default(),
+            CameraFeed::default(),
+            GadgetEnergy {
+                current: gadget_config.max_energy,
+            },
         ))
+        .insert((Awareness::default(), Beliefs::default()))
         .with_children(|p| {
             if is_playable.is_some() {
                 p.spawn((
@@ -305,26 +1545,25 @@ fn setup_entities(
                 ));
             }
         });
-    let player_tile_idx = level.get_empty();
     commands
         .spawn((
+            LevelEntity,
+            game_id(),
             PlayerAgent,
             Agent::default(),
             NextAction::default(),
-            Collider::ball(GRID_CELL_SIZE * 0.25),
+            Collider::ball(AGENT_RADIUS),
             RigidBody::KinematicPositionBased,
             KinematicCharacterController::default(),
             TransformBundle::from_transform(Transform::from_translation(
-                Vec3::new(
-                    (player_tile_idx % level.size) as f32,
-                    (player_tile_idx / level.size) as f32,
-                    0.,
-                ) * GRID_CELL_SIZE,
+                grid.cell_to_world(grid.idx_cell(player_tile_idx))
+                    .extend(0.),
             )),
-            Observer::default(),
+            Observer::new(player_vision),
             Observable,
             DebugObserver,
         ))
+        .insert(Beliefs::default())
         .with_children(|p| {
             if is_playable.is_some() {
                 p.spawn((
@@ -341,14 +1580,19 @@ fn setup_entities(
         });
 
     // Add floor
-    commands.spawn(SceneBundle {
-        scene: asset_server.load("furniture/floorFull.glb#Scene0"),
-        transform: Transform::default()
-            .with_translation(Vec3::new(-1., -1., 0.) * GRID_CELL_SIZE / 2.)
-            .with_rotation(Quat::from_rotation_x(std::f32::consts::PI / 2.))
-            .with_scale(Vec3::new(level.size as f32, 1., level.size as f32) * GRID_CELL_SIZE),
-        ..default()
-    });
+    commands.spawn((
+        LevelEntity,
+        SceneBundle {
+            scene: asset_server.load("furniture/floorFull.glb#Scene0"),
+            transform: Transform::default()
+                .with_translation(Vec3::new(-1., -1., 0.) * GRID_CELL_SIZE / 2.)
+                .with_rotation(Quat::from_rotation_x(std::f32::consts::PI / 2.))
+                .with_scale(
+                    Vec3::new(level.width as f32, 1., level.height as f32) * GRID_CELL_SIZE,
+                ),
+            ..default()
+        },
+    ));
 
     // Set up walls and doors
     let wall_mesh = meshes.add(Cuboid::new(GRID_CELL_SIZE, GRID_CELL_SIZE, GRID_CELL_SIZE));
@@ -358,15 +1602,18 @@ fn setup_entities(
         ..default()
     });
     let mut rng = rand::thread_rng();
-    for y in 0..level.size {
-        for x in 0..level.size {
-            if level.walls[y * level.size + x] {
+    for y in 0..level.height {
+        for x in 0..level.width {
+            // Dynamic walls are spawned separately
+            let is_dynamic = level.dynamic_walls.contains(&grid.flip_y((x, y)));
+            if level.walls[y * level.width + x] && !is_dynamic {
                 commands
                     .spawn((
+                        LevelEntity,
                         Wall,
                         Collider::cuboid(GRID_CELL_SIZE / 2., GRID_CELL_SIZE / 2.),
                         TransformBundle::from_transform(Transform::from_translation(
-                            Vec3::new(x as f32, y as f32, 0.) * GRID_CELL_SIZE,
+                            grid.cell_to_world((x, y)).extend(0.),
                         )),
                         VisibilityBundle::default(),
                     ))
@@ -379,15 +1626,15 @@ fn setup_entities(
                                 .with_scale(Vec3::ONE * GRID_CELL_SIZE);
                             for (i, offset) in offsets.iter().enumerate() {
                                 let should_spawn = match i {
-                                    3 => (y > 0) && !level.walls[(y - 1) * level.size + x],
+                                    3 => (y > 0) && !level.walls[(y - 1) * level.width + x],
                                     2 => {
-                                        (y < level.size - 1)
-                                            && !level.walls[(y + 1) * level.size + x]
+                                        (y < level.height - 1)
+                                            && !level.walls[(y + 1) * level.width + x]
                                     }
-                                    1 => (x > 0) && !level.walls[y * level.size + (x - 1)],
+                                    1 => (x > 0) && !level.walls[y * level.width + (x - 1)],
                                     0 => {
-                                        (x < level.size - 1)
-                                            && !level.walls[y * level.size + (x + 1)]
+                                        (x < level.width - 1)
+                                            && !level.walls[y * level.width + (x + 1)]
                                     }
                                     _ => unreachable!(),
                                 };
@@ -430,18 +1677,23 @@ fn setup_entities(
     }
 
     // Set up the sides of the game world
-    let half_sizes = [GRID_CELL_SIZE / 2., GRID_CELL_SIZE * level.size as f32 / 2.];
-    let wall_positions = [-GRID_CELL_SIZE, GRID_CELL_SIZE * level.size as f32];
-    let wall_pos_offset = GRID_CELL_SIZE * (level.size - 1) as f32 / 2.;
+    // The first two sides are the left and right, the last two are the bottom and top
+    let level_dims = [level.width, level.height];
     for i in 0..4 {
+        let axis = i / 2;
+        let side_len = level_dims[1 - axis];
+        let half_sizes = [GRID_CELL_SIZE / 2., GRID_CELL_SIZE * side_len as f32 / 2.];
+        let wall_positions = [-GRID_CELL_SIZE, GRID_CELL_SIZE * level_dims[axis] as f32];
+        let wall_pos_offset = GRID_CELL_SIZE * (side_len - 1) as f32 / 2.;
         let positions = [wall_positions[i % 2], wall_pos_offset];
         commands
             .spawn((
+                LevelEntity,
                 Wall,
-                Collider::cuboid(half_sizes[i / 2], half_sizes[1 - i / 2]),
+                Collider::cuboid(half_sizes[axis], half_sizes[1 - axis]),
                 TransformBundle::from_transform(Transform::from_translation(Vec3::new(
-                    positions[i / 2],
-                    positions[1 - i / 2],
+                    positions[axis],
+                    positions[1 - axis],
                     0.,
                 ))),
                 VisibilityBundle::default(),
@@ -450,9 +1702,9 @@ fn setup_entities(
                 if is_playable.is_some() {
                     let offsets = [Vec3::X, -Vec3::X, Vec3::Y, -Vec3::Y];
                     let base_xform = Transform::default()
-                        .with_translation(-Vec3::X * GRID_CELL_SIZE * level.size as f32 / 2.)
+                        .with_translation(-Vec3::X * GRID_CELL_SIZE * side_len as f32 / 2.)
                         .with_rotation(Quat::from_rotation_x(std::f32::consts::PI / 2.))
-                        .with_scale(Vec3::new(level.size as f32, 1., 1.) * GRID_CELL_SIZE);
+                        .with_scale(Vec3::new(side_len as f32, 1., 1.) * GRID_CELL_SIZE);
                     let rot = if i >= 2 {
                         Quat::IDENTITY
                     } else {
@@ -469,8 +1721,8 @@ fn setup_entities(
                 } else {
                     p.spawn(PbrBundle {
                         mesh: meshes.add(Cuboid::new(
-                            half_sizes[i / 2] * 2.,
-                            half_sizes[1 - i / 2] * 2.,
+                            half_sizes[axis] * 2.,
+                            half_sizes[1 - axis] * 2.,
                             GRID_CELL_SIZE,
                         )),
                         material: wall_mat.clone(),
@@ -486,12 +1738,14 @@ fn setup_entities(
         unlit: true,
         ..default()
     });
-    for obj in &level.objects {
-        let (x, y) = obj.pos;
-        let pos = Vec3::new(x as f32, (level.size - y - 1) as f32, 0.) * GRID_CELL_SIZE;
+    for (i, obj) in level.objects.iter().enumerate() {
+        let pos = grid.file_cell_to_world(obj.pos).extend(0.);
         let collider_size = GRID_CELL_SIZE * 0.8;
         let e = commands
             .spawn((
+                LevelEntity,
+                LevelObject(i),
+                game_id(),
                 Collider::cuboid(collider_size / 2., collider_size / 2.),
                 TransformBundle::from_transform(Transform::from_translation(pos)),
                 VisibilityBundle::default(),
@@ -512,6 +1766,7 @@ fn setup_entities(
                 },
                 VisualMarker,
                 Observable,
+                OccludesVision,
             ));
         }
         commands.entity(e).with_children(|p| {
@@ -520,12 +1775,17 @@ fn setup_entities(
                     .with_translation(-Vec3::X * GRID_CELL_SIZE / 2.)
                     .with_rotation(Quat::from_rotation_x(std::f32::consts::PI / 2.))
                     .with_scale(Vec3::ONE * GRID_CELL_SIZE * 2.);
-                let rot = match obj.dir.clone().unwrap_or("left".into()).as_str() {
-                    "left" => Quat::from_rotation_z(std::f32::consts::PI * 3. / 2.),
+                // Levels from the editor and generators aren't validated, so unknown directions face left
+                let rot = match obj.dir.as_deref().unwrap_or("left") {
                     "up" => Quat::from_rotation_z(std::f32::consts::PI),
                     "right" => Quat::from_rotation_z(std::f32::consts::PI / 2.),
                     "down" => Quat::IDENTITY,
-                    _ => unimplemented!(),
+                    dir => {
+                        if dir != "left" {
+                            warn!("Unknown direction \"{dir}\" for object \"{}\"", obj.name);
+                        }
+                        Quat::from_rotation_z(std::f32::consts::PI * 3. / 2.)
+                    }
                 };
                 p.spawn(SceneBundle {
                     scene: asset_server.load(format!("furniture/{}.glb#Scene0", obj.name)),
@@ -542,12 +1802,453 @@ fn setup_entities(
         });
     }
 
+    // Add the key and the exit it unlocks
+    let cell_pos = |cell| grid.file_cell_to_world(cell).extend(0.);
+    if let Some(key_pos) = level.key_pos {
+        commands.spawn((
+            LevelEntity,
+            game_id(),
+            Key,
+            PbrBundle {
+                mesh: meshes.add(Cuboid::new(
+                    GRID_CELL_SIZE * 0.3,
+                    GRID_CELL_SIZE * 0.3,
+                    GRID_CELL_SIZE * 0.3,
+                )),
+                material: materials.add(StandardMaterial {
+                    base_color: Color::YELLOW,
+                    unlit: true,
+                    ..default()
+                }),
+                transform: Transform::from_translation(cell_pos(key_pos)),
+                ..default()
+            },
+        ));
+    }
+    if let Some(door_pos) = level.door_pos {
+        commands.spawn((
+            LevelEntity,
+            game_id(),
+            ExitDoor::default(),
+            Wall,
+            Collider::cuboid(GRID_CELL_SIZE / 2., GRID_CELL_SIZE / 2.),
+            PbrBundle {
+                mesh: wall_mesh.clone(),
+                material: materials.add(StandardMaterial {
+                    base_color: Color::MAROON,
+                    unlit: true,
+                    ..default()
+                }),
+                transform: Transform::from_translation(
+                    cell_pos(door_pos) + Vec3::Z * GRID_CELL_SIZE / 2.,
+                ),
+                ..default()
+            },
+        ));
+    }
+
+    // Add walls that can be opened and closed
+    let dynamic_wall_mat = materials.add(StandardMaterial {
+        base_color: Color::DARK_GRAY,
+        unlit: true,
+        ..default()
+    });
+    for &(x, y) in &level.dynamic_walls {
+        commands.spawn((
+            LevelEntity,
+            game_id(),
+            DynamicWall {
+                cell_idx: grid.file_cell_idx((x, y)).unwrap(),
+                open: false,
+            },
+            Wall,
+            Collider::cuboid(GRID_CELL_SIZE / 2., GRID_CELL_SIZE / 2.),
+            PbrBundle {
+                mesh: wall_mesh.clone(),
+                material: dynamic_wall_mat.clone(),
+                transform: Transform::from_translation(
+                    cell_pos((x, y)) + Vec3::Z * GRID_CELL_SIZE / 2.,
+                ),
+                ..default()
+            },
+        ));
+    }
+
+    // Add terrain as flat tiles on the floor
+    let terrain_mesh = meshes.add(Cuboid::new(GRID_CELL_SIZE, GRID_CELL_SIZE, 0.1));
+    for terrain in [Terrain::Mud, Terrain::Gravel, Terrain::Carpet] {
+        let terrain_mat = materials.add(StandardMaterial {
+            base_color: terrain.color(),
+            unlit: true,
+            ..default()
+        });
+        for (i, _) in level
+            .terrain
+            .iter()
+            .enumerate()
+            .filter(|(_, &cell_terrain)| cell_terrain == terrain)
+        {
+            commands.spawn((
+                LevelEntity,
+                PbrBundle {
+                    mesh: terrain_mesh.clone(),
+                    material: terrain_mat.clone(),
+                    transform: Transform::from_translation(
+                        grid.cell_to_world(grid.idx_cell(i)).extend(0.),
+                    ),
+                    ..default()
+                },
+            ));
+        }
+    }
+
+    // Add conveyors as tiles with an arrow pointing the way they push
+    let conveyor_mat = materials.add(StandardMaterial {
+        base_color: Color::TEAL,
+        unlit: true,
+        ..default()
+    });
+    let arrow_mat = materials.add(StandardMaterial {
+        base_color: Color::WHITE,
+        unlit: true,
+        ..default()
+    });
+    let arrow_mesh = meshes.add(Triangle2d::new(
+        Vec2::new(0.3, 0.) * GRID_CELL_SIZE,
+        Vec2::new(-0.2, 0.25) * GRID_CELL_SIZE,
+        Vec2::new(-0.2, -0.25) * GRID_CELL_SIZE,
+    ));
+    for (i, dir) in level
+        .conveyors
+        .iter()
+        .enumerate()
+        .filter_map(|(i, dir)| Some((i, (*dir)?)))
+    {
+        let dir = dir.to_world();
+        commands
+            .spawn((
+                LevelEntity,
+                PbrBundle {
+                    mesh: terrain_mesh.clone(),
+                    material: conveyor_mat.clone(),
+                    transform: Transform::from_translation(
+                        grid.cell_to_world(grid.idx_cell(i)).extend(0.),
+                    ),
+                    ..default()
+                },
+            ))
+            .with_children(|p| {
+                p.spawn(PbrBundle {
+                    mesh: arrow_mesh.clone(),
+                    material: arrow_mat.clone(),
+                    transform: Transform::from_translation(Vec3::Z * 0.06)
+                        .with_rotation(Quat::from_rotation_z(dir.y.atan2(dir.x))),
+                    ..default()
+                });
+            });
+    }
+
+    // Add cover, which blocks vision without blocking movement
+    let cover_mat = materials.add(StandardMaterial {
+        base_color: Color::DARK_GREEN,
+        unlit: true,
+        ..default()
+    });
+    let cover_mesh = meshes.add(Cuboid::new(
+        GRID_CELL_SIZE,
+        GRID_CELL_SIZE,
+        GRID_CELL_SIZE * 0.5,
+    ));
+    for y in 0..level.height {
+        for x in 0..level.width {
+            if level.cover[y * level.width + x] {
+                commands.spawn((
+                    LevelEntity,
+                    Cover,
+                    Wall,
+                    Sensor,
+                    Collider::cuboid(GRID_CELL_SIZE / 2., GRID_CELL_SIZE / 2.),
+                    PbrBundle {
+                        mesh: cover_mesh.clone(),
+                        material: cover_mat.clone(),
+                        transform: Transform::from_translation(
+                            grid.cell_to_world((x, y)).extend(GRID_CELL_SIZE * 0.25),
+                        ),
+                        ..default()
+                    },
+                ));
+            }
+        }
+    }
+
+    // Add fog, which limits how far observers can see into it
+    let fog_mat = materials.add(StandardMaterial {
+        base_color: Color::rgba(0.8, 0.8, 0.8, 0.5),
+        alpha_mode: AlphaMode::Blend,
+        unlit: true,
+        ..default()
+    });
+    for fog in &level.fog {
+        let rect = fog.world_rect(grid);
+        commands.spawn((
+            LevelEntity,
+            Fog {
+                rect,
+                view_dist: fog.view_dist * GRID_CELL_SIZE,
+            },
+            PbrBundle {
+                mesh: meshes.add(Cuboid::new(
+                    rect.width(),
+                    rect.height(),
+                    GRID_CELL_SIZE * 0.5,
+                )),
+                material: fog_mat.clone(),
+                transform: Transform::from_translation(rect.center().extend(GRID_CELL_SIZE * 0.25)),
+                ..default()
+            },
+        ));
+    }
+
+    // Add vents, which make noise when agents use them
+    let vent_mat = materials.add(StandardMaterial {
+        base_color: Color::SILVER,
+        unlit: true,
+        ..default()
+    });
+    let vent_mesh = meshes.add(Cuboid::new(
+        GRID_CELL_SIZE * 0.8,
+        GRID_CELL_SIZE * 0.8,
+        GRID_CELL_SIZE * 0.05,
+    ));
+    for &ends in &level.vents {
+        for (i, &end) in ends.iter().enumerate() {
+            commands.spawn((
+                LevelEntity,
+                game_id(),
+                Vent {
+                    exit: cell_pos(ends[1 - i]).xy(),
+                },
+                NoiseSource {
+                    noise_radius: GRID_CELL_SIZE * 3.,
+                    active_radius: GRID_CELL_SIZE * 0.5,
+                    activated_by: None,
+                },
+                PbrBundle {
+                    mesh: vent_mesh.clone(),
+                    material: vent_mat.clone(),
+                    transform: Transform::from_translation(cell_pos(end)),
+                    ..default()
+                },
+            ));
+        }
+    }
+
+    // Add patrols, which block vision like walls but move
+    let patrol_mat = materials.add(StandardMaterial {
+        base_color: Color::ORANGE,
+        unlit: true,
+        ..default()
+    });
+    let patrol_size = GRID_CELL_SIZE * 0.8;
+    let patrol_mesh = meshes.add(Cuboid::new(patrol_size, patrol_size, patrol_size));
+    for patrol in &level.patrols {
+        let waypoints: Vec<_> = patrol
+            .waypoints
+            .iter()
+            .map(|&cell| cell_pos(cell).xy())
+            .collect();
+        let Some(&start) = waypoints.first() else {
+            continue;
+        };
+        commands.spawn((
+            LevelEntity,
+            game_id(),
+            Patrol {
+                next: 1 % waypoints.len(),
+                speed: patrol.speed * GRID_CELL_SIZE,
+                waypoints,
+            },
+            Wall,
+            RigidBody::KinematicPositionBased,
+            Collider::cuboid(patrol_size / 2., patrol_size / 2.),
+            PbrBundle {
+                mesh: patrol_mesh.clone(),
+                material: patrol_mat.clone(),
+                transform: Transform::from_translation(start.extend(patrol_size / 2.)),
+                ..default()
+            },
+        ));
+    }
+
+    // Add open exits
+    let exit_mat = materials.add(StandardMaterial {
+        base_color: Color::LIME_GREEN,
+        unlit: true,
+        ..default()
+    });
+    let exit_mesh = meshes.add(Cuboid::new(GRID_CELL_SIZE, GRID_CELL_SIZE, 0.2));
+    for &exit_pos in &level.exits {
+        commands.spawn((
+            LevelEntity,
+            Exit {
+                needs_key: level.exits_need_key,
+            },
+            PbrBundle {
+                mesh: exit_mesh.clone(),
+                material: exit_mat.clone(),
+                transform: Transform::from_translation(cell_pos(exit_pos)),
+                ..default()
+            },
+        ));
+    }
+
+    // Add fixed cameras, which pass what they see to the pursuer, and radio it when they see the player
+    let camera_mat = materials.add(StandardMaterial {
+        base_color: Color::GRAY,
+        unlit: true,
+        ..default()
+    });
+    let camera_mesh = meshes.add(Sphere::new(GRID_CELL_SIZE * 0.2));
+    for camera in &level.cameras {
+        commands.spawn((
+            LevelEntity,
+            StationaryCamera {
+                facing: camera.cone.map_or(Vec2::X, |cone| cone.dir.to_world()),
+            },
+            match camera.cone {
+                Some(cone) => Observer::new(VisionParams {
+                    fov_degrees: cone.fov_degrees,
+                    max_range: Some(camera.range),
+                }),
+                None => Observer::omni(camera.range),
+            },
+            DebugObserver,
+            Radio::default(),
+            PbrBundle {
+                mesh: camera_mesh.clone(),
+                material: camera_mat.clone(),
+                transform: Transform::from_translation(
+                    grid.file_cell_to_world(camera.pos)
+                        .extend(GRID_CELL_SIZE * 0.8),
+                ),
+                ..default()
+            },
+        ));
+    }
+
+    // Add lights, which let agents notice things from further away in dark levels
+    let light_mat = materials.add(StandardMaterial {
+        base_color: Color::YELLOW,
+        unlit: true,
+        ..default()
+    });
+    let light_mesh = meshes.add(Sphere::new(GRID_CELL_SIZE * 0.15));
+    for light in &level.lights {
+        commands.spawn((
+            LevelEntity,
+            LightSource {
+                radius: light.radius * GRID_CELL_SIZE,
+                intensity: light.intensity,
+            },
+            PbrBundle {
+                mesh: light_mesh.clone(),
+                material: light_mat.clone(),
+                transform: Transform::from_translation(
+                    grid.file_cell_to_world(light.pos)
+                        .extend(GRID_CELL_SIZE * 0.8),
+                ),
+                ..default()
+            },
+        ));
+    }
+
     // Indicate we should start the game
+    commands.remove_resource::<LevelComplete>();
+    commands.insert_resource(RadioChannel::default());
+    commands.insert_resource(level.meta.clone());
     commands.insert_resource(ShouldRun);
 }
 
 pub const GRID_CELL_SIZE: f32 = 25.;
 
+/// The radius of an agent's collider.
+pub const AGENT_RADIUS: f32 = GRID_CELL_SIZE * 0.25;
+
+/// Converts between world positions, cells, and cell indices in a level.
+///
+/// Cells are `(x, y)` pairs with rows from bottom to top, indexed the same way as `LevelLayout::walls`. Level files
+/// store rows from top to bottom, so cells read from them (e.g. `LevelLayout::objects`) must be flipped with
+/// `flip_y` first. Cell centers lie on multiples of `GRID_CELL_SIZE`, with the bottom left cell centered on the origin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GridTransform {
+    pub width: usize,
+    pub height: usize,
+}
+
+impl GridTransform {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self { width, height }
+    }
+
+    /// Returns the cell containing this world position, if it's inside the level.
+    pub fn world_to_cell(&self, pos: Vec2) -> Option<(usize, usize)> {
+        let cell = (pos / GRID_CELL_SIZE).round();
+        if cell.x < 0. || cell.y < 0. {
+            return None;
+        }
+        let cell = (cell.x as usize, cell.y as usize);
+        self.in_bounds(cell).then_some(cell)
+    }
+
+    /// Returns the cell containing this world position, or the closest cell to it if it's outside the level.
+    pub fn world_to_cell_clamped(&self, pos: Vec2) -> (usize, usize) {
+        let cell = (pos / GRID_CELL_SIZE).round();
+        (
+            (cell.x.max(0.) as usize).min(self.width - 1),
+            (cell.y.max(0.) as usize).min(self.height - 1),
+        )
+    }
+
+    /// Returns the world position of the center of this cell.
+    pub fn cell_to_world(&self, (x, y): (usize, usize)) -> Vec2 {
+        Vec2::new(x as f32, y as f32) * GRID_CELL_SIZE
+    }
+
+    /// Returns true if the cell is inside the level.
+    pub fn in_bounds(&self, (x, y): (usize, usize)) -> bool {
+        x < self.width && y < self.height
+    }
+
+    /// Returns the index of this cell in per-cell arrays like `LevelLayout::walls`, if it's inside the level.
+    pub fn cell_idx(&self, cell: (usize, usize)) -> Option<usize> {
+        self.in_bounds(cell).then_some(cell.1 * self.width + cell.0)
+    }
+
+    /// Returns the cell at this index in per-cell arrays.
+    pub fn idx_cell(&self, idx: usize) -> (usize, usize) {
+        (idx % self.width, idx / self.width)
+    }
+
+    /// Converts a cell between level file coordinates and layout coordinates.
+    /// Flipping twice returns the original cell.
+    pub fn flip_y(&self, (x, y): (usize, usize)) -> (usize, usize) {
+        (x, self.height - y - 1)
+    }
+
+    /// Returns the world position of the center of a cell given in level file coordinates.
+    pub fn file_cell_to_world(&self, cell: (usize, usize)) -> Vec2 {
+        self.cell_to_world(self.flip_y(cell))
+    }
+
+    /// Returns the index in per-cell arrays of a cell given in level file coordinates.
+    pub fn file_cell_idx(&self, cell: (usize, usize)) -> Option<usize> {
+        self.in_bounds(cell).then(|| {
+            let (x, y) = self.flip_y(cell);
+            y * self.width + x
+        })
+    }
+}
+
 /// Adds a visual to newly created agents.
 fn visualize_agent<T: Component>(
     color: Color,
@@ -575,6 +2276,35 @@ fn visualize_agent<T: Component>(
 
 const AGENT_SPEED: f32 = GRID_CELL_SIZE * 2.;
 
+/// Configures how fast each agent walks, as a multiple of the usual speed.
+///
+/// Conveyors only move agents exactly one cell per step in library builds if agents walk at the usual speed.
+#[derive(Resource, Clone, Copy)]
+pub struct SpeedConfig {
+    pub pursuer: f32,
+    pub player: f32,
+}
+
+impl Default for SpeedConfig {
+    fn default() -> Self {
+        Self {
+            pursuer: 1.,
+            player: 1.,
+        }
+    }
+}
+
+impl SpeedConfig {
+    /// Returns true if both speeds are positive.
+    pub fn is_valid(&self) -> bool {
+        self.pursuer > 0. && self.player > 0.
+    }
+}
+
+/// How fast conveyors push agents. This matches walking speed, so with the fixed timestep used by library builds,
+/// conveyors move agents one cell per step, and agents can't walk against them.
+pub const CONVEYOR_SPEED: f32 = AGENT_SPEED;
+
 /// Holds the next action for an agent.
 #[derive(Default, Component)]
 pub struct NextAction {
@@ -582,56 +2312,146 @@ pub struct NextAction {
     pub dir: Vec2,
     /// Whether the agent should toggle nearby objects this frame.
     pub toggle_objs: bool,
+    /// A gadget the agent wants to use this frame, if it has the energy.
+    pub gadget: Option<Gadget>,
+}
+
+/// Where levels saved during play are written, relative to the working directory.
+/// `{}` is replaced with the lowest number that doesn't overwrite an existing level.
+const SAVED_LEVEL_PATH: &str = "assets/levels/saved_{}.json";
+
+/// Saves the current level, including where objects have been pushed to, when F5 is pressed.
+fn save_level_hotkey(
+    inpt: Res<ButtonInput<KeyCode>>,
+    level: Res<LevelLayout>,
+    obj_query: Query<(&LevelObject, &GlobalTransform)>,
+) {
+    if !inpt.just_pressed(KeyCode::F5) {
+        return;
+    }
+    let path = (0..)
+        .map(|i| SAVED_LEVEL_PATH.replace("{}", &i.to_string()))
+        .find(|path| !std::path::Path::new(path).exists())
+        .unwrap();
+    let json = serde_json::to_string_pretty(&level.snapshot(&obj_query))
+        .expect("level data should always serialize");
+    match std::fs::write(&path, json) {
+        Ok(()) => info!("Saved level to {path}"),
+        Err(err) => error!("Could not save level to {path}: {err}"),
+    }
+}
+
+/// The keys used to control agents.
+#[derive(Resource, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyBindings {
+    pub up: KeyCode,
+    pub down: KeyCode,
+    pub left: KeyCode,
+    pub right: KeyCode,
+    /// Toggles nearby objects, like doors.
+    pub toggle_objs: KeyCode,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            up: KeyCode::KeyW,
+            down: KeyCode::KeyS,
+            left: KeyCode::KeyA,
+            right: KeyCode::KeyD,
+            toggle_objs: KeyCode::KeyF,
+        }
+    }
 }
 
-/// Allows the player to set the Players next action.
-fn set_player_action(
+/// Allows the player to set the next action of the agent marked with `T`, unless a policy or someone else is playing
+/// as it.
+pub fn set_keyboard_action<T: Component>(
     inpt: Res<ButtonInput<KeyCode>>,
-    mut player_query: Query<&mut NextAction, With<PlayerAgent>>,
+    bindings: Res<KeyBindings>,
+    mut agent_query: Query<
+        &mut NextAction,
+        (With<T>, Without<PolicyRunner>, Without<RemoteControlled>),
+    >,
 ) {
+    let Ok(mut next_action) = agent_query.get_single_mut() else {
+        return;
+    };
     let mut dir = Vec2::ZERO;
-    if inpt.pressed(KeyCode::KeyW) {
+    if inpt.pressed(bindings.up) {
         dir.y += 1.;
     }
-    if inpt.pressed(KeyCode::KeyS) {
+    if inpt.pressed(bindings.down) {
         dir.y -= 1.;
     }
-    if inpt.pressed(KeyCode::KeyA) {
+    if inpt.pressed(bindings.left) {
         dir.x -= 1.;
     }
-    if inpt.pressed(KeyCode::KeyD) {
+    if inpt.pressed(bindings.right) {
         dir.x += 1.;
     }
-    let mut next_action = player_query.single_mut();
     next_action.dir = dir;
     next_action.toggle_objs = false;
-    if inpt.just_pressed(KeyCode::KeyF) {
+    if inpt.just_pressed(bindings.toggle_objs) {
         next_action.toggle_objs = true;
     }
 }
 
 /// Moves agents around.
 pub fn move_agents(
-    mut agent_query: Query<(
-        Entity,
-        &mut Agent,
-        &mut KinematicCharacterController,
-        &NextAction,
-        &Children,
-    )>,
+    mut agent_query: Query<
+        (
+            Entity,
+            &mut Agent,
+            &mut KinematicCharacterController,
+            &NextAction,
+            &Children,
+            &GlobalTransform,
+            Has<Sprinting>,
+            Option<&Awareness>,
+            Has<PursuerAgent>,
+        ),
+        Without<InVent>,
+    >,
     child_query: Query<(Entity, Option<&Name>, Option<&Children>)>,
     mut vis_query: Query<&mut Transform, With<AgentVisuals>>,
     mut anim_query: Query<&mut AnimationPlayer>,
     time: Res<Time>,
     asset_server: Res<AssetServer>,
+    (level, speed_config): (Res<LevelLayout>, Res<SpeedConfig>),
 ) {
-    for (agent_e, mut agent, mut controller, next_action, children) in agent_query.iter_mut() {
+    for (
+        agent_e,
+        mut agent,
+        mut controller,
+        next_action,
+        children,
+        xform,
+        sprinting,
+        awareness,
+        is_pursuer,
+    ) in agent_query.iter_mut()
+    {
         let dir = next_action.dir;
         let anim_e = get_entity(&agent_e, &["", "", "Root"], &child_query);
+        let pos = xform.translation().xy();
+        let mut delta = Vec2::ZERO;
         if dir.length_squared() > 0.1 {
             let dir = dir.normalize();
             agent.dir = dir;
-            controller.translation = Some(dir * AGENT_SPEED * time.delta_seconds());
+            let speed_scale = if is_pursuer {
+                speed_config.pursuer
+            } else {
+                speed_config.player
+            };
+            let mut speed = AGENT_SPEED * speed_scale * level.terrain_at(pos).speed_scale();
+            if sprinting {
+                speed *= SPRINT_SPEED_SCALE;
+            }
+            if let Some(awareness) = awareness {
+                speed *= awareness.speed_scale;
+            }
+            delta = dir * speed * time.delta_seconds();
             for child in children.iter() {
                 if let Ok(mut xform) = vis_query.get_mut(*child) {
                     xform.look_to(-dir.extend(0.), Vec3::Z);
@@ -654,6 +2474,29 @@ pub fn move_agents(
                 .repeat();
             }
         }
+
+        // Conveyors push agents whether or not they're walking
+        if let Some(conveyor_dir) = level.conveyor_at(pos) {
+            delta += conveyor_dir.to_world() * CONVEYOR_SPEED * time.delta_seconds();
+        }
+
+        // Check each axis on its own, so agents can still slide along edges they can't cross
+        let grid = level.grid();
+        if let Some(cell) = grid.world_to_cell(pos) {
+            let blocked = |offset: Vec2| {
+                grid.world_to_cell(pos + offset)
+                    .is_some_and(|next| next != cell && !level.can_cross(cell, next))
+            };
+            if blocked(delta * Vec2::X) {
+                delta.x = 0.;
+            }
+            if blocked(delta * Vec2::Y) {
+                delta.y = 0.;
+            }
+        }
+        if delta != Vec2::ZERO {
+            controller.translation = Some(delta);
+        }
     }
 }
 
@@ -0,0 +1,400 @@
+//! Tools for comparing levels and storing level variants as patches.
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::gridworld::{
+    CameraData, FogData, GridDir, LevelDataError, LevelMeta, LevelVision, LightData,
+    LoadedLevelData, LoadedObjData, OneWayEdge, PatrolData, SpawnZone, Terrain,
+};
+
+/// A position-only level feature.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LevelMarker {
+    PlayerSpawn,
+    PursuerSpawn,
+    Key,
+    Door,
+}
+
+impl LevelMarker {
+    const ALL: [Self; 4] = [Self::PlayerSpawn, Self::PursuerSpawn, Self::Key, Self::Door];
+
+    fn get_mut(self, level: &mut LoadedLevelData) -> &mut Option<(usize, usize)> {
+        match self {
+            Self::PlayerSpawn => &mut level.player_spawn,
+            Self::PursuerSpawn => &mut level.pursuer_spawn,
+            Self::Key => &mut level.key_pos,
+            Self::Door => &mut level.door_pos,
+        }
+    }
+
+    fn get(self, level: &LoadedLevelData) -> Option<(usize, usize)> {
+        match self {
+            Self::PlayerSpawn => level.player_spawn,
+            Self::PursuerSpawn => level.pursuer_spawn,
+            Self::Key => level.key_pos,
+            Self::Door => level.door_pos,
+        }
+    }
+
+    /// Returns the spawn zone for this marker, if it's a spawn point.
+    fn spawn_zone_mut(self, level: &mut LoadedLevelData) -> Option<&mut Option<SpawnZone>> {
+        match self {
+            Self::PlayerSpawn => Some(&mut level.player_spawn_zone),
+            Self::PursuerSpawn => Some(&mut level.pursuer_spawn_zone),
+            Self::Key | Self::Door => None,
+        }
+    }
+
+    fn spawn_zone(self, level: &LoadedLevelData) -> Option<SpawnZone> {
+        match self {
+            Self::PlayerSpawn => level.player_spawn_zone,
+            Self::PursuerSpawn => level.pursuer_spawn_zone,
+            Self::Key | Self::Door => None,
+        }
+    }
+}
+
+/// An object that changed position between two levels.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ObjectMove {
+    pub name: String,
+    pub from: (usize, usize),
+    pub to: (usize, usize),
+}
+
+/// The changes needed to turn one level into another.
+///
+/// All positions use the same coordinates as level files.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct LevelPatch {
+    /// Cells whose wall state is flipped.
+    #[serde(default)]
+    pub toggled_walls: Vec<(usize, usize)>,
+    /// Cells that gain or lose a dynamic wall.
+    #[serde(default)]
+    pub toggled_dynamic_walls: Vec<(usize, usize)>,
+    /// Vent pairs that are added or removed.
+    #[serde(default)]
+    pub toggled_vents: Vec<[(usize, usize); 2]>,
+    /// Cells that gain or lose cover.
+    #[serde(default)]
+    pub toggled_cover: Vec<(usize, usize)>,
+    /// Exits that are added or removed.
+    #[serde(default)]
+    pub toggled_exits: Vec<(usize, usize)>,
+    /// Whether exits need the key, if that changed.
+    #[serde(default)]
+    pub exits_need_key: Option<bool>,
+    /// Terrain entries that are added or removed.
+    #[serde(default)]
+    pub toggled_terrain: Vec<((usize, usize), Terrain)>,
+    /// Patrols that are added or removed.
+    #[serde(default)]
+    pub toggled_patrols: Vec<PatrolData>,
+    /// One-way edges that are added or removed.
+    #[serde(default)]
+    pub toggled_one_way: Vec<OneWayEdge>,
+    /// Conveyors that are added or removed.
+    #[serde(default)]
+    pub toggled_conveyors: Vec<((usize, usize), GridDir)>,
+    /// Fog zones that are added or removed.
+    #[serde(default)]
+    pub toggled_fog: Vec<FogData>,
+    /// Cameras that are added or removed.
+    #[serde(default)]
+    pub toggled_cameras: Vec<CameraData>,
+    /// Lights that are added or removed.
+    #[serde(default)]
+    pub toggled_lights: Vec<LightData>,
+    #[serde(default)]
+    pub moved_objects: Vec<ObjectMove>,
+    #[serde(default)]
+    pub removed_objects: Vec<LoadedObjData>,
+    #[serde(default)]
+    pub added_objects: Vec<LoadedObjData>,
+    /// New values for markers that changed.
+    #[serde(default)]
+    pub markers: Vec<(LevelMarker, Option<(usize, usize)>)>,
+    /// New values for spawn zones that changed. Only spawn point markers have zones.
+    #[serde(default)]
+    pub spawn_zones: Vec<(LevelMarker, Option<SpawnZone>)>,
+    /// The new minimum spawn separation, if it changed.
+    #[serde(default)]
+    pub min_spawn_separation: Option<f32>,
+    /// The new ambient light, if it changed.
+    #[serde(default)]
+    pub ambient_light: Option<f32>,
+    /// The new vision overrides, if they changed.
+    #[serde(default)]
+    pub vision: Option<LevelVision>,
+    /// The new metadata, if it changed.
+    #[serde(default)]
+    pub meta: Option<LevelMeta>,
+}
+
+/// Errors that can occur when diffing or patching levels.
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum LevelPatchError {
+    #[error("Levels have different sizes ({0:?} and {1:?})")]
+    SizeMismatch((usize, usize), (usize, usize)),
+    #[error("Cell {0:?} is outside the level")]
+    OutOfBounds((usize, usize)),
+    #[error("No object \"{name}\" at {pos:?}")]
+    ObjectNotFound { name: String, pos: (usize, usize) },
+    #[error("{0:?} does not have a spawn zone")]
+    NoSpawnZone(LevelMarker),
+    #[error("Could not parse patch JSON: {0}")]
+    Json(#[from] serde_json::error::Error),
+    #[error("Patched level is invalid: {0}")]
+    LevelData(#[from] LevelDataError),
+}
+
+impl LevelPatch {
+    /// Computes the patch that turns `old` into `new`.
+    ///
+    /// Objects that only changed position are recorded as moves, matched by name.
+    pub fn diff(old: &LoadedLevelData, new: &LoadedLevelData) -> Result<Self, LevelPatchError> {
+        if (old.width, old.height) != (new.width, new.height) {
+            return Err(LevelPatchError::SizeMismatch(
+                (old.width, old.height),
+                (new.width, new.height),
+            ));
+        }
+
+        let toggled_walls = old
+            .walls
+            .iter()
+            .zip(&new.walls)
+            .enumerate()
+            .filter(|(_, (old_wall, new_wall))| (**old_wall != 0) != (**new_wall != 0))
+            .map(|(i, _)| (i % old.width, i / old.width))
+            .collect();
+
+        let toggled_dynamic_walls = symmetric_difference(&old.dynamic_walls, &new.dynamic_walls);
+        let toggled_vents = symmetric_difference(&old.vents, &new.vents);
+        let toggled_cover = symmetric_difference(&old.cover, &new.cover);
+        let toggled_terrain = symmetric_difference(&old.terrain, &new.terrain);
+        let toggled_patrols = symmetric_difference(&old.patrols, &new.patrols);
+        let toggled_one_way = symmetric_difference(&old.one_way, &new.one_way);
+        let toggled_conveyors = symmetric_difference(&old.conveyors, &new.conveyors);
+        let toggled_fog = symmetric_difference(&old.fog, &new.fog);
+        let toggled_cameras = symmetric_difference(&old.cameras, &new.cameras);
+        let toggled_lights = symmetric_difference(&old.lights, &new.lights);
+        let toggled_exits = symmetric_difference(&old.exits, &new.exits);
+        let exits_need_key = Some(new.exits_need_key).filter(|&need| need != old.exits_need_key);
+
+        // Objects present in both levels are unchanged
+        let mut removed_objects = old.objects.clone();
+        let mut added_objects = Vec::new();
+        for obj in &new.objects {
+            if let Some(i) = removed_objects.iter().position(|old_obj| old_obj == obj) {
+                removed_objects.remove(i);
+            } else {
+                added_objects.push(obj.clone());
+            }
+        }
+
+        // Pair up removed and added objects that only differ in position
+        let mut moved_objects = Vec::new();
+        added_objects.retain(|added: &LoadedObjData| {
+            let moved_from = removed_objects.iter().position(|removed| {
+                removed.name == added.name
+                    && removed.dir == added.dir
+                    && removed.movable == added.movable
+            });
+            match moved_from {
+                Some(i) => {
+                    let removed = removed_objects.remove(i);
+                    moved_objects.push(ObjectMove {
+                        name: added.name.clone(),
+                        from: removed.pos,
+                        to: added.pos,
+                    });
+                    false
+                }
+                None => true,
+            }
+        });
+
+        let markers = LevelMarker::ALL
+            .into_iter()
+            .filter(|marker| marker.get(old) != marker.get(new))
+            .map(|marker| (marker, marker.get(new)))
+            .collect();
+
+        let spawn_zones = [LevelMarker::PlayerSpawn, LevelMarker::PursuerSpawn]
+            .into_iter()
+            .filter(|marker| marker.spawn_zone(old) != marker.spawn_zone(new))
+            .map(|marker| (marker, marker.spawn_zone(new)))
+            .collect();
+        let min_spawn_separation = Some(new.min_spawn_separation)
+            .filter(|&separation| separation != old.min_spawn_separation);
+        let ambient_light = Some(new.ambient_light).filter(|&light| light != old.ambient_light);
+        let vision = Some(new.vision).filter(|&vision| vision != old.vision);
+        let meta = Some(new.meta.clone()).filter(|meta| *meta != old.meta);
+
+        Ok(Self {
+            toggled_walls,
+            toggled_dynamic_walls,
+            toggled_vents,
+            toggled_cover,
+            toggled_terrain,
+            toggled_patrols,
+            toggled_one_way,
+            toggled_conveyors,
+            toggled_fog,
+            toggled_cameras,
+            toggled_lights,
+            toggled_exits,
+            exits_need_key,
+            moved_objects,
+            removed_objects,
+            added_objects,
+            markers,
+            spawn_zones,
+            min_spawn_separation,
+            ambient_light,
+            vision,
+            meta,
+        })
+    }
+
+    /// Returns a copy of `level` with this patch applied.
+    pub fn apply(&self, level: &LoadedLevelData) -> Result<LoadedLevelData, LevelPatchError> {
+        let mut level = level.clone();
+        let in_bounds = |pos: (usize, usize)| pos.0 < level.width && pos.1 < level.height;
+
+        for &pos in &self.toggled_walls {
+            if !in_bounds(pos) {
+                return Err(LevelPatchError::OutOfBounds(pos));
+            }
+            let idx = pos.1 * level.width + pos.0;
+            level.walls[idx] = (level.walls[idx] == 0) as u8;
+        }
+
+        toggle_items(&mut level.dynamic_walls, &self.toggled_dynamic_walls);
+        toggle_items(&mut level.vents, &self.toggled_vents);
+        toggle_items(&mut level.cover, &self.toggled_cover);
+        toggle_items(&mut level.terrain, &self.toggled_terrain);
+        toggle_items(&mut level.patrols, &self.toggled_patrols);
+        toggle_items(&mut level.one_way, &self.toggled_one_way);
+        toggle_items(&mut level.conveyors, &self.toggled_conveyors);
+        toggle_items(&mut level.fog, &self.toggled_fog);
+        toggle_items(&mut level.cameras, &self.toggled_cameras);
+        toggle_items(&mut level.lights, &self.toggled_lights);
+        toggle_items(&mut level.exits, &self.toggled_exits);
+        if let Some(need) = self.exits_need_key {
+            level.exits_need_key = need;
+        }
+
+        for obj_move in &self.moved_objects {
+            let obj = level
+                .objects
+                .iter_mut()
+                .find(|obj| obj.name == obj_move.name && obj.pos == obj_move.from)
+                .ok_or_else(|| LevelPatchError::ObjectNotFound {
+                    name: obj_move.name.clone(),
+                    pos: obj_move.from,
+                })?;
+            obj.pos = obj_move.to;
+        }
+
+        for removed in &self.removed_objects {
+            let i = level
+                .objects
+                .iter()
+                .position(|obj| obj == removed)
+                .ok_or_else(|| LevelPatchError::ObjectNotFound {
+                    name: removed.name.clone(),
+                    pos: removed.pos,
+                })?;
+            level.objects.remove(i);
+        }
+        level.objects.extend(self.added_objects.iter().cloned());
+
+        for &(marker, pos) in &self.markers {
+            *marker.get_mut(&mut level) = pos;
+        }
+        for &(marker, zone) in &self.spawn_zones {
+            *marker
+                .spawn_zone_mut(&mut level)
+                .ok_or(LevelPatchError::NoSpawnZone(marker))? = zone;
+        }
+        if let Some(separation) = self.min_spawn_separation {
+            level.min_spawn_separation = separation;
+        }
+        if let Some(light) = self.ambient_light {
+            level.ambient_light = light;
+        }
+        if let Some(vision) = self.vision {
+            level.vision = vision;
+        }
+        if let Some(meta) = &self.meta {
+            level.meta = meta.clone();
+        }
+
+        level.validate()?;
+        Ok(level)
+    }
+
+    /// Parses a patch from JSON.
+    pub fn from_json(json: &str) -> Result<Self, LevelPatchError> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Serializes the patch as JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("patches should always serialize")
+    }
+
+    /// Returns true if applying this patch changes nothing.
+    pub fn is_empty(&self) -> bool {
+        self.toggled_walls.is_empty()
+            && self.toggled_dynamic_walls.is_empty()
+            && self.toggled_vents.is_empty()
+            && self.toggled_cover.is_empty()
+            && self.toggled_terrain.is_empty()
+            && self.toggled_patrols.is_empty()
+            && self.toggled_one_way.is_empty()
+            && self.toggled_conveyors.is_empty()
+            && self.toggled_fog.is_empty()
+            && self.toggled_cameras.is_empty()
+            && self.toggled_lights.is_empty()
+            && self.toggled_exits.is_empty()
+            && self.exits_need_key.is_none()
+            && self.moved_objects.is_empty()
+            && self.removed_objects.is_empty()
+            && self.added_objects.is_empty()
+            && self.markers.is_empty()
+            && self.spawn_zones.is_empty()
+            && self.min_spawn_separation.is_none()
+            && self.ambient_light.is_none()
+            && self.vision.is_none()
+            && self.meta.is_none()
+    }
+}
+
+/// Returns the items that are in exactly one of `old` and `new`.
+fn symmetric_difference<T: PartialEq + Clone>(old: &[T], new: &[T]) -> Vec<T> {
+    old.iter()
+        .filter(|item| !new.contains(item))
+        .chain(new.iter().filter(|item| !old.contains(item)))
+        .cloned()
+        .collect()
+}
+
+/// Removes each of `toggled` from `items` if present, or adds it if not.
+fn toggle_items<T: PartialEq + Clone>(items: &mut Vec<T>, toggled: &[T]) {
+    for item in toggled {
+        match items.iter().position(|existing| existing == item) {
+            Some(i) => {
+                items.remove(i);
+            }
+            None => items.push(item.clone()),
+        }
+    }
+}
@@ -0,0 +1,261 @@
+//! Generates walls from obstacle shapes, rather than independently random cells, and makes levels symmetric.
+//!
+//! Walls are indexed the same way as `LevelLayout::walls`.
+
+use std::str::FromStr;
+
+use rand::{seq::SliceRandom, Rng};
+use thiserror::Error;
+
+/// How many random positions to try for each obstacle before skipping it.
+const MAX_PLACEMENT_ATTEMPTS: usize = 50;
+
+/// A kind of obstacle the generator can place.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObstacleShape {
+    /// A solid rectangle.
+    Rect,
+    /// Two rectangular arms meeting at a corner.
+    LShape,
+    /// A single wall cell.
+    Pillar,
+}
+
+/// Controls how obstacles are generated.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObstacleParams {
+    /// How many obstacles to try to place. Fewer may be placed if the level runs out of room.
+    pub count: usize,
+    /// The smallest width or height of rectangles and L-shapes.
+    pub min_size: usize,
+    /// The largest width or height of rectangles and L-shapes.
+    pub max_size: usize,
+    /// How many empty cells must separate obstacles from each other, and from the sides of the level unless they
+    /// touch them.
+    pub min_corridor_width: usize,
+    /// The shapes to pick from, uniformly.
+    pub shapes: Vec<ObstacleShape>,
+}
+
+impl Default for ObstacleParams {
+    fn default() -> Self {
+        Self {
+            count: 4,
+            min_size: 2,
+            max_size: 3,
+            min_corridor_width: 1,
+            shapes: vec![
+                ObstacleShape::Rect,
+                ObstacleShape::LShape,
+                ObstacleShape::Pillar,
+            ],
+        }
+    }
+}
+
+/// Generates walls by placing obstacles one at a time.
+///
+/// Obstacles that would break the corridor width rule, or cut off part of the level, are tried somewhere else.
+pub fn obstacle_walls(
+    width: usize,
+    height: usize,
+    params: &ObstacleParams,
+    rng: &mut impl Rng,
+) -> Vec<bool> {
+    let mut walls = vec![false; width * height];
+    for _ in 0..params.count {
+        let Some(&shape) = params.shapes.choose(rng) else {
+            break;
+        };
+        for _ in 0..MAX_PLACEMENT_ATTEMPTS {
+            let cells = shape_cells(shape, params, rng);
+            let shape_w = cells.iter().map(|(x, _)| x + 1).max().unwrap_or(0);
+            let shape_h = cells.iter().map(|(_, y)| y + 1).max().unwrap_or(0);
+            if shape_w > width || shape_h > height {
+                continue;
+            }
+            let origin = (
+                rng.gen_range(0..=(width - shape_w)),
+                rng.gen_range(0..=(height - shape_h)),
+            );
+            let cells: Vec<_> = cells
+                .into_iter()
+                .map(|(x, y)| (origin.0 + x, origin.1 + y))
+                .collect();
+            if !fits(&walls, width, height, &cells, params.min_corridor_width) {
+                continue;
+            }
+
+            let mut candidate = walls.clone();
+            for &(x, y) in &cells {
+                candidate[y * width + x] = true;
+            }
+            if is_connected(&candidate, width, height) {
+                walls = candidate;
+                break;
+            }
+        }
+    }
+    walls
+}
+
+/// Returns the cells of a randomly sized shape, relative to its bottom left corner.
+fn shape_cells(
+    shape: ObstacleShape,
+    params: &ObstacleParams,
+    rng: &mut impl Rng,
+) -> Vec<(usize, usize)> {
+    let min_size = params.min_size.max(1);
+    let max_size = params.max_size.max(min_size);
+    let w = rng.gen_range(min_size..=max_size);
+    let h = rng.gen_range(min_size..=max_size);
+    let rect = |w, h| (0..h).flat_map(move |y| (0..w).map(move |x| (x, y)));
+    match shape {
+        ObstacleShape::Rect => rect(w, h).collect(),
+        ObstacleShape::Pillar => vec![(0, 0)],
+        ObstacleShape::LShape => {
+            // Arms are thinner than the shape, so it doesn't fill in to a rectangle
+            let thickness = rng.gen_range(1..=(w.min(h) - 1).max(1));
+            let (flip_x, flip_y) = (rng.gen_bool(0.5), rng.gen_bool(0.5));
+            rect(w, h)
+                .filter(|&(x, y)| x < thickness || y < thickness)
+                .map(|(x, y)| {
+                    (
+                        if flip_x { w - x - 1 } else { x },
+                        if flip_y { h - y - 1 } else { y },
+                    )
+                })
+                .collect()
+        }
+    }
+}
+
+/// Returns true if no existing walls are within `corridor` cells of the shape, and the gaps between the shape and
+/// the sides of the level are either empty or at least `corridor` cells wide.
+fn fits(
+    walls: &[bool],
+    width: usize,
+    height: usize,
+    cells: &[(usize, usize)],
+    corridor: usize,
+) -> bool {
+    let side_gaps = [
+        cells.iter().map(|&(x, _)| x).min().unwrap_or(0),
+        width - 1 - cells.iter().map(|&(x, _)| x).max().unwrap_or(0),
+        cells.iter().map(|&(_, y)| y).min().unwrap_or(0),
+        height - 1 - cells.iter().map(|&(_, y)| y).max().unwrap_or(0),
+    ];
+    if side_gaps.iter().any(|&gap| gap > 0 && gap < corridor) {
+        return false;
+    }
+
+    cells.iter().all(|&(x, y)| {
+        let (min_x, max_x) = (x.saturating_sub(corridor), (x + corridor).min(width - 1));
+        let (min_y, max_y) = (y.saturating_sub(corridor), (y + corridor).min(height - 1));
+        (min_y..=max_y).all(|ny| (min_x..=max_x).all(|nx| !walls[ny * width + nx]))
+    })
+}
+
+/// Returns true if every empty cell can be reached from every other one.
+fn is_connected(walls: &[bool], width: usize, height: usize) -> bool {
+    let Some(start) = walls.iter().position(|&wall| !wall) else {
+        return true;
+    };
+    let mut reached = vec![false; walls.len()];
+    reached[start] = true;
+    let mut to_visit = vec![start];
+    while let Some(i) = to_visit.pop() {
+        let (x, y) = (i % width, i / width);
+        let neighbors = [
+            (x > 0).then(|| i - 1),
+            (x + 1 < width).then_some(i + 1),
+            (y > 0).then(|| i - width),
+            (y + 1 < height).then_some(i + width),
+        ];
+        for j in neighbors.into_iter().flatten() {
+            if !walls[j] && !reached[j] {
+                reached[j] = true;
+                to_visit.push(j);
+            }
+        }
+    }
+    walls
+        .iter()
+        .zip(&reached)
+        .all(|(&wall, &reached)| wall || reached)
+}
+
+/// A symmetry that levels can be generated with, so neither agent's side of the level is easier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Symmetry {
+    /// The left half mirrors the right half.
+    MirrorX,
+    /// The bottom half mirrors the top half.
+    MirrorY,
+    /// The level looks the same after a half turn.
+    Rotate180,
+}
+
+#[derive(Debug, Error)]
+#[error("Unknown symmetry \"{0}\", expected \"mirror_x\", \"mirror_y\", or \"rotate_180\"")]
+pub struct UnknownSymmetryError(pub String);
+
+impl FromStr for Symmetry {
+    type Err = UnknownSymmetryError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mirror_x" => Ok(Self::MirrorX),
+            "mirror_y" => Ok(Self::MirrorY),
+            "rotate_180" => Ok(Self::Rotate180),
+            _ => Err(UnknownSymmetryError(s.into())),
+        }
+    }
+}
+
+impl Symmetry {
+    /// Returns the cell that this cell maps to.
+    ///
+    /// Since every symmetry maps cells back when applied twice, and flipping rows doesn't affect them, this works
+    /// with both level file and `LevelLayout` coordinates.
+    pub fn image(self, (x, y): (usize, usize), width: usize, height: usize) -> (usize, usize) {
+        match self {
+            Self::MirrorX => (width - x - 1, y),
+            Self::MirrorY => (x, height - y - 1),
+            Self::Rotate180 => (width - x - 1, height - y - 1),
+        }
+    }
+
+    /// Returns true if the cell is in the half of the level that gets copied to the other half.
+    /// Cells that map to themselves count as being in both halves.
+    pub fn is_source(self, cell: (usize, usize), width: usize, height: usize) -> bool {
+        let (x, y) = self.image(cell, width, height);
+        cell.1 * width + cell.0 <= y * width + x
+    }
+
+    /// Copies each value in the source half of a grid onto its image.
+    pub fn apply<T: Clone>(self, cells: &mut [T], width: usize, height: usize) {
+        for y in 0..height {
+            for x in 0..width {
+                if self.is_source((x, y), width, height) {
+                    let (img_x, img_y) = self.image((x, y), width, height);
+                    cells[img_y * width + img_x] = cells[y * width + x].clone();
+                }
+            }
+        }
+    }
+
+    /// Returns the direction an object facing `dir` faces after being mapped.
+    pub fn image_dir(self, dir: &str) -> String {
+        let flip_x = matches!(self, Self::MirrorX | Self::Rotate180);
+        let flip_y = matches!(self, Self::MirrorY | Self::Rotate180);
+        match dir {
+            "left" if flip_x => "right",
+            "right" if flip_x => "left",
+            "up" if flip_y => "down",
+            "down" if flip_y => "up",
+            other => other,
+        }
+        .into()
+    }
+}
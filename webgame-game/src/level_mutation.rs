@@ -0,0 +1,179 @@
+//! Random edits to levels, and a search that uses them to find levels that maximize a score.
+//!
+//! All positions use the same coordinates as level files.
+
+use rand::Rng;
+
+use crate::gridworld::{GridDir, LoadedLevelData};
+
+/// How many random mutations to try before giving up on finding a playable one.
+const MAX_ATTEMPTS: usize = 100;
+
+/// A single edit to a level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LevelMutation {
+    /// Flips whether the cell is a wall.
+    FlipWall((usize, usize)),
+    MovePlayerSpawn((usize, usize)),
+    MovePursuerSpawn((usize, usize)),
+    MoveKey((usize, usize)),
+    /// Moves the object at this index of `objects`.
+    MoveObject(usize, (usize, usize)),
+}
+
+impl LevelMutation {
+    /// Picks a random mutation for the level.
+    /// Only features the level already has are moved, and the result may not be playable.
+    pub fn random(level: &LoadedLevelData, rng: &mut impl Rng) -> Self {
+        let pos = (
+            rng.gen_range(0..level.width),
+            rng.gen_range(0..level.height),
+        );
+        let mut options = vec![Self::FlipWall(pos)];
+        if level.player_spawn.is_some() {
+            options.push(Self::MovePlayerSpawn(pos));
+        }
+        if level.pursuer_spawn.is_some() {
+            options.push(Self::MovePursuerSpawn(pos));
+        }
+        if level.key_pos.is_some() {
+            options.push(Self::MoveKey(pos));
+        }
+        if !level.objects.is_empty() {
+            options.push(Self::MoveObject(rng.gen_range(0..level.objects.len()), pos));
+        }
+        options[rng.gen_range(0..options.len())]
+    }
+
+    /// Applies the mutation to a level.
+    pub fn apply(&self, level: &mut LoadedLevelData) {
+        match *self {
+            Self::FlipWall((x, y)) => {
+                let idx = y * level.width + x;
+                level.walls[idx] = (level.walls[idx] == 0) as u8;
+            }
+            Self::MovePlayerSpawn(pos) => level.player_spawn = Some(pos),
+            Self::MovePursuerSpawn(pos) => level.pursuer_spawn = Some(pos),
+            Self::MoveKey(pos) => level.key_pos = Some(pos),
+            Self::MoveObject(i, pos) => level.objects[i].pos = pos,
+        }
+    }
+}
+
+/// Returns true if the level can be played.
+///
+/// Spawns, the key, objects, exits, vents, and patrol waypoints must be on open cells, and every open cell must be
+/// reachable from every other one, taking one-way edges and conveyors into account. Dynamic walls and the door count as open, since
+/// they can be opened during play.
+pub fn is_playable(level: &LoadedLevelData) -> bool {
+    if level.validate().is_err() {
+        return false;
+    }
+    let is_wall = |(x, y): (usize, usize)| level.walls[y * level.width + x] != 0;
+    let waypoints = level.patrols.iter().flat_map(|patrol| &patrol.waypoints);
+    let fixed_positions = [level.player_spawn, level.pursuer_spawn, level.key_pos]
+        .into_iter()
+        .flatten()
+        .chain(level.objects.iter().map(|obj| obj.pos))
+        .chain(level.exits.iter().copied())
+        .chain(level.vents.iter().flatten().copied())
+        .chain(waypoints.copied());
+    for pos in fixed_positions {
+        if is_wall(pos) {
+            return false;
+        }
+    }
+
+    let is_open = |pos: (usize, usize)| {
+        !is_wall(pos) || level.dynamic_walls.contains(&pos) || level.door_pos == Some(pos)
+    };
+    let open_cells: Vec<usize> = (0..level.walls.len())
+        .filter(|&i| is_open((i % level.width, i / level.width)))
+        .collect();
+    if open_cells.len() < 2 {
+        return false;
+    }
+    let start = open_cells[0];
+    let grid = level.grid();
+    let conveyor_at = |pos| {
+        level
+            .conveyors
+            .iter()
+            .find(|&&(cell, _)| cell == pos)
+            .map(|&(_, dir)| dir)
+    };
+    // Agents can't walk against conveyors, whether they're stepping onto or off of them
+    let can_step = |from, to| {
+        let backwards = GridDir::between(to, from);
+        let against_conveyor = conveyor_at(from) == backwards || conveyor_at(to) == backwards;
+        !against_conveyor && !level.one_way.iter().any(|edge| edge.blocks(from, to, grid))
+    };
+
+    // Flood fill from the first open cell, and count how many open cells were reached.
+    // Walking edges backwards instead counts the cells that can reach the first one.
+    let reached_count = |backwards: bool| {
+        let mut reached = vec![false; level.walls.len()];
+        let mut to_visit = vec![start];
+        reached[start] = true;
+        let mut count = 1;
+        while let Some(i) = to_visit.pop() {
+            let (x, y) = (i % level.width, i / level.width);
+            let neighbors = [
+                (x > 0).then(|| (x - 1, y)),
+                (x + 1 < level.width).then_some((x + 1, y)),
+                (y > 0).then(|| (x, y - 1)),
+                (y + 1 < level.height).then_some((x, y + 1)),
+            ];
+            for pos in neighbors.into_iter().flatten() {
+                let j = pos.1 * level.width + pos.0;
+                let crossable = if backwards {
+                    can_step(pos, (x, y))
+                } else {
+                    can_step((x, y), pos)
+                };
+                if !reached[j] && is_open(pos) && crossable {
+                    reached[j] = true;
+                    count += 1;
+                    to_visit.push(j);
+                }
+            }
+        }
+        count
+    };
+    reached_count(false) == open_cells.len() && reached_count(true) == open_cells.len()
+}
+
+/// Applies random mutations to a copy of the level until one is playable, and returns it.
+/// Returns `None` if no playable mutation was found.
+pub fn mutate_level(level: &LoadedLevelData, rng: &mut impl Rng) -> Option<LoadedLevelData> {
+    (0..MAX_ATTEMPTS).find_map(|_| {
+        let mut mutated = level.clone();
+        LevelMutation::random(level, rng).apply(&mut mutated);
+        is_playable(&mutated).then_some(mutated)
+    })
+}
+
+/// Searches for a level with a high score by repeatedly mutating the best level found so far.
+///
+/// Mutations that score at least as well as the current best replace it, so the search can move across plateaus.
+/// Returns the best level and its score, or the first error returned by `score`.
+pub fn search_levels<E>(
+    start: LoadedLevelData,
+    iterations: usize,
+    rng: &mut impl Rng,
+    mut score: impl FnMut(&LoadedLevelData) -> Result<f32, E>,
+) -> Result<(LoadedLevelData, f32), E> {
+    let mut best_score = score(&start)?;
+    let mut best = start;
+    for _ in 0..iterations {
+        let Some(candidate) = mutate_level(&best, rng) else {
+            continue;
+        };
+        let candidate_score = score(&candidate)?;
+        if candidate_score >= best_score {
+            best = candidate;
+            best_score = candidate_score;
+        }
+    }
+    Ok((best, best_score))
+}
@@ -0,0 +1,86 @@
+//! Collections of levels that are cycled through on every reset.
+
+use bevy::prelude::*;
+use rand::{distributions::WeightedIndex, prelude::Distribution, Rng};
+use thiserror::Error;
+
+use crate::gridworld::LevelLayout;
+
+/// How the next level is picked from a level set.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LevelSampling {
+    /// Plays levels in order, starting over after the last one.
+    RoundRobin,
+    /// Picks a level uniformly at random.
+    Uniform,
+    /// Picks a level at random, with probability proportional to its weight.
+    Weighted(Vec<f64>),
+}
+
+/// Errors that can occur when creating level sets.
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum LevelSetError {
+    #[error("Level sets must contain at least one level")]
+    Empty,
+    #[error("Expected {expected} weights (one per level), got {actual}")]
+    WeightCount { expected: usize, actual: usize },
+    #[error("Invalid weights: {0}")]
+    InvalidWeights(#[from] rand::distributions::WeightedError),
+}
+
+/// A collection of levels, one of which is picked every time the level is reset.
+#[derive(Resource, Clone)]
+pub struct LevelSet {
+    levels: Vec<LevelLayout>,
+    sampler: Sampler,
+}
+
+/// Sampling state for each `LevelSampling` strategy.
+#[derive(Clone)]
+enum Sampler {
+    RoundRobin { next: usize },
+    Uniform,
+    Weighted(WeightedIndex<f64>),
+}
+
+impl LevelSet {
+    pub fn new(levels: Vec<LevelLayout>, sampling: LevelSampling) -> Result<Self, LevelSetError> {
+        if levels.is_empty() {
+            return Err(LevelSetError::Empty);
+        }
+        let sampler = match sampling {
+            LevelSampling::RoundRobin => Sampler::RoundRobin { next: 0 },
+            LevelSampling::Uniform => Sampler::Uniform,
+            LevelSampling::Weighted(weights) => {
+                if weights.len() != levels.len() {
+                    return Err(LevelSetError::WeightCount {
+                        expected: levels.len(),
+                        actual: weights.len(),
+                    });
+                }
+                Sampler::Weighted(WeightedIndex::new(weights)?)
+            }
+        };
+        Ok(Self { levels, sampler })
+    }
+
+    /// Returns the levels in the set.
+    pub fn levels(&self) -> &[LevelLayout] {
+        &self.levels
+    }
+
+    /// Picks the next level to play.
+    pub fn next_level(&mut self, rng: &mut impl Rng) -> LevelLayout {
+        let idx = match &mut self.sampler {
+            Sampler::RoundRobin { next } => {
+                let idx = *next;
+                *next = (idx + 1) % self.levels.len();
+                idx
+            }
+            Sampler::Uniform => rng.gen_range(0..self.levels.len()),
+            Sampler::Weighted(dist) => dist.sample(rng),
+        };
+        self.levels[idx].clone()
+    }
+}
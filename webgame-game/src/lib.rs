@@ -2,7 +2,48 @@
 #![feature(iter_array_chunks)]
 
 pub mod net;
+#[cfg(feature = "multiplayer")]
+pub mod authoritative;
+pub mod awareness;
+pub mod behavior;
+pub mod bitgrid;
+pub mod comms;
 pub mod configs;
+pub mod difficulty;
+pub mod editor;
+pub mod filter;
+pub mod gadgets;
 pub mod gridworld;
+pub mod level_diff;
+pub mod level_gen;
+pub mod level_mutation;
+pub mod level_set;
+pub mod lighting;
+pub mod main_menu;
+#[cfg(feature = "multiplayer")]
+pub mod lobby;
+#[cfg(feature = "multiplayer")]
+pub mod multiplayer;
 pub mod observer;
+#[cfg(feature = "onnx")]
+pub mod onnx;
+pub mod pathfinding;
+pub mod pause_menu;
+pub mod pursuer_ai;
+pub mod replay;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod remote_policy;
+pub mod screens;
+pub mod sensors;
+pub mod settings;
+#[cfg(feature = "spectator")]
+pub mod spectator;
+#[cfg(feature = "telemetry")]
+pub mod telemetry;
+pub mod thumbnail;
+#[cfg(any(feature = "multiplayer", feature = "viewer"))]
+pub mod transport;
+#[cfg(feature = "viewer")]
+pub mod viewer;
+pub mod visibility;
 pub mod world_objs;
\ No newline at end of file
@@ -0,0 +1,128 @@
+//! Computes how brightly lit each cell is, which limits how far away agents can notice things.
+
+use bevy::prelude::*;
+
+use crate::{
+    bitgrid::BitGrid,
+    gridworld::{setup_entities, GridTransform, LevelLayout, GRID_CELL_SIZE},
+    observer::update_observers,
+};
+
+/// Plugin for lighting.
+pub struct LightingPlugin;
+
+impl Plugin for LightingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Illumination>().add_systems(
+            Update,
+            update_illumination
+                .after(setup_entities)
+                .before(update_observers)
+                .run_if(resource_exists::<LevelLayout>),
+        );
+    }
+}
+
+/// How close agents have to be to notice something in total darkness, in world units.
+pub const DARK_DETECTION_DIST: f32 = GRID_CELL_SIZE;
+
+/// A light that brightens the cells around it.
+#[derive(Component, Clone, Copy)]
+pub struct LightSource {
+    /// How far the light reaches, in world units.
+    pub radius: f32,
+    /// How much the light brightens its own cell, from 0 to 1.
+    pub intensity: f32,
+}
+
+/// Stores how brightly lit every cell is, from 0 to 1, indexed the same way as `LevelLayout::walls`.
+///
+/// Until the level is loaded, every position counts as fully lit.
+#[derive(Resource, Clone, Default)]
+pub struct Illumination {
+    pub width: usize,
+    pub height: usize,
+    pub levels: Vec<f32>,
+}
+
+impl Illumination {
+    /// Returns how brightly lit the cell containing a world position is.
+    pub fn at(&self, pos: Vec2) -> f32 {
+        let grid = GridTransform::new(self.width, self.height);
+        grid.world_to_cell(pos)
+            .and_then(|cell| grid.cell_idx(cell))
+            .and_then(|idx| self.levels.get(idx).copied())
+            .unwrap_or(1.)
+    }
+
+    /// Returns how far away an observer with the given range can notice something at `pos`, or `None` if there's no
+    /// limit.
+    ///
+    /// The range shrinks in proportion to how brightly lit `pos` is, down to `DARK_DETECTION_DIST`. Observers with
+    /// unlimited range are treated as if they could see across the whole level.
+    pub fn detection_range(&self, pos: Vec2, range: Option<f32>) -> Option<f32> {
+        let light = self.at(pos);
+        if light >= 1. {
+            return range;
+        }
+        let range = range.unwrap_or_else(|| {
+            Vec2::new(self.width as f32, self.height as f32).length() * GRID_CELL_SIZE
+        });
+        Some((range * light).max(DARK_DETECTION_DIST))
+    }
+}
+
+/// Returns how brightly lit each cell is, given the level's walls, its ambient light, and lights at world positions.
+///
+/// Each light adds its intensity to cells it can reach, falling off linearly to nothing at its radius. Walls block
+/// light, so wall cells and cells behind them aren't lit. Levels are clamped to 1.
+pub fn compute_illumination<'a>(
+    walls: &BitGrid,
+    ambient: f32,
+    lights: impl IntoIterator<Item = (Vec2, &'a LightSource)>,
+) -> Vec<f32> {
+    let grid = GridTransform::new(walls.width(), walls.height());
+    let mut levels = vec![ambient; walls.len()];
+    for (pos, light) in lights {
+        if light.radius <= 0. {
+            continue;
+        }
+        for (i, level) in levels.iter_mut().enumerate() {
+            let center = grid.cell_to_world(grid.idx_cell(i));
+            let falloff = 1. - center.distance(pos) / light.radius;
+            if falloff > 0. && walls.line_of_sight(pos / GRID_CELL_SIZE, center / GRID_CELL_SIZE) {
+                *level += light.intensity * falloff;
+            }
+        }
+    }
+    for level in &mut levels {
+        *level = level.min(1.);
+    }
+    levels
+}
+
+/// Recomputes illumination whenever the level's walls or any lights change.
+/// Runs after the level is set up, so illumination includes the level's lights from the first update.
+fn update_illumination(
+    level: Res<LevelLayout>,
+    light_query: Query<(&Transform, &LightSource)>,
+    changed_query: Query<(), Changed<LightSource>>,
+    mut removed_lights: RemovedComponents<LightSource>,
+    mut illumination: ResMut<Illumination>,
+) {
+    let lights_removed = removed_lights.read().count() > 0;
+    if !level.is_changed() && changed_query.is_empty() && !lights_removed {
+        return;
+    }
+    *illumination = Illumination {
+        width: level.width,
+        height: level.height,
+        levels: compute_illumination(
+            &level.walls,
+            level.ambient_light,
+            light_query
+                .iter()
+                .map(|(xform, light)| (xform.translation.xy(), light)),
+        ),
+    };
+}
@@ -0,0 +1,283 @@
+//! The lobby screen, where online matches are set up. Only built with the `multiplayer` feature.
+//!
+//! People host a room or join one by its code, pick whether they play as the player, the pursuer or a spectator, then
+//! ready up. The relay server starts the match once both agents have someone controlling them and everyone is ready.
+
+use bevy::{prelude::*, window::ReceivedCharacter};
+
+use crate::{
+    gridworld::ShouldRun,
+    multiplayer::{
+        connect_to_relay, Connection, LobbyRole, MultiplayerSession, MultiplayerState,
+        RelayMessage, ROOM_CODE_LEN,
+    },
+    screens::ScreenState,
+};
+
+/// Adds the lobby screen, toggled with F4. Games that weren't told which room to host or join start on it.
+pub struct LobbyPlugin;
+
+impl Plugin for LobbyPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<RoomCodeInput>()
+            .add_systems(Startup, open_lobby_on_start)
+            .add_systems(
+                OnEnter(ScreenState::Lobby),
+                (connect_to_relay, setup_lobby, pause_game),
+            )
+            .add_systems(OnExit(ScreenState::Lobby), (cleanup_lobby, resume_game))
+            .add_systems(
+                Update,
+                (
+                    toggle_lobby,
+                    close_lobby_on_start,
+                    (
+                        pause_game.run_if(resource_added::<ShouldRun>),
+                        lobby_input,
+                        update_lobby_text,
+                    )
+                        .run_if(in_state(ScreenState::Lobby)),
+                ),
+            );
+    }
+}
+
+const LOBBY_JOIN_HELP: &str =
+    "Type a room code and press Enter to join it, or press Tab to host a new room.";
+
+const LOBBY_START_HINT: &str =
+    "The match starts once someone is playing each agent and everyone is ready.";
+
+const LOBBY_ROOM_HELP: &str =
+    "1: Player, 2: Pursuer, 3: Spectator, Space: Ready\nF4: Back to the game";
+
+/// The room code typed in so far.
+#[derive(Resource, Default)]
+struct RoomCodeInput(String);
+
+/// Marks entities that belong to the lobby screen.
+#[derive(Component)]
+struct LobbyUi;
+
+/// The text describing the room.
+#[derive(Component)]
+struct LobbyText;
+
+/// Indicates that the level was paused when the lobby was opened, and should resume when it closes.
+#[derive(Resource)]
+struct LobbyPaused;
+
+/// Switches between the game and the lobby.
+fn toggle_lobby(
+    inpt: Res<ButtonInput<KeyCode>>,
+    state: Res<State<ScreenState>>,
+    mut next_state: ResMut<NextState<ScreenState>>,
+) {
+    if inpt.just_pressed(KeyCode::F4) {
+        next_state.set(match state.get() {
+            ScreenState::Lobby => ScreenState::Game,
+            _ => ScreenState::Lobby,
+        });
+    }
+}
+
+/// Opens the lobby straight away if no room was picked ahead of time.
+fn open_lobby_on_start(
+    state: Res<MultiplayerState>,
+    mut next_state: ResMut<NextState<ScreenState>>,
+) {
+    if state.session == MultiplayerSession::Lobby {
+        next_state.set(ScreenState::Lobby);
+    }
+}
+
+/// Pauses the level while the lobby is open, including levels that start while it's open, unless a match is being
+/// played, since others would have to wait.
+fn pause_game(
+    mut commands: Commands,
+    state: Res<MultiplayerState>,
+    should_run: Option<Res<ShouldRun>>,
+) {
+    if state.role.is_none() && should_run.is_some() {
+        commands.remove_resource::<ShouldRun>();
+        commands.insert_resource(LobbyPaused);
+    }
+}
+
+/// Resumes the level if the lobby paused it. If a match started instead, its first level replaces this one.
+fn resume_game(
+    mut commands: Commands,
+    state: Res<MultiplayerState>,
+    paused: Option<Res<LobbyPaused>>,
+) {
+    if paused.is_some() {
+        commands.remove_resource::<LobbyPaused>();
+        if state.role.is_none() {
+            commands.insert_resource(ShouldRun);
+        }
+    }
+}
+
+/// Spawns the lobby's text over the game.
+fn setup_lobby(mut commands: Commands) {
+    commands.spawn((
+        LobbyUi,
+        Camera2dBundle {
+            camera: Camera {
+                // Draw over the level, if there is one
+                order: 1,
+                clear_color: ClearColorConfig::None,
+                ..default()
+            },
+            ..default()
+        },
+    ));
+    commands
+        .spawn((
+            LobbyUi,
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.),
+                    height: Val::Percent(100.),
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                background_color: Color::rgba(0., 0., 0., 0.8).into(),
+                ..default()
+            },
+        ))
+        .with_children(|p| {
+            p.spawn((
+                LobbyText,
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font_size: 16.,
+                        color: Color::WHITE,
+                        ..default()
+                    },
+                ),
+            ));
+        });
+}
+
+fn cleanup_lobby(mut commands: Commands, ui_query: Query<Entity, With<LobbyUi>>) {
+    for e in ui_query.iter() {
+        commands.entity(e).despawn_recursive();
+    }
+}
+
+/// Types room codes, hosts and joins rooms, picks roles and readies up.
+fn lobby_input(
+    inpt: Res<ButtonInput<KeyCode>>,
+    mut ev_char: EventReader<ReceivedCharacter>,
+    mut conn: NonSendMut<Connection>,
+    state: Res<MultiplayerState>,
+    mut code: ResMut<RoomCodeInput>,
+) {
+    let typed: Vec<_> = ev_char.read().flat_map(|ev| ev.char.chars()).collect();
+    if state.role.is_some() {
+        return;
+    }
+    let Some(lobby) = &state.lobby else {
+        for c in typed {
+            if c.is_ascii_alphabetic() && code.0.len() < ROOM_CODE_LEN {
+                code.0.push(c.to_ascii_uppercase());
+            }
+        }
+        if inpt.just_pressed(KeyCode::Backspace) {
+            code.0.pop();
+        }
+        if inpt.just_pressed(KeyCode::Enter) && code.0.len() == ROOM_CODE_LEN {
+            conn.send(&RelayMessage::Join {
+                room: std::mem::take(&mut code.0),
+            });
+        }
+        if inpt.just_pressed(KeyCode::Tab) {
+            conn.send(&RelayMessage::Host {
+                role: LobbyRole::Player,
+            });
+        }
+        return;
+    };
+
+    let roles = [
+        (KeyCode::Digit1, LobbyRole::Player),
+        (KeyCode::Digit2, LobbyRole::Pursuer),
+        (KeyCode::Digit3, LobbyRole::Spectator),
+    ];
+    for (key, role) in roles {
+        if inpt.just_pressed(key) {
+            conn.send(&RelayMessage::SelectRole { role });
+        }
+    }
+    if let (true, Some(me)) = (inpt.just_pressed(KeyCode::Space), lobby.me()) {
+        conn.send(&RelayMessage::Ready { ready: !me.ready });
+    }
+}
+
+/// Goes back to the game once the match starts.
+fn close_lobby_on_start(
+    state: Res<MultiplayerState>,
+    screen: Res<State<ScreenState>>,
+    mut next_state: ResMut<NextState<ScreenState>>,
+    mut was_started: Local<bool>,
+) {
+    let started = state.role.is_some();
+    if started && !*was_started && *screen.get() == ScreenState::Lobby {
+        next_state.set(ScreenState::Game);
+    }
+    *was_started = started;
+}
+
+/// Describes the room, or how to get into one.
+fn update_lobby_text(
+    conn: NonSend<Connection>,
+    state: Res<MultiplayerState>,
+    code: Res<RoomCodeInput>,
+    mut text_query: Query<&mut Text, With<LobbyText>>,
+) {
+    let Ok(mut text) = text_query.get_single_mut() else {
+        return;
+    };
+    let desc = match (&state.lobby, state.role) {
+        _ if !conn.is_connected() => format!(
+            "Couldn't reach the relay server at {}. Press F4 twice to try again.",
+            state.server_url
+        ),
+        (_, Some(role)) => {
+            format!("Match in progress, playing as the {role:?}.\n\nF4: Back to the game")
+        }
+        (None, None) => format!(
+            "{LOBBY_JOIN_HELP}\n\nRoom code: {}{}",
+            code.0,
+            "_".repeat(ROOM_CODE_LEN - code.0.len())
+        ),
+        (Some(lobby), None) => {
+            let members: Vec<_> = lobby
+                .members
+                .iter()
+                .enumerate()
+                .map(|(i, member)| {
+                    format!(
+                        "{:?}{}{} - {}",
+                        member.role,
+                        if member.host { " (host)" } else { "" },
+                        if i == lobby.you { " (you)" } else { "" },
+                        if member.ready { "Ready" } else { "Not ready" },
+                    )
+                })
+                .collect();
+            format!(
+                "Room {}\n\n{}\n\n{LOBBY_START_HINT}\n\n{LOBBY_ROOM_HELP}",
+                lobby.room,
+                members.join("\n")
+            )
+        }
+    };
+    text.sections[0].value = match &state.last_error {
+        Some(err) => format!("{desc}\n\n{err}"),
+        None => desc,
+    };
+}
@@ -1,16 +1,7 @@
-#![allow(clippy::type_complexity)]
-#![feature(iter_array_chunks)]
-
 use bevy::prelude::*;
 #[cfg(feature = "editor")]
 use bevy_editor_pls::EditorPlugin;
-use configs::ReleaseCfgPlugin;
-
-mod net;
-mod configs;
-mod gridworld;
-mod observer;
-mod world_objs;
+use webgame_game::configs::ReleaseCfgPlugin;
 
 /// Main entry point for our game.
 fn main() {
@@ -0,0 +1,315 @@
+//! The main menu, where a level is picked from the ones in `assets/levels/`.
+//!
+//! Levels are listed with a thumbnail, and the name, author and difficulty from their metadata. Browsers can't list
+//! folders, so web builds list `FALLBACK_LEVELS` instead.
+
+use bevy::{
+    asset::{LoadState, LoadedFolder},
+    prelude::*,
+};
+
+use crate::{
+    gridworld::{teardown_level, LevelLayout, LevelLoader, LoadedLevelData},
+    level_set::LevelSet,
+    screens::ScreenState,
+    thumbnail::Thumbnail,
+};
+
+/// Adds the main menu screen.
+pub struct MainMenuPlugin;
+
+impl Plugin for MainMenuPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            OnEnter(ScreenState::MainMenu),
+            (setup_main_menu, teardown_level),
+        )
+        .add_systems(OnExit(ScreenState::MainMenu), cleanup_main_menu)
+        .add_systems(
+            Update,
+            (list_levels, menu_input, highlight_selected)
+                .chain()
+                .run_if(in_state(ScreenState::MainMenu)),
+        );
+    }
+}
+
+/// The folder levels are listed from, relative to the assets folder.
+pub const LEVELS_FOLDER: &str = "levels";
+
+/// The levels listed if `LEVELS_FOLDER` can't be read.
+pub const FALLBACK_LEVELS: &[&str] = &["levels/test.json"];
+
+/// How many pixels wide each cell is in thumbnails on the menu.
+const MENU_THUMBNAIL_CELL_PIXELS: usize = 6;
+
+const MENU_HELP: &str = "W/S: Select, Enter: Play, O: Settings, F2: Editor";
+
+const SELECTED_COLOR: Color = Color::rgb(0.25, 0.25, 0.35);
+const UNSELECTED_COLOR: Color = Color::NONE;
+
+/// Opens the main menu when the game starts.
+pub fn open_main_menu(mut next_state: ResMut<NextState<ScreenState>>) {
+    next_state.set(ScreenState::MainMenu);
+}
+
+/// The levels on the menu.
+#[derive(Resource)]
+struct MenuLevels {
+    folder: Handle<LoadedFolder>,
+    /// Every level being loaded, once it's known which ones to load.
+    handles: Option<Vec<Handle<LoadedLevelData>>>,
+    /// The asset paths of the levels shown, in the order they're listed.
+    paths: Vec<String>,
+    selected: usize,
+}
+
+/// Marks entities that belong to the main menu.
+#[derive(Component)]
+struct MainMenuUi;
+
+/// The node levels are listed under.
+#[derive(Component)]
+struct LevelList;
+
+/// A level on the menu, storing its index in `MenuLevels::paths`.
+#[derive(Component)]
+struct LevelEntry(usize);
+
+/// Starts loading levels, and spawns the menu with an empty list.
+fn setup_main_menu(mut commands: Commands, asset_server: Res<AssetServer>) {
+    // Levels are picked from the menu rather than being loaded by whoever opened it
+    commands.remove_resource::<LevelLoader>();
+    commands.remove_resource::<LevelSet>();
+    commands.insert_resource(MenuLevels {
+        folder: asset_server.load_folder(LEVELS_FOLDER),
+        handles: None,
+        paths: Vec::new(),
+        selected: 0,
+    });
+    commands.spawn((MainMenuUi, Camera2dBundle::default()));
+    let text_style = TextStyle {
+        font_size: 16.,
+        color: Color::WHITE,
+        ..default()
+    };
+    commands
+        .spawn((
+            MainMenuUi,
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.),
+                    height: Val::Percent(100.),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    padding: UiRect::all(Val::Px(16.)),
+                    row_gap: Val::Px(8.),
+                    ..default()
+                },
+                ..default()
+            },
+        ))
+        .with_children(|p| {
+            p.spawn(TextBundle::from_section(
+                "Pursuer",
+                TextStyle {
+                    font_size: 32.,
+                    ..text_style.clone()
+                },
+            ));
+            p.spawn(TextBundle::from_section(MENU_HELP, text_style));
+            p.spawn((
+                LevelList,
+                NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Column,
+                        row_gap: Val::Px(4.),
+                        overflow: Overflow::clip(),
+                        ..default()
+                    },
+                    ..default()
+                },
+            ));
+        });
+}
+
+fn cleanup_main_menu(mut commands: Commands, ui_query: Query<Entity, With<MainMenuUi>>) {
+    for e in ui_query.iter() {
+        commands.entity(e).despawn_recursive();
+    }
+    commands.remove_resource::<MenuLevels>();
+}
+
+/// Lists the levels once they've all loaded.
+fn list_levels(
+    mut commands: Commands,
+    mut menu: ResMut<MenuLevels>,
+    asset_server: Res<AssetServer>,
+    folders: Res<Assets<LoadedFolder>>,
+    level_data: Res<Assets<LoadedLevelData>>,
+    mut images: ResMut<Assets<Image>>,
+    list_query: Query<Entity, With<LevelList>>,
+) {
+    if !menu.paths.is_empty() {
+        return;
+    }
+    if menu.handles.is_none() {
+        menu.handles = match asset_server.load_state(&menu.folder) {
+            LoadState::Loaded => folders.get(&menu.folder).map(|folder| {
+                folder
+                    .handles
+                    .iter()
+                    .filter_map(|handle| handle.clone().try_typed::<LoadedLevelData>().ok())
+                    .collect()
+            }),
+            LoadState::Failed => {
+                info!("Couldn't list the levels folder, so only the default levels are shown");
+                Some(
+                    FALLBACK_LEVELS
+                        .iter()
+                        .map(|path| asset_server.load(*path))
+                        .collect(),
+                )
+            }
+            LoadState::NotLoaded | LoadState::Loading => None,
+        };
+    }
+    let Some(handles) = &menu.handles else {
+        return;
+    };
+    let loading = handles.iter().any(|handle| {
+        matches!(
+            asset_server.load_state(handle),
+            LoadState::NotLoaded | LoadState::Loading
+        )
+    });
+    if loading {
+        return;
+    }
+
+    let mut levels: Vec<_> = handles
+        .iter()
+        .filter_map(|handle| Some((handle.path()?.to_string(), level_data.get(handle)?)))
+        .collect();
+    levels.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let Ok(list_e) = list_query.get_single() else {
+        return;
+    };
+    let text_style = TextStyle {
+        font_size: 16.,
+        color: Color::WHITE,
+        ..default()
+    };
+    commands.entity(list_e).with_children(|p| {
+        if levels.is_empty() {
+            p.spawn(TextBundle::from_section(
+                format!("No levels found in assets/{LEVELS_FOLDER}"),
+                text_style.clone(),
+            ));
+        }
+        for (i, (path, data)) in levels.iter().enumerate() {
+            let layout = LevelLayout::from_data(data);
+            let thumbnail =
+                images.add(Thumbnail::render(&layout, MENU_THUMBNAIL_CELL_PIXELS).to_image());
+            p.spawn((
+                LevelEntry(i),
+                ButtonBundle {
+                    style: Style {
+                        align_items: AlignItems::Center,
+                        column_gap: Val::Px(12.),
+                        padding: UiRect::all(Val::Px(4.)),
+                        ..default()
+                    },
+                    background_color: UNSELECTED_COLOR.into(),
+                    ..default()
+                },
+            ))
+            .with_children(|p| {
+                p.spawn(ImageBundle {
+                    image: UiImage::new(thumbnail),
+                    style: Style {
+                        width: Val::Px(64.),
+                        height: Val::Px(64.),
+                        ..default()
+                    },
+                    ..default()
+                });
+                p.spawn(TextBundle::from_section(
+                    describe_level(path, data),
+                    text_style.clone(),
+                ));
+            });
+        }
+    });
+    menu.paths = levels.into_iter().map(|(path, _)| path).collect();
+}
+
+/// Describes a level using its metadata, falling back to its file name.
+fn describe_level(path: &str, data: &LoadedLevelData) -> String {
+    let file_name = path.rsplit('/').next().unwrap_or(path);
+    let mut desc = data
+        .meta
+        .name
+        .clone()
+        .unwrap_or_else(|| file_name.trim_end_matches(".json").into());
+    if let Some(author) = &data.meta.author {
+        desc += &format!("\nby {author}");
+    }
+    if let Some(difficulty) = data.meta.difficulty {
+        desc += &format!("\nDifficulty: {difficulty}");
+    }
+    desc
+}
+
+/// Moves the selection, plays the selected level with Enter or a click, and opens the settings with O.
+fn menu_input(
+    mut commands: Commands,
+    inpt: Res<ButtonInput<KeyCode>>,
+    mut menu: ResMut<MenuLevels>,
+    entry_query: Query<(&LevelEntry, &Interaction), Changed<Interaction>>,
+    mut next_state: ResMut<NextState<ScreenState>>,
+) {
+    if inpt.just_pressed(KeyCode::KeyO) {
+        next_state.set(ScreenState::Settings);
+        return;
+    }
+    if menu.paths.is_empty() {
+        return;
+    }
+    let count = menu.paths.len();
+    if inpt.any_just_pressed([KeyCode::KeyS, KeyCode::ArrowDown]) {
+        menu.selected = (menu.selected + 1) % count;
+    }
+    if inpt.any_just_pressed([KeyCode::KeyW, KeyCode::ArrowUp]) {
+        menu.selected = (menu.selected + count - 1) % count;
+    }
+    let mut play = inpt.just_pressed(KeyCode::Enter);
+    for (entry, interaction) in entry_query.iter() {
+        match interaction {
+            Interaction::Pressed => {
+                menu.selected = entry.0;
+                play = true;
+            }
+            Interaction::Hovered => menu.selected = entry.0,
+            Interaction::None => {}
+        }
+    }
+    if play {
+        commands.insert_resource(LevelLoader::Path(menu.paths[menu.selected].clone()));
+        next_state.set(ScreenState::Game);
+    }
+}
+
+fn highlight_selected(
+    menu: Res<MenuLevels>,
+    mut entry_query: Query<(&LevelEntry, &mut BackgroundColor)>,
+) {
+    for (entry, mut color) in entry_query.iter_mut() {
+        *color = if entry.0 == menu.selected {
+            SELECTED_COLOR
+        } else {
+            UNSELECTED_COLOR
+        }
+        .into();
+    }
+}
@@ -0,0 +1,775 @@
+//! Online matches between two people, one playing as the player and the other as the pursuer, plus anyone watching.
+//! Only built with the `multiplayer` feature.
+//!
+//! Games meet in rooms on a relay server (`webgame-cli relay`), picking their roles and readying up in the lobby
+//! (see `lobby`). Matches run in lockstep: every game simulates the whole level one fixed tick at a time, and players
+//! only send each other their own agent's inputs. Inputs are scheduled `input_delay` ticks ahead, so they usually reach
+//! the other games before they're needed. A game waits if they don't, instead of guessing. The host picks levels,
+//! spawn points and random seeds, and sends them to everyone else when each level starts, so every game starts every
+//! level in the same state. Checksums of the state are compared regularly to catch games that have drifted apart
+//! anyway.
+
+use std::{collections::HashMap, str::FromStr, time::Duration};
+
+use bevy::{prelude::*, time::TimeUpdateStrategy, utils::Instant};
+use bevy_rapier2d::prelude::*;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    gridworld::{
+        move_agents, reset_level, set_keyboard_action, setup_entities, GameId, LevelLayout,
+        LoadedLevelData, NextAction, PlayerAgent, PursuerAgent, RemoteControlled, ResetEvent,
+        ShouldRun, SpawnRng,
+    },
+    lobby::LobbyPlugin,
+    replay::{
+        reseed_level, start_tick, state_checksum, RecordedAction, ReplayRecorder, CHECKSUM_INTERVAL,
+    },
+    transport::{self, Transport, TransportEvent},
+};
+
+/// The relay server used if none is given.
+pub const DEFAULT_SERVER_URL: &str = "ws://localhost:9000";
+/// How many ticks ahead inputs are scheduled if no delay is given.
+pub const DEFAULT_INPUT_DELAY: u32 = 3;
+/// How many letters room codes have.
+pub const ROOM_CODE_LEN: usize = 4;
+/// How much game time each tick covers. Like library builds, matches use a fixed timestep so every game simulates the
+/// same steps, but a short one, since people are playing.
+pub const LOCKSTEP_TS: f32 = 1. / 60.;
+/// The most real time games try to catch up on if they fall behind.
+const MAX_CATCH_UP: Duration = Duration::from_millis(250);
+
+/// Plays online matches through a relay server, and adds the lobby screen for setting them up.
+#[derive(Clone)]
+pub struct MultiplayerPlugin {
+    /// The relay server's address, e.g. `ws://localhost:9000`.
+    pub server_url: String,
+    pub session: MultiplayerSession,
+    /// How many ticks after being pressed inputs take effect. Higher delays hide more latency, but feel less
+    /// responsive. At least 1.
+    pub input_delay: u32,
+}
+
+impl Plugin for MultiplayerPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_non_send_resource(Connection::default())
+            .insert_resource(MultiplayerState {
+                session: self.session.clone(),
+                server_url: self.server_url.clone(),
+                role: None,
+                lobby: None,
+                peer_connected: false,
+                last_error: None,
+                ready_sent: false,
+            })
+            .insert_resource(Lockstep::new(self.input_delay))
+            .add_plugins(LobbyPlugin)
+            .add_systems(
+                Startup,
+                (
+                    use_fixed_timestep,
+                    connect_to_relay.run_if(|state: Res<MultiplayerState>| {
+                        state.session != MultiplayerSession::Lobby
+                    }),
+                ),
+            )
+            .add_systems(
+                PreUpdate,
+                (
+                    restart_lockstep.run_if(resource_added::<LevelLayout>),
+                    begin_tick.after(start_tick),
+                )
+                    .chain(),
+            )
+            .add_systems(
+                Update,
+                (
+                    receive_messages,
+                    share_level
+                        .run_if(resource_added::<LevelLayout>)
+                        .after(receive_messages)
+                        .after(reset_level)
+                        .before(setup_entities),
+                    mark_remote_agents.after(receive_messages),
+                    set_keyboard_action::<PursuerAgent>
+                        .run_if(resource_exists::<ShouldRun>.and_then(is_role(Role::Pursuer))),
+                    exchange_inputs
+                        .run_if(resource_exists::<ShouldRun>)
+                        .after(mark_remote_agents)
+                        .after(set_keyboard_action::<PlayerAgent>)
+                        .after(set_keyboard_action::<PursuerAgent>)
+                        .before(move_agents),
+                ),
+            )
+            .add_systems(Last, (finish_tick, pace_lockstep).chain());
+    }
+}
+
+impl MultiplayerPlugin {
+    /// Reads a session from a URL query string, like `?host=pursuer` or `?join=ABCD`. Without either, the lobby is
+    /// used. A relay server can be given with `server=<url>`, and an input delay with `delay=<ticks>`.
+    pub fn from_query(query: &str) -> Self {
+        let mut server_url = DEFAULT_SERVER_URL.to_string();
+        let mut session = MultiplayerSession::Lobby;
+        let mut input_delay = DEFAULT_INPUT_DELAY;
+        for (key, value) in query
+            .trim_start_matches('?')
+            .split('&')
+            .filter_map(|param| param.split_once('='))
+        {
+            match key {
+                "server" => server_url = value.into(),
+                "host" => match value.parse() {
+                    Ok(role) => session = MultiplayerSession::Host(role),
+                    Err(err) => warn!("{err}"),
+                },
+                "join" => session = MultiplayerSession::Join(value.into()),
+                "delay" => match value.parse() {
+                    Ok(delay) => input_delay = delay,
+                    Err(err) => warn!("Invalid input delay \"{value}\": {err}"),
+                },
+                _ => (),
+            }
+        }
+        Self {
+            server_url,
+            session,
+            input_delay,
+        }
+    }
+
+    /// Reads a session from the page's URL. See `from_query`. Returns `None` if the game isn't running in a page.
+    #[cfg(target_arch = "wasm32")]
+    pub fn from_location() -> Option<Self> {
+        let query = web_sys::window()?.location().search().ok()?;
+        Some(Self::from_query(&query))
+    }
+
+    /// Returns whether this game picks the levels. Games using the lobby pick levels until they join someone else's
+    /// room.
+    pub fn is_host(&self) -> bool {
+        !matches!(self.session, MultiplayerSession::Join(_))
+    }
+}
+
+/// How to get into a match.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MultiplayerSession {
+    /// Opens a room with the given role, and readies up. The relay server replies with a code for others to join with.
+    Host(LobbyRole),
+    /// Joins the room with the given code in whichever role is free, and readies up.
+    Join(String),
+    /// Waits for rooms to be hosted or joined from the lobby screen.
+    Lobby,
+}
+
+/// Which agent someone controls.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Player,
+    Pursuer,
+}
+
+impl Role {
+    pub const ALL: [Self; 2] = [Self::Player, Self::Pursuer];
+
+    /// Returns the role the other person plays.
+    pub fn other(&self) -> Self {
+        match self {
+            Self::Player => Self::Pursuer,
+            Self::Pursuer => Self::Player,
+        }
+    }
+}
+
+/// What someone does in a room: control one of the agents, or watch.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum LobbyRole {
+    Player,
+    Pursuer,
+    Spectator,
+}
+
+impl LobbyRole {
+    /// Returns the agent this role controls, if any.
+    pub fn agent(&self) -> Option<Role> {
+        match self {
+            Self::Player => Some(Role::Player),
+            Self::Pursuer => Some(Role::Pursuer),
+            Self::Spectator => None,
+        }
+    }
+}
+
+impl From<Role> for LobbyRole {
+    fn from(role: Role) -> Self {
+        match role {
+            Role::Player => Self::Player,
+            Role::Pursuer => Self::Pursuer,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("Unknown role \"{0}\", expected \"player\", \"pursuer\" or \"spectator\"")]
+pub struct UnknownRoleError(pub String);
+
+impl FromStr for LobbyRole {
+    type Err = UnknownRoleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "player" => Ok(Self::Player),
+            "pursuer" => Ok(Self::Pursuer),
+            "spectator" => Ok(Self::Spectator),
+            _ => Err(UnknownRoleError(s.into())),
+        }
+    }
+}
+
+/// Messages sent between games and the relay server, as JSON.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RelayMessage {
+    /// Asks the server to open a room, where the sender starts out as `role`.
+    Host { role: LobbyRole },
+    /// Asks the server to join the room with the code `room`.
+    Join { room: String },
+    /// Asks the server to change the sender's role. Agents can only be controlled by one person at a time.
+    SelectRole { role: LobbyRole },
+    /// Tells the server whether the sender is ready to start.
+    Ready { ready: bool },
+    /// Sent by the server to everyone in a room whenever someone joins, leaves, or changes their role or readiness.
+    Lobby(LobbyInfo),
+    /// Sent by the server to everyone in a room once both agents have someone controlling them and everyone is ready,
+    /// with the role the recipient plays.
+    Started { role: LobbyRole },
+    /// Sent by the server when someone controlling an agent leaves a match.
+    PeerLeft,
+    /// Sent by the server to everyone left in a room when its host leaves, since no one else can pick its levels.
+    RoomClosed,
+    /// Sent by the server when it can't do what was asked.
+    Error { message: String },
+    /// The sender's agent's input for a tick. Relayed to everyone else in the room.
+    Input(LockstepInput),
+    /// The level the host just started, with its spawn points filled in. Relayed to everyone else in the room.
+    Reset {
+        level: LoadedLevelData,
+        /// The seed for the level's random numbers.
+        seed: u64,
+        /// How many levels the host has started this match, including this one.
+        round: u32,
+    },
+    /// The sender's `state_checksum` at the end of a tick. Relayed to everyone else in the room.
+    Checksum {
+        round: u32,
+        tick: u64,
+        checksum: u64,
+    },
+}
+
+/// Who's in a room, as seen by one of its members.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LobbyInfo {
+    /// The code others join the room with.
+    pub room: String,
+    pub members: Vec<LobbyMember>,
+    /// The recipient's index in `members`.
+    pub you: usize,
+}
+
+impl LobbyInfo {
+    /// Returns the recipient's entry.
+    pub fn me(&self) -> Option<&LobbyMember> {
+        self.members.get(self.you)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct LobbyMember {
+    pub role: LobbyRole,
+    pub ready: bool,
+    /// Whether this member opened the room, and picks the levels.
+    pub host: bool,
+}
+
+/// An agent's input for one tick, as sent to the other games.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct LockstepInput {
+    /// The agent the input is for.
+    pub role: Role,
+    /// The level this input is for. Inputs for other levels are dropped.
+    pub round: u32,
+    /// The tick the input takes effect on.
+    pub tick: u64,
+    pub action: RecordedAction,
+}
+
+/// The connection to the relay server, if one has been opened.
+/// This isn't `Send` in browsers, so it's stored as a non-send resource.
+#[derive(Default)]
+pub struct Connection {
+    socket: Option<Box<dyn Transport>>,
+}
+
+impl Connection {
+    /// Sends a message to the relay server, if connected.
+    pub fn send(&mut self, msg: &RelayMessage) {
+        let json = serde_json::to_string(msg).expect("relay messages should always serialize");
+        if let Some(socket) = &mut self.socket {
+            socket.send(json);
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.socket.is_some()
+    }
+}
+
+/// The state of the online match.
+#[derive(Resource, Debug)]
+pub struct MultiplayerState {
+    pub session: MultiplayerSession,
+    pub server_url: String,
+    /// The role this game plays, once the match has started.
+    pub role: Option<LobbyRole>,
+    /// The room this game is in, if any.
+    pub lobby: Option<LobbyInfo>,
+    /// Whether someone else is controlling an agent in the match.
+    pub peer_connected: bool,
+    /// The last thing the relay server couldn't do, until the room changes.
+    pub last_error: Option<String>,
+    /// Whether this game has readied up on its own, for sessions that skip the lobby screen.
+    ready_sent: bool,
+}
+
+impl MultiplayerState {
+    /// Returns the agent this game controls in the match, if any.
+    pub fn agent(&self) -> Option<Role> {
+        self.role.and_then(|role| role.agent())
+    }
+
+    /// Returns whether this game opened the room it's in.
+    pub fn is_hosting(&self) -> bool {
+        self.lobby
+            .as_ref()
+            .and_then(LobbyInfo::me)
+            .is_some_and(|me| me.host)
+    }
+}
+
+/// The state of the lockstep simulation for the current level.
+#[derive(Resource)]
+pub struct Lockstep {
+    /// How many ticks ahead local inputs are scheduled.
+    pub input_delay: u64,
+    /// How many levels the host has started this match.
+    pub round: u32,
+    /// The seed for the current level's random numbers.
+    seed: u64,
+    /// Whether the current round's level has been set up.
+    started: bool,
+    /// The tick being played, or the next one to play.
+    pub tick: u64,
+    /// Whether a tick is being played this frame.
+    ticking: bool,
+    /// This game's inputs for upcoming ticks, by tick.
+    local_inputs: HashMap<u64, RecordedAction>,
+    /// The other games' inputs for upcoming ticks, by agent and tick.
+    remote_inputs: HashMap<(Role, u64), RecordedAction>,
+    /// Checksums waiting for the other game's, by tick.
+    local_checksums: HashMap<u64, u64>,
+    remote_checksums: HashMap<u64, u64>,
+    /// Set once the games' checksums differ, to the tick they first did.
+    pub desynced_at: Option<u64>,
+    /// Game time owed by the ticks that should have played by now.
+    budget: Duration,
+    last_frame: Option<Instant>,
+    /// Whether the game is being held back by removing `ShouldRun`.
+    frozen: bool,
+}
+
+impl Lockstep {
+    fn new(input_delay: u32) -> Self {
+        Self {
+            input_delay: input_delay.max(1) as u64,
+            round: 0,
+            seed: 0,
+            started: false,
+            tick: 0,
+            ticking: false,
+            local_inputs: HashMap::new(),
+            remote_inputs: HashMap::new(),
+            local_checksums: HashMap::new(),
+            remote_checksums: HashMap::new(),
+            desynced_at: None,
+            budget: Duration::ZERO,
+            last_frame: None,
+            frozen: false,
+        }
+    }
+
+    /// Forgets the last level's inputs, ready for the next one to be set up.
+    fn new_round(&mut self, round: u32, seed: u64) {
+        self.round = round;
+        self.seed = seed;
+        self.started = false;
+        self.local_inputs.clear();
+        self.remote_inputs.clear();
+        self.local_checksums.clear();
+        self.remote_checksums.clear();
+        self.desynced_at = None;
+    }
+
+    /// Compares checksums both games have sent for the same tick.
+    fn compare_checksums(&mut self) {
+        let ticks: Vec<_> = self
+            .local_checksums
+            .keys()
+            .filter(|tick| self.remote_checksums.contains_key(tick))
+            .copied()
+            .collect();
+        for tick in ticks {
+            let local = self.local_checksums.remove(&tick);
+            let remote = self.remote_checksums.remove(&tick);
+            if local != remote && self.desynced_at.is_none() {
+                error!(
+                    "The games have desynced as of tick {tick} of round {}",
+                    self.round
+                );
+                self.desynced_at = Some(tick);
+            }
+        }
+    }
+
+    /// Returns whether the other games' inputs for the next tick are in.
+    fn has_inputs(&self, local: Option<Role>) -> bool {
+        self.tick < self.input_delay
+            || Role::ALL
+                .into_iter()
+                .filter(|&role| Some(role) != local)
+                .all(|role| self.remote_inputs.contains_key(&(role, self.tick)))
+    }
+}
+
+/// Returns a run condition that's true when this game controls the agent `role`.
+fn is_role(role: Role) -> impl Fn(Res<MultiplayerState>) -> bool {
+    move |state| state.agent() == Some(role)
+}
+
+/// Returns whether this game is in a match with someone.
+fn is_active(state: &MultiplayerState) -> bool {
+    state.peer_connected && state.role.is_some()
+}
+
+/// Makes ticks cover a fixed amount of game time, and steps physics by the same amount.
+pub fn use_fixed_timestep(
+    mut time_strategy: ResMut<TimeUpdateStrategy>,
+    mut rapier_config: ResMut<RapierConfiguration>,
+) {
+    *time_strategy = TimeUpdateStrategy::ManualDuration(Duration::from_secs_f32(LOCKSTEP_TS));
+    rapier_config.timestep_mode = TimestepMode::Fixed {
+        dt: LOCKSTEP_TS,
+        substeps: 1,
+    };
+}
+
+/// Connects to the relay server, unless already connected.
+pub fn connect_to_relay(mut conn: NonSendMut<Connection>, state: Res<MultiplayerState>) {
+    if conn.is_connected() {
+        return;
+    }
+    match transport::connect(&state.server_url) {
+        Ok(socket) => conn.socket = Some(socket),
+        Err(err) => error!("Couldn't connect to {}: {err}", state.server_url),
+    }
+}
+
+/// Handles messages from the relay server.
+fn receive_messages(
+    mut conn: NonSendMut<Connection>,
+    mut state: ResMut<MultiplayerState>,
+    mut lockstep: ResMut<Lockstep>,
+    mut ev_reset: EventWriter<ResetEvent>,
+    level: Option<Res<LevelLayout>>,
+) {
+    while let Some(ev) = conn.socket.as_mut().and_then(|socket| socket.try_recv()) {
+        let msg = match ev {
+            TransportEvent::Opened => {
+                let request = match &state.session {
+                    MultiplayerSession::Host(role) => RelayMessage::Host { role: *role },
+                    MultiplayerSession::Join(room) => RelayMessage::Join { room: room.clone() },
+                    MultiplayerSession::Lobby => continue,
+                };
+                conn.send(&request);
+                continue;
+            }
+            TransportEvent::Message(text) => match serde_json::from_str(&text) {
+                Ok(msg) => msg,
+                Err(err) => {
+                    warn!("Ignoring invalid message from the relay server: {err}");
+                    continue;
+                }
+            },
+            TransportEvent::Error(err) => {
+                error!("Multiplayer connection error: {err}");
+                continue;
+            }
+            TransportEvent::Closed => {
+                warn!("Lost connection to the relay server");
+                // Connect again the next time the lobby is opened
+                conn.socket = None;
+                state.lobby = None;
+                state.role = None;
+                state.peer_connected = false;
+                break;
+            }
+        };
+        match msg {
+            RelayMessage::Lobby(info) => {
+                // Sessions that skip the lobby screen ready up as soon as they're in a room
+                let auto_ready = state.session != MultiplayerSession::Lobby && !state.ready_sent;
+                if auto_ready && info.me().is_some_and(|me| !me.ready) {
+                    conn.send(&RelayMessage::Ready { ready: true });
+                    state.ready_sent = true;
+                }
+                if state.lobby.is_none() {
+                    info!("Joined room {}", info.room);
+                }
+                state.lobby = Some(info);
+                state.last_error = None;
+            }
+            RelayMessage::Started { role } => {
+                info!("Match started, playing as the {role:?}");
+                state.role = Some(role);
+                state.peer_connected = true;
+                lockstep.started = false;
+                // Restart the host's level so every game plays it from the beginning. Everyone else waits for the
+                // host to send it
+                if state.is_hosting() && level.is_some() {
+                    ev_reset.send(ResetEvent { level: None });
+                }
+            }
+            RelayMessage::PeerLeft => {
+                warn!("The other player left");
+                state.peer_connected = false;
+            }
+            RelayMessage::RoomClosed => {
+                warn!("The host closed the room");
+                state.lobby = None;
+                state.role = None;
+                state.peer_connected = false;
+                state.last_error = Some("The host closed the room".into());
+            }
+            RelayMessage::Error { message } => {
+                error!("Relay server error: {message}");
+                state.last_error = Some(message);
+            }
+            RelayMessage::Input(input) => {
+                if input.round == lockstep.round {
+                    lockstep
+                        .remote_inputs
+                        .insert((input.role, input.tick), input.action);
+                }
+            }
+            RelayMessage::Reset { level, seed, round } => {
+                lockstep.new_round(round, seed);
+                ev_reset.send(ResetEvent {
+                    level: Some(LevelLayout::from_data(&level)),
+                });
+            }
+            RelayMessage::Checksum {
+                round,
+                tick,
+                checksum,
+            } => {
+                // Only players send checksums, so spectators would get two for every tick
+                if round == lockstep.round && state.agent().is_some() {
+                    lockstep.remote_checksums.insert(tick, checksum);
+                    lockstep.compare_checksums();
+                }
+            }
+            RelayMessage::Host { .. }
+            | RelayMessage::Join { .. }
+            | RelayMessage::SelectRole { .. }
+            | RelayMessage::Ready { .. } => (),
+        }
+    }
+}
+
+/// Starts a new round when the host starts a level, picking where the agents spawn and a seed for the level's random
+/// numbers, and sends it all to everyone else.
+fn share_level(
+    mut conn: NonSendMut<Connection>,
+    state: Res<MultiplayerState>,
+    mut lockstep: ResMut<Lockstep>,
+    mut level: ResMut<LevelLayout>,
+    mut spawn_rng: ResMut<SpawnRng>,
+) {
+    if !is_active(&state) || !state.is_hosting() {
+        return;
+    }
+    // Spawns are random unless they're fixed, so fix them here, before the level is set up
+    level.fix_spawns(&mut spawn_rng.0);
+
+    let seed = rand::random();
+    let round = lockstep.round + 1;
+    lockstep.new_round(round, seed);
+    conn.send(&RelayMessage::Reset {
+        level: level.to_data(),
+        seed,
+        round,
+    });
+}
+
+/// Starts counting ticks from the beginning of the level that was just set up.
+fn restart_lockstep(mut lockstep: ResMut<Lockstep>) {
+    lockstep.tick = 0;
+    lockstep.started = true;
+}
+
+/// Checks whether a tick is played this frame, and seeds the level's random numbers on its first one.
+fn begin_tick(
+    mut commands: Commands,
+    state: Res<MultiplayerState>,
+    mut lockstep: ResMut<Lockstep>,
+    recorder: Option<ResMut<ReplayRecorder>>,
+    should_run: Option<Res<ShouldRun>>,
+) {
+    lockstep.ticking = should_run.is_some() && lockstep.started && is_active(&state);
+    if lockstep.ticking && lockstep.tick == 0 {
+        reseed_level(&mut commands, recorder, lockstep.seed);
+    }
+}
+
+/// Marks the agents other people control, so the keyboard doesn't move them.
+fn mark_remote_agents(
+    mut commands: Commands,
+    state: Res<MultiplayerState>,
+    player_query: Query<Entity, (With<PlayerAgent>, Without<RemoteControlled>)>,
+    pursuer_query: Query<Entity, (With<PursuerAgent>, Without<RemoteControlled>)>,
+) {
+    if state.role.is_none() {
+        return;
+    }
+    for role in Role::ALL
+        .into_iter()
+        .filter(|&role| Some(role) != state.agent())
+    {
+        let remote = match role {
+            Role::Player => player_query.get_single(),
+            Role::Pursuer => pursuer_query.get_single(),
+        };
+        if let Ok(e) = remote {
+            commands.entity(e).insert(RemoteControlled);
+        }
+    }
+}
+
+/// Schedules this game's agent's input `input_delay` ticks ahead and sends it to the other games, then gives both
+/// agents the inputs scheduled for this tick.
+fn exchange_inputs(
+    mut conn: NonSendMut<Connection>,
+    state: Res<MultiplayerState>,
+    mut lockstep: ResMut<Lockstep>,
+    mut player_query: Query<&mut NextAction, (With<PlayerAgent>, Without<PursuerAgent>)>,
+    mut pursuer_query: Query<&mut NextAction, (With<PursuerAgent>, Without<PlayerAgent>)>,
+) {
+    if !lockstep.ticking {
+        return;
+    }
+    let tick = lockstep.tick;
+    let local = state.agent();
+    for role in Role::ALL {
+        let next_action = match role {
+            Role::Player => player_query.get_single_mut(),
+            Role::Pursuer => pursuer_query.get_single_mut(),
+        };
+        let Ok(mut next_action) = next_action else {
+            continue;
+        };
+        // Agents stand still until the first inputs take effect
+        let action = if Some(role) == local {
+            let scheduled = tick + lockstep.input_delay;
+            let input = RecordedAction::from(next_action.as_ref());
+            lockstep.local_inputs.insert(scheduled, input);
+            conn.send(&RelayMessage::Input(LockstepInput {
+                role,
+                round: lockstep.round,
+                tick: scheduled,
+                action: input,
+            }));
+            lockstep.local_inputs.remove(&tick)
+        } else {
+            lockstep.remote_inputs.remove(&(role, tick))
+        };
+        action.unwrap_or_default().apply(&mut next_action);
+    }
+}
+
+/// Sends players' checksums of the state every `CHECKSUM_INTERVAL` ticks, and moves on to the next tick.
+fn finish_tick(
+    mut conn: NonSendMut<Connection>,
+    state: Res<MultiplayerState>,
+    mut lockstep: ResMut<Lockstep>,
+    id_query: Query<(&GameId, &GlobalTransform)>,
+) {
+    if !lockstep.ticking {
+        return;
+    }
+    let tick = lockstep.tick;
+    if tick % CHECKSUM_INTERVAL as u64 == 0 && state.agent().is_some() {
+        let checksum = state_checksum(&id_query);
+        lockstep.local_checksums.insert(tick, checksum);
+        lockstep.compare_checksums();
+        conn.send(&RelayMessage::Checksum {
+            round: lockstep.round,
+            tick,
+            checksum,
+        });
+    }
+    lockstep.tick += 1;
+}
+
+/// Decides whether the next frame plays a tick, and if not, holds the game back until it should. Ticks are played at
+/// the rate they cover game time, and in a match, only once the other games' inputs for them have arrived.
+fn pace_lockstep(
+    mut commands: Commands,
+    state: Res<MultiplayerState>,
+    mut lockstep: ResMut<Lockstep>,
+    should_run: Option<Res<ShouldRun>>,
+    mut time_strategy: ResMut<TimeUpdateStrategy>,
+    mut rapier_config: ResMut<RapierConfiguration>,
+) {
+    let now = Instant::now();
+    let elapsed = lockstep
+        .last_frame
+        .map(|last| now - last)
+        .unwrap_or_default();
+    let tick_duration = Duration::from_secs_f32(LOCKSTEP_TS);
+
+    let lockstep = lockstep.as_mut();
+    lockstep.last_frame = Some(now);
+    lockstep.budget = (lockstep.budget + elapsed).min(MAX_CATCH_UP);
+    let ready = !is_active(&state) || !lockstep.started || lockstep.has_inputs(state.agent());
+    if ready && lockstep.budget >= tick_duration {
+        lockstep.budget -= tick_duration;
+        *time_strategy = TimeUpdateStrategy::ManualDuration(tick_duration);
+        if lockstep.frozen {
+            commands.insert_resource(ShouldRun);
+            rapier_config.physics_pipeline_active = true;
+            lockstep.frozen = false;
+        }
+    } else {
+        *time_strategy = TimeUpdateStrategy::ManualDuration(Duration::ZERO);
+        if !lockstep.frozen && should_run.is_some() {
+            commands.remove_resource::<ShouldRun>();
+            rapier_config.physics_pipeline_active = false;
+            lockstep.frozen = true;
+        }
+    }
+}
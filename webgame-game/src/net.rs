@@ -1,21 +1,142 @@
 use bevy::{
-    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext, LoadState},
     prelude::*,
     utils::BoxedFuture,
 };
+use std::{marker::PhantomData, str::FromStr};
+
 use candle_nn as nn;
+use rand::{
+    distributions::{Distribution, WeightedError, WeightedIndex},
+    rngs::StdRng,
+    Rng, SeedableRng,
+};
 use serde::Deserialize;
+use thiserror::Error;
+
+use crate::{
+    gridworld::{
+        move_agents, Agent, LevelLayout, LevelMeta, NextAction, PlayerAgent, PursuerAgent,
+        ResetEvent, ShouldRun, GRID_CELL_SIZE,
+    },
+    observer::Observer,
+};
 
 /// Simplifies working with neural networks, particularly loading them.
 pub struct NetPlugin;
 
 impl Plugin for NetPlugin {
     fn build(&self, app: &mut App) {
-        app.init_asset::<SafeTensorsData>()
+        app.init_resource::<ComputeDevice>()
+            .init_resource::<NetPrecision>()
+            .init_asset::<SafeTensorsData>()
             .init_asset_loader::<SafeTensorsDataLoader>();
     }
 }
 
+/// Which device neural networks run on.
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ComputeDevice {
+    /// Uses the first CUDA GPU if there is one, then the first Metal GPU, then the CPU.
+    #[default]
+    Auto,
+    Cpu,
+    /// A CUDA GPU, by index. Needs the `cuda` feature.
+    Cuda(usize),
+    /// A Metal GPU, by index. Needs the `metal` feature.
+    Metal(usize),
+}
+
+#[derive(Debug, Error)]
+#[error("Unknown compute device \"{0}\", expected \"auto\", \"cpu\", \"cuda[:<index>]\", or \"metal[:<index>]\"")]
+pub struct UnknownComputeDeviceError(pub String);
+
+impl FromStr for ComputeDevice {
+    type Err = UnknownComputeDeviceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, ordinal) = match s.split_once(':') {
+            Some((kind, ordinal)) => {
+                let ordinal = ordinal
+                    .parse()
+                    .map_err(|_| UnknownComputeDeviceError(s.into()))?;
+                (kind, Some(ordinal))
+            }
+            None => (s, None),
+        };
+        match (kind, ordinal) {
+            ("auto", None) => Ok(Self::Auto),
+            ("cpu", None) => Ok(Self::Cpu),
+            ("cuda", ordinal) => Ok(Self::Cuda(ordinal.unwrap_or(0))),
+            ("metal", ordinal) => Ok(Self::Metal(ordinal.unwrap_or(0))),
+            _ => Err(UnknownComputeDeviceError(s.into())),
+        }
+    }
+}
+
+impl ComputeDevice {
+    /// Returns the device to create tensors on. Falls back to the CPU if the device isn't available, such as when the
+    /// crate was built without the matching feature.
+    pub fn device(&self) -> candle_core::Device {
+        use candle_core::{utils, Device};
+
+        let device = match *self {
+            Self::Cpu => return Device::Cpu,
+            Self::Auto if utils::cuda_is_available() => Device::new_cuda(0),
+            Self::Auto if utils::metal_is_available() => Device::new_metal(0),
+            Self::Auto => return Device::Cpu,
+            Self::Cuda(ordinal) => Device::new_cuda(ordinal),
+            Self::Metal(ordinal) => Device::new_metal(ordinal),
+        };
+        device.unwrap_or_else(|err| {
+            warn!("Could not use {self:?} for neural networks, falling back to the CPU: {err}");
+            Device::Cpu
+        })
+    }
+}
+
+/// The floating point format networks store their weights and run in. Weights are converted when they're loaded, so
+/// checkpoints are always saved as `f32`.
+///
+/// Half precision formats use half the memory and run faster on hardware that supports them, at the cost of some
+/// accuracy in the action probabilities. There's no integer format, since candle only quantizes matrix multiplications
+/// and these networks are mostly convolutions.
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NetPrecision {
+    #[default]
+    F32,
+    F16,
+    BF16,
+}
+
+#[derive(Debug, Error)]
+#[error("Unknown precision \"{0}\", expected \"f32\", \"f16\", or \"bf16\"")]
+pub struct UnknownNetPrecisionError(pub String);
+
+impl FromStr for NetPrecision {
+    type Err = UnknownNetPrecisionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "f32" => Ok(Self::F32),
+            "f16" => Ok(Self::F16),
+            "bf16" => Ok(Self::BF16),
+            _ => Err(UnknownNetPrecisionError(s.into())),
+        }
+    }
+}
+
+impl NetPrecision {
+    /// Returns the matching tensor type.
+    pub fn dtype(&self) -> candle_core::DType {
+        match self {
+            Self::F32 => candle_core::DType::F32,
+            Self::F16 => candle_core::DType::F16,
+            Self::BF16 => candle_core::DType::BF16,
+        }
+    }
+}
+
 /// A component that wraps a neural network.
 ///
 /// Handles loading the safetensors file and initializing the model when ready.
@@ -46,6 +167,22 @@ pub trait LoadableNN: std::marker::Send + std::marker::Sync + 'static {
 #[derive(Asset, TypePath, Debug, Deserialize, Clone)]
 pub struct SafeTensorsData(pub Vec<u8>);
 
+impl SafeTensorsData {
+    /// Initializes a network from these weights on a device, converting them to `precision`.
+    pub fn load_net<T: LoadableNN>(
+        &self,
+        compute_device: ComputeDevice,
+        precision: NetPrecision,
+    ) -> candle_core::Result<T> {
+        let vb = nn::VarBuilder::from_buffered_safetensors(
+            self.0.clone(),
+            precision.dtype(),
+            &compute_device.device(),
+        )?;
+        T::load(vb)
+    }
+}
+
 #[derive(Default)]
 pub struct SafeTensorsDataLoader;
 
@@ -76,22 +213,1093 @@ impl AssetLoader for SafeTensorsDataLoader {
 }
 
 /// Checks if safetensors are loaded and initializes the network if so.
-#[allow(dead_code)]
-fn load_weights_into_net<T: LoadableNN>(
-    mut net_query: Query<&mut NNWrapper<T>>,
+/// If the weights don't fit the network, logs an error and removes the wrapper.
+pub fn load_weights_into_net<T: LoadableNN>(
+    mut commands: Commands,
+    mut net_query: Query<(Entity, &mut NNWrapper<T>)>,
     st_assets: Res<Assets<SafeTensorsData>>,
+    (compute_device, precision): (Res<ComputeDevice>, Res<NetPrecision>),
 ) {
-    for mut net in net_query.iter_mut() {
+    for (e, mut net) in net_query.iter_mut() {
         if net.net.is_none() {
             if let Some(st_data) = st_assets.get(&net.weights) {
-                let vb = nn::VarBuilder::from_buffered_safetensors(
-                    st_data.0.clone(),
-                    candle_core::DType::F32,
-                    &candle_core::Device::Cpu,
-                )
-                .unwrap();
-                net.net = Some(T::load(vb).expect("Couldn't load model."));
+                match st_data.load_net(*compute_device, *precision) {
+                    Ok(loaded) => net.net = Some(loaded),
+                    Err(err) => {
+                        error!("Couldn't load model: {err}");
+                        commands.entity(e).remove::<NNWrapper<T>>();
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Rust port of the policy network in `webgame/models.py`.
+///
+/// Only supports checkpoints trained without objects or position encodings. The input grid has shape
+/// `(batch_size, 9, size, size)`: 7 scalar features tiled across the grid, then walls, then the agent's belief.
+#[allow(dead_code)]
+pub struct PolicyNet {
+    backbone: [nn::Conv2d; 3],
+    convs: [nn::Conv2d; 2],
+    linears: [nn::Linear; 3],
+    device: candle_core::Device,
+    /// The type the weights were loaded as. Inputs are converted to it before running.
+    dtype: candle_core::DType,
+}
+
+/// The number of input channels `PolicyNet` expects.
+#[allow(dead_code)]
+pub const POLICY_CHANNELS: usize = 9;
+
+/// The number of actions `PolicyNet` picks between. Matches `AgentAction` in `webgame_rust`.
+pub const ACTION_COUNT: usize = 10;
+/// The action that toggles nearby objects.
+pub const TOGGLE_ACTION: usize = 9;
+
+/// Returns the direction an action moves an agent in. Matches `AgentAction` in `webgame_rust`.
+pub fn action_dir(action: usize) -> Vec2 {
+    match action {
+        1 => Vec2::Y,
+        2 => (Vec2::Y + Vec2::X).normalize(),
+        3 => Vec2::X,
+        4 => (-Vec2::Y + Vec2::X).normalize(),
+        5 => -Vec2::Y,
+        6 => (-Vec2::Y + -Vec2::X).normalize(),
+        7 => -Vec2::X,
+        8 => (Vec2::Y + -Vec2::X).normalize(),
+        _ => Vec2::ZERO,
+    }
+}
+
+/// Builds the input grid that `PolicyNet` and `MeasureNet` expect for an agent at `pos` facing `dir`, flattened in
+/// channel, then row, then column order. `other_pos` is where the agent sees its opponent, if it does. Matches
+/// `GameEnv` in `webgame/envs.py` when no filter is used, so the belief channel is empty.
+pub fn policy_grid(level: &LevelLayout, pos: Vec2, dir: Vec2, other_pos: Option<Vec2>) -> Vec<f32> {
+    let level_size = Vec2::new(level.width as f32, level.height as f32) * GRID_CELL_SIZE;
+    let mut scalars = [0.; 7];
+    scalars[0] = 0.5 + pos.x / level_size.x;
+    scalars[1] = 0.5 + pos.y / level_size.y;
+    scalars[2] = dir.x;
+    scalars[3] = dir.y;
+    if let Some(other_pos) = other_pos {
+        scalars[4] = 1.;
+        scalars[5] = 0.5 + other_pos.x / level_size.x;
+        scalars[6] = 0.5 + other_pos.y / level_size.y;
+    }
+
+    let cell_count = level.walls.len();
+    let mut grid = Vec::with_capacity(POLICY_CHANNELS * cell_count);
+    for scalar in scalars {
+        grid.extend(std::iter::repeat(scalar).take(cell_count));
+    }
+    grid.extend(level.walls.iter().map(|wall| wall as u8 as f32));
+    grid.extend(std::iter::repeat(0.).take(cell_count));
+    grid
+}
+
+/// Loads the convolutions that `PolicyNet` and `RecurrentPolicyNet` share, as `(backbone, convs)`.
+fn load_policy_convs(
+    vb: &nn::VarBuilder,
+) -> candle_core::Result<([nn::Conv2d; 3], [nn::Conv2d; 2])> {
+    let conv = |in_c, out_c, kernel, name: &str| {
+        nn::conv2d(
+            in_c,
+            out_c,
+            kernel,
+            nn::Conv2dConfig {
+                padding: kernel / 2,
+                ..Default::default()
+            },
+            vb.pp(name),
+        )
+    };
+    let backbone = [
+        conv(POLICY_CHANNELS, 16, 5, "backbone.grid_net.0")?,
+        conv(16, 16, 5, "backbone.grid_net.3")?,
+        conv(16, 32, 5, "backbone.grid_net.6")?,
+    ];
+    let convs = [conv(32, 32, 3, "net.0")?, conv(32, 16, 3, "net.2")?];
+    Ok((backbone, convs))
+}
+
+/// Runs the convolutions loaded by `load_policy_convs`, returning flattened features.
+fn run_policy_convs(
+    backbone: &[nn::Conv2d; 3],
+    convs: &[nn::Conv2d; 2],
+    grid: &candle_core::Tensor,
+) -> candle_core::Result<candle_core::Tensor> {
+    use candle_core::Module;
+
+    let mut x = grid.clone();
+    for conv in backbone.iter().chain(convs) {
+        x = conv.forward(&x)?.silu()?;
+    }
+    x.flatten_from(1)
+}
+
+impl LoadableNN for PolicyNet {
+    fn load(vb: nn::VarBuilder) -> candle_core::Result<Self> {
+        let (backbone, convs) = load_policy_convs(&vb)?;
+
+        // The first linear layer's input size depends on the grid size it was trained with
+        let flat_size = vb.pp("net.5").get_unchecked("weight")?.dim(1)?;
+        let action_count = vb.pp("net.9").get_unchecked("weight")?.dim(0)?;
+        let linears = [
+            nn::linear(flat_size, 256, vb.pp("net.5"))?,
+            nn::linear(256, 256, vb.pp("net.7"))?,
+            nn::linear(256, action_count, vb.pp("net.9"))?,
+        ];
+        Ok(Self {
+            backbone,
+            convs,
+            linears,
+            device: vb.device().clone(),
+            dtype: vb.dtype(),
+        })
+    }
+}
+
+#[allow(dead_code)]
+impl PolicyNet {
+    /// Returns `f32` action logits with shape `(batch_size, action_count)`.
+    pub fn forward(&self, grid: &candle_core::Tensor) -> candle_core::Result<candle_core::Tensor> {
+        use candle_core::Module;
+
+        let mut x = run_policy_convs(&self.backbone, &self.convs, &grid.to_dtype(self.dtype)?)?;
+        let [l1, l2, l3] = &self.linears;
+        x = l1.forward(&x)?.silu()?;
+        x = l2.forward(&x)?.silu()?;
+        l3.forward(&x)?.to_dtype(candle_core::DType::F32)
+    }
+
+    /// Returns action logits for a batch of input grids built by `policy_grid`, flattened into one list, on a level of
+    /// this size.
+    pub fn logits(
+        &self,
+        grids: Vec<f32>,
+        width: usize,
+        height: usize,
+    ) -> candle_core::Result<Vec<Vec<f32>>> {
+        let batch_size = grids.len() / (POLICY_CHANNELS * width * height).max(1);
+        let grids = candle_core::Tensor::from_vec(
+            grids,
+            (batch_size, POLICY_CHANNELS, height, width),
+            &self.device,
+        )?;
+        self.forward(&grids)?.to_vec2()
+    }
+}
+
+/// Rust port of `ValueNet` in `webgame/train_agents.py`, which has the same layers as `PolicyNet` but a single output.
+pub struct ValueNet(PolicyNet);
+
+impl LoadableNN for ValueNet {
+    fn load(vb: nn::VarBuilder) -> candle_core::Result<Self> {
+        PolicyNet::load(vb).map(Self)
+    }
+}
+
+impl ValueNet {
+    /// Estimates the return of the agent whose input grid was built by `policy_grid`.
+    pub fn value(&self, level: &LevelLayout, grid: Vec<f32>) -> candle_core::Result<f32> {
+        Ok(self.0.logits(grid, level.width, level.height)?[0][0])
+    }
+}
+
+/// The size of `RecurrentPolicyNet`'s hidden state.
+const RECURRENT_HIDDEN_DIM: usize = 256;
+
+/// Rust port of `RecurrentPolicyNet` in `webgame/models.py`, which is `PolicyNet` with a GRU or LSTM layer before its
+/// output. Which one is worked out from the weights.
+///
+/// Only supports checkpoints trained without objects or position encodings. Takes the same input grid as `PolicyNet`.
+pub struct RecurrentPolicyNet {
+    backbone: [nn::Conv2d; 3],
+    convs: [nn::Conv2d; 2],
+    linear: nn::Linear,
+    cell: RecurrentCell,
+    out: nn::Linear,
+    device: candle_core::Device,
+    /// The type the weights were loaded as. Inputs are converted to it before running.
+    dtype: candle_core::DType,
+}
+
+/// The recurrent layer of a `RecurrentPolicyNet`.
+enum RecurrentCell {
+    Gru(nn::GRU),
+    Lstm(nn::LSTM),
+}
+
+/// The hidden state of a `RecurrentPolicyNet`'s recurrent layer.
+#[derive(Clone, Debug)]
+pub enum RecurrentState {
+    Gru(nn::rnn::GRUState),
+    Lstm(nn::rnn::LSTMState),
+}
+
+impl LoadableNN for RecurrentPolicyNet {
+    fn load(vb: nn::VarBuilder) -> candle_core::Result<Self> {
+        let (backbone, convs) = load_policy_convs(&vb)?;
+        let flat_size = vb.pp("net.5").get_unchecked("weight")?.dim(1)?;
+        let linear = nn::linear(flat_size, RECURRENT_HIDDEN_DIM, vb.pp("net.5"))?;
+
+        // GRUs have 3 gates and LSTMs have 4, stacked in the input weights
+        let rnn_vb = vb.pp("rnn");
+        let gate_count = rnn_vb.get_unchecked("weight_ih_l0")?.dim(0)? / RECURRENT_HIDDEN_DIM;
+        let dim = RECURRENT_HIDDEN_DIM;
+        let cell = match gate_count {
+            3 => RecurrentCell::Gru(nn::gru(dim, dim, Default::default(), rnn_vb)?),
+            4 => RecurrentCell::Lstm(nn::lstm(dim, dim, Default::default(), rnn_vb)?),
+            _ => {
+                return Err(candle_core::Error::Msg(format!(
+                    "Expected a GRU or LSTM layer, but its weights have {gate_count} gates"
+                )))
+            }
+        };
+
+        let action_count = vb.pp("out").get_unchecked("weight")?.dim(0)?;
+        let out = nn::linear(RECURRENT_HIDDEN_DIM, action_count, vb.pp("out"))?;
+        Ok(Self {
+            backbone,
+            convs,
+            linear,
+            cell,
+            out,
+            device: vb.device().clone(),
+            dtype: vb.dtype(),
+        })
+    }
+}
+
+impl RecurrentPolicyNet {
+    /// Returns `f32` action logits with shape `(batch_size, action_count)`, and the hidden state to pass in on the next
+    /// step. Starts from a zero hidden state if `state` isn't given.
+    pub fn forward(
+        &self,
+        grid: &candle_core::Tensor,
+        state: Option<&RecurrentState>,
+    ) -> candle_core::Result<(candle_core::Tensor, RecurrentState)> {
+        use candle_core::Module;
+        use nn::RNN;
+
+        let x = run_policy_convs(&self.backbone, &self.convs, &grid.to_dtype(self.dtype)?)?;
+        let x = self.linear.forward(&x)?.silu()?;
+        let batch_size = x.dim(0)?;
+        let (h, state) = match &self.cell {
+            RecurrentCell::Gru(gru) => {
+                let state = match state {
+                    Some(RecurrentState::Gru(state)) => gru.step(&x, state)?,
+                    _ => gru.step(&x, &gru.zero_state(batch_size)?)?,
+                };
+                (state.h().clone(), RecurrentState::Gru(state))
+            }
+            RecurrentCell::Lstm(lstm) => {
+                let state = match state {
+                    Some(RecurrentState::Lstm(state)) => lstm.step(&x, state)?,
+                    _ => lstm.step(&x, &lstm.zero_state(batch_size)?)?,
+                };
+                (state.h().clone(), RecurrentState::Lstm(state))
+            }
+        };
+        Ok((
+            self.out.forward(&h)?.to_dtype(candle_core::DType::F32)?,
+            state,
+        ))
+    }
+}
+
+/// The hidden state of an agent's recurrent policy, carried between updates. Empty until the policy first runs, and
+/// emptied again whenever the level resets.
+#[derive(Component, Default)]
+pub struct PolicyHidden(pub Option<RecurrentState>);
+
+/// Rust port of the measurement model in `webgame/models.py`, which outputs the likelihood of the agent's
+/// observations if its opponent were in each cell. Used by the filter in place of the hand-written likelihood.
+///
+/// Only supports checkpoints trained without objects or position encodings. Takes the same input grid as
+/// `PolicyNet`, and works on levels of any size.
+pub struct MeasureNet {
+    backbone: [(nn::Conv2d, nn::BatchNorm); 3],
+    out_net: [(nn::Conv2d, nn::BatchNorm); 2],
+    out_conv: nn::Conv2d,
+    device: candle_core::Device,
+    /// The type the weights were loaded as. Inputs are converted to it before running.
+    dtype: candle_core::DType,
+}
+
+impl LoadableNN for MeasureNet {
+    fn load(vb: nn::VarBuilder) -> candle_core::Result<Self> {
+        // Each convolution is followed by a batch norm layer, which is named by the next index
+        let conv_bn = |in_c, out_c, kernel, prefix: &str, idx: usize| {
+            let conv = nn::conv2d(
+                in_c,
+                out_c,
+                kernel,
+                nn::Conv2dConfig {
+                    padding: kernel / 2,
+                    ..Default::default()
+                },
+                vb.pp(format!("{prefix}.{idx}")),
+            )?;
+            let bn = nn::batch_norm(
+                out_c,
+                nn::BatchNormConfig::default(),
+                vb.pp(format!("{prefix}.{}", idx + 1)),
+            )?;
+            Ok::<_, candle_core::Error>((conv, bn))
+        };
+        let backbone = [
+            conv_bn(POLICY_CHANNELS, 16, 5, "backbone.grid_net", 0)?,
+            conv_bn(16, 16, 5, "backbone.grid_net", 3)?,
+            conv_bn(16, 32, 5, "backbone.grid_net", 6)?,
+        ];
+        let out_net = [
+            conv_bn(32, 32, 3, "out_net", 0)?,
+            conv_bn(32, 32, 3, "out_net", 3)?,
+        ];
+        let out_conv = nn::conv2d(
+            32,
+            1,
+            3,
+            nn::Conv2dConfig {
+                padding: 1,
+                ..Default::default()
+            },
+            vb.pp("out_net.6"),
+        )?;
+        Ok(Self {
+            backbone,
+            out_net,
+            out_conv,
+            device: vb.device().clone(),
+            dtype: vb.dtype(),
+        })
+    }
+}
+
+impl MeasureNet {
+    /// Returns the likelihood of each cell, from 0 to 1, for a single input grid built by `policy_grid`. Indexed the
+    /// same way as `LevelLayout::walls`.
+    pub fn likelihood(&self, level: &LevelLayout, grid: Vec<f32>) -> candle_core::Result<Vec<f32>> {
+        let likelihoods = self.likelihoods(grid, level.width, level.height)?;
+        Ok(likelihoods.into_iter().next().unwrap_or_default())
+    }
+
+    /// Returns the likelihood of each cell for a batch of input grids built by `policy_grid`, flattened into one list,
+    /// on a level of this size.
+    pub fn likelihoods(
+        &self,
+        grids: Vec<f32>,
+        width: usize,
+        height: usize,
+    ) -> candle_core::Result<Vec<Vec<f32>>> {
+        use candle_core::{Module, Tensor};
+
+        let batch_size = grids.len() / (POLICY_CHANNELS * width * height).max(1);
+        let mut x = Tensor::from_vec(
+            grids,
+            (batch_size, POLICY_CHANNELS, height, width),
+            &self.device,
+        )?
+        .to_dtype(self.dtype)?;
+        for (conv, bn) in self.backbone.iter().chain(&self.out_net) {
+            x = conv.forward(&x)?.apply_t(bn, false)?.silu()?;
+        }
+        nn::ops::sigmoid(&self.out_conv.forward(&x)?)?
+            .flatten_from(1)?
+            .to_dtype(candle_core::DType::F32)?
+            .to_vec2()
+    }
+}
+
+/// Lets a trained policy control an agent, so agents trained in Python can play in the shipped game. Add one for each
+/// agent to have policies play each other, e.g. to watch two checkpoints go head to head.
+///
+/// Paths ending in `.onnx` are loaded as ONNX models, which needs the `onnx` feature. Paths ending in `.remote` point
+/// to a server in another process that picks actions, as described by `RemotePolicy`. Anything else is loaded as
+/// `PolicyNet` weights, or `RecurrentPolicyNet` weights if `recurrent` is set. Insert a `PolicyRunnerConfig` to change
+/// how actions are picked.
+#[derive(Default)]
+pub struct PolicyRunnerPlugin {
+    /// The asset path of the policy's weights.
+    pub path: String,
+    /// Whether the policy is a `RecurrentPolicyNet`.
+    pub recurrent: bool,
+    /// The agent the policy controls.
+    pub agent: PolicyAgent,
+    /// The asset path of a `ValueNet` checkpoint trained alongside the policy. If set, its estimates are stored in
+    /// each agent's `PolicyOutput`.
+    pub value_path: Option<String>,
+}
+
+impl Plugin for PolicyRunnerPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<PolicyOutput>();
+        match self.agent {
+            PolicyAgent::Pursuer => self.build_for::<PursuerAgent, PlayerAgent>(app),
+            PolicyAgent::Player => self.build_for::<PlayerAgent, PursuerAgent>(app),
+        }
+    }
+
+    fn is_unique(&self) -> bool {
+        false
+    }
+}
+
+impl PolicyRunnerPlugin {
+    /// Adds systems that drive agents tagged with `T`, whose opponent is tagged with `O`.
+    fn build_for<T: Component, O: Component>(&self, app: &mut App) {
+        if let Some(value_path) = &self.value_path {
+            add_value_systems::<T, O>(app, value_path.clone());
+        }
+        if self.path.ends_with(".remote") {
+            #[cfg(not(target_arch = "wasm32"))]
+            {
+                use crate::remote_policy::{
+                    load_remote_policies, RemotePolicy, RemotePolicyPlugin,
+                };
+
+                add_policy_systems::<T, O, RemotePolicy>(app, self.path.clone(), self.agent);
+                if !app.is_plugin_added::<RemotePolicyPlugin>() {
+                    app.add_plugins(RemotePolicyPlugin);
+                }
+                app.add_systems(
+                    Update,
+                    load_remote_policies
+                        .after(add_policy_runners::<T, RemotePolicy>)
+                        .before(run_policies::<T, O, RemotePolicy>),
+                );
+            }
+            #[cfg(target_arch = "wasm32")]
+            error!(
+                "Can't run the remote policy {}, since browsers can't connect to policy servers",
+                self.path
+            );
+            return;
+        }
+        if !self.path.ends_with(".onnx") {
+            if self.recurrent {
+                add_net_policy_systems::<T, O, RecurrentPolicyNet>(
+                    app,
+                    self.path.clone(),
+                    self.agent,
+                );
+            } else {
+                add_net_policy_systems::<T, O, PolicyNet>(app, self.path.clone(), self.agent);
+            }
+            return;
+        }
+        #[cfg(feature = "onnx")]
+        {
+            use crate::onnx::{load_onnx_policies, OnnxPlugin, OnnxPolicy};
+
+            add_policy_systems::<T, O, OnnxPolicy>(app, self.path.clone(), self.agent);
+            if !app.is_plugin_added::<OnnxPlugin>() {
+                app.add_plugins(OnnxPlugin);
+            }
+            app.add_systems(
+                Update,
+                load_onnx_policies
+                    .after(add_policy_runners::<T, OnnxPolicy>)
+                    .before(run_policies::<T, O, OnnxPolicy>),
+            );
+        }
+        #[cfg(not(feature = "onnx"))]
+        error!(
+            "Can't run the ONNX policy {}, since the game was built without the `onnx` feature",
+            self.path
+        );
+    }
+}
+
+/// The agents a `PolicyRunnerPlugin` can control.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PolicyAgent {
+    #[default]
+    Pursuer,
+    Player,
+}
+
+/// Loads the network at `path` at startup, and has it drive agents tagged with `T`.
+fn add_net_policy_systems<T: Component, O: Component, N: LoadableNN>(
+    app: &mut App,
+    path: String,
+    agent: PolicyAgent,
+) where
+    NNWrapper<N>: PolicySource,
+{
+    add_policy_systems::<T, O, NNWrapper<N>>(app, path, agent);
+    app.add_systems(
+        Update,
+        load_weights_into_net::<N>
+            .after(add_policy_runners::<T, NNWrapper<N>>)
+            .before(run_policies::<T, O, NNWrapper<N>>),
+    );
+}
+
+/// Gives agents tagged with `T` the value network at `path` as they spawn, and has it estimate their values.
+fn add_value_systems<T: Component, O: Component>(app: &mut App, path: String) {
+    app.add_systems(
+        Update,
+        (
+            move |mut commands: Commands,
+                  agent_query: Query<Entity, Added<T>>,
+                  asset_server: Res<AssetServer>| {
+                for agent_e in agent_query.iter() {
+                    commands
+                        .entity(agent_e)
+                        .insert(NNWrapper::<ValueNet>::with_sftensors(
+                            asset_server.load(path.clone()),
+                        ));
+                }
+            },
+            load_weights_into_net::<ValueNet>,
+            estimate_values::<T, O>.run_if(resource_exists::<ShouldRun>),
+        )
+            .chain()
+            .before(move_agents),
+    );
+}
+
+/// Loads the policy at `path` at startup, and has it drive agents tagged with `T`.
+fn add_policy_systems<T: Component, O: Component, S: PolicySource>(
+    app: &mut App,
+    path: String,
+    agent: PolicyAgent,
+) {
+    app.init_resource::<PolicyRunnerConfig>()
+        .add_event::<SwapPolicy>()
+        .add_systems(
+            Startup,
+            move |mut commands: Commands, asset_server: Res<AssetServer>| {
+                commands.insert_resource(PolicyWeights::<T, S> {
+                    handle: asset_server.load(path.clone()),
+                    path: path.clone(),
+                    agent,
+                    _marker: PhantomData,
+                });
+            },
+        )
+        .add_systems(
+            Update,
+            (
+                reset_policy_hidden::<T>,
+                swap_policies::<T, S>,
+                add_policy_runners::<T, S>,
+                run_policies::<T, O, S>.run_if(resource_exists::<ShouldRun>),
+            )
+                .chain()
+                .before(move_agents),
+        );
+}
+
+/// Replaces the weights of the policy that a `PolicyRunnerPlugin` runs for `agent` with the ones at an asset path,
+/// without restarting. The new weights must be in the same format as the old ones. Levels whose `LevelMeta` names their
+/// own policy still switch to it when they start.
+///
+/// Policies are also reloaded whenever their weights file changes, if the `bevy/file_watcher` feature is enabled, so
+/// checkpoints can be watched live as they're written during training.
+#[derive(Event, Clone)]
+pub struct SwapPolicy {
+    pub path: String,
+    pub agent: PolicyAgent,
+}
+
+/// A component holding a policy that can choose agents' actions.
+pub trait PolicySource: Component {
+    /// The asset the policy is loaded from.
+    type Weights: Asset;
+    /// The file extension of the policy's weights.
+    const EXTENSION: &'static str;
+
+    /// Creates a policy that's loaded once `weights` is.
+    fn with_weights(weights: Handle<Self::Weights>) -> Self;
+
+    /// Returns the probability of taking each action, given an input grid built by `policy_grid`, or `None` if the
+    /// policy hasn't loaded yet. Recurrent policies read and update the agent's hidden state.
+    fn action_probs(
+        &mut self,
+        level: &LevelLayout,
+        grid: Vec<f32>,
+        hidden: &mut PolicyHidden,
+    ) -> Option<Result<Vec<f32>, String>>;
+
+    /// Like `action_probs`, but for every agent sharing this policy at once, with a grid and hidden state for each.
+    /// Runs agents one at a time unless the policy can run them in a single batch.
+    fn batch_action_probs(
+        &mut self,
+        level: &LevelLayout,
+        grids: Vec<Vec<f32>>,
+        hidden: &mut [&mut PolicyHidden],
+    ) -> Option<Result<Vec<Vec<f32>>, String>> {
+        grids
+            .into_iter()
+            .zip(hidden)
+            .map(|(grid, hidden)| self.action_probs(level, grid, hidden))
+            .collect()
+    }
+}
+
+impl PolicySource for NNWrapper<PolicyNet> {
+    type Weights = SafeTensorsData;
+    const EXTENSION: &'static str = "safetensors";
+
+    fn with_weights(weights: Handle<SafeTensorsData>) -> Self {
+        Self::with_sftensors(weights)
+    }
+
+    fn action_probs(
+        &mut self,
+        level: &LevelLayout,
+        grid: Vec<f32>,
+        _hidden: &mut PolicyHidden,
+    ) -> Option<Result<Vec<f32>, String>> {
+        let net = self.net.as_ref()?;
+        let probs: candle_core::Result<Vec<f32>> = candle_core::Tensor::from_vec(
+            grid,
+            (1, POLICY_CHANNELS, level.height, level.width),
+            &net.device,
+        )
+        .and_then(|grid| {
+            nn::ops::softmax_last_dim(&net.forward(&grid)?)?
+                .squeeze(0)?
+                .to_vec1()
+        });
+        Some(probs.map_err(|err| err.to_string()))
+    }
+
+    fn batch_action_probs(
+        &mut self,
+        level: &LevelLayout,
+        grids: Vec<Vec<f32>>,
+        _hidden: &mut [&mut PolicyHidden],
+    ) -> Option<Result<Vec<Vec<f32>>, String>> {
+        let net = self.net.as_ref()?;
+        let batch_size = grids.len();
+        let probs: candle_core::Result<Vec<Vec<f32>>> = candle_core::Tensor::from_vec(
+            grids.concat(),
+            (batch_size, POLICY_CHANNELS, level.height, level.width),
+            &net.device,
+        )
+        .and_then(|grids| nn::ops::softmax_last_dim(&net.forward(&grids)?)?.to_vec2());
+        Some(probs.map_err(|err| err.to_string()))
+    }
+}
+
+impl PolicySource for NNWrapper<RecurrentPolicyNet> {
+    type Weights = SafeTensorsData;
+    const EXTENSION: &'static str = "safetensors";
+
+    fn with_weights(weights: Handle<SafeTensorsData>) -> Self {
+        Self::with_sftensors(weights)
+    }
+
+    fn action_probs(
+        &mut self,
+        level: &LevelLayout,
+        grid: Vec<f32>,
+        hidden: &mut PolicyHidden,
+    ) -> Option<Result<Vec<f32>, String>> {
+        let net = self.net.as_ref()?;
+        let probs: candle_core::Result<Vec<f32>> = candle_core::Tensor::from_vec(
+            grid,
+            (1, POLICY_CHANNELS, level.height, level.width),
+            &net.device,
+        )
+        .and_then(|grid| {
+            let (logits, state) = net.forward(&grid, hidden.0.as_ref())?;
+            hidden.0 = Some(state);
+            nn::ops::softmax_last_dim(&logits)?.squeeze(0)?.to_vec1()
+        });
+        Some(probs.map_err(|err| err.to_string()))
+    }
+}
+
+/// The weights of the policy that a `PolicyRunnerPlugin` gives agents tagged with `T`.
+#[derive(Resource)]
+struct PolicyWeights<T, S: PolicySource> {
+    handle: Handle<S::Weights>,
+    /// The asset path of the weights used on levels that don't name their own.
+    path: String,
+    agent: PolicyAgent,
+    _marker: PhantomData<fn() -> T>,
+}
+
+/// How policy runners pick an action from the probabilities a policy gives each one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ActionSelection {
+    /// Always takes the most likely action. Deterministic, but agents can get stuck against walls.
+    Greedy,
+    /// Takes a uniformly random action this fraction of the time, and the most likely action otherwise.
+    EpsilonGreedy(f32),
+    /// Samples actions in proportion to their probabilities raised to `1 / temperature`. A temperature of 1 samples
+    /// from the policy as is, like `model_policy` in `webgame/filter.py`. Lower temperatures approach greedy
+    /// selection, and higher ones approach uniformly random actions.
+    Temperature(f32),
+}
+
+impl Default for ActionSelection {
+    fn default() -> Self {
+        Self::Temperature(1.)
+    }
+}
+
+impl ActionSelection {
+    /// Returns true if epsilon is between 0 and 1, or the temperature is positive.
+    pub fn is_valid(&self) -> bool {
+        match *self {
+            Self::Greedy => true,
+            Self::EpsilonGreedy(epsilon) => (0. ..=1.).contains(&epsilon),
+            Self::Temperature(temperature) => temperature > 0.,
+        }
+    }
+
+    /// Picks an action given the probability of taking each one.
+    pub fn select(&self, probs: &[f32], rng: &mut impl Rng) -> Result<usize, WeightedError> {
+        let greedy = || {
+            probs
+                .iter()
+                .enumerate()
+                .max_by(|(_, a), (_, b)| a.total_cmp(b))
+                .map(|(action, _)| action)
+                .ok_or(WeightedError::NoItem)
+        };
+        match *self {
+            Self::Greedy => greedy(),
+            Self::EpsilonGreedy(epsilon) if rng.gen_bool(epsilon as f64) => {
+                if probs.is_empty() {
+                    return Err(WeightedError::NoItem);
+                }
+                Ok(rng.gen_range(0..probs.len()))
+            }
+            Self::EpsilonGreedy(_) => greedy(),
+            Self::Temperature(temperature) => {
+                if probs.iter().any(|prob| prob.is_nan()) {
+                    return Err(WeightedError::InvalidWeight);
+                }
+                // Clamp first, since a zero probability would give an infinite logit and a NaN after the softmax
+                let logits = probs
+                    .iter()
+                    .map(|prob| prob.max(f32::MIN_POSITIVE).ln() / temperature)
+                    .collect::<Vec<_>>();
+                Ok(WeightedIndex::new(softmax(&logits))?.sample(rng))
+            }
+        }
+    }
+}
+
+/// Configures how policy runners choose actions.
+#[derive(Resource, Clone, Copy, Default)]
+pub struct PolicyRunnerConfig {
+    pub selection: ActionSelection,
+    /// Seeds each runner's RNG, so runs can be repeated. Runners are seeded from entropy if not set.
+    pub seed: Option<u64>,
+}
+
+/// Chooses an agent's `NextAction` every update using a `PolicySource` on the same agent, picking actions from their
+/// probabilities as set by `PolicyRunnerConfig`.
+#[derive(Component)]
+pub struct PolicyRunner {
+    rng: StdRng,
+}
+
+impl PolicyRunner {
+    pub fn new(config: &PolicyRunnerConfig) -> Self {
+        Self {
+            rng: config
+                .seed
+                .map_or_else(StdRng::from_entropy, StdRng::seed_from_u64),
+        }
+    }
+}
+
+/// The output of an agent's policy on the last update, so its behavior can be inspected alongside the game. It's
+/// reflected, so library builds with `VisualizerPlugin` log it to Rerun with the rest of the agent, and
+/// `PolicyOverlayPlugin` draws it over the level.
+#[derive(Component, Reflect, Default, Debug, Clone)]
+#[reflect(Component)]
+pub struct PolicyOutput {
+    /// The probability of each action.
+    pub probs: Vec<f32>,
+    /// The estimate of the agent's `ValueNet`, if `PolicyRunnerPlugin` was given one.
+    pub value: Option<f32>,
+}
+
+/// Gives agents tagged with `T` the policy from `PolicyWeights` as they spawn.
+fn add_policy_runners<T: Component, S: PolicySource>(
+    mut commands: Commands,
+    agent_query: Query<Entity, Added<T>>,
+    weights: Res<PolicyWeights<T, S>>,
+    config: Res<PolicyRunnerConfig>,
+) {
+    for agent_e in agent_query.iter() {
+        commands.entity(agent_e).insert((
+            PolicyRunner::new(&config),
+            PolicyHidden::default(),
+            PolicyOutput::default(),
+            S::with_weights(weights.handle.clone()),
+        ));
+    }
+}
+
+/// Empties the hidden states of agents tagged with `T` when the level resets.
+fn reset_policy_hidden<T: Component>(
+    mut ev_reset: EventReader<ResetEvent>,
+    mut hidden_query: Query<&mut PolicyHidden, With<T>>,
+) {
+    if ev_reset.read().count() == 0 {
+        return;
+    }
+    for mut hidden in hidden_query.iter_mut() {
+        hidden.0 = None;
+    }
+}
+
+/// Points `PolicyWeights` at new weights when a `SwapPolicy` for its agent asks for them, or when a level that names
+/// its own policy starts, and gives agents tagged with `T` a fresh policy when the weights change. Agents whose
+/// policies were removed after failing get them back.
+fn swap_policies<T: Component, S: PolicySource>(
+    mut commands: Commands,
+    mut swap_events: EventReader<SwapPolicy>,
+    mut asset_events: EventReader<AssetEvent<S::Weights>>,
+    mut weights: ResMut<PolicyWeights<T, S>>,
+    (asset_server, config, meta): (
+        Res<AssetServer>,
+        Res<PolicyRunnerConfig>,
+        Option<Res<LevelMeta>>,
+    ),
+    agent_query: Query<Entity, With<T>>,
+) {
+    let mut swapped = false;
+    for ev in swap_events.read() {
+        if ev.agent != weights.agent || !has_extension::<S>(&ev.path) {
+            continue;
+        }
+        weights.path = ev.path.clone();
+        weights.handle = asset_server.load(ev.path.clone());
+        swapped = true;
+    }
+    // Levels can pick their own opponents, and go back to the usual policy if they don't
+    if let Some(meta) = meta.filter(|meta| meta.is_changed()) {
+        let level_path = match weights.agent {
+            PolicyAgent::Pursuer => meta.pursuer_policy.as_ref(),
+            PolicyAgent::Player => meta.player_policy.as_ref(),
+        };
+        let path = level_path
+            .filter(|path| has_extension::<S>(path))
+            .unwrap_or(&weights.path);
+        let handle = asset_server.load(path.clone());
+        swapped |= handle != weights.handle;
+        weights.handle = handle;
+    }
+    for ev in asset_events.read() {
+        swapped |= ev.is_modified(&weights.handle);
+    }
+    if !swapped {
+        return;
+    }
+    for agent_e in agent_query.iter() {
+        commands.entity(agent_e).insert((
+            PolicyRunner::new(&config),
+            PolicyHidden::default(),
+            PolicyOutput::default(),
+            S::with_weights(weights.handle.clone()),
+        ));
+    }
+}
+
+/// Returns whether the weights at `path` can be loaded by `S`, logging an error if they can't.
+fn has_extension<S: PolicySource>(path: &str) -> bool {
+    let matches = path.ends_with(&format!(".{}", S::EXTENSION));
+    if !matches {
+        error!(
+            "Can't swap in the policy {path}, since the running policy is loaded from .{} files",
+            S::EXTENSION
+        );
+    }
+    matches
+}
+
+/// Sets the next action of agents tagged with `T` with a policy, whose opponent is tagged with `O`.
+///
+/// Every agent tagged with `T` is given the same weights, so they're all run through the first agent's policy in one
+/// batch. Policies that fail to load or run, such as ones trained on a different level size, are removed and the
+/// agents stop moving, unless something else like a `ScriptedPursuer` controls them.
+fn run_policies<T: Component, O: Component, S: PolicySource>(
+    mut commands: Commands,
+    mut agent_query: Query<
+        (
+            Entity,
+            &mut PolicyRunner,
+            &mut S,
+            &mut PolicyHidden,
+            &mut PolicyOutput,
+            &Agent,
+            &Observer,
+            &GlobalTransform,
+            &mut NextAction,
+        ),
+        With<T>,
+    >,
+    other_query: Query<(Entity, &GlobalTransform), With<O>>,
+    level: Res<LevelLayout>,
+    (asset_server, weights): (Res<AssetServer>, Res<PolicyWeights<T, S>>),
+    config: Res<PolicyRunnerConfig>,
+) {
+    // Without weights, leave agents to whatever else controls them, such as a `ScriptedPursuer`
+    if asset_server.load_state(&weights.handle) == LoadState::Failed {
+        for (agent_e, ..) in agent_query.iter() {
+            error!("Couldn't load policy weights, removing the policy");
+            commands.entity(agent_e).remove::<(PolicyRunner, S)>();
+        }
+        return;
+    }
+    let Ok((other_e, other_xform)) = other_query.get_single() else {
+        return;
+    };
+    let mut grids = Vec::new();
+    let mut policies = Vec::new();
+    let mut hiddens = Vec::new();
+    let mut agents = Vec::new();
+    for (agent_e, runner, policy, hidden, output, agent, observer, xform, next_action) in
+        agent_query.iter_mut()
+    {
+        let seen = observer
+            .observing
+            .contains(&other_e)
+            .then(|| other_xform.translation().xy());
+        grids.push(policy_grid(
+            &level,
+            xform.translation().xy(),
+            agent.dir,
+            seen,
+        ));
+        policies.push(policy);
+        hiddens.push(hidden);
+        agents.push((agent_e, runner, output, next_action));
+    }
+    let Some(policy) = policies.first_mut() else {
+        return;
+    };
+    let mut hiddens = hiddens
+        .iter_mut()
+        .map(|hidden| &mut **hidden)
+        .collect::<Vec<_>>();
+    let Some(probs) = policy.batch_action_probs(&level, grids, &mut hiddens) else {
+        return;
+    };
+    let actions = probs.and_then(|probs| {
+        agents
+            .iter_mut()
+            .zip(probs)
+            .map(|((_, runner, output, _), probs)| {
+                output.probs.clone_from(&probs);
+                config
+                    .selection
+                    .select(&probs, &mut runner.rng)
+                    .map_err(|err| err.to_string())
+            })
+            .collect::<Result<Vec<_>, _>>()
+    });
+    let actions = match actions {
+        Ok(actions) => actions,
+        Err(err) => {
+            error!("Policy failed to run, removing it: {err}");
+            for (agent_e, _, _, next_action) in &mut agents {
+                commands.entity(*agent_e).remove::<PolicyRunner>();
+                **next_action = NextAction::default();
+            }
+            return;
+        }
+    };
+    for ((_, _, _, next_action), action) in agents.iter_mut().zip(actions) {
+        next_action.dir = action_dir(action);
+        next_action.toggle_objs = action == TOGGLE_ACTION;
+    }
+}
+
+/// Stores the value estimates of agents tagged with `T`, whose opponent is tagged with `O`, in their `PolicyOutput`.
+/// Value networks that fail to run are removed.
+fn estimate_values<T: Component, O: Component>(
+    mut commands: Commands,
+    mut agent_query: Query<
+        (
+            Entity,
+            &NNWrapper<ValueNet>,
+            &Agent,
+            &Observer,
+            &GlobalTransform,
+            &mut PolicyOutput,
+        ),
+        With<T>,
+    >,
+    other_query: Query<(Entity, &GlobalTransform), With<O>>,
+    level: Res<LevelLayout>,
+) {
+    let Ok((other_e, other_xform)) = other_query.get_single() else {
+        return;
+    };
+    for (agent_e, value_net, agent, observer, xform, mut output) in agent_query.iter_mut() {
+        let Some(net) = &value_net.net else {
+            continue;
+        };
+        let seen = observer
+            .observing
+            .contains(&other_e)
+            .then(|| other_xform.translation().xy());
+        let grid = policy_grid(&level, xform.translation().xy(), agent.dir, seen);
+        match net.value(&level, grid) {
+            Ok(value) => output.value = Some(value),
+            Err(err) => {
+                error!("Value network failed to run, removing it: {err}");
+                commands.entity(agent_e).remove::<NNWrapper<ValueNet>>();
+                output.value = None;
             }
         }
     }
 }
+
+/// Draws each agent's `PolicyOutput` over the level, as a line in each direction the agent could move that's longer
+/// the more likely the policy is to move that way. Circles show how likely it is to stand still or toggle objects.
+pub struct PolicyOverlayPlugin;
+
+impl Plugin for PolicyOverlayPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, draw_policy_overlays);
+    }
+}
+
+fn draw_policy_overlays(
+    mut gizmos: Gizmos,
+    output_query: Query<(&GlobalTransform, &PolicyOutput)>,
+) {
+    for (xform, output) in output_query.iter() {
+        let pos = xform.translation().xy();
+        for (action, &prob) in output.probs.iter().enumerate() {
+            let dir = action_dir(action);
+            if dir == Vec2::ZERO {
+                let color = if action == TOGGLE_ACTION {
+                    Color::CYAN
+                } else {
+                    Color::YELLOW
+                };
+                gizmos.circle_2d(pos, prob * GRID_CELL_SIZE, color);
+            } else {
+                gizmos.line_2d(pos, pos + dir * prob * GRID_CELL_SIZE * 2., Color::YELLOW);
+            }
+        }
+    }
+}
+
+/// Turns logits into probabilities that sum to 1.
+pub fn softmax(logits: &[f32]) -> Vec<f32> {
+    let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let exps = logits
+        .iter()
+        .map(|logit| (logit - max).exp())
+        .collect::<Vec<_>>();
+    let total = exps.iter().sum::<f32>();
+    exps.into_iter().map(|exp| exp / total).collect()
+}
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
 
 use bevy::{
     prelude::*,
@@ -7,10 +7,13 @@ use bevy::{
 };
 use bevy_rapier2d::{math::Real, prelude::*};
 use ordered_float::OrderedFloat;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    gridworld::{move_agents, Agent},
-    world_objs::VisualMarker,
+    gridworld::{move_agents, Agent, LevelLayout, GRID_CELL_SIZE},
+    lighting::Illumination,
+    world_objs::{StationaryCamera, VisualMarker},
 };
 
 /// Plugins for determining what agents can see.
@@ -18,19 +21,27 @@ pub struct ObserverPlugin;
 
 impl Plugin for ObserverPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(
-            Update,
-            (
-                update_observers.after(move_agents),
-                update_vm_data,
-                add_vis_cones,
-                remove_vis_cones,
-                draw_observer_areas
-                    .after(update_observers)
-                    .after(add_vis_cones)
-                    .after(remove_vis_cones),
-            ),
-        );
+        app.add_event::<EnteredView>()
+            .add_event::<ExitedView>()
+            .init_resource::<VisionConfig>()
+            .init_resource::<DetectionConfig>()
+            .init_resource::<DetectionRng>()
+            .init_resource::<MarkerConfig>()
+            .init_resource::<ShowVisCones>()
+            .add_systems(
+                Update,
+                (
+                    show_vis_cones.after(add_vis_cones),
+                    update_observers.after(move_agents),
+                    update_vm_data,
+                    add_vis_cones,
+                    remove_vis_cones,
+                    draw_observer_areas
+                        .after(update_observers)
+                        .after(add_vis_cones)
+                        .after(remove_vis_cones),
+                ),
+            );
     }
 }
 
@@ -51,17 +62,218 @@ pub struct VMSeenData {
     pub pos: Vec2,
     /// The last known position of this object (if never seen before this is the position it starts at).
     pub last_pos: Vec2,
+    /// How far the object moved the last time the observer saw it move, in world units. Cleared once the evidence
+    /// is older than `MarkerConfig::evidence_duration`.
+    pub displacement: Vec2,
+    /// How fast the object was moving when `displacement` was seen, in world units per second. Always zero unless
+    /// `MarkerConfig::estimate_velocity` is set.
+    pub velocity: Vec2,
+    /// When `displacement` was seen (time since startup).
+    pub displaced_at: f32,
+}
+
+impl VMSeenData {
+    /// Creates data for a marker at `pos` that hasn't been seen moving.
+    fn new(pos: Vec2, now: f32) -> Self {
+        Self {
+            last_seen: now,
+            last_seen_elapsed: now,
+            pos,
+            last_pos: pos,
+            displacement: Vec2::ZERO,
+            velocity: Vec2::ZERO,
+            displaced_at: now,
+        }
+    }
+
+    /// Clears movement evidence that's older than the config allows.
+    fn expire_displacement(&mut self, config: &MarkerConfig, now: f32) {
+        if now - self.displaced_at > config.evidence_duration {
+            self.displacement = Vec2::ZERO;
+            self.velocity = Vec2::ZERO;
+        }
+    }
+}
+
+/// Configures how observers notice visual markers moving.
+#[derive(Resource, Clone, Copy)]
+pub struct MarkerConfig {
+    /// Markers have to move further than this, in cells, between two sightings to count as moved.
+    pub move_threshold: f32,
+    /// Whether to estimate how fast markers that moved were going.
+    pub estimate_velocity: bool,
+    /// How long, in seconds, evidence that a marker moved is kept after it's seen.
+    pub evidence_duration: f32,
+}
+
+impl Default for MarkerConfig {
+    fn default() -> Self {
+        Self {
+            move_threshold: 0.,
+            estimate_velocity: false,
+            evidence_duration: 0.,
+        }
+    }
+}
+
+impl MarkerConfig {
+    /// Returns true if neither the threshold nor the duration is negative.
+    pub fn is_valid(&self) -> bool {
+        self.move_threshold >= 0. && self.evidence_duration >= 0.
+    }
+}
+
+/// How wide agents' vision cones are by default, in degrees.
+pub const DEFAULT_FOV_DEGREES: f32 = 60.;
+
+/// How wide and how far an agent can see.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct VisionParams {
+    /// How wide the vision cone is, in degrees.
+    #[serde(default = "default_fov_degrees")]
+    pub fov_degrees: f32,
+    /// How far the agent can see, in cells. If not set, the agent can see until something blocks its view.
+    #[serde(default)]
+    pub max_range: Option<f32>,
+}
+
+fn default_fov_degrees() -> f32 {
+    DEFAULT_FOV_DEGREES
+}
+
+impl Default for VisionParams {
+    fn default() -> Self {
+        Self {
+            fov_degrees: default_fov_degrees(),
+            max_range: None,
+        }
+    }
+}
+
+impl VisionParams {
+    /// Returns true if the vision cone is wider than nothing but no wider than a full turn, and the range isn't
+    /// negative.
+    pub fn is_valid(&self) -> bool {
+        self.fov_degrees > 0. && self.fov_degrees <= 360. && self.max_range.unwrap_or(0.) >= 0.
+    }
+}
+
+/// Configures how agents see. Levels can override these for each agent.
+#[derive(Resource, Default, Clone, Copy)]
+pub struct VisionConfig {
+    pub pursuer: VisionParams,
+    pub player: VisionParams,
+}
+
+/// Configures how likely observers are to notice observable entities in their field of view.
+///
+/// The chance of noticing an entity falls off linearly with distance past `certain_dist`, and with how far the entity
+/// is from the center of the vision cone. By default, nothing falls off, so observers notice everything they can see.
+#[derive(Resource, Clone, Copy)]
+pub struct DetectionConfig {
+    /// Entities closer than this, in cells, are noticed as if they were right in front of the observer.
+    pub certain_dist: f32,
+    /// How much the chance of noticing an entity drops for every cell past `certain_dist`.
+    pub dist_falloff: f32,
+    /// How much lower the chance of noticing an entity is at the edge of the vision cone than at its center.
+    /// Observers that see in every direction don't have edges, so this doesn't apply to them.
+    pub peripheral_falloff: f32,
+}
+
+impl Default for DetectionConfig {
+    fn default() -> Self {
+        Self {
+            certain_dist: 0.,
+            dist_falloff: 0.,
+            peripheral_falloff: 0.,
+        }
+    }
+}
+
+impl DetectionConfig {
+    /// Returns true if the settings are in range: none are negative, and the peripheral falloff is at most 1.
+    pub fn is_valid(&self) -> bool {
+        self.certain_dist >= 0.
+            && self.dist_falloff >= 0.
+            && (0. ..=1.).contains(&self.peripheral_falloff)
+    }
+
+    /// Returns the chance of noticing a visible entity `dist` world units away, at `angle` radians from the center of
+    /// a vision cone `fov` radians wide.
+    pub fn probability(&self, dist: f32, angle: f32, fov: f32) -> f32 {
+        let dist_cells = dist / GRID_CELL_SIZE;
+        let dist_factor = 1. - self.dist_falloff * (dist_cells - self.certain_dist).max(0.);
+        let angle_factor = if fov >= 2. * std::f32::consts::PI {
+            1.
+        } else {
+            1. - self.peripheral_falloff * (angle / (fov / 2.)).min(1.)
+        };
+        (dist_factor * angle_factor).clamp(0., 1.)
+    }
+}
+
+/// The random numbers used to decide whether observers notice entities. Insert a seeded one for reproducible runs.
+#[derive(Resource)]
+pub struct DetectionRng(pub StdRng);
+
+impl Default for DetectionRng {
+    fn default() -> Self {
+        Self(StdRng::from_entropy())
+    }
 }
 
 /// Indicates that this entity can observe observable entities.
-#[derive(Default, Component)]
+#[derive(Component)]
 pub struct Observer {
-    /// Entities the observer can see.
+    /// Entities the observer can see and has noticed this tick.
     pub observing: Vec<Entity>,
+    /// The chance of noticing each entity in the observer's field of view this tick, including ones it didn't notice.
+    pub detection_probs: HashMap<Entity, f32>,
     /// Stores data on visual markers that it's seen.
     pub seen_markers: HashMap<Entity, VMSeenData>,
     /// Stores a list of triangles that make up the observer's field of vision.
-    pub vis_mesh: Vec<[Vec2; 3]>,
+    /// This is shared, so it can be kept without copying it while it's reused between frames.
+    pub vis_mesh: Arc<Vec<[Vec2; 3]>>,
+    /// How wide the vision cone is, in degrees.
+    pub fov_degrees: f32,
+    /// How far the observer can see, in world units. If not set, only walls limit how far the observer can see.
+    pub max_range: Option<f32>,
+    /// What `vis_mesh` was generated from, or `None` if it needs to be generated.
+    vis_key: Option<VisKey>,
+}
+
+impl Default for Observer {
+    fn default() -> Self {
+        Self::new(VisionParams::default())
+    }
+}
+
+impl Observer {
+    /// Creates an observer that sees as described by `vision`.
+    pub fn new(vision: VisionParams) -> Self {
+        Self {
+            observing: Vec::new(),
+            detection_probs: HashMap::new(),
+            seen_markers: HashMap::new(),
+            vis_mesh: Arc::default(),
+            fov_degrees: vision.fov_degrees,
+            max_range: vision.max_range.map(|range| range * GRID_CELL_SIZE),
+            vis_key: None,
+        }
+    }
+
+    /// Creates an observer that sees `range` cells in every direction, like a fixed camera.
+    pub fn omni(range: f32) -> Self {
+        Self::new(VisionParams {
+            fov_degrees: 360.,
+            max_range: Some(range),
+        })
+    }
+
+    /// Returns true if the observer sees in every direction.
+    pub fn is_omni(&self) -> bool {
+        self.fov_degrees >= 360.
+    }
 }
 
 /// Indicates that this entity can be observed.
@@ -77,102 +289,220 @@ pub struct DebugObserver;
 #[derive(Component)]
 pub struct Wall;
 
+/// Blocks the observer's field of view like a `Wall`, but for objects that move around the level, such as crates.
+/// Unlike walls, occluders can still be observed themselves if they're `Observable`.
+/// Currently, only supports entities with rect colliders.
+#[derive(Component)]
+pub struct OccludesVision;
+
+/// How close the visible area needs to reach to an occluder's collider for the occluder to be seen.
+/// Rays stop at the occluder's surface, so its center is never inside the visible area.
+const OCCLUDER_SEEN_DIST: f32 = 1.;
+
+/// Marks a `Wall` that agents can walk into, such as a bush.
+/// Observers inside cover can see out of it.
+#[derive(Component)]
+pub struct Cover;
+
+/// Limits how far observers can see into an area, such as fog.
+/// Observers inside the area see `view_dist` in every direction, and observers outside see `view_dist` past its edge.
+#[derive(Component)]
+pub struct Fog {
+    /// The area covered, in world space.
+    pub rect: Rect,
+    /// How far observers can see into the area, in world units.
+    pub view_dist: f32,
+}
+
+impl Fog {
+    /// Returns how far along a ray observers can see before the fog hides everything, or `None` if the ray never
+    /// enters it. `dir` must be normalized.
+    pub fn ray_limit(&self, start: Vec2, dir: Vec2) -> Option<f32> {
+        // Clip the ray against each pair of sides in turn
+        let (mut enter, mut exit) = (0_f32, f32::INFINITY);
+        for axis in 0..2 {
+            let (min, max) = (self.rect.min[axis], self.rect.max[axis]);
+            if dir[axis].abs() < f32::EPSILON {
+                if start[axis] < min || start[axis] > max {
+                    return None;
+                }
+            } else {
+                let t1 = (min - start[axis]) / dir[axis];
+                let t2 = (max - start[axis]) / dir[axis];
+                enter = enter.max(t1.min(t2));
+                exit = exit.min(t1.max(t2));
+            }
+        }
+        (enter <= exit).then_some(enter + self.view_dist)
+    }
+}
+
+/// How many degrees apart to cast extra rays across the vision cone when rays can be cut short by fog or the
+/// observer's range. Rays can end between wall corners then, so without these, the edges of the visible area would be
+/// skipped over.
+const EXTRA_RAY_SPACING_DEGREES: f32 = 2.;
+
+/// What an observer's vision mesh was last generated from.
+#[derive(Clone, Copy, PartialEq)]
+struct VisKey {
+    start: Vec2,
+    facing: Vec2,
+    fov_degrees: f32,
+    max_range: Option<f32>,
+}
+
+/// The occluders and fog that vision meshes were last generated against.
+#[derive(Default)]
+pub struct VisionCache {
+    /// The corners of each wall or occluder, and whether it's cover.
+    occluders: HashMap<Entity, ([Vec2; 4], bool)>,
+    fog: Vec<(Rect, f32)>,
+}
+
+/// Returns the corners of a wall's rect collider.
+fn wall_corners(xform: &Transform, collider: &Collider) -> [Vec2; 4] {
+    let half = collider.as_cuboid().unwrap().raw.half_extents.xy();
+    let x_axis = xform.right().xy();
+    let y_axis = xform.up().xy();
+    let center = xform.translation.xy();
+    [0, 1, 2, 3]
+        .map(|i| (((i % 2) * 2 - 1) as f32, ((i / 2) * 2 - 1) as f32))
+        .map(|(x_sign, y_sign)| center + x_sign * x_axis * half.x + y_sign * y_axis * half.y)
+}
+
 /// Updates observers with observable entities they can see.
-fn update_observers(
-    wall_query: Query<(Entity, &Transform, &Collider), With<Wall>>,
-    mut observer_query: Query<(Entity, &mut Observer, &Transform, &Agent)>,
-    observable_query: Query<(Entity, &Transform), With<Observable>>,
+///
+/// Vision meshes are only regenerated when the observer moves or turns, fog changes, or a wall or occluder within the
+/// observer's range is added, removed, or moved. Otherwise, the last mesh is reused.
+///
+/// Objects in view are only observed if the observer notices them, as decided by `DetectionConfig`. Objects in dimly
+/// lit cells can't be noticed from as far away.
+///
+/// Observers that aren't agents, such as fixed cameras, face `+X`.
+///
+/// Sends `EnteredView` and `ExitedView` whenever an observer's `observing` list gains or loses an entity.
+pub fn update_observers(
+    wall_query: Query<
+        (Entity, &Transform, &Collider, Has<Cover>),
+        Or<(With<Wall>, With<OccludesVision>)>,
+    >,
+    fog_query: Query<&Fog>,
+    mut observer_query: Query<(
+        Entity,
+        &mut Observer,
+        &Transform,
+        Option<&Agent>,
+        Option<&StationaryCamera>,
+    )>,
+    observable_query: Query<
+        (Entity, &Transform, Option<&Collider>, Has<OccludesVision>),
+        With<Observable>,
+    >,
     rapier_ctx: Res<RapierContext>,
+    level: Option<Res<LevelLayout>>,
+    detection: Res<DetectionConfig>,
+    illumination: Res<Illumination>,
+    mut rng: ResMut<DetectionRng>,
+    time: Res<Time>,
+    mut entered_events: EventWriter<EnteredView>,
+    mut exited_events: EventWriter<ExitedView>,
+    mut cache: Local<VisionCache>,
 ) {
     // Collect wall endpoints
     let mut all_endpoints = Vec::new();
-    for (_, wall_xform, wall_c) in wall_query.iter() {
-        let rect = wall_c.as_cuboid().unwrap();
-        let half = rect.raw.half_extents.xy();
-        let x_axis = wall_xform.right().xy();
-        let y_axis = wall_xform.up().xy();
-        let center = wall_xform.translation.xy();
-        let endpoints = (0..4)
-            .map(|i| (((i % 2) * 2 - 1) as f32, ((i / 2) * 2 - 1) as f32))
-            .map(|(x_sign, y_sign)| center + x_sign * x_axis * half.x + y_sign * y_axis * half.y)
-            .collect::<Vec<_>>();
-        all_endpoints.extend_from_slice(&endpoints);
+    let mut occluders = HashMap::new();
+    for (wall_e, wall_xform, wall_c, is_cover) in wall_query.iter() {
+        let corners = wall_corners(wall_xform, wall_c);
+        all_endpoints.extend_from_slice(&corners);
+        occluders.insert(wall_e, (corners, is_cover));
     }
 
+    // Find walls that changed since last time, as bounding circles
+    let changed_walls = occluders
+        .iter()
+        .filter(|&(e, occluder)| cache.occluders.get(e) != Some(occluder))
+        .chain(
+            cache
+                .occluders
+                .iter()
+                .filter(|&(e, occluder)| occluders.get(e) != Some(occluder)),
+        )
+        .map(|(_, (corners, _))| {
+            let center = corners.iter().sum::<Vec2>() / 4.;
+            (center, center.distance(corners[0]))
+        })
+        .collect::<Vec<_>>();
+    let fog = fog_query
+        .iter()
+        .map(|fog| (fog.rect, fog.view_dist))
+        .collect::<Vec<_>>();
+    let fog_changed = fog != cache.fog;
+    *cache = VisionCache { occluders, fog };
+
     // Draw per agent visibility triangles
-    let walls = wall_query.iter().map(|(e, _, _)| e).collect::<Vec<_>>();
-    for (observer_e, mut observer, observer_xform, agent) in observer_query.iter_mut() {
-        // Draw vision cone
-        let fov = 60_f32.to_radians();
+    for (observer_e, mut observer, observer_xform, agent, camera) in observer_query.iter_mut() {
         let start = observer_xform.translation.xy();
-        let cone_l = Mat2::from_angle(-fov / 2.) * agent.dir;
-        let cone_r = Mat2::from_angle(fov / 2.) * agent.dir;
-
-        // Add cone boundaries to endpoints
-        let mut sorted_endpoints = all_endpoints.clone();
-        sorted_endpoints.extend_from_slice(&[start + cone_l, start + cone_r]);
-
-        // Sort endpoints by angle and remove any points not within the vision cone
-        sorted_endpoints.retain_mut(|p| {
-            let dir = (*p - start).normalize();
-            dir.dot(agent.dir).acos() <= fov / 2. + 0.01
-        });
-        sorted_endpoints.sort_unstable_by_key(|p| {
-            let dir = (*p - start).normalize();
-            OrderedFloat(dir.x * -dir.y.signum() - dir.y.signum())
-        });
+        let facing = agent
+            .map(|agent| agent.dir)
+            .or(camera.map(|camera| camera.facing))
+            .unwrap_or(Vec2::X);
 
-        let first_idx = sorted_endpoints
-            .iter()
-            .position(|p| p.abs_diff_eq(start + cone_l, 0.1))
-            .unwrap_or(0);
-
-        // Sweep from `cone_l` to `cone_r`
-        let mut all_tris = Vec::new();
-        for i in 0..sorted_endpoints.len() {
-            let i = (i + first_idx) % sorted_endpoints.len();
-            let p = sorted_endpoints[i];
-            let dir = (p - start).normalize();
-            let mut tri = Vec::new();
-            for mat in [Mat2::from_angle(-0.001), Mat2::from_angle(0.001)] {
-                let dir = mat * dir;
-                let result = rapier_ctx.cast_ray(
-                    start,
-                    dir,
-                    Real::MAX,
-                    false,
-                    QueryFilter::new().predicate(&|e| walls.contains(&e)),
-                );
-                if let Some((_, dist)) = result {
-                    tri.push(start + dir * dist);
-                }
-            }
-            if tri.len() == 2 {
-                all_tris.push(tri);
-            }
-        }
-
-        // Generate new vision mesh
-        let mut vis_mesh = Vec::new();
-        if !all_tris.is_empty() {
-            for i in 0..(all_tris.len() - 1) {
-                let next_i = (i + 1) % all_tris.len();
-                let tri = &all_tris[i];
-                let next_tri = &all_tris[next_i];
-                vis_mesh.push([start, tri[1], next_tri[0]]);
-            }
+        let key = VisKey {
+            start,
+            facing,
+            fov_degrees: observer.fov_degrees,
+            max_range: observer.max_range,
+        };
+        let walls_changed = match observer.max_range {
+            Some(range) => changed_walls
+                .iter()
+                .any(|&(center, radius)| center.distance(start) - radius <= range),
+            None => !changed_walls.is_empty(),
+        };
+        if observer.vis_key != Some(key) || walls_changed || fog_changed {
+            observer.vis_mesh = Arc::new(vision_mesh(
+                &observer,
+                start,
+                facing,
+                &all_endpoints,
+                &wall_query,
+                &fog_query,
+                &rapier_ctx,
+            ));
+            observer.vis_key = Some(key);
         }
-        observer.vis_mesh = vis_mesh;
 
-        // Check which observable objects fall within the mesh
-        let mut observing = Vec::new();
-        for (observable_e, observable_xform) in observable_query.iter() {
+        // Check which observable objects fall within the mesh.
+        // Objects behind walls can't be, so they're ruled out first without testing every triangle. Observers inside
+        // a wall's cell (e.g. mounted cameras) would be blocked by that wall, so they skip this.
+        let los_level = level.as_deref().filter(|level| {
+            let grid = level.grid();
+            grid.world_to_cell(start)
+                .and_then(|cell| grid.cell_idx(cell))
+                .is_some_and(|idx| !level.walls[idx])
+        });
+        let mut in_view = Vec::new();
+        for (observable_e, observable_xform, observable_c, occludes) in observable_query.iter() {
             if observable_e == observer_e {
                 continue;
             }
 
             let p = observable_xform.translation.xy();
-            for tri in &observer.vis_mesh {
+            if occludes {
+                let half = observable_c
+                    .and_then(|c| c.as_cuboid())
+                    .map_or(Vec2::ZERO, |rect| rect.raw.half_extents.xy());
+                let rect = Rect::from_center_half_size(p, half).inflate(OCCLUDER_SEEN_DIST);
+                let mut vertices = observer.vis_mesh.iter().flatten();
+                if vertices.any(|&v| rect.contains(v)) {
+                    in_view.push((observable_e, p));
+                }
+                continue;
+            }
+            if los_level.is_some_and(|level| !line_of_sight(level, start, p)) {
+                continue;
+            }
+            for tri in observer.vis_mesh.iter() {
                 let d1 = sign(p, tri[0], tri[1]);
                 let d2 = sign(p, tri[1], tri[2]);
                 let d3 = sign(p, tri[2], tri[0]);
@@ -181,55 +511,235 @@ fn update_observers(
                 let has_pos = d1 > 0. || d2 > 0. || d3 > 0.;
 
                 if !(has_neg && has_pos) {
-                    observing.push(observable_e);
+                    in_view.push((observable_e, p));
                     break;
                 }
             }
         }
+
+        // Roll for whether each object in view is noticed
+        let fov = observer.fov_degrees.to_radians();
+        let mut observing = Vec::new();
+        let mut detection_probs = HashMap::new();
+        for (observable_e, p) in in_view {
+            let offset = p - start;
+            let angle = offset
+                .try_normalize()
+                .map_or(0., |dir| dir.dot(facing).clamp(-1., 1.).acos());
+            let dist = offset.length();
+            let lit = illumination
+                .detection_range(p, observer.max_range)
+                .map_or(true, |range| dist <= range);
+            let prob = if lit {
+                detection.probability(dist, angle, fov)
+            } else {
+                0.
+            };
+            if prob >= 1. || rng.0.gen::<f32>() < prob {
+                observing.push(observable_e);
+            }
+            detection_probs.insert(observable_e, prob);
+        }
+        for &observable in observing.iter().filter(|e| !observer.observing.contains(e)) {
+            entered_events.send(EnteredView {
+                observer: observer_e,
+                observable,
+                time: time.elapsed_seconds_wrapped(),
+            });
+        }
+        for &observable in observer.observing.iter().filter(|e| !observing.contains(e)) {
+            exited_events.send(ExitedView {
+                observer: observer_e,
+                observable,
+                time: time.elapsed_seconds_wrapped(),
+            });
+        }
         observer.observing = observing;
+        observer.detection_probs = detection_probs;
+    }
+}
+
+/// Generates the triangles an observer at `start` facing `facing` can see, by casting rays towards wall endpoints.
+fn vision_mesh(
+    observer: &Observer,
+    start: Vec2,
+    facing: Vec2,
+    all_endpoints: &[Vec2],
+    wall_query: &Query<
+        (Entity, &Transform, &Collider, Has<Cover>),
+        Or<(With<Wall>, With<OccludesVision>)>,
+    >,
+    fog_query: &Query<&Fog>,
+    rapier_ctx: &RapierContext,
+) -> Vec<[Vec2; 3]> {
+    let fov = observer.fov_degrees.to_radians();
+
+    // Ignore any cover the observer is standing in
+    let blocking = wall_query
+        .iter()
+        .filter(|&(_, wall_xform, wall_c, is_cover)| {
+            let half = wall_c.as_cuboid().unwrap().raw.half_extents.xy();
+            let offset = (start - wall_xform.translation.xy()).abs();
+            !(is_cover && offset.x <= half.x && offset.y <= half.y)
+        })
+        .map(|(e, _, _, _)| e)
+        .collect::<Vec<_>>();
+    let cone_l = Mat2::from_angle(-fov / 2.) * facing;
+    let cone_r = Mat2::from_angle(fov / 2.) * facing;
+
+    // Add cone boundaries to endpoints
+    let mut sorted_endpoints = all_endpoints.to_vec();
+    sorted_endpoints.extend_from_slice(&[start + cone_l, start + cone_r]);
+    if !fog_query.is_empty() || observer.max_range.is_some() {
+        let extra_rays = (observer.fov_degrees / EXTRA_RAY_SPACING_DEGREES).ceil() as usize;
+        sorted_endpoints.extend((1..extra_rays).map(|i| {
+            let angle = fov * (i as f32 / extra_rays as f32 - 0.5);
+            start + Mat2::from_angle(angle) * facing
+        }));
+    }
+
+    // Sort endpoints by angle and remove any points not within the vision cone
+    sorted_endpoints.retain_mut(|p| {
+        let dir = (*p - start).normalize();
+        dir.dot(facing).acos() <= fov / 2. + 0.01
+    });
+    sorted_endpoints.sort_unstable_by_key(|p| {
+        let dir = (*p - start).normalize();
+        OrderedFloat(dir.x * -dir.y.signum() - dir.y.signum())
+    });
+
+    let first_idx = sorted_endpoints
+        .iter()
+        .position(|p| p.abs_diff_eq(start + cone_l, 0.1))
+        .unwrap_or(0);
+
+    // Sweep from `cone_l` to `cone_r`
+    let mut all_tris = Vec::new();
+    for i in 0..sorted_endpoints.len() {
+        let i = (i + first_idx) % sorted_endpoints.len();
+        let p = sorted_endpoints[i];
+        let dir = (p - start).normalize();
+        let mut tri = Vec::new();
+        for mat in [Mat2::from_angle(-0.001), Mat2::from_angle(0.001)] {
+            let dir = mat * dir;
+            let max_dist = fog_query
+                .iter()
+                .filter_map(|fog| fog.ray_limit(start, dir))
+                .fold(observer.max_range.unwrap_or(Real::MAX), f32::min);
+            let result = rapier_ctx.cast_ray(
+                start,
+                dir,
+                max_dist,
+                false,
+                QueryFilter::new().predicate(&|e| blocking.contains(&e)),
+            );
+            match result {
+                Some((_, dist)) => tri.push(start + dir * dist),
+                None if max_dist < Real::MAX => tri.push(start + dir * max_dist),
+                None => (),
+            }
+        }
+        if tri.len() == 2 {
+            all_tris.push(tri);
+        }
+    }
+
+    // Join the last ray back to the first if there's no gap between them
+    let mut vis_mesh = Vec::new();
+    if !all_tris.is_empty() {
+        let tri_count = if observer.is_omni() {
+            all_tris.len()
+        } else {
+            all_tris.len() - 1
+        };
+        for i in 0..tri_count {
+            let next_i = (i + 1) % all_tris.len();
+            let tri = &all_tris[i];
+            let next_tri = &all_tris[next_i];
+            vis_mesh.push([start, tri[1], next_tri[0]]);
+        }
     }
+    vis_mesh
 }
 
 /// Updates observers' visual marker data.
-fn update_vm_data(
+///
+/// Markers that move further than `MarkerConfig::move_threshold` between two sightings are recorded as displaced.
+pub fn update_vm_data(
     mut observer_query: Query<&mut Observer>,
     visual_query: Query<(Entity, &GlobalTransform), With<VisualMarker>>,
     time: Res<Time>,
+    config: Res<MarkerConfig>,
 ) {
+    let now = time.elapsed_seconds_wrapped();
     for mut observer in observer_query.iter_mut() {
         for (v_e, xform) in visual_query.iter() {
+            let pos = xform.translation().xy();
             if observer.observing.contains(&v_e) {
                 if let Some(vm_data) = observer.seen_markers.get_mut(&v_e) {
-                    vm_data.last_seen_elapsed = time.elapsed_seconds_wrapped() - vm_data.last_seen;
-                    vm_data.last_seen = time.elapsed_seconds_wrapped();
+                    let moved = pos - vm_data.pos;
+                    let elapsed = now - vm_data.last_seen;
+                    if moved.length() > config.move_threshold * GRID_CELL_SIZE {
+                        vm_data.displacement = moved;
+                        vm_data.displaced_at = now;
+                        vm_data.velocity = if config.estimate_velocity && elapsed > 0. {
+                            moved / elapsed
+                        } else {
+                            Vec2::ZERO
+                        };
+                    } else {
+                        vm_data.expire_displacement(&config, now);
+                    }
+                    vm_data.last_seen_elapsed = elapsed;
+                    vm_data.last_seen = now;
                     vm_data.last_pos = vm_data.pos;
-                    vm_data.pos = xform.translation().xy();
+                    vm_data.pos = pos;
                 } else {
-                    observer.seen_markers.insert(
-                        v_e,
-                        VMSeenData {
-                            last_seen: time.elapsed_seconds_wrapped(),
-                            last_seen_elapsed: time.elapsed_seconds_wrapped(),
-                            pos: xform.translation().xy(),
-                            last_pos: xform.translation().xy(),
-                        },
-                    );
+                    observer.seen_markers.insert(v_e, VMSeenData::new(pos, now));
                 }
             } else {
-                observer.seen_markers.insert(
-                    v_e,
-                    VMSeenData {
-                        last_seen: time.elapsed_seconds_wrapped(),
-                        last_seen_elapsed: time.elapsed_seconds_wrapped(),
-                        last_pos: xform.translation().xy(),
-                        pos: xform.translation().xy(),
-                    },
-                );
+                // Movement evidence outlives the sighting, until it expires
+                let mut vm_data = VMSeenData::new(pos, now);
+                if let Some(old) = observer.seen_markers.get(&v_e) {
+                    vm_data.displacement = old.displacement;
+                    vm_data.velocity = old.velocity;
+                    vm_data.displaced_at = old.displaced_at;
+                    vm_data.expire_displacement(&config, now);
+                }
+                observer.seen_markers.insert(v_e, vm_data);
             }
         }
     }
 }
 
+/// Sent when an observer starts observing an entity.
+#[derive(Event, Clone, Copy, Debug)]
+pub struct EnteredView {
+    pub observer: Entity,
+    pub observable: Entity,
+    /// When the entity was first observed (time since startup).
+    pub time: f32,
+}
+
+/// Sent when an observer stops observing an entity, including when the entity is despawned.
+#[derive(Event, Clone, Copy, Debug)]
+pub struct ExitedView {
+    pub observer: Entity,
+    pub observable: Entity,
+    /// When the entity was first no longer observed (time since startup).
+    pub time: f32,
+}
+
+/// Returns true if no walls lie between two world positions.
+///
+/// This walks the level's wall grid, so it's much cheaper than casting against colliders or building a vision mesh.
+/// Cover, occluders, and agents don't block line of sight here, and neither does fog.
+pub fn line_of_sight(level: &LevelLayout, from: Vec2, to: Vec2) -> bool {
+    level
+        .walls
+        .line_of_sight(from / GRID_CELL_SIZE, to / GRID_CELL_SIZE)
+}
+
 /// Helper function for detecting if a point is in a triangle.
 fn sign(p1: Vec2, p2: Vec2, p3: Vec2) -> f32 {
     (p1.x - p3.x) * (p2.y - p3.y) - (p2.x - p3.x) * (p1.y - p3.y)
@@ -274,6 +784,31 @@ fn add_vis_cones(
     }
 }
 
+/// Whether `DebugObserver`s' vision cones are drawn.
+#[derive(Resource)]
+pub struct ShowVisCones(pub bool);
+
+impl Default for ShowVisCones {
+    fn default() -> Self {
+        Self(true)
+    }
+}
+
+/// Hides or shows vision cones, depending on `ShowVisCones`.
+fn show_vis_cones(
+    show: Res<ShowVisCones>,
+    mut vis_cone_query: Query<&mut Visibility, With<VisCone>>,
+) {
+    let visibility = if show.0 {
+        Visibility::Inherited
+    } else {
+        Visibility::Hidden
+    };
+    for mut vis in vis_cone_query.iter_mut() {
+        vis.set_if_neq(visibility);
+    }
+}
+
 /// If `DebugObserver` is removed, removes the vision cone.
 fn remove_vis_cones(mut observer_query: RemovedComponents<DebugObserver>, mut commands: Commands) {
     for e in observer_query.read() {
@@ -289,7 +824,7 @@ fn draw_observer_areas(
 ) {
     for (observer, children, xform) in observer_query.iter() {
         let mut vertices = Vec::new();
-        for tri in &observer.vis_mesh {
+        for tri in observer.vis_mesh.iter() {
             vertices.push([tri[0].x, tri[0].y, 2.]);
             vertices.push([tri[1].x, tri[1].y, 2.]);
             vertices.push([tri[2].x, tri[2].y, 2.]);
@@ -0,0 +1,158 @@
+//! Runs policies exported to ONNX, for models trained in PyTorch that don't have a candle port in `net`.
+//! Only built with the `onnx` feature.
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
+    prelude::*,
+    utils::BoxedFuture,
+};
+use tract_onnx::prelude::*;
+
+use crate::{
+    gridworld::LevelLayout,
+    net::{softmax, PolicyHidden, PolicySource, POLICY_CHANNELS},
+};
+
+/// Adds support for loading ONNX models as assets.
+pub struct OnnxPlugin;
+
+impl Plugin for OnnxPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<OnnxData>()
+            .init_asset_loader::<OnnxDataLoader>();
+    }
+}
+
+/// The raw bytes of an ONNX model.
+#[derive(Asset, TypePath, Debug, Clone)]
+pub struct OnnxData(pub Vec<u8>);
+
+#[derive(Default)]
+pub struct OnnxDataLoader;
+
+#[derive(Debug, thiserror::Error)]
+pub enum OnnxDataError {}
+
+impl AssetLoader for OnnxDataLoader {
+    type Asset = OnnxData;
+    type Settings = ();
+    type Error = OnnxDataError;
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<Self::Asset, Self::Error>> {
+        Box::pin(async move {
+            let mut buf = Vec::new();
+            reader.read_to_end(&mut buf).await.unwrap();
+            Ok(OnnxData(buf))
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["onnx"]
+    }
+}
+
+/// A policy exported from PyTorch to ONNX.
+///
+/// The model must take the same input as `PolicyNet`, with shape `(1, 9, height, width)`, and output action logits
+/// with shape `(1, action_count)`. It's optimized for the level's size the first time it runs on a level of that size.
+#[derive(Component)]
+pub struct OnnxPolicy {
+    pub weights: Handle<OnnxData>,
+    model: Option<InferenceModel>,
+    /// The model optimized for a level size, as `(width, height)`.
+    plan: Option<((usize, usize), TypedRunnableModel<TypedModel>)>,
+}
+
+impl OnnxPolicy {
+    /// Returns the model optimized for the level's size, optimizing it first if it hasn't been yet.
+    fn plan(
+        &mut self,
+        level: &LevelLayout,
+    ) -> Option<TractResult<&TypedRunnableModel<TypedModel>>> {
+        let model = self.model.as_ref()?;
+        let size = (level.width, level.height);
+        if self.plan.as_ref().map(|(plan_size, _)| *plan_size) != Some(size) {
+            let plan = model
+                .clone()
+                .with_input_fact(
+                    0,
+                    InferenceFact::dt_shape(
+                        f32::datum_type(),
+                        [1, POLICY_CHANNELS, level.height, level.width],
+                    ),
+                )
+                .and_then(|model| model.into_optimized())
+                .and_then(|model| model.into_runnable());
+            match plan {
+                Ok(plan) => self.plan = Some((size, plan)),
+                Err(err) => return Some(Err(err)),
+            }
+        }
+        self.plan.as_ref().map(|(_, plan)| Ok(plan))
+    }
+}
+
+impl PolicySource for OnnxPolicy {
+    type Weights = OnnxData;
+    const EXTENSION: &'static str = "onnx";
+
+    fn with_weights(weights: Handle<OnnxData>) -> Self {
+        Self {
+            weights,
+            model: None,
+            plan: None,
+        }
+    }
+
+    fn action_probs(
+        &mut self,
+        level: &LevelLayout,
+        grid: Vec<f32>,
+        _hidden: &mut PolicyHidden,
+    ) -> Option<Result<Vec<f32>, String>> {
+        let plan = match self.plan(level)? {
+            Ok(plan) => plan,
+            Err(err) => return Some(Err(err.to_string())),
+        };
+        let logits = Tensor::from_shape(&[1, POLICY_CHANNELS, level.height, level.width], &grid)
+            .and_then(|input| plan.run(tvec!(input.into())))
+            .and_then(|outputs| {
+                Ok(outputs[0]
+                    .to_array_view::<f32>()?
+                    .iter()
+                    .copied()
+                    .collect::<Vec<_>>())
+            });
+        Some(
+            logits
+                .map(|logits| softmax(&logits))
+                .map_err(|err| err.to_string()),
+        )
+    }
+}
+
+/// Parses ONNX policies once their models are loaded.
+/// If a model can't be parsed, logs an error and removes the policy.
+pub fn load_onnx_policies(
+    mut commands: Commands,
+    mut policy_query: Query<(Entity, &mut OnnxPolicy)>,
+    onnx_assets: Res<Assets<OnnxData>>,
+) {
+    for (e, mut policy) in policy_query.iter_mut() {
+        if policy.model.is_none() {
+            if let Some(onnx_data) = onnx_assets.get(&policy.weights) {
+                match tract_onnx::onnx().model_for_read(&mut onnx_data.0.as_slice()) {
+                    Ok(model) => policy.model = Some(model),
+                    Err(err) => {
+                        error!("Couldn't load ONNX model: {err}");
+                        commands.entity(e).remove::<OnnxPolicy>();
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,265 @@
+//! Shortest paths between cells of the level.
+//!
+//! Cells are given as `(x, y)`, indexed the same way as `LevelLayout::walls`. Agents move between the four
+//! neighbors of each cell, unless a one-way edge is in the way.
+
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, VecDeque},
+};
+
+use bevy::prelude::*;
+
+use crate::gridworld::{Agent, LevelLayout};
+
+/// Plugin for answering path requests.
+pub struct PathfindingPlugin;
+
+impl Plugin for PathfindingPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<PathRequest>()
+            .add_event::<PathResult>()
+            .init_resource::<DistanceFields>()
+            .init_resource::<DistanceField>()
+            .add_systems(
+                Update,
+                (
+                    invalidate_distance_fields.run_if(resource_changed::<LevelLayout>),
+                    answer_path_requests,
+                    update_distance_field,
+                )
+                    .chain()
+                    .run_if(resource_exists::<LevelLayout>),
+            );
+    }
+}
+
+/// Asks for the shortest path between two world positions.
+/// Each request is answered with a `PathResult` for the same `requester`.
+#[derive(Event, Clone, Copy)]
+pub struct PathRequest {
+    pub requester: Entity,
+    pub start: Vec2,
+    pub goal: Vec2,
+}
+
+/// The answer to a `PathRequest`.
+#[derive(Event, Clone)]
+pub struct PathResult {
+    pub requester: Entity,
+    /// The world positions of the centers of each cell on the path, including the start and goal cells.
+    /// `None` if the goal can't be reached.
+    pub path: Option<Vec<Vec2>>,
+}
+
+/// Caches the distance from every cell to goals that have been asked about, indexed by the goal's cell index.
+///
+/// Distance fields are thrown away whenever the level changes, e.g. when a dynamic wall opens or closes.
+#[derive(Resource, Default)]
+pub struct DistanceFields {
+    fields: HashMap<usize, Vec<Option<u32>>>,
+}
+
+impl DistanceFields {
+    /// Returns how many steps it takes to reach `goal` from each cell, or `None` for cells that can't reach it.
+    pub fn get(&mut self, level: &LevelLayout, goal: (usize, usize)) -> &[Option<u32>] {
+        self.fields
+            .entry(goal.1 * level.width + goal.0)
+            .or_insert_with(|| distance_field(level, goal))
+    }
+
+    /// Removes all cached distance fields.
+    pub fn clear(&mut self) {
+        self.fields.clear();
+    }
+}
+
+/// Distances from every cell to the level's exits and to each agent, kept up to date as the level changes.
+///
+/// Fields are only recomputed when their sources move or the walls change, so reading them is cheap.
+#[derive(Resource, Default)]
+pub struct DistanceField {
+    /// Cells of the door and open exits.
+    exit_cells: Vec<(usize, usize)>,
+    exit_dists: Vec<Option<u32>>,
+    /// The cell each agent was in when its field was computed, along with the field.
+    agent_dists: HashMap<Entity, ((usize, usize), Vec<Option<u32>>)>,
+}
+
+impl DistanceField {
+    /// Returns how many steps it takes to reach the nearest exit from this cell.
+    /// Both the door and open exits count, whether or not they're locked.
+    pub fn to_exit(&self, level: &LevelLayout, (x, y): (usize, usize)) -> Option<u32> {
+        self.exit_dists.get(y * level.width + x).copied().flatten()
+    }
+
+    /// Returns how many steps it takes to reach this agent from this cell.
+    pub fn to_agent(
+        &self,
+        level: &LevelLayout,
+        agent: Entity,
+        (x, y): (usize, usize),
+    ) -> Option<u32> {
+        let (_, dists) = self.agent_dists.get(&agent)?;
+        dists.get(y * level.width + x).copied().flatten()
+    }
+}
+
+/// Returns the open cells next to this one.
+pub fn open_neighbors(
+    level: &LevelLayout,
+    (x, y): (usize, usize),
+) -> impl Iterator<Item = (usize, usize)> + '_ {
+    [
+        (x > 0).then(|| (x - 1, y)),
+        (x + 1 < level.width).then_some((x + 1, y)),
+        (y > 0).then(|| (x, y - 1)),
+        (y + 1 < level.height).then_some((x, y + 1)),
+    ]
+    .into_iter()
+    .flatten()
+    .filter(|&(x, y)| !level.walls[y * level.width + x])
+}
+
+/// Finds the shortest path from `start` to `goal` with A*, including both ends.
+/// Returns `None` if either end is a wall or the goal can't be reached.
+pub fn find_path(
+    level: &LevelLayout,
+    start: (usize, usize),
+    goal: (usize, usize),
+) -> Option<Vec<(usize, usize)>> {
+    let idx = |(x, y): (usize, usize)| y * level.width + x;
+    if level.walls[idx(start)] || level.walls[idx(goal)] {
+        return None;
+    }
+    let heuristic = |(x, y): (usize, usize)| (x.abs_diff(goal.0) + y.abs_diff(goal.1)) as u32;
+
+    let mut came_from = vec![None; level.walls.len()];
+    let mut cost = vec![u32::MAX; level.walls.len()];
+    cost[idx(start)] = 0;
+    let mut to_visit = BinaryHeap::new();
+    to_visit.push(Reverse((heuristic(start), start)));
+    while let Some(Reverse((_, cell))) = to_visit.pop() {
+        if cell == goal {
+            let mut path = vec![goal];
+            while let Some(prev) = came_from[idx(*path.last().unwrap())] {
+                path.push(prev);
+            }
+            path.reverse();
+            return Some(path);
+        }
+        let next_cost = cost[idx(cell)] + 1;
+        for neighbor in open_neighbors(level, cell).filter(|&n| level.can_cross(cell, n)) {
+            if next_cost < cost[idx(neighbor)] {
+                cost[idx(neighbor)] = next_cost;
+                came_from[idx(neighbor)] = Some(cell);
+                to_visit.push(Reverse((next_cost + heuristic(neighbor), neighbor)));
+            }
+        }
+    }
+    None
+}
+
+/// Computes how many steps it takes to reach `goal` from each cell with a breadth first search.
+pub fn distance_field(level: &LevelLayout, goal: (usize, usize)) -> Vec<Option<u32>> {
+    if level.walls[goal.1 * level.width + goal.0] {
+        return vec![None; level.walls.len()];
+    }
+    multi_source_distance_field(level, &[goal])
+}
+
+/// Computes how many steps it takes to reach the closest of `goals` from each cell.
+/// Goals can be walls (e.g. doors), in which case the search starts from their open neighbors.
+pub fn multi_source_distance_field(
+    level: &LevelLayout,
+    goals: &[(usize, usize)],
+) -> Vec<Option<u32>> {
+    let mut dists = vec![None; level.walls.len()];
+    for &(x, y) in goals {
+        dists[y * level.width + x] = Some(0);
+    }
+    let mut to_visit = VecDeque::from_iter(goals.iter().copied());
+    while let Some(cell) = to_visit.pop_front() {
+        let next_dist = dists[cell.1 * level.width + cell.0].unwrap() + 1;
+        // Distances are to the goals, so walk edges backwards
+        for (x, y) in open_neighbors(level, cell).filter(|&n| level.can_cross(n, cell)) {
+            let dist = &mut dists[y * level.width + x];
+            if dist.is_none() {
+                *dist = Some(next_dist);
+                to_visit.push_back((x, y));
+            }
+        }
+    }
+    dists
+}
+
+/// Clears cached distance fields, since the walls they were computed from may have changed.
+fn invalidate_distance_fields(mut dist_fields: ResMut<DistanceFields>) {
+    dist_fields.clear();
+}
+
+/// Finds paths for all requests sent this frame.
+fn answer_path_requests(
+    mut request_evs: EventReader<PathRequest>,
+    mut result_evs: EventWriter<PathResult>,
+    level: Res<LevelLayout>,
+) {
+    let grid = level.grid();
+    for req in request_evs.read() {
+        let path = grid
+            .world_to_cell(req.start)
+            .zip(grid.world_to_cell(req.goal))
+            .and_then(|(start, goal)| find_path(&level, start, goal))
+            .map(|path| {
+                path.into_iter()
+                    .map(|cell| grid.cell_to_world(cell))
+                    .collect()
+            });
+        result_evs.send(PathResult {
+            requester: req.requester,
+            path,
+        });
+    }
+}
+
+/// Recomputes distance fields whose sources moved, or all of them if the walls changed.
+pub fn update_distance_field(
+    level: Res<LevelLayout>,
+    agent_query: Query<(Entity, &GlobalTransform), With<Agent>>,
+    mut dist_field: ResMut<DistanceField>,
+) {
+    let walls_changed = level.is_changed();
+    let dist_field = &mut *dist_field;
+    let grid = level.grid();
+
+    // Exits are stored with rows from top to bottom, so flip them
+    let exit_cells: Vec<_> = level
+        .door_pos
+        .iter()
+        .chain(&level.exits)
+        .map(|&cell| grid.flip_y(cell))
+        .collect();
+    if walls_changed || exit_cells != dist_field.exit_cells {
+        dist_field.exit_dists = multi_source_distance_field(&level, &exit_cells);
+        dist_field.exit_cells = exit_cells;
+    }
+
+    dist_field
+        .agent_dists
+        .retain(|agent_e, _| agent_query.contains(*agent_e));
+    for (agent_e, xform) in agent_query.iter() {
+        let Some(cell) = grid.world_to_cell(xform.translation().xy()) else {
+            continue;
+        };
+        let up_to_date = dist_field
+            .agent_dists
+            .get(&agent_e)
+            .is_some_and(|(old_cell, _)| *old_cell == cell);
+        if walls_changed || !up_to_date {
+            dist_field.agent_dists.insert(
+                agent_e,
+                (cell, multi_source_distance_field(&level, &[cell])),
+            );
+        }
+    }
+}
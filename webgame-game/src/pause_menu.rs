@@ -0,0 +1,165 @@
+//! The pause menu, opened with Escape during a level.
+
+use bevy::{app::AppExit, prelude::*};
+use bevy_rapier2d::prelude::*;
+
+use crate::{
+    gridworld::{reset_level, LevelLayout, ResetEvent, ShouldRun},
+    screens::ScreenState,
+};
+
+/// Adds the pause menu, which stops agents, physics and observers until the level is resumed.
+pub struct PauseMenuPlugin;
+
+impl Plugin for PauseMenuPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(ScreenState::Paused), (setup_pause_menu, pause_game))
+            .add_systems(
+                OnExit(ScreenState::Paused),
+                (cleanup_pause_menu, resume_game),
+            )
+            .add_systems(
+                Update,
+                (
+                    open_pause_menu.run_if(in_state(ScreenState::Game)),
+                    (
+                        pause_game.run_if(resource_added::<ShouldRun>),
+                        pause_menu_input.before(reset_level),
+                    )
+                        .run_if(in_state(ScreenState::Paused)),
+                ),
+            );
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+const PAUSE_HELP: &str = "Esc: Resume, R: Restart, M: Main menu, Q: Quit";
+// Browsers can't close the page from the game, so there's nothing to quit to
+#[cfg(target_arch = "wasm32")]
+const PAUSE_HELP: &str = "Esc: Resume, R: Restart, M: Main menu";
+
+/// Marks entities that belong to the pause menu.
+#[derive(Component)]
+struct PauseUi;
+
+/// Indicates that the level was paused when the menu was opened, and should resume when it closes.
+#[derive(Resource)]
+struct MenuPaused;
+
+fn open_pause_menu(
+    inpt: Res<ButtonInput<KeyCode>>,
+    mut next_state: ResMut<NextState<ScreenState>>,
+) {
+    if inpt.just_pressed(KeyCode::Escape) {
+        next_state.set(ScreenState::Paused);
+    }
+}
+
+/// Pauses the level while the menu is open, including levels that start while it's open.
+fn pause_game(
+    mut commands: Commands,
+    should_run: Option<Res<ShouldRun>>,
+    mut rapier_config: ResMut<RapierConfiguration>,
+) {
+    if should_run.is_some() {
+        commands.remove_resource::<ShouldRun>();
+        commands.insert_resource(MenuPaused);
+        rapier_config.physics_pipeline_active = false;
+    }
+}
+
+/// Resumes the level if the menu paused it.
+fn resume_game(
+    mut commands: Commands,
+    paused: Option<Res<MenuPaused>>,
+    mut rapier_config: ResMut<RapierConfiguration>,
+) {
+    if paused.is_some() {
+        commands.remove_resource::<MenuPaused>();
+        commands.insert_resource(ShouldRun);
+        rapier_config.physics_pipeline_active = true;
+    }
+}
+
+/// Spawns the menu over the game.
+fn setup_pause_menu(mut commands: Commands) {
+    commands.spawn((
+        PauseUi,
+        Camera2dBundle {
+            camera: Camera {
+                // Draw over the level
+                order: 1,
+                clear_color: ClearColorConfig::None,
+                ..default()
+            },
+            ..default()
+        },
+    ));
+    commands
+        .spawn((
+            PauseUi,
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.),
+                    height: Val::Percent(100.),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    row_gap: Val::Px(16.),
+                    ..default()
+                },
+                background_color: Color::rgba(0., 0., 0., 0.6).into(),
+                ..default()
+            },
+        ))
+        .with_children(|p| {
+            p.spawn(TextBundle::from_section(
+                "Paused",
+                TextStyle {
+                    font_size: 32.,
+                    color: Color::WHITE,
+                    ..default()
+                },
+            ));
+            p.spawn(TextBundle::from_section(
+                PAUSE_HELP,
+                TextStyle {
+                    font_size: 16.,
+                    color: Color::WHITE,
+                    ..default()
+                },
+            ));
+        });
+}
+
+fn cleanup_pause_menu(mut commands: Commands, ui_query: Query<Entity, With<PauseUi>>) {
+    for e in ui_query.iter() {
+        commands.entity(e).despawn_recursive();
+    }
+}
+
+/// Resumes, restarts the level, goes back to the main menu, or quits.
+fn pause_menu_input(
+    inpt: Res<ButtonInput<KeyCode>>,
+    mut next_state: ResMut<NextState<ScreenState>>,
+    level: Option<Res<LevelLayout>>,
+    mut ev_reset: EventWriter<ResetEvent>,
+    mut ev_exit: EventWriter<AppExit>,
+) {
+    if inpt.just_pressed(KeyCode::Escape) {
+        next_state.set(ScreenState::Game);
+    }
+    if inpt.just_pressed(KeyCode::KeyR) {
+        // Restart the current level, rather than moving on to the next one in the level set
+        ev_reset.send(ResetEvent {
+            level: level.map(|level| level.clone()),
+        });
+        next_state.set(ScreenState::Game);
+    }
+    if inpt.just_pressed(KeyCode::KeyM) {
+        next_state.set(ScreenState::MainMenu);
+    }
+    if cfg!(not(target_arch = "wasm32")) && inpt.just_pressed(KeyCode::KeyQ) {
+        ev_exit.send(AppExit);
+    }
+}
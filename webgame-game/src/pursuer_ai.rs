@@ -0,0 +1,359 @@
+//! A scripted pursuer that patrols the level, chases the player on sight, and searches where it last saw or heard them.
+//!
+//! Used as a baseline opponent for trained agents, and drives the pursuer in the shipped game when there's no trained
+//! policy to run. What the pursuer does is decided by a behavior tree (see `behavior`), which can be loaded from a
+//! `.pursuer.ron` file. The built-in tree is in `assets/behaviors/default.pursuer.ron`.
+
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    behavior::{BehaviorTree, BehaviorTreeLoader, Leaf, Node, Status},
+    gridworld::{
+        move_agents, LevelLayout, NextAction, PlayerAgent, PursuerAgent, ShouldRun, GRID_CELL_SIZE,
+    },
+    net::PolicyRunner,
+    observer::{update_observers, Observer},
+    pathfinding::{find_path, open_neighbors},
+    world_objs::{audible_noises, HearingConfig, NoiseSource},
+};
+
+/// Plugin for scripted pursuers. Pursuers that are also given a trained policy only follow the script if the policy
+/// can't be loaded or fails to run.
+#[derive(Default)]
+pub struct ScriptedPursuerPlugin {
+    /// The asset path of a behavior tree to use instead of the built-in one. It's swapped in once it loads, and again
+    /// whenever the file changes.
+    pub behavior_path: Option<String>,
+}
+
+impl Plugin for ScriptedPursuerPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PursuerBehavior>()
+            .init_asset::<BehaviorTree<PursuerLeaf>>()
+            .register_asset_loader(BehaviorTreeLoader::<PursuerLeaf>::new("pursuer.ron"))
+            .add_systems(
+                Update,
+                (
+                    (add_scripted_pursuers, update_pursuer_behavior),
+                    run_scripted_pursuers
+                        .after(update_observers)
+                        .before(move_agents)
+                        .run_if(resource_exists::<ShouldRun>),
+                )
+                    .chain(),
+            );
+        if let Some(path) = self.behavior_path.clone() {
+            app.add_systems(
+                Startup,
+                move |mut commands: Commands, asset_server: Res<AssetServer>| {
+                    commands.insert_resource(PursuerBehaviorAsset(asset_server.load(path.clone())));
+                },
+            );
+        }
+    }
+}
+
+/// How far a pursuer searches around where it lost track of the player, in steps between cells.
+const SEARCH_RADIUS: u32 = 2;
+/// How close a pursuer has to get to a position to count as reaching it.
+const ARRIVE_DIST: f32 = GRID_CELL_SIZE * 0.25;
+
+/// The behavior tree that scripted pursuers follow.
+#[derive(Resource)]
+pub struct PursuerBehavior(pub Node<PursuerLeaf>);
+
+impl Default for PursuerBehavior {
+    fn default() -> Self {
+        Self(
+            Node::from_ron(include_str!("../assets/behaviors/default.pursuer.ron"))
+                .expect("the built-in pursuer behavior should parse"),
+        )
+    }
+}
+
+/// The behavior tree asset that replaces `PursuerBehavior` once it loads.
+#[derive(Resource)]
+struct PursuerBehaviorAsset(Handle<BehaviorTree<PursuerLeaf>>);
+
+/// What scripted pursuers remember between updates.
+#[derive(Component, Default)]
+pub struct ScriptedPursuer {
+    /// World positions to walk to in order while patrolling. If empty, the pursuer patrols the middle of each quadrant
+    /// of the level.
+    pub waypoints: Vec<Vec2>,
+    /// The index of the waypoint currently being walked to.
+    pub next_waypoint: usize,
+    /// Where the player was last seen or heard, if the pursuer hasn't gone to look there yet.
+    pub lead: Option<Vec2>,
+    /// Cells left to check around the last lead, in order.
+    pub search: VecDeque<Vec2>,
+}
+
+/// The checks and actions that pursuer behavior trees are built from.
+#[derive(Deserialize, Serialize, TypePath, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PursuerLeaf {
+    /// Succeeds if the pursuer can see the player.
+    SeesPlayer,
+    /// Succeeds if the pursuer can hear a noise the player made.
+    HearsPlayer,
+    /// Succeeds if the pursuer has a lead it hasn't investigated yet.
+    HasLead,
+    /// Succeeds if the pursuer has cells left to search.
+    IsSearching,
+    /// Runs towards the player, remembering where they were as a lead. Fails if the player can't be seen.
+    Chase,
+    /// Makes the loudest noise the player made the lead. Fails if the pursuer can't hear one.
+    FollowNoise,
+    /// Walks to the lead, then succeeds and starts searching around it. Fails if there's no lead or it can't be
+    /// reached.
+    Investigate,
+    /// Walks to each cell left to search in turn, succeeding once they've all been checked.
+    Search,
+    /// Walks between patrol waypoints. Always running.
+    Patrol,
+}
+
+/// What pursuer behavior tree leaves check and act on.
+pub struct PursuerContext<'a> {
+    pub pursuer: &'a mut ScriptedPursuer,
+    pub level: &'a LevelLayout,
+    pub pos: Vec2,
+    /// Where the player is, if the pursuer can see them.
+    pub seen: Option<Vec2>,
+    /// Where the loudest noise the player made is, if the pursuer can hear it.
+    pub heard: Option<Vec2>,
+    /// The direction the pursuer walks in this update.
+    pub dir: Vec2,
+}
+
+impl PursuerContext<'_> {
+    /// Walks along the shortest path to `goal`. Returns whether the pursuer has arrived, or `None` if the goal can't be
+    /// reached.
+    fn walk_to(&mut self, goal: Vec2) -> Option<bool> {
+        if self.pos.distance(goal) < ARRIVE_DIST {
+            return Some(true);
+        }
+        let grid = self.level.grid();
+        let path = grid
+            .world_to_cell(self.pos)
+            .zip(grid.world_to_cell(goal))
+            .and_then(|(start, end)| find_path(self.level, start, end))?;
+        // Walk to the next cell on the path, or straight to the goal once in its cell
+        let next = match path.as_slice() {
+            [_, next, ..] => grid.cell_to_world(*next),
+            _ => goal,
+        };
+        self.dir = (next - self.pos).normalize_or_zero();
+        Some(false)
+    }
+}
+
+impl<'a> Leaf<PursuerContext<'a>> for PursuerLeaf {
+    fn tick(&self, ctx: &mut PursuerContext<'a>) -> Status {
+        let check = |cond: bool| {
+            if cond {
+                Status::Success
+            } else {
+                Status::Failure
+            }
+        };
+        match self {
+            Self::SeesPlayer => check(ctx.seen.is_some()),
+            Self::HearsPlayer => check(ctx.heard.is_some()),
+            Self::HasLead => check(ctx.pursuer.lead.is_some()),
+            Self::IsSearching => check(!ctx.pursuer.search.is_empty()),
+            Self::Chase => {
+                let Some(player_pos) = ctx.seen else {
+                    return Status::Failure;
+                };
+                ctx.pursuer.lead = Some(player_pos);
+                ctx.walk_to(player_pos);
+                Status::Running
+            }
+            Self::FollowNoise => {
+                ctx.pursuer.lead = ctx.heard.or(ctx.pursuer.lead);
+                check(ctx.heard.is_some())
+            }
+            Self::Investigate => {
+                let Some(lead) = ctx.pursuer.lead else {
+                    return Status::Failure;
+                };
+                match ctx.walk_to(lead) {
+                    Some(false) => Status::Running,
+                    Some(true) => {
+                        ctx.pursuer.lead = None;
+                        ctx.pursuer.search = search_cells(ctx.level, lead);
+                        Status::Success
+                    }
+                    None => {
+                        ctx.pursuer.lead = None;
+                        Status::Failure
+                    }
+                }
+            }
+            Self::Search => {
+                let Some(&cell) = ctx.pursuer.search.front() else {
+                    return Status::Success;
+                };
+                // Cells that can't be reached are skipped
+                if ctx.walk_to(cell) != Some(false) {
+                    ctx.pursuer.search.pop_front();
+                }
+                if ctx.pursuer.search.is_empty() {
+                    Status::Success
+                } else {
+                    Status::Running
+                }
+            }
+            Self::Patrol => {
+                if ctx.pursuer.waypoints.is_empty() {
+                    ctx.pursuer.waypoints = quadrant_waypoints(ctx.level);
+                }
+                let waypoint_count = ctx.pursuer.waypoints.len();
+                if let Some(&waypoint) = ctx.pursuer.waypoints.get(ctx.pursuer.next_waypoint) {
+                    if ctx.walk_to(waypoint) != Some(false) {
+                        ctx.pursuer.next_waypoint =
+                            (ctx.pursuer.next_waypoint + 1) % waypoint_count;
+                    }
+                }
+                Status::Running
+            }
+        }
+    }
+}
+
+/// Returns the open cell nearest the middle of each quadrant of the level, going around the level in a loop.
+pub fn quadrant_waypoints(level: &LevelLayout) -> Vec<Vec2> {
+    let grid = level.grid();
+    let (w, h) = (level.width as f32, level.height as f32);
+    [(0.25, 0.25), (0.75, 0.25), (0.75, 0.75), (0.25, 0.75)]
+        .into_iter()
+        .filter_map(|(fx, fy)| {
+            let center = Vec2::new(fx * w - 0.5, fy * h - 0.5);
+            (0..level.walls.len())
+                .filter(|&idx| !level.walls[idx])
+                .map(|idx| (idx % level.width, idx / level.width))
+                .min_by(|&(ax, ay), &(bx, by)| {
+                    let dist =
+                        |x: usize, y: usize| center.distance_squared(Vec2::new(x as f32, y as f32));
+                    dist(ax, ay).total_cmp(&dist(bx, by))
+                })
+        })
+        .map(|cell| grid.cell_to_world(cell))
+        .collect()
+}
+
+/// Returns the world positions of cells within `SEARCH_RADIUS` steps of `pos`, nearest first.
+fn search_cells(level: &LevelLayout, pos: Vec2) -> VecDeque<Vec2> {
+    let grid = level.grid();
+    let Some(start) = grid.world_to_cell(pos) else {
+        return VecDeque::new();
+    };
+    let mut dists = vec![None; level.walls.len()];
+    dists[start.1 * level.width + start.0] = Some(0);
+    let mut to_visit = VecDeque::from([start]);
+    let mut cells = VecDeque::new();
+    while let Some(cell) = to_visit.pop_front() {
+        let dist = dists[cell.1 * level.width + cell.0].unwrap();
+        if dist > 0 {
+            cells.push_back(grid.cell_to_world(cell));
+        }
+        if dist == SEARCH_RADIUS {
+            continue;
+        }
+        for next in open_neighbors(level, cell).filter(|&next| level.can_cross(cell, next)) {
+            let next_dist = &mut dists[next.1 * level.width + next.0];
+            if next_dist.is_none() {
+                *next_dist = Some(dist + 1);
+                to_visit.push_back(next);
+            }
+        }
+    }
+    cells
+}
+
+/// Gives pursuers a script as they spawn.
+fn add_scripted_pursuers(
+    mut commands: Commands,
+    pursuer_query: Query<Entity, Added<PursuerAgent>>,
+) {
+    for pursuer_e in pursuer_query.iter() {
+        commands
+            .entity(pursuer_e)
+            .insert(ScriptedPursuer::default());
+    }
+}
+
+/// Swaps in the behavior tree asset when it loads or changes.
+fn update_pursuer_behavior(
+    behavior_asset: Option<Res<PursuerBehaviorAsset>>,
+    trees: Res<Assets<BehaviorTree<PursuerLeaf>>>,
+    mut asset_events: EventReader<AssetEvent<BehaviorTree<PursuerLeaf>>>,
+    mut behavior: ResMut<PursuerBehavior>,
+) {
+    let Some(behavior_asset) = behavior_asset else {
+        return;
+    };
+    for ev in asset_events.read() {
+        if ev.is_loaded_with_dependencies(&behavior_asset.0) || ev.is_modified(&behavior_asset.0) {
+            if let Some(tree) = trees.get(&behavior_asset.0) {
+                behavior.0 = tree.0.clone();
+            }
+        }
+    }
+}
+
+/// Runs the behavior tree for each scripted pursuer, using what it sees and hears.
+fn run_scripted_pursuers(
+    mut pursuer_query: Query<
+        (
+            &mut ScriptedPursuer,
+            &Observer,
+            &GlobalTransform,
+            &mut NextAction,
+        ),
+        Without<PolicyRunner>,
+    >,
+    player_query: Query<(Entity, &GlobalTransform), With<PlayerAgent>>,
+    noise_query: Query<(&GlobalTransform, &NoiseSource)>,
+    level: Res<LevelLayout>,
+    (behavior, hearing_config): (Res<PursuerBehavior>, Res<HearingConfig>),
+) {
+    for (mut pursuer, observer, xform, mut next_action) in pursuer_query.iter_mut() {
+        let pos = xform.translation().xy();
+        let seen = player_query
+            .iter()
+            .find(|(player_e, _)| observer.observing.contains(player_e))
+            .map(|(_, player_xform)| player_xform.translation().xy());
+        let player_noises = noise_query
+            .iter()
+            .filter(|(_, noise_src)| {
+                noise_src
+                    .activated_by
+                    .is_some_and(|e| player_query.contains(e))
+            })
+            .map(|(noise_xform, noise_src)| {
+                let noise_pos = noise_xform.translation().xy();
+                (noise_pos, noise_pos, noise_src)
+            });
+        let heard = audible_noises(&level, pos, hearing_config.pursuer, player_noises)
+            .into_iter()
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(noise_pos, _)| noise_pos);
+
+        let mut ctx = PursuerContext {
+            pursuer: &mut *pursuer,
+            level: &level,
+            pos,
+            seen,
+            heard,
+            dir: Vec2::ZERO,
+        };
+        behavior.0.tick(&mut ctx);
+        next_action.dir = ctx.dir;
+        next_action.toggle_objs = false;
+    }
+}
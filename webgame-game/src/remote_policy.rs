@@ -0,0 +1,249 @@
+//! Runs policies in another process, for models too big to run in the game or that can't be exported for browsers.
+//! Every tick, the game sends the agent's observation to a policy server and waits for an action back.
+//! Not built for browsers, since they can't open plain TCP connections or block while waiting.
+
+use std::{
+    io::{self, BufRead, BufReader, ErrorKind, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::{Duration, Instant},
+};
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
+    prelude::*,
+    utils::BoxedFuture,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    gridworld::LevelLayout,
+    net::{PolicyHidden, PolicySource, ACTION_COUNT, POLICY_CHANNELS},
+};
+
+/// How long to wait before reconnecting to a policy server that couldn't be reached.
+pub const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Adds support for loading remote policy descriptions as assets.
+pub struct RemotePolicyPlugin;
+
+impl Plugin for RemotePolicyPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<RemotePolicyConfig>()
+            .init_asset_loader::<RemotePolicyConfigLoader>();
+    }
+}
+
+/// Where a policy server is, read from a `.remote` file containing JSON like the following:
+///
+/// ```json
+/// { "addr": "127.0.0.1:9100", "timeout_ms": 100 }
+/// ```
+#[derive(Asset, TypePath, Deserialize, Debug, Clone)]
+pub struct RemotePolicyConfig {
+    /// The server's address, as `host:port`.
+    pub addr: String,
+    /// How long to wait for the server to connect or reply before falling back.
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+fn default_timeout_ms() -> u64 {
+    100
+}
+
+impl RemotePolicyConfig {
+    fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+}
+
+#[derive(Default)]
+pub struct RemotePolicyConfigLoader;
+
+#[non_exhaustive]
+#[derive(Debug, thiserror::Error)]
+pub enum RemotePolicyConfigError {
+    #[error("Could not load asset: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid remote policy: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+impl AssetLoader for RemotePolicyConfigLoader {
+    type Asset = RemotePolicyConfig;
+    type Settings = ();
+    type Error = RemotePolicyConfigError;
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<Self::Asset, Self::Error>> {
+        Box::pin(async move {
+            let mut buf = String::new();
+            reader.read_to_string(&mut buf).await?;
+            Ok(serde_json::from_str(&buf)?)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["remote"]
+    }
+}
+
+/// Sent to the policy server every tick, as a single line of JSON.
+#[derive(Serialize)]
+pub struct ObservationMessage<'a> {
+    /// How many observations this connection has sent before this one.
+    pub tick: u64,
+    pub width: usize,
+    pub height: usize,
+    pub channels: usize,
+    /// The input grid built by `policy_grid`, with shape `(channels, height, width)`.
+    pub grid: &'a [f32],
+}
+
+/// What the policy server replies to each observation with, as a single line of JSON.
+/// Either `{"action": 3}`, or `{"probs": [...]}` with a probability for each action.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum ActionMessage {
+    Action { action: usize },
+    Probs { probs: Vec<f32> },
+}
+
+/// A policy run by a server in another process.
+///
+/// If the server doesn't reply in time or can't be reached, the agent keeps doing whatever it did last, and the game
+/// reconnects. Replies that don't describe a valid action remove the policy, like a model that fails to run.
+#[derive(Component)]
+pub struct RemotePolicy {
+    pub config: Handle<RemotePolicyConfig>,
+    loaded_config: Option<RemotePolicyConfig>,
+    stream: Option<BufReader<TcpStream>>,
+    tick: u64,
+    /// If connecting failed, when to try again.
+    retry_at: Option<Instant>,
+}
+
+impl RemotePolicy {
+    /// Sends an observation to the server, returning the probability it gives each action.
+    fn request(
+        &mut self,
+        config: &RemotePolicyConfig,
+        level: &LevelLayout,
+        grid: &[f32],
+    ) -> io::Result<Vec<f32>> {
+        if self.stream.is_none() {
+            if self
+                .retry_at
+                .is_some_and(|retry_at| Instant::now() < retry_at)
+            {
+                return Err(io::Error::new(
+                    ErrorKind::NotConnected,
+                    "waiting to reconnect",
+                ));
+            }
+            self.retry_at = Some(Instant::now() + RECONNECT_DELAY);
+            self.stream = Some(connect(config)?);
+            self.retry_at = None;
+            self.tick = 0;
+        }
+        let stream = self.stream.as_mut().expect("should have just connected");
+
+        let mut line = serde_json::to_vec(&ObservationMessage {
+            tick: self.tick,
+            width: level.width,
+            height: level.height,
+            channels: POLICY_CHANNELS,
+            grid,
+        })?;
+        line.push(b'\n');
+        stream.get_mut().write_all(&line)?;
+        self.tick += 1;
+
+        let mut reply = String::new();
+        if stream.read_line(&mut reply)? == 0 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        let reply = serde_json::from_str(&reply)
+            .map_err(|err| io::Error::new(ErrorKind::InvalidData, err))?;
+        match reply {
+            ActionMessage::Action { action } if action < ACTION_COUNT => {
+                let mut probs = vec![0.; ACTION_COUNT];
+                probs[action] = 1.;
+                Ok(probs)
+            }
+            ActionMessage::Probs { probs } if probs.len() == ACTION_COUNT => Ok(probs),
+            _ => Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("replies must pick one of {ACTION_COUNT} actions"),
+            )),
+        }
+    }
+}
+
+/// Connects to the server, with reads and writes that time out.
+fn connect(config: &RemotePolicyConfig) -> io::Result<BufReader<TcpStream>> {
+    let addr = config
+        .addr
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "address didn't resolve"))?;
+    let stream = TcpStream::connect_timeout(&addr, config.timeout())?;
+    stream.set_read_timeout(Some(config.timeout()))?;
+    stream.set_write_timeout(Some(config.timeout()))?;
+    stream.set_nodelay(true)?;
+    Ok(BufReader::new(stream))
+}
+
+impl PolicySource for RemotePolicy {
+    type Weights = RemotePolicyConfig;
+    const EXTENSION: &'static str = "remote";
+
+    fn with_weights(weights: Handle<RemotePolicyConfig>) -> Self {
+        Self {
+            config: weights,
+            loaded_config: None,
+            stream: None,
+            tick: 0,
+            retry_at: None,
+        }
+    }
+
+    fn action_probs(
+        &mut self,
+        level: &LevelLayout,
+        grid: Vec<f32>,
+        _hidden: &mut PolicyHidden,
+    ) -> Option<Result<Vec<f32>, String>> {
+        let config = self.loaded_config.clone()?;
+        match self.request(&config, level, &grid) {
+            Ok(probs) => Some(Ok(probs)),
+            Err(err) if err.kind() == ErrorKind::InvalidData => Some(Err(err.to_string())),
+            // A late reply would be mistaken for the next one, so start over with a new connection
+            Err(err) => {
+                if err.kind() != ErrorKind::NotConnected {
+                    warn!(
+                        "Policy server at {} didn't reply, repeating the last action: {err}",
+                        config.addr
+                    );
+                }
+                self.stream = None;
+                None
+            }
+        }
+    }
+}
+
+/// Gives remote policies their configs once they're loaded.
+pub fn load_remote_policies(
+    mut policy_query: Query<&mut RemotePolicy>,
+    config_assets: Res<Assets<RemotePolicyConfig>>,
+) {
+    for mut policy in policy_query.iter_mut() {
+        if policy.loaded_config.is_none() {
+            policy.loaded_config = config_assets.get(&policy.config).cloned();
+        }
+    }
+}
@@ -0,0 +1,563 @@
+//! Records matches to replay files, and plays them back in the game.
+//!
+//! A replay stores the level, the seed used for the game's random numbers, and what both agents did on every tick, so
+//! playing it back reproduces the match without saving its state. Checksums of where everything is are stored every
+//! `CHECKSUM_INTERVAL` ticks, so playback can tell when it stops matching the recording.
+//!
+//! Replay files start with `REPLAY_MAGIC` and a little endian `u32` format version, followed by a `Replay` encoded with
+//! bincode.
+
+use std::{hash::Hasher, io, path::Path, time::Duration};
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
+    prelude::*,
+    time::TimeUpdateStrategy,
+    utils::{BoxedFuture, Instant},
+    window::{PresentMode, PrimaryWindow},
+};
+use bevy_rapier2d::prelude::*;
+use rand::{rngs::StdRng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    filter::FilterRng,
+    gadgets::Gadget,
+    gridworld::{
+        GameId, LevelLayout, LevelObject, LoadedLevelData, NextAction, PlayerAgent, PursuerAgent,
+        RemoteControlled, ResetEvent, ShouldRun,
+    },
+    observer::DetectionRng,
+};
+
+/// The first bytes of every replay file.
+pub const REPLAY_MAGIC: &[u8; 4] = b"PRPL";
+/// The current version of the replay format. Replays with other versions can't be played.
+pub const REPLAY_FORMAT_VERSION: u32 = 1;
+/// How many ticks apart state checksums are recorded.
+pub const CHECKSUM_INTERVAL: usize = 30;
+
+/// A recorded match.
+#[derive(Asset, TypePath, Serialize, Deserialize, Clone)]
+pub struct Replay {
+    /// The level as it was when the match started, with spawns set to where the agents started.
+    pub level: LoadedLevelData,
+    /// The seed for `DetectionRng` and `FilterRng`.
+    pub seed: u64,
+    pub ticks: Vec<ReplayTick>,
+    /// `(tick, checksum)` pairs, where the checksum is `state_checksum` at the end of that tick.
+    pub checksums: Vec<(usize, u64)>,
+}
+
+/// What happened on a single tick.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+pub struct ReplayTick {
+    /// How much game time the tick covered.
+    pub delta: Duration,
+    pub player: RecordedAction,
+    pub pursuer: RecordedAction,
+}
+
+/// A recorded `NextAction`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct RecordedAction {
+    pub dir: [f32; 2],
+    pub toggle_objs: bool,
+    pub gadget: Option<Gadget>,
+}
+
+impl From<&NextAction> for RecordedAction {
+    fn from(action: &NextAction) -> Self {
+        Self {
+            dir: action.dir.into(),
+            toggle_objs: action.toggle_objs,
+            gadget: action.gadget,
+        }
+    }
+}
+
+impl RecordedAction {
+    pub fn apply(&self, action: &mut NextAction) {
+        action.dir = self.dir.into();
+        action.toggle_objs = self.toggle_objs;
+        action.gadget = self.gadget;
+    }
+}
+
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum ReplayError {
+    #[error("Could not load asset: {0}")]
+    Io(#[from] io::Error),
+    #[error("Not a replay file")]
+    NotAReplay,
+    #[error("Replay format version {0} is not supported, expected {REPLAY_FORMAT_VERSION}")]
+    UnsupportedVersion(u32),
+    #[error("Invalid replay: {0}")]
+    Decode(#[from] bincode::Error),
+}
+
+impl Replay {
+    /// Encodes this replay in the replay file format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = REPLAY_MAGIC.to_vec();
+        bytes.extend(REPLAY_FORMAT_VERSION.to_le_bytes());
+        bincode::serialize_into(&mut bytes, self).expect("replays should always serialize");
+        bytes
+    }
+
+    /// Decodes a replay file.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ReplayError> {
+        let body = bytes
+            .strip_prefix(REPLAY_MAGIC)
+            .ok_or(ReplayError::NotAReplay)?;
+        let (version, body) = body
+            .split_first_chunk::<4>()
+            .ok_or(ReplayError::NotAReplay)?;
+        let version = u32::from_le_bytes(*version);
+        if version != REPLAY_FORMAT_VERSION {
+            return Err(ReplayError::UnsupportedVersion(version));
+        }
+        Ok(bincode::deserialize(body)?)
+    }
+}
+
+/// Hashes where every entity with a `GameId` is. The order entities are given in doesn't matter.
+pub fn state_checksum<'a>(
+    entities: impl IntoIterator<Item = (&'a GameId, &'a GlobalTransform)>,
+) -> u64 {
+    entities
+        .into_iter()
+        .map(|(id, xform)| {
+            let pos = xform.translation();
+            let mut hasher = FnvHasher::default();
+            hasher.write_u64(id.0);
+            hasher.write_u32(pos.x.to_bits());
+            hasher.write_u32(pos.y.to_bits());
+            hasher.finish()
+        })
+        .fold(0, u64::wrapping_add)
+}
+
+/// FNV-1a, which unlike `DefaultHasher` gives the same hashes on every platform and Rust version.
+pub struct FnvHasher(u64);
+
+impl Default for FnvHasher {
+    fn default() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+
+impl Hasher for FnvHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+#[derive(Default)]
+struct ReplayLoader;
+
+impl AssetLoader for ReplayLoader {
+    type Asset = Replay;
+    type Settings = ();
+    type Error = ReplayError;
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<Self::Asset, Self::Error>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            Replay::from_bytes(&bytes)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["replay"]
+    }
+}
+
+/// Where replays are written, relative to the working directory.
+/// `{}` is replaced with the lowest number that doesn't overwrite an existing replay.
+const SAVED_REPLAY_PATH: &str = "assets/replays/replay_{}.replay";
+
+/// Records the current level, and saves it as a replay when F6 is pressed.
+///
+/// The game's random numbers are seeded when each level starts, so the recording can be played back exactly.
+pub struct ReplayRecorderPlugin;
+
+impl Plugin for ReplayRecorderPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ReplayRecorder>()
+            .add_systems(
+                PreUpdate,
+                (
+                    restart_recording.run_if(resource_added::<LevelLayout>),
+                    start_tick,
+                )
+                    .chain(),
+            )
+            .add_systems(Update, save_replay_hotkey)
+            .add_systems(Last, record_tick);
+    }
+}
+
+/// The replay of the current level so far.
+#[derive(Resource, Default)]
+pub struct ReplayRecorder {
+    /// Set once the level starts running.
+    pub replay: Option<Replay>,
+    /// Whether the game is running this frame.
+    ticking: bool,
+}
+
+/// Throws away the last level's recording.
+fn restart_recording(mut recorder: ResMut<ReplayRecorder>) {
+    recorder.replay = None;
+}
+
+/// Checks whether the game runs this frame, and starts recording if the level just started.
+pub fn start_tick(
+    mut commands: Commands,
+    mut recorder: ResMut<ReplayRecorder>,
+    should_run: Option<Res<ShouldRun>>,
+    level: Option<Res<LevelLayout>>,
+    agent_query: (
+        Query<&GlobalTransform, With<PlayerAgent>>,
+        Query<&GlobalTransform, With<PursuerAgent>>,
+    ),
+    obj_query: Query<(&LevelObject, &GlobalTransform)>,
+) {
+    recorder.ticking = should_run.is_some();
+    let (Some(level), true, None) = (level, recorder.ticking, &recorder.replay) else {
+        return;
+    };
+    let (player_query, pursuer_query) = agent_query;
+    let grid = level.grid();
+    let spawn = |xform: Option<&GlobalTransform>| {
+        let cell = grid.world_to_cell(xform?.translation().xy())?;
+        Some(grid.flip_y(cell))
+    };
+    let mut level_data = level.snapshot(&obj_query);
+    level_data.player_spawn = spawn(player_query.get_single().ok());
+    level_data.pursuer_spawn = spawn(pursuer_query.get_single().ok());
+
+    let seed = rand::random();
+    seed_rngs(&mut commands, seed);
+    recorder.replay = Some(Replay {
+        level: level_data,
+        seed,
+        ticks: Vec::new(),
+        checksums: Vec::new(),
+    });
+}
+
+/// Records what the agents did this tick.
+fn record_tick(
+    mut recorder: ResMut<ReplayRecorder>,
+    time: Res<Time>,
+    player_query: Query<&NextAction, With<PlayerAgent>>,
+    pursuer_query: Query<&NextAction, With<PursuerAgent>>,
+    id_query: Query<(&GameId, &GlobalTransform)>,
+) {
+    if !recorder.ticking {
+        return;
+    }
+    let Some(replay) = &mut recorder.replay else {
+        return;
+    };
+    let tick = replay.ticks.len();
+    replay.ticks.push(ReplayTick {
+        delta: time.delta(),
+        player: player_query
+            .get_single()
+            .map(RecordedAction::from)
+            .unwrap_or_default(),
+        pursuer: pursuer_query
+            .get_single()
+            .map(RecordedAction::from)
+            .unwrap_or_default(),
+    });
+    if tick % CHECKSUM_INTERVAL == 0 {
+        replay.checksums.push((tick, state_checksum(&id_query)));
+    }
+}
+
+/// Seeds the game's random numbers.
+fn seed_rngs(commands: &mut Commands, seed: u64) {
+    commands.insert_resource(DetectionRng(StdRng::seed_from_u64(seed)));
+    commands.insert_resource(FilterRng(StdRng::seed_from_u64(seed)));
+}
+
+/// Replaces the seed the recorder picked for the level that just started, for anything that needs both games in a
+/// match to use the same one. Must run on the level's first tick, after `start_tick`.
+pub fn reseed_level(commands: &mut Commands, recorder: Option<ResMut<ReplayRecorder>>, seed: u64) {
+    seed_rngs(commands, seed);
+    if let Some(replay) = recorder.and_then(|recorder| recorder.into_inner().replay.as_mut()) {
+        replay.seed = seed;
+    }
+}
+
+/// Saves the current level's replay when F6 is pressed.
+fn save_replay_hotkey(inpt: Res<ButtonInput<KeyCode>>, recorder: Res<ReplayRecorder>) {
+    if !inpt.just_pressed(KeyCode::F6) {
+        return;
+    }
+    let Some(replay) = &recorder.replay else {
+        warn!("Nothing to save, the level hasn't started yet");
+        return;
+    };
+    let path = (0..)
+        .map(|i| SAVED_REPLAY_PATH.replace("{}", &i.to_string()))
+        .find(|path| !Path::new(path).exists())
+        .unwrap();
+    let result = std::fs::create_dir_all(Path::new(&path).parent().unwrap())
+        .and_then(|_| std::fs::write(&path, replay.to_bytes()));
+    match result {
+        Ok(()) => info!("Saved replay to {path}"),
+        Err(err) => error!("Could not save replay to {path}: {err}"),
+    }
+}
+
+/// The slowest and fastest playback speeds.
+pub const PLAYBACK_SPEEDS: (f32, f32) = (0.25, 8.);
+
+/// Plays back the replay at `path`, an asset path, instead of letting anyone control the agents.
+///
+/// Press Space to pause, Right to step forward a tick while paused, and Up or Down to change the speed. Don't add
+/// policies or the scripted pursuer alongside this, since they'd fight playback for control of the agents.
+pub struct ReplayPlaybackPlugin {
+    pub path: String,
+}
+
+impl Plugin for ReplayPlaybackPlugin {
+    fn build(&self, app: &mut App) {
+        let path = self.path.clone();
+        app.init_asset::<Replay>()
+            .init_asset_loader::<ReplayLoader>()
+            .add_systems(
+                Startup,
+                move |mut commands: Commands, asset_server: Res<AssetServer>| {
+                    commands.insert_resource(Playback::new(asset_server.load(&path)));
+                },
+            )
+            .add_systems(
+                PreUpdate,
+                play_tick
+                    .run_if(resource_exists::<ShouldRun>)
+                    .after(start_tick),
+            )
+            .add_systems(Update, (start_playback, playback_controls))
+            .add_systems(Last, (check_checksum, pace_playback).chain());
+    }
+}
+
+/// The state of the replay being played back.
+#[derive(Resource)]
+pub struct Playback {
+    handle: Handle<Replay>,
+    /// Set once the replay loads.
+    replay: Option<Replay>,
+    /// The tick to play next.
+    pub next_tick: usize,
+    pub paused: bool,
+    /// How fast playback runs, relative to the recording.
+    pub speed: f32,
+    /// Whether to play a single tick while paused.
+    step: bool,
+    /// Game time owed by the ticks that should have played by now.
+    budget: Duration,
+    last_frame: Option<Instant>,
+    /// Whether playback is holding the game back by removing `ShouldRun`.
+    frozen: bool,
+    /// Whether a tick was played this frame.
+    ticked: bool,
+    desynced: bool,
+}
+
+impl Playback {
+    fn new(handle: Handle<Replay>) -> Self {
+        Self {
+            handle,
+            replay: None,
+            next_tick: 0,
+            paused: false,
+            speed: 1.,
+            step: false,
+            budget: Duration::ZERO,
+            last_frame: None,
+            frozen: false,
+            ticked: false,
+            desynced: false,
+        }
+    }
+}
+
+/// Starts the replay's level once it loads.
+fn start_playback(
+    mut playback: ResMut<Playback>,
+    replays: Res<Assets<Replay>>,
+    mut ev_reset: EventWriter<ResetEvent>,
+    mut time_strategy: ResMut<TimeUpdateStrategy>,
+    mut window_query: Query<&mut Window, With<PrimaryWindow>>,
+) {
+    if playback.replay.is_some() {
+        return;
+    }
+    let Some(replay) = replays.get(&playback.handle) else {
+        return;
+    };
+    ev_reset.send(ResetEvent {
+        level: Some(LevelLayout::from_data(&replay.level)),
+    });
+    let first_delta = replay.ticks.first().map(|tick| tick.delta);
+    *time_strategy = TimeUpdateStrategy::ManualDuration(first_delta.unwrap_or_default());
+    // Frames shouldn't wait for the display at higher speeds, since each one plays a single tick
+    if let Ok(mut window) = window_query.get_single_mut() {
+        window.present_mode = PresentMode::AutoNoVsync;
+    }
+    playback.replay = Some(replay.clone());
+}
+
+/// Gives the agents their recorded actions for this tick.
+fn play_tick(
+    mut commands: Commands,
+    mut playback: ResMut<Playback>,
+    recorder: Option<ResMut<ReplayRecorder>>,
+    mut player_query: Query<(Entity, &mut NextAction), (With<PlayerAgent>, Without<PursuerAgent>)>,
+    mut pursuer_query: Query<(Entity, &mut NextAction), (With<PursuerAgent>, Without<PlayerAgent>)>,
+) {
+    playback.ticked = false;
+    let next_tick = playback.next_tick;
+    let Some(replay) = &playback.replay else {
+        return;
+    };
+    let Some(&tick) = replay.ticks.get(next_tick) else {
+        return;
+    };
+    if next_tick == 0 {
+        // Recording playback should give back the same replay
+        reseed_level(&mut commands, recorder, replay.seed);
+    }
+    for (e, mut next_action) in player_query.iter_mut() {
+        tick.player.apply(&mut next_action);
+        commands.entity(e).insert(RemoteControlled);
+    }
+    for (e, mut next_action) in pursuer_query.iter_mut() {
+        tick.pursuer.apply(&mut next_action);
+        commands.entity(e).insert(RemoteControlled);
+    }
+    playback.next_tick += 1;
+    playback.ticked = true;
+}
+
+/// Warns if the game stops matching the recording.
+fn check_checksum(mut playback: ResMut<Playback>, id_query: Query<(&GameId, &GlobalTransform)>) {
+    if !playback.ticked || playback.desynced {
+        return;
+    }
+    let tick = playback.next_tick - 1;
+    let Some(replay) = &playback.replay else {
+        return;
+    };
+    let Ok(idx) = replay
+        .checksums
+        .binary_search_by_key(&tick, |(tick, _)| *tick)
+    else {
+        return;
+    };
+    if replay.checksums[idx].1 != state_checksum(&id_query) {
+        warn!("Playback no longer matches the recording as of tick {tick}");
+        playback.desynced = true;
+    }
+}
+
+/// Pauses, steps, and changes the speed of playback.
+fn playback_controls(inpt: Res<ButtonInput<KeyCode>>, mut playback: ResMut<Playback>) {
+    if inpt.just_pressed(KeyCode::Space) {
+        playback.paused = !playback.paused;
+    }
+    if inpt.just_pressed(KeyCode::ArrowRight) && playback.paused {
+        playback.step = true;
+    }
+    let (min_speed, max_speed) = PLAYBACK_SPEEDS;
+    if inpt.just_pressed(KeyCode::ArrowUp) {
+        playback.speed = (playback.speed * 2.).min(max_speed);
+        info!("Playback speed: {}x", playback.speed);
+    }
+    if inpt.just_pressed(KeyCode::ArrowDown) {
+        playback.speed = (playback.speed / 2.).max(min_speed);
+        info!("Playback speed: {}x", playback.speed);
+    }
+}
+
+/// Decides whether the next frame plays a tick, and if not, holds the game back until it should.
+fn pace_playback(
+    mut commands: Commands,
+    mut playback: ResMut<Playback>,
+    should_run: Option<Res<ShouldRun>>,
+    mut time_strategy: ResMut<TimeUpdateStrategy>,
+    mut rapier_config: ResMut<RapierConfiguration>,
+) {
+    // The level runs on its own until the first tick has played
+    if playback.next_tick == 0 {
+        return;
+    }
+    let Some(replay) = &playback.replay else {
+        return;
+    };
+    let now = Instant::now();
+    let elapsed = playback
+        .last_frame
+        .map(|last| now - last)
+        .unwrap_or_default();
+    let next_tick = replay.ticks.get(playback.next_tick).copied();
+
+    let playback = playback.as_mut();
+    playback.last_frame = Some(now);
+    let play = match next_tick {
+        None => false,
+        Some(_) if playback.paused => {
+            playback.budget = Duration::ZERO;
+            std::mem::take(&mut playback.step)
+        }
+        Some(tick) => {
+            // Don't try to catch up on more than a second if frames fall behind
+            playback.budget =
+                (playback.budget + elapsed.mul_f32(playback.speed)).min(Duration::from_secs(1));
+            let play = playback.budget >= tick.delta;
+            if play {
+                playback.budget -= tick.delta;
+            }
+            play
+        }
+    };
+
+    match next_tick {
+        Some(tick) if play => {
+            *time_strategy = TimeUpdateStrategy::ManualDuration(tick.delta);
+            if playback.frozen {
+                commands.insert_resource(ShouldRun);
+                rapier_config.physics_pipeline_active = true;
+                playback.frozen = false;
+            }
+        }
+        _ => {
+            *time_strategy = TimeUpdateStrategy::ManualDuration(Duration::ZERO);
+            if !playback.frozen && should_run.is_some() {
+                commands.remove_resource::<ShouldRun>();
+                rapier_config.physics_pipeline_active = false;
+                playback.frozen = true;
+            }
+        }
+    }
+}
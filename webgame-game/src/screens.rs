@@ -0,0 +1,21 @@
+//! Defines the screens the playable version of the game can be on.
+
+use bevy::prelude::*;
+
+/// The screen currently being shown.
+#[derive(States, Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ScreenState {
+    /// The game itself.
+    #[default]
+    Game,
+    /// The level editor.
+    Editor,
+    /// Where online matches are set up. Only used with the `multiplayer` feature.
+    Lobby,
+    /// The pause menu, shown over the game while it's stopped.
+    Paused,
+    /// Where levels are picked.
+    MainMenu,
+    /// Where settings are changed. Opened from the main menu.
+    Settings,
+}
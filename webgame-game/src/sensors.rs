@@ -0,0 +1,254 @@
+//! Low resolution egocentric camera images for agents.
+//!
+//! Images are raycast on the CPU from the level layout, so they work in headless builds without a GPU.
+
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+
+use crate::{
+    gridworld::{Agent, LevelLayout, PlayerAgent, PursuerAgent, GRID_CELL_SIZE},
+    observer::update_observers,
+};
+
+/// Plugin for rendering camera sensor images.
+///
+/// Agents only get cameras if a `CameraSensorConfig` resource exists.
+pub struct SensorPlugin;
+
+impl Plugin for SensorPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (add_camera_sensors, render_camera_sensors)
+                .chain()
+                .after(update_observers)
+                .run_if(resource_exists::<CameraSensorConfig>)
+                .run_if(resource_exists::<LevelLayout>),
+        );
+    }
+}
+
+/// Configures the cameras given to agents.
+#[derive(Resource, Clone, Copy)]
+pub struct CameraSensorConfig {
+    /// The width and height of images, in pixels.
+    pub size: usize,
+    /// The horizontal field of view, in radians.
+    pub fov: f32,
+}
+
+impl Default for CameraSensorConfig {
+    fn default() -> Self {
+        Self {
+            size: 64,
+            fov: PI / 2.,
+        }
+    }
+}
+
+/// Holds the latest image seen from an agent's point of view.
+#[derive(Component, Default)]
+pub struct CameraSensor {
+    /// RGB pixels, stored row by row with the top row first.
+    pub image: Vec<u8>,
+}
+
+/// The radius of agents in images, in cells.
+const AGENT_RADIUS: f32 = 0.25;
+/// How tall agents are in images, relative to walls.
+const AGENT_HEIGHT: f32 = 0.8;
+const CEILING_COLOR: [u8; 3] = [30, 30, 30];
+const WALL_COLOR: Color = Color::GRAY;
+const COVER_COLOR: Color = Color::DARK_GREEN;
+
+/// Gives every agent a camera.
+pub fn add_camera_sensors(
+    mut commands: Commands,
+    agent_query: Query<Entity, (With<Agent>, Without<CameraSensor>)>,
+) {
+    for e in agent_query.iter() {
+        commands.entity(e).insert(CameraSensor::default());
+    }
+}
+
+/// Renders what each agent sees into its camera.
+pub fn render_camera_sensors(
+    mut camera_query: Query<(Entity, &Agent, &GlobalTransform, &mut CameraSensor)>,
+    agent_query: Query<(Entity, &GlobalTransform, Has<PursuerAgent>), With<Agent>>,
+    player_query: Query<(), With<PlayerAgent>>,
+    level: Res<LevelLayout>,
+    config: Res<CameraSensorConfig>,
+) {
+    for (camera_e, agent, xform, mut camera) in camera_query.iter_mut() {
+        // Shift positions by half a cell, so cell `i` spans `i` to `i + 1`
+        let origin = xform.translation().xy() / GRID_CELL_SIZE + 0.5;
+        let others: Vec<_> = agent_query
+            .iter()
+            .filter(|(e, _, _)| *e != camera_e)
+            .map(|(e, other_xform, is_pursuer)| {
+                let color = if is_pursuer {
+                    Color::RED
+                } else if player_query.contains(e) {
+                    Color::GREEN
+                } else {
+                    Color::WHITE
+                };
+                (other_xform.translation().xy() / GRID_CELL_SIZE + 0.5, color)
+            })
+            .collect();
+        camera.image = render_view(&level, origin, agent.dir, &others, &config);
+    }
+}
+
+/// Raycasts an image from `origin` facing `dir`, with positions measured in cells.
+fn render_view(
+    level: &LevelLayout,
+    origin: Vec2,
+    dir: Vec2,
+    others: &[(Vec2, Color)],
+    config: &CameraSensorConfig,
+) -> Vec<u8> {
+    let size = config.size;
+    let half = size as f32 / 2.;
+    let mut image = vec![0; size * size * 3];
+    let mut set_pixel = |x: usize, y: usize, rgb: [u8; 3]| {
+        let idx = (y * size + x) * 3;
+        image[idx..idx + 3].copy_from_slice(&rgb);
+    };
+
+    let dir = dir.try_normalize().unwrap_or(Vec2::X);
+    for x in 0..size {
+        // Angles go from left to right across the image
+        let offset = config.fov * (0.5 - (x as f32 + 0.5) / size as f32);
+        let ray_dir = Vec2::from_angle(offset).rotate(dir);
+        // Correct for fisheye distortion by measuring distances along the view direction
+        let depth_scale = offset.cos();
+
+        let (wall_dist, wall_color, is_side) = cast_ray(level, origin, ray_dir);
+        let wall_depth = wall_dist * depth_scale;
+        let wall_height = half / wall_depth.max(0.01);
+        let mut wall_rgb = shade(wall_color, wall_depth);
+        if is_side {
+            wall_rgb = wall_rgb.map(|c| (c as f32 * 0.8) as u8);
+        }
+
+        // The closest agent in front of the wall along this ray, if any
+        let agent_hit = others
+            .iter()
+            .filter_map(|&(pos, color)| Some((ray_circle(origin, ray_dir, pos)?, color)))
+            .filter(|(dist, _)| *dist < wall_dist)
+            .min_by(|(d1, _), (d2, _)| d1.total_cmp(d2))
+            .map(|(dist, color)| (dist * depth_scale, color));
+
+        for y in 0..size {
+            // Distance from the horizon, measured from pixel centers
+            let from_horizon = y as f32 + 0.5 - half;
+            let rgb = match agent_hit {
+                Some((depth, color))
+                    if from_horizon <= half / depth
+                        && from_horizon >= half / depth * (1. - 2. * AGENT_HEIGHT) =>
+                {
+                    shade(color, depth)
+                }
+                _ if from_horizon.abs() <= wall_height => wall_rgb,
+                _ if from_horizon < 0. => CEILING_COLOR,
+                _ => {
+                    // The camera is half a wall up, so the floor at this row is this far away
+                    let floor_dist = half / from_horizon / depth_scale;
+                    let floor_pos = origin + ray_dir * floor_dist;
+                    shade(floor_color(level, floor_pos), floor_dist * depth_scale)
+                }
+            };
+            set_pixel(x, y, rgb);
+        }
+    }
+    image
+}
+
+/// Steps through the grid until the ray hits a wall, cover, or the edge of the level.
+/// Returns the distance travelled, the color of what was hit, and whether it was hit on a side facing along y.
+fn cast_ray(level: &LevelLayout, origin: Vec2, ray_dir: Vec2) -> (f32, Color, bool) {
+    let start_cell = origin.floor().as_ivec2();
+    let mut cell = start_cell;
+    let step = IVec2::new(
+        if ray_dir.x < 0. { -1 } else { 1 },
+        if ray_dir.y < 0. { -1 } else { 1 },
+    );
+    // Clamped so rays parallel to an axis don't produce NaNs
+    let delta = Vec2::new(
+        1. / ray_dir.x.abs().max(1e-6),
+        1. / ray_dir.y.abs().max(1e-6),
+    );
+    let mut side_dist = Vec2::new(
+        if ray_dir.x < 0. {
+            origin.x - cell.x as f32
+        } else {
+            cell.x as f32 + 1. - origin.x
+        },
+        if ray_dir.y < 0. {
+            origin.y - cell.y as f32
+        } else {
+            cell.y as f32 + 1. - origin.y
+        },
+    ) * delta;
+
+    loop {
+        let is_side = side_dist.x >= side_dist.y;
+        let dist = if is_side {
+            cell.y += step.y;
+            side_dist.y += delta.y;
+            side_dist.y - delta.y
+        } else {
+            cell.x += step.x;
+            side_dist.x += delta.x;
+            side_dist.x - delta.x
+        };
+
+        let Some(idx) = cell_idx(level, cell) else {
+            return (dist, WALL_COLOR, is_side);
+        };
+        if level.walls[idx] {
+            return (dist, WALL_COLOR, is_side);
+        }
+        // Agents can see out of cover they're standing in
+        if level.cover[idx] && cell != start_cell {
+            return (dist, COVER_COLOR, is_side);
+        }
+    }
+}
+
+/// Returns the distance along the ray to an agent at `center`, if the ray hits it.
+fn ray_circle(origin: Vec2, ray_dir: Vec2, center: Vec2) -> Option<f32> {
+    let to_center = center - origin;
+    let along = to_center.dot(ray_dir);
+    let perp_sq = to_center.length_squared() - along * along;
+    let radius_sq = AGENT_RADIUS * AGENT_RADIUS;
+    if along <= 0. || perp_sq > radius_sq {
+        return None;
+    }
+    Some(along - (radius_sq - perp_sq).sqrt())
+}
+
+/// Returns the color of the floor at this position.
+fn floor_color(level: &LevelLayout, pos: Vec2) -> Color {
+    match cell_idx(level, pos.floor().as_ivec2()) {
+        Some(idx) => level.terrain[idx].color(),
+        None => WALL_COLOR,
+    }
+}
+
+/// Returns the index of this cell in the level's cell lists, if it's inside the level.
+fn cell_idx(level: &LevelLayout, cell: IVec2) -> Option<usize> {
+    if cell.x < 0 || cell.y < 0 || cell.x >= level.width as i32 || cell.y >= level.height as i32 {
+        return None;
+    }
+    Some(cell.y as usize * level.width + cell.x as usize)
+}
+
+/// Darkens colors the further away they are.
+fn shade(color: Color, dist: f32) -> [u8; 3] {
+    let brightness = 1. / (1. + dist * 0.2);
+    let [r, g, b, _] = color.as_rgba_u8();
+    [r, g, b].map(|c| (c as f32 * brightness) as u8)
+}
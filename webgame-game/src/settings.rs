@@ -0,0 +1,373 @@
+//! Player settings, and the screen for changing them, opened from the main menu.
+//!
+//! Settings are saved whenever they change, to `SETTINGS_PATH` natively, or to the browser's local storage under
+//! `SETTINGS_KEY` in web builds, and loaded when the game starts.
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    difficulty::Difficulty, filter::ShowBeliefHeatmaps, gridworld::KeyBindings,
+    observer::ShowVisCones, screens::ScreenState,
+};
+
+/// Loads settings when the game starts, applies and saves them whenever they change, and adds the settings screen.
+pub struct SettingsPlugin;
+
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Settings::load())
+            .init_resource::<SettingsScreen>()
+            .add_systems(OnEnter(ScreenState::Settings), setup_settings_screen)
+            .add_systems(OnExit(ScreenState::Settings), cleanup_settings_screen)
+            .add_systems(
+                Update,
+                (
+                    (
+                        settings_input,
+                        update_settings_text.run_if(
+                            resource_changed::<Settings>
+                                .or_else(resource_changed::<SettingsScreen>),
+                        ),
+                    )
+                        .chain()
+                        .run_if(in_state(ScreenState::Settings)),
+                    apply_settings.run_if(resource_changed::<Settings>),
+                    record_difficulty.run_if(resource_changed::<Difficulty>),
+                    save_settings.run_if(resource_changed::<Settings>),
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// Where settings are saved natively, relative to the working directory.
+pub const SETTINGS_PATH: &str = "settings.json";
+
+/// The local storage key settings are saved under in web builds.
+pub const SETTINGS_KEY: &str = "pursuer_settings";
+
+/// How much the volume changes with each press.
+const VOLUME_STEP: f32 = 0.1;
+
+const SETTINGS_HELP: &str =
+    "W/S: Select, A/D: Change, Enter: Toggle or rebind, Esc: Back to the main menu";
+
+/// Everything the player can change on the settings screen. Fields missing from saved settings keep their defaults.
+#[derive(Resource, Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct Settings {
+    /// From 0 to 1. The game doesn't play sound yet, so this is only saved for when it does.
+    pub master_volume: f32,
+    pub show_vision_cones: bool,
+    pub show_belief_heatmaps: bool,
+    pub bindings: KeyBindings,
+    /// If not set, the build's default difficulty is used.
+    pub difficulty: Option<Difficulty>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            master_volume: 1.,
+            show_vision_cones: true,
+            show_belief_heatmaps: true,
+            bindings: KeyBindings::default(),
+            difficulty: None,
+        }
+    }
+}
+
+impl Settings {
+    /// Loads saved settings, or the defaults if there aren't any or they can't be read.
+    pub fn load() -> Self {
+        let Some(json) = read_saved() else {
+            return Self::default();
+        };
+        serde_json::from_str(&json).unwrap_or_else(|err| {
+            warn!("Ignoring invalid saved settings: {err}");
+            Self::default()
+        })
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self).expect("settings should always serialize");
+        write_saved(&json)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_saved() -> Option<String> {
+    std::fs::read_to_string(SETTINGS_PATH).ok()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn write_saved(json: &str) -> Result<(), String> {
+    std::fs::write(SETTINGS_PATH, json).map_err(|err| err.to_string())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
+
+#[cfg(target_arch = "wasm32")]
+fn read_saved() -> Option<String> {
+    local_storage()?.get_item(SETTINGS_KEY).ok()?
+}
+
+#[cfg(target_arch = "wasm32")]
+fn write_saved(json: &str) -> Result<(), String> {
+    local_storage()
+        .ok_or("Local storage isn't available")?
+        .set_item(SETTINGS_KEY, json)
+        .map_err(|err| format!("{err:?}"))
+}
+
+/// Copies the settings into the resources they control.
+fn apply_settings(
+    settings: Res<Settings>,
+    mut bindings: ResMut<KeyBindings>,
+    mut show_vis_cones: ResMut<ShowVisCones>,
+    mut show_heatmaps: ResMut<ShowBeliefHeatmaps>,
+    mut difficulty: ResMut<Difficulty>,
+) {
+    *bindings = settings.bindings;
+    show_vis_cones.0 = settings.show_vision_cones;
+    show_heatmaps.0 = settings.show_belief_heatmaps;
+    if let Some(saved) = settings.difficulty {
+        difficulty.set_if_neq(saved);
+    }
+}
+
+/// Remembers difficulties picked outside the settings screen, e.g. with F3.
+fn record_difficulty(difficulty: Res<Difficulty>, mut settings: ResMut<Settings>) {
+    // The build's default difficulty shouldn't override the saved one
+    if difficulty.is_added() || settings.difficulty == Some(*difficulty) {
+        return;
+    }
+    settings.difficulty = Some(*difficulty);
+}
+
+fn save_settings(settings: Res<Settings>) {
+    // Loading the settings doesn't need them saved again
+    if settings.is_added() {
+        return;
+    }
+    if let Err(err) = settings.save() {
+        error!("Could not save settings: {err}");
+    }
+}
+
+/// A line on the settings screen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SettingsRow {
+    Volume,
+    VisionCones,
+    BeliefHeatmaps,
+    Difficulty,
+    Up,
+    Down,
+    Left,
+    Right,
+    ToggleObjs,
+}
+
+impl SettingsRow {
+    const ALL: [Self; 9] = [
+        Self::Volume,
+        Self::VisionCones,
+        Self::BeliefHeatmaps,
+        Self::Difficulty,
+        Self::Up,
+        Self::Down,
+        Self::Left,
+        Self::Right,
+        Self::ToggleObjs,
+    ];
+
+    fn label(&self) -> &'static str {
+        match self {
+            Self::Volume => "Master volume",
+            Self::VisionCones => "Vision cones",
+            Self::BeliefHeatmaps => "Belief heatmaps",
+            Self::Difficulty => "Difficulty",
+            Self::Up => "Move up",
+            Self::Down => "Move down",
+            Self::Left => "Move left",
+            Self::Right => "Move right",
+            Self::ToggleObjs => "Use doors",
+        }
+    }
+
+    /// The key this row binds, if it's a binding.
+    fn binding<'a>(&self, bindings: &'a mut KeyBindings) -> Option<&'a mut KeyCode> {
+        match self {
+            Self::Up => Some(&mut bindings.up),
+            Self::Down => Some(&mut bindings.down),
+            Self::Left => Some(&mut bindings.left),
+            Self::Right => Some(&mut bindings.right),
+            Self::ToggleObjs => Some(&mut bindings.toggle_objs),
+            _ => None,
+        }
+    }
+
+    fn value(&self, settings: &Settings) -> String {
+        let on_off = |on: bool| if on { "On" } else { "Off" }.to_string();
+        match self {
+            Self::Volume => format!("{:.0}%", settings.master_volume * 100.),
+            Self::VisionCones => on_off(settings.show_vision_cones),
+            Self::BeliefHeatmaps => on_off(settings.show_belief_heatmaps),
+            Self::Difficulty => match settings.difficulty {
+                Some(difficulty) => format!("{difficulty:?}"),
+                None => "Default".into(),
+            },
+            _ => {
+                let mut bindings = settings.bindings;
+                format!("{:?}", self.binding(&mut bindings).unwrap())
+            }
+        }
+    }
+}
+
+/// Where the player is on the settings screen.
+#[derive(Resource, Default)]
+struct SettingsScreen {
+    /// The index of the selected row in `SettingsRow::ALL`.
+    selected: usize,
+    /// Whether the next key pressed becomes the selected row's binding.
+    rebinding: bool,
+}
+
+/// Marks entities that belong to the settings screen.
+#[derive(Component)]
+struct SettingsUi;
+
+/// The text listing the settings.
+#[derive(Component)]
+struct SettingsText;
+
+fn setup_settings_screen(mut commands: Commands, mut screen: ResMut<SettingsScreen>) {
+    *screen = SettingsScreen::default();
+    commands.spawn((SettingsUi, Camera2dBundle::default()));
+    let text_style = TextStyle {
+        font_size: 16.,
+        color: Color::WHITE,
+        ..default()
+    };
+    commands
+        .spawn((
+            SettingsUi,
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.),
+                    height: Val::Percent(100.),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    padding: UiRect::all(Val::Px(16.)),
+                    row_gap: Val::Px(8.),
+                    ..default()
+                },
+                ..default()
+            },
+        ))
+        .with_children(|p| {
+            p.spawn(TextBundle::from_section(
+                "Settings",
+                TextStyle {
+                    font_size: 32.,
+                    ..text_style.clone()
+                },
+            ));
+            p.spawn(TextBundle::from_section(SETTINGS_HELP, text_style.clone()));
+            p.spawn((SettingsText, TextBundle::from_section("", text_style)));
+        });
+}
+
+fn cleanup_settings_screen(mut commands: Commands, ui_query: Query<Entity, With<SettingsUi>>) {
+    for e in ui_query.iter() {
+        commands.entity(e).despawn_recursive();
+    }
+}
+
+/// Moves the selection, changes settings, and rebinds keys.
+fn settings_input(
+    inpt: Res<ButtonInput<KeyCode>>,
+    mut screen: ResMut<SettingsScreen>,
+    mut settings: ResMut<Settings>,
+    mut next_state: ResMut<NextState<ScreenState>>,
+) {
+    let row = SettingsRow::ALL[screen.selected];
+    if screen.rebinding {
+        // Escape cancels, so it can't be bound
+        if inpt.just_pressed(KeyCode::Escape) {
+            screen.rebinding = false;
+        } else if let Some(&key) = inpt.get_just_pressed().next() {
+            *row.binding(&mut settings.bindings).unwrap() = key;
+            screen.rebinding = false;
+        }
+        return;
+    }
+
+    if inpt.just_pressed(KeyCode::Escape) {
+        next_state.set(ScreenState::MainMenu);
+        return;
+    }
+    let count = SettingsRow::ALL.len();
+    if inpt.any_just_pressed([KeyCode::KeyS, KeyCode::ArrowDown]) {
+        screen.selected = (screen.selected + 1) % count;
+    }
+    if inpt.any_just_pressed([KeyCode::KeyW, KeyCode::ArrowUp]) {
+        screen.selected = (screen.selected + count - 1) % count;
+    }
+    let increase = inpt.any_just_pressed([KeyCode::KeyD, KeyCode::ArrowRight]);
+    let decrease = inpt.any_just_pressed([KeyCode::KeyA, KeyCode::ArrowLeft]);
+    let toggle = inpt.just_pressed(KeyCode::Enter);
+    if !(increase || decrease || toggle) {
+        return;
+    }
+    match row {
+        SettingsRow::Volume => {
+            let step = match (increase, decrease) {
+                (true, _) => VOLUME_STEP,
+                (_, true) => -VOLUME_STEP,
+                _ => return,
+            };
+            settings.master_volume = (settings.master_volume + step).clamp(0., 1.);
+        }
+        SettingsRow::VisionCones => settings.show_vision_cones = !settings.show_vision_cones,
+        SettingsRow::BeliefHeatmaps => {
+            settings.show_belief_heatmaps = !settings.show_belief_heatmaps
+        }
+        SettingsRow::Difficulty => {
+            let difficulty = settings.difficulty.unwrap_or_default();
+            settings.difficulty = Some(difficulty.next());
+        }
+        _ => screen.rebinding = toggle,
+    }
+}
+
+/// Lists every setting, marking the selected one.
+fn update_settings_text(
+    settings: Res<Settings>,
+    screen: Res<SettingsScreen>,
+    mut text_query: Query<&mut Text, With<SettingsText>>,
+) {
+    let Ok(mut text) = text_query.get_single_mut() else {
+        return;
+    };
+    let lines: Vec<_> = SettingsRow::ALL
+        .iter()
+        .enumerate()
+        .map(|(i, row)| {
+            let value = if i == screen.selected && screen.rebinding {
+                "Press a key...".into()
+            } else {
+                row.value(&settings)
+            };
+            let marker = if i == screen.selected { ">" } else { " " };
+            format!("{marker} {}: {value}", row.label())
+        })
+        .collect();
+    text.sections[0].value = lines.join("\n");
+}
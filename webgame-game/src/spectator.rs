@@ -0,0 +1,238 @@
+//! Streams the game to spectators over WebSocket, so dashboards can watch training or live matches without running
+//! Bevy. Only built with the `spectator` feature.
+//!
+//! Spectators are sent the level once when they connect and again whenever a new one starts, then a small frame with
+//! the state of the level every update. All messages are JSON.
+
+use std::{
+    io::{self, ErrorKind},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use tungstenite::{Message, WebSocket};
+
+use crate::{
+    gridworld::{
+        move_agents, Agent, LevelLayout, LevelObject, LoadedLevelData, PlayerAgent, PursuerAgent,
+        GRID_CELL_SIZE,
+    },
+    observer::Observer,
+    world_objs::{GameOutcome, LevelComplete},
+};
+
+/// How long a connecting spectator has to finish the WebSocket handshake.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Streams the game to spectators connected to `server`.
+pub struct SpectatorPlugin {
+    pub server: SpectatorServer,
+}
+
+impl Plugin for SpectatorPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(self.server.clone()).add_systems(
+            Update,
+            (
+                accept_spectators,
+                send_level.run_if(resource_added::<LevelLayout>),
+                send_frame
+                    .run_if(resource_exists::<LevelLayout>)
+                    .after(move_agents),
+            )
+                .chain(),
+        );
+    }
+}
+
+/// Accepts spectators and sends them messages.
+///
+/// The server is shared between its clones, so it can outlive the app it was added to and keep spectators connected
+/// when another app takes over, e.g. when a library build starts a new episode.
+#[derive(Resource, Clone)]
+pub struct SpectatorServer(Arc<Mutex<SpectatorClients>>);
+
+struct SpectatorClients {
+    listener: TcpListener,
+    sockets: Vec<WebSocket<TcpStream>>,
+    /// The updates since the current level started.
+    tick: u64,
+}
+
+impl SpectatorServer {
+    /// Starts listening for spectators on `addr`.
+    pub fn bind(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self(Arc::new(Mutex::new(SpectatorClients {
+            listener,
+            sockets: Vec::new(),
+            tick: 0,
+        }))))
+    }
+}
+
+impl SpectatorClients {
+    /// Sends a message to every spectator, dropping ones that have disconnected.
+    fn broadcast(&mut self, msg: &SpectatorMessage) {
+        let msg = to_message(msg);
+        self.sockets
+            .retain_mut(|socket| send_or_queue(socket, msg.clone()).is_ok());
+    }
+}
+
+/// Messages sent to spectators.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SpectatorMessage {
+    /// Sent when a spectator connects, and whenever a new level starts.
+    Level {
+        level: LoadedLevelData,
+        /// The width of a cell in the positions `Frame`s use.
+        cell_size: f32,
+    },
+    Frame(SpectatorFrame),
+}
+
+/// The state of the level on one update.
+///
+/// Positions are in world coordinates, with the center of the bottom left cell at the origin and `cell_size` units per
+/// cell. Unlike level files, `y` increases upwards.
+#[derive(Serialize, Deserialize)]
+pub struct SpectatorFrame {
+    /// The updates since the level started.
+    pub tick: u64,
+    pub player: Option<SpectatorAgent>,
+    pub pursuer: Option<SpectatorAgent>,
+    /// Where each of the level's objects is, by its index in the level's `objects`.
+    pub objects: Vec<(usize, [f32; 2])>,
+    /// Whether the player has escaped.
+    pub escaped: bool,
+}
+
+/// The state of an agent on one update.
+#[derive(Serialize, Deserialize)]
+pub struct SpectatorAgent {
+    pub pos: [f32; 2],
+    /// The direction the agent is looking in.
+    pub dir: [f32; 2],
+    /// Whether the agent can see the other one.
+    pub sees_other: bool,
+}
+
+/// Accepts spectators waiting to connect, and sends each of them the current level.
+fn accept_spectators(server: Res<SpectatorServer>, level: Option<Res<LevelLayout>>) {
+    let mut clients = server.0.lock().unwrap();
+    loop {
+        let stream = match clients.listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+            Err(err) => {
+                warn!("Couldn't accept spectator: {err}");
+                break;
+            }
+        };
+        let mut socket = match handshake(stream) {
+            Ok(socket) => socket,
+            Err(err) => {
+                warn!("Couldn't connect to spectator: {err}");
+                continue;
+            }
+        };
+        if let Some(level) = &level {
+            let msg = to_message(&SpectatorMessage::Level {
+                level: level.to_data(),
+                cell_size: GRID_CELL_SIZE,
+            });
+            if send_or_queue(&mut socket, msg).is_err() {
+                continue;
+            }
+        }
+        clients.sockets.push(socket);
+    }
+}
+
+/// Completes a WebSocket handshake, then makes the socket non-blocking, so slow spectators don't hold up the game.
+fn handshake(stream: TcpStream) -> Result<WebSocket<TcpStream>, String> {
+    stream
+        .set_read_timeout(Some(HANDSHAKE_TIMEOUT))
+        .map_err(|err| err.to_string())?;
+    let socket = tungstenite::accept(stream).map_err(|err| err.to_string())?;
+    socket
+        .get_ref()
+        .set_nonblocking(true)
+        .map_err(|err| err.to_string())?;
+    Ok(socket)
+}
+
+/// Sends spectators the level that just started.
+fn send_level(server: Res<SpectatorServer>, level: Res<LevelLayout>) {
+    let mut clients = server.0.lock().unwrap();
+    clients.tick = 0;
+    clients.broadcast(&SpectatorMessage::Level {
+        level: level.to_data(),
+        cell_size: GRID_CELL_SIZE,
+    });
+}
+
+/// Sends spectators the state of the level.
+fn send_frame(
+    server: Res<SpectatorServer>,
+    player_query: Query<(Entity, &Agent, &Observer, &GlobalTransform), With<PlayerAgent>>,
+    pursuer_query: Query<(Entity, &Agent, &Observer, &GlobalTransform), With<PursuerAgent>>,
+    obj_query: Query<(&LevelObject, &GlobalTransform)>,
+    level_complete: Option<Res<LevelComplete>>,
+) {
+    let mut clients = server.0.lock().unwrap();
+    let tick = clients.tick;
+    clients.tick += 1;
+    // Read from every spectator, so pings are answered and closed connections are noticed
+    clients.sockets.retain_mut(|socket| match socket.read() {
+        Ok(_) => true,
+        Err(tungstenite::Error::Io(err)) => err.kind() == ErrorKind::WouldBlock,
+        Err(_) => false,
+    });
+    if clients.sockets.is_empty() {
+        return;
+    }
+
+    let player = player_query.get_single().ok();
+    let pursuer = pursuer_query.get_single().ok();
+    let describe = |agent: Option<(Entity, &Agent, &Observer, &GlobalTransform)>,
+                    other: Option<Entity>| {
+        agent.map(|(_, agent, observer, xform)| SpectatorAgent {
+            pos: xform.translation().xy().into(),
+            dir: agent.dir.into(),
+            sees_other: other.is_some_and(|other| observer.observing.contains(&other)),
+        })
+    };
+    let frame = SpectatorFrame {
+        tick,
+        player: describe(player, pursuer.map(|(e, ..)| e)),
+        pursuer: describe(pursuer, player.map(|(e, ..)| e)),
+        objects: obj_query
+            .iter()
+            .map(|(obj, xform)| (obj.0, xform.translation().xy().into()))
+            .collect(),
+        escaped: matches!(
+            level_complete.as_deref(),
+            Some(LevelComplete(GameOutcome::PlayerEscaped { .. }))
+        ),
+    };
+    clients.broadcast(&SpectatorMessage::Frame(frame));
+}
+
+/// Sends a message, or queues it to be sent later if the spectator isn't ready for it yet.
+fn send_or_queue(socket: &mut WebSocket<TcpStream>, msg: Message) -> tungstenite::Result<()> {
+    match socket.send(msg) {
+        Err(tungstenite::Error::Io(err)) if err.kind() == ErrorKind::WouldBlock => Ok(()),
+        result => result,
+    }
+}
+
+fn to_message(msg: &SpectatorMessage) -> Message {
+    Message::Text(serde_json::to_string(msg).expect("spectator messages should always serialize"))
+}
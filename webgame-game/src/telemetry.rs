@@ -0,0 +1,238 @@
+//! Reports how people do against the pursuer, by posting stats about every episode they play to an HTTP endpoint.
+//! Only built with the `telemetry` feature, and only runs if `TelemetryPlugin` is added, so nothing is sent unless a
+//! build asks for it.
+//!
+//! Episodes are batched, and each batch is posted as JSON like the following:
+//!
+//! ```json
+//! {
+//!   "session": "5f0c3a1e9b2d4c77",
+//!   "episodes": [
+//!     { "outcome": "escaped", "duration_secs": 41.5, "level": "test", "difficulty": "normal" }
+//!   ]
+//! }
+//! ```
+//!
+//! Levels are identified by their name, or a hash of their layout if they don't have one. Only episodes where someone
+//! is playing the player with the keyboard are reported.
+
+use std::{hash::Hasher, time::Duration};
+
+use bevy::{app::AppExit, prelude::*};
+use serde::Serialize;
+
+use crate::{
+    difficulty::Difficulty,
+    gridworld::{LevelLayout, PlayerAgent, RemoteControlled},
+    net::PolicyRunner,
+    replay::FnvHasher,
+    world_objs::GameOutcome,
+};
+
+/// Posts episode stats to `endpoint` in batches.
+pub struct TelemetryPlugin {
+    /// The URL to post batches to.
+    pub endpoint: String,
+    /// How many episodes to collect before posting them.
+    pub batch_size: usize,
+    /// How long to wait before posting a batch that isn't full.
+    pub flush_interval: Duration,
+}
+
+impl Default for TelemetryPlugin {
+    fn default() -> Self {
+        Self {
+            endpoint: String::new(),
+            batch_size: 10,
+            flush_interval: Duration::from_secs(60),
+        }
+    }
+}
+
+impl Plugin for TelemetryPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Telemetry {
+            endpoint: self.endpoint.clone(),
+            batch_size: self.batch_size.max(1),
+            flush_interval: self.flush_interval,
+            session: format!("{:016x}", rand::random::<u64>()),
+            episode: None,
+            episodes: Vec::new(),
+            last_flush: Duration::ZERO,
+        })
+        .add_systems(
+            Update,
+            (
+                start_episode.run_if(resource_added::<LevelLayout>),
+                end_episode,
+                flush_telemetry,
+            )
+                .chain(),
+        )
+        .add_systems(Last, flush_on_exit);
+    }
+}
+
+/// How an episode ended.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EpisodeOutcome {
+    /// The player made it out.
+    Escaped,
+    /// A pursuer caught the player.
+    Caught,
+    /// The episode ran out of steps.
+    TimedOut,
+    /// The level was replaced before it ended, e.g. by restarting or skipping it.
+    Abandoned,
+}
+
+/// Stats about one episode.
+#[derive(Serialize, Clone, Debug)]
+pub struct EpisodeStats {
+    pub outcome: EpisodeOutcome,
+    /// How long the episode lasted, in seconds of game time.
+    pub duration_secs: f32,
+    pub level: String,
+    pub difficulty: Difficulty,
+}
+
+/// A batch of episodes, as it's posted.
+#[derive(Serialize)]
+struct TelemetryBatch<'a> {
+    /// Random for every run of the game, so episodes played by the same person can be grouped together.
+    session: &'a str,
+    episodes: &'a [EpisodeStats],
+}
+
+/// The episode being played.
+struct EpisodeStart {
+    level: String,
+    difficulty: Difficulty,
+    /// The game time when the episode started.
+    started: Duration,
+    /// Whether someone has been playing the player with the keyboard.
+    human: bool,
+}
+
+/// Episodes that haven't been posted yet.
+#[derive(Resource)]
+pub struct Telemetry {
+    pub endpoint: String,
+    pub batch_size: usize,
+    pub flush_interval: Duration,
+    pub session: String,
+    episode: Option<EpisodeStart>,
+    episodes: Vec<EpisodeStats>,
+    /// The real time since startup when a batch was last posted.
+    last_flush: Duration,
+}
+
+impl Telemetry {
+    fn finish_episode(&mut self, outcome: EpisodeOutcome, now: Duration) {
+        let Some(episode) = self.episode.take().filter(|episode| episode.human) else {
+            return;
+        };
+        self.episodes.push(EpisodeStats {
+            outcome,
+            duration_secs: (now - episode.started).as_secs_f32(),
+            level: episode.level,
+            difficulty: episode.difficulty,
+        });
+    }
+
+    /// Posts every episode that hasn't been posted yet. Failures are logged, and the episodes are dropped.
+    fn flush(&mut self) {
+        if self.episodes.is_empty() {
+            return;
+        }
+        let batch = TelemetryBatch {
+            session: &self.session,
+            episodes: &self.episodes,
+        };
+        match ehttp::Request::json(&self.endpoint, &batch) {
+            Ok(request) => ehttp::fetch(request, |result| match result {
+                Ok(response) if response.ok => {}
+                Ok(response) => warn!(
+                    "Telemetry endpoint rejected episodes: {} {}",
+                    response.status, response.status_text
+                ),
+                Err(err) => warn!("Couldn't post episodes to the telemetry endpoint: {err}"),
+            }),
+            Err(err) => error!("Couldn't serialize episodes: {err}"),
+        }
+        self.episodes.clear();
+    }
+}
+
+/// Returns the level's name, or a hash of its layout if it doesn't have one.
+pub fn level_id(level: &LevelLayout) -> String {
+    if let Some(name) = &level.meta.name {
+        return name.clone();
+    }
+    let json = serde_json::to_vec(&level.to_data()).expect("level data should always serialize");
+    let mut hasher = FnvHasher::default();
+    hasher.write(&json);
+    format!("{:016x}", hasher.finish())
+}
+
+/// Starts a new episode whenever a level starts, counting the last one as abandoned if it didn't end.
+fn start_episode(
+    mut telemetry: ResMut<Telemetry>,
+    level: Res<LevelLayout>,
+    difficulty: Res<Difficulty>,
+    time: Res<Time>,
+) {
+    telemetry.finish_episode(EpisodeOutcome::Abandoned, time.elapsed());
+    telemetry.episode = Some(EpisodeStart {
+        level: level_id(&level),
+        difficulty: *difficulty,
+        started: time.elapsed(),
+        human: false,
+    });
+}
+
+/// Ends the episode when the level does.
+fn end_episode(
+    mut telemetry: ResMut<Telemetry>,
+    mut ev_outcome: EventReader<GameOutcome>,
+    human_query: Query<
+        (),
+        (
+            With<PlayerAgent>,
+            Without<PolicyRunner>,
+            Without<RemoteControlled>,
+        ),
+    >,
+    time: Res<Time>,
+) {
+    if let Some(episode) = &mut telemetry.episode {
+        episode.human |= !human_query.is_empty();
+    }
+    for outcome in ev_outcome.read() {
+        let outcome = match outcome {
+            GameOutcome::PlayerEscaped { .. } => EpisodeOutcome::Escaped,
+            GameOutcome::PlayerCaught { .. } => EpisodeOutcome::Caught,
+            GameOutcome::TimedOut => EpisodeOutcome::TimedOut,
+        };
+        telemetry.finish_episode(outcome, time.elapsed());
+    }
+}
+
+/// Posts the batch once it's full, or once it's been waiting for `flush_interval`.
+fn flush_telemetry(mut telemetry: ResMut<Telemetry>, time: Res<Time<Real>>) {
+    let now = time.elapsed();
+    if telemetry.episodes.len() >= telemetry.batch_size
+        || now - telemetry.last_flush >= telemetry.flush_interval
+    {
+        telemetry.flush();
+        telemetry.last_flush = now;
+    }
+}
+
+/// Posts whatever's left when the game closes.
+fn flush_on_exit(mut telemetry: ResMut<Telemetry>, ev_exit: EventReader<AppExit>) {
+    if !ev_exit.is_empty() {
+        telemetry.flush();
+    }
+}
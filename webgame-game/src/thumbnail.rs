@@ -0,0 +1,254 @@
+//! Small top-down previews of levels, drawn straight from the layout without running the game.
+
+use bevy::{
+    prelude::*,
+    render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+        texture::ImageSampler,
+    },
+};
+use thiserror::Error;
+
+use crate::gridworld::{GridDir, LevelLayout};
+
+/// How many pixels wide each cell is in thumbnails, unless otherwise specified.
+pub const DEFAULT_THUMBNAIL_CELL_PIXELS: usize = 4;
+
+const WALL_COLOR: Color = Color::BLACK;
+const DYNAMIC_WALL_COLOR: Color = Color::DARK_GRAY;
+const COVER_COLOR: Color = Color::DARK_GREEN;
+const EXIT_COLOR: Color = Color::LIME_GREEN;
+const DOOR_COLOR: Color = Color::MAROON;
+const KEY_COLOR: Color = Color::YELLOW;
+const VENT_COLOR: Color = Color::SILVER;
+/// Movable objects make noise when pushed.
+const NOISE_SOURCE_COLOR: Color = Color::BLUE;
+const STATIC_OBJ_COLOR: Color = Color::NAVY;
+const PATROL_COLOR: Color = Color::ORANGE;
+const CAMERA_COLOR: Color = Color::GRAY;
+const LIGHT_COLOR: Color = Color::GOLD;
+const ONE_WAY_COLOR: Color = Color::PURPLE;
+const CONVEYOR_COLOR: Color = Color::TEAL;
+/// Blended with the colors of cells under fog.
+const FOG_COLOR: Color = Color::SILVER;
+/// Drawn along the side of a conveyor that agents get pushed out of.
+const CONVEYOR_ARROW_COLOR: Color = Color::WHITE;
+const PURSUER_COLOR: Color = Color::RED;
+const PLAYER_COLOR: Color = Color::GREEN;
+
+#[derive(Debug, Error)]
+#[error("Could not encode thumbnail: {0}")]
+pub struct ThumbnailError(#[from] png::EncodingError);
+
+/// An RGB image of a level, with the top row of pixels first.
+#[derive(Debug, Clone)]
+pub struct Thumbnail {
+    pub width: usize,
+    pub height: usize,
+    /// Three bytes per pixel.
+    pub pixels: Vec<u8>,
+}
+
+impl Thumbnail {
+    /// Draws a level with each cell as a square `cell_pixels` wide.
+    ///
+    /// Cells are filled with their terrain, conveyors, cover, walls, exits, and door. The key, vents, objects, patrol
+    /// waypoints, cameras, lights, and fixed spawn points are drawn as smaller squares on top, so the cell underneath stays
+    /// visible.
+    /// One-way edges and conveyors are drawn as a strip along the side of the cell agents can leave through, and fog
+    /// lightens the ground under it.
+    pub fn render(level: &LevelLayout, cell_pixels: usize) -> Self {
+        let cell_pixels = cell_pixels.max(1);
+        let mut thumbnail = Self {
+            width: level.width * cell_pixels,
+            height: level.height * cell_pixels,
+            pixels: vec![0; level.width * level.height * cell_pixels * cell_pixels * 3],
+        };
+        let grid = level.grid();
+
+        let fog = level.fog_view_dists();
+        for i in 0..level.walls.len() {
+            let cell = grid.idx_cell(i);
+            let color = if level.walls[i] {
+                WALL_COLOR
+            } else if level.cover[i] {
+                COVER_COLOR
+            } else if level.conveyors[i].is_some() {
+                CONVEYOR_COLOR
+            } else {
+                level.terrain[i].color()
+            };
+            let color = if fog[i].is_some() {
+                blend(color, FOG_COLOR)
+            } else {
+                color
+            };
+            thumbnail.fill_cell(level, cell, cell_pixels, color, false);
+        }
+
+        // Everything below is stored in level file coordinates
+        let mut draw = |file_cell, color, marker| {
+            thumbnail.fill_cell(level, grid.flip_y(file_cell), cell_pixels, color, marker);
+        };
+        for &cell in &level.dynamic_walls {
+            draw(cell, DYNAMIC_WALL_COLOR, false);
+        }
+        for &cell in &level.exits {
+            draw(cell, EXIT_COLOR, false);
+        }
+        if let Some(cell) = level.door_pos {
+            draw(cell, DOOR_COLOR, false);
+        }
+        for &cell in level.vents.iter().flatten() {
+            draw(cell, VENT_COLOR, true);
+        }
+        for obj in &level.objects {
+            let color = if obj.movable {
+                NOISE_SOURCE_COLOR
+            } else {
+                STATIC_OBJ_COLOR
+            };
+            draw(obj.pos, color, true);
+        }
+        for &cell in level.patrols.iter().flat_map(|patrol| &patrol.waypoints) {
+            draw(cell, PATROL_COLOR, true);
+        }
+        for camera in &level.cameras {
+            draw(camera.pos, CAMERA_COLOR, true);
+        }
+        for light in &level.lights {
+            draw(light.pos, LIGHT_COLOR, true);
+        }
+        if let Some(cell) = level.key_pos {
+            draw(cell, KEY_COLOR, true);
+        }
+        if let Some(cell) = level.pursuer_spawn {
+            draw(cell, PURSUER_COLOR, true);
+        }
+        if let Some(cell) = level.player_spawn {
+            draw(cell, PLAYER_COLOR, true);
+        }
+        // Strips fit in the margin around markers, except in thumbnails too small to have one
+        for edge in &level.one_way {
+            let cell = grid.flip_y(edge.cell);
+            thumbnail.fill_edge(level, cell, edge.dir, cell_pixels, ONE_WAY_COLOR);
+        }
+        for (i, dir) in level.conveyors.iter().enumerate() {
+            if let Some(dir) = *dir {
+                let cell = grid.idx_cell(i);
+                thumbnail.fill_edge(level, cell, dir, cell_pixels, CONVEYOR_ARROW_COLOR);
+            }
+        }
+
+        thumbnail
+    }
+
+    /// Fills a cell given in layout coordinates. Markers only fill the middle of the cell.
+    fn fill_cell(
+        &mut self,
+        level: &LevelLayout,
+        (x, y): (usize, usize),
+        cell_pixels: usize,
+        color: Color,
+        marker: bool,
+    ) {
+        if !level.grid().in_bounds((x, y)) {
+            return;
+        }
+        let [r, g, b, _] = color.as_rgba_u8();
+        let margin = if marker { cell_pixels / 4 } else { 0 };
+        // Pixel rows go from top to bottom, while cell rows go from bottom to top
+        let top = (level.height - y - 1) * cell_pixels;
+        let left = x * cell_pixels;
+        for py in (top + margin)..(top + cell_pixels - margin) {
+            for px in (left + margin)..(left + cell_pixels - margin) {
+                let idx = (py * self.width + px) * 3;
+                self.pixels[idx..idx + 3].copy_from_slice(&[r, g, b]);
+            }
+        }
+    }
+
+    /// Draws a strip along one side of a cell given in layout coordinates.
+    fn fill_edge(
+        &mut self,
+        level: &LevelLayout,
+        (x, y): (usize, usize),
+        dir: GridDir,
+        cell_pixels: usize,
+        color: Color,
+    ) {
+        if !level.grid().in_bounds((x, y)) {
+            return;
+        }
+        let [r, g, b, _] = color.as_rgba_u8();
+        let thickness = (cell_pixels / 4).max(1);
+        let top = (level.height - y - 1) * cell_pixels;
+        let left = x * cell_pixels;
+        // Directions are in level file coordinates, so up is towards the top of the image
+        let (rows, cols) = match dir {
+            GridDir::Up => (top..top + thickness, left..left + cell_pixels),
+            GridDir::Down => (
+                top + cell_pixels - thickness..top + cell_pixels,
+                left..left + cell_pixels,
+            ),
+            GridDir::Left => (top..top + cell_pixels, left..left + thickness),
+            GridDir::Right => (
+                top..top + cell_pixels,
+                left + cell_pixels - thickness..left + cell_pixels,
+            ),
+        };
+        for py in rows {
+            for px in cols.clone() {
+                let idx = (py * self.width + px) * 3;
+                self.pixels[idx..idx + 3].copy_from_slice(&[r, g, b]);
+            }
+        }
+    }
+
+    /// Encodes the thumbnail as a PNG file.
+    pub fn to_png(&self) -> Result<Vec<u8>, ThumbnailError> {
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&self.pixels)?;
+        Ok(png)
+    }
+
+    /// Converts the thumbnail into a texture, which stays sharp when scaled up.
+    pub fn to_image(&self) -> Image {
+        let rgba = self
+            .pixels
+            .chunks_exact(3)
+            .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 255])
+            .collect();
+        let mut image = Image::new(
+            Extent3d {
+                width: self.width as u32,
+                height: self.height as u32,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            rgba,
+            TextureFormat::Rgba8UnormSrgb,
+            RenderAssetUsages::default(),
+        );
+        image.sampler = ImageSampler::nearest();
+        image
+    }
+}
+
+/// Mixes two colors evenly.
+fn blend(a: Color, b: Color) -> Color {
+    let ([r1, g1, b1, _], [r2, g2, b2, _]) = (a.as_rgba_f32(), b.as_rgba_f32());
+    Color::rgb((r1 + r2) / 2., (g1 + g2) / 2., (b1 + b2) / 2.)
+}
+
+/// Draws a level and encodes it as a PNG file, with each cell `cell_pixels` wide.
+pub fn level_thumbnail_png(
+    level: &LevelLayout,
+    cell_pixels: usize,
+) -> Result<Vec<u8>, ThumbnailError> {
+    Thumbnail::render(level, cell_pixels).to_png()
+}
@@ -0,0 +1,222 @@
+//! WebSocket connections that work the same natively and in browsers, so online matches and viewers don't need to know
+//! which one they're built for. Only built with the `multiplayer` or `viewer` features.
+//!
+//! Natively, connections run on tungstenite in a background thread. In browsers, they use the page's `WebSocket`.
+//! Either way, messages are text, and events are polled every frame instead of arriving in callbacks.
+//!
+//! Servers, like the spectator server, stay native only, since browsers can't accept connections.
+
+/// Something that happened on a connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransportEvent {
+    /// The connection is ready to send messages. Messages sent before this are queued.
+    Opened,
+    /// A text message arrived. Binary messages are ignored.
+    Message(String),
+    Error(String),
+    /// The connection closed. No more events will arrive.
+    Closed,
+}
+
+/// A connection to a WebSocket server.
+pub trait Transport {
+    /// Sends a text message, or queues it until the connection opens.
+    fn send(&mut self, text: String);
+    /// Returns the next event, if one has happened since the last call.
+    fn try_recv(&mut self) -> Option<TransportEvent>;
+}
+
+/// Opens a connection to `url`, e.g. `ws://localhost:9000`, using whichever transport this build supports.
+pub fn connect(url: &str) -> Result<Box<dyn Transport>, String> {
+    #[cfg(not(target_arch = "wasm32"))]
+    let transport = native::NativeTransport::connect(url);
+    #[cfg(target_arch = "wasm32")]
+    let transport = web::WebTransport::connect(url)?;
+    Ok(Box::new(transport))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod native {
+    use std::{
+        io::ErrorKind,
+        net::TcpStream,
+        sync::mpsc::{self, Receiver, Sender, TryRecvError},
+        thread,
+        time::Duration,
+    };
+
+    use tungstenite::{stream::MaybeTlsStream, Message, WebSocket};
+
+    use super::{Transport, TransportEvent};
+
+    /// How long the connection thread waits for a message before checking for ones to send.
+    const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+    /// A connection running on its own thread, which games talk to over channels.
+    pub struct NativeTransport {
+        outgoing: Sender<String>,
+        events: Receiver<TransportEvent>,
+    }
+
+    impl NativeTransport {
+        pub fn connect(url: &str) -> Self {
+            let (outgoing, outgoing_rx) = mpsc::channel();
+            let (events_tx, events) = mpsc::channel();
+            let url = url.to_string();
+            thread::spawn(move || run_connection(&url, outgoing_rx, events_tx));
+            Self { outgoing, events }
+        }
+    }
+
+    impl Transport for NativeTransport {
+        fn send(&mut self, text: String) {
+            // If the thread has stopped, it's already sent `Closed`
+            let _ = self.outgoing.send(text);
+        }
+
+        fn try_recv(&mut self) -> Option<TransportEvent> {
+            self.events.try_recv().ok()
+        }
+    }
+
+    /// Connects, then passes messages both ways until either side closes the connection.
+    fn run_connection(url: &str, outgoing: Receiver<String>, events: Sender<TransportEvent>) {
+        let mut socket = match tungstenite::connect(url) {
+            Ok((socket, _)) => socket,
+            Err(err) => {
+                let _ = events.send(TransportEvent::Error(err.to_string()));
+                let _ = events.send(TransportEvent::Closed);
+                return;
+            }
+        };
+        // Time out reads, so messages to send don't wait for one to arrive
+        if let MaybeTlsStream::Plain(stream) = socket.get_mut() {
+            let _ = stream.set_read_timeout(Some(POLL_INTERVAL));
+        }
+        let _ = events.send(TransportEvent::Opened);
+        if let Err(err) = pass_messages(&mut socket, &outgoing, &events) {
+            let _ = events.send(TransportEvent::Error(err));
+        }
+        let _ = socket.close(None);
+        let _ = events.send(TransportEvent::Closed);
+    }
+
+    /// Passes messages until the connection closes, or the game drops its end.
+    fn pass_messages(
+        socket: &mut WebSocket<MaybeTlsStream<TcpStream>>,
+        outgoing: &Receiver<String>,
+        events: &Sender<TransportEvent>,
+    ) -> Result<(), String> {
+        loop {
+            loop {
+                match outgoing.try_recv() {
+                    Ok(text) => socket
+                        .send(Message::Text(text))
+                        .map_err(|err| err.to_string())?,
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                }
+            }
+            match socket.read() {
+                Ok(Message::Text(text)) => {
+                    if events.send(TransportEvent::Message(text)).is_err() {
+                        return Ok(());
+                    }
+                }
+                Ok(Message::Close(_)) => return Ok(()),
+                Ok(_) => {}
+                Err(tungstenite::Error::Io(err))
+                    if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
+                Err(err) => return Err(err.to_string()),
+            }
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+mod web {
+    use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+
+    use wasm_bindgen::{closure::Closure, JsCast};
+    use web_sys::{Event, MessageEvent, WebSocket};
+
+    use super::{Transport, TransportEvent};
+
+    /// A connection using the browser's `WebSocket`, whose callbacks queue events for the game to poll.
+    pub struct WebTransport {
+        socket: WebSocket,
+        events: Rc<RefCell<VecDeque<TransportEvent>>>,
+        /// Messages sent before the connection opened.
+        pending: Vec<String>,
+        /// Kept so the callbacks live as long as the socket.
+        _callbacks: [Closure<dyn FnMut(Event)>; 3],
+        _on_message: Closure<dyn FnMut(MessageEvent)>,
+    }
+
+    impl WebTransport {
+        pub fn connect(url: &str) -> Result<Self, String> {
+            let socket = WebSocket::new(url).map_err(|err| format!("{err:?}"))?;
+            let events = Rc::new(RefCell::new(VecDeque::new()));
+            let on_event = |event: TransportEvent| {
+                let events = events.clone();
+                Closure::<dyn FnMut(Event)>::new(move |_: Event| {
+                    events.borrow_mut().push_back(event.clone())
+                })
+            };
+            let on_open = on_event(TransportEvent::Opened);
+            let on_error = on_event(TransportEvent::Error("WebSocket error".into()));
+            let on_close = on_event(TransportEvent::Closed);
+            let on_message = {
+                let events = events.clone();
+                Closure::<dyn FnMut(MessageEvent)>::new(move |ev: MessageEvent| {
+                    if let Some(text) = ev.data().as_string() {
+                        events.borrow_mut().push_back(TransportEvent::Message(text));
+                    }
+                })
+            };
+            socket.set_onopen(Some(on_open.as_ref().unchecked_ref()));
+            socket.set_onerror(Some(on_error.as_ref().unchecked_ref()));
+            socket.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+            socket.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+            Ok(Self {
+                socket,
+                events,
+                pending: Vec::new(),
+                _callbacks: [on_open, on_error, on_close],
+                _on_message: on_message,
+            })
+        }
+
+        fn send_pending(&mut self) {
+            if self.socket.ready_state() != WebSocket::OPEN {
+                return;
+            }
+            for text in self.pending.drain(..) {
+                let _ = self.socket.send_with_str(&text);
+            }
+        }
+    }
+
+    impl Transport for WebTransport {
+        fn send(&mut self, text: String) {
+            self.pending.push(text);
+            self.send_pending();
+        }
+
+        fn try_recv(&mut self) -> Option<TransportEvent> {
+            self.send_pending();
+            self.events.borrow_mut().pop_front()
+        }
+    }
+
+    impl Drop for WebTransport {
+        fn drop(&mut self) {
+            self.socket.set_onopen(None);
+            self.socket.set_onerror(None);
+            self.socket.set_onclose(None);
+            self.socket.set_onmessage(None);
+            let _ = self.socket.close();
+        }
+    }
+}
@@ -0,0 +1,233 @@
+//! Shows a game running somewhere else, like a training server or someone's match, by following its spectator stream
+//! (see `spectator`). Only built with the `viewer` feature.
+//!
+//! Levels are started the same way guests in online matches start the host's levels, by sending a `ResetEvent` with the
+//! level. Nothing is simulated here, since frames describe where everything is, so viewers never drift from the game
+//! they're showing.
+//!
+//! Frames can arrive far less often than the viewer draws, e.g. from training servers stepping every half second, so
+//! agents and objects are drawn between the last two frames, one frame behind. If the next frame is late, they keep
+//! moving the way they were for a little while, then stop until it arrives.
+
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+
+use crate::{
+    gridworld::{
+        move_agents, reset_level, Agent, LevelLayout, LevelObject, PlayerAgent, PursuerAgent,
+        RemoteControlled, ResetEvent, GRID_CELL_SIZE,
+    },
+    spectator::{SpectatorAgent, SpectatorFrame, SpectatorMessage},
+    transport::{self, Transport, TransportEvent},
+};
+
+/// How far past the latest frame things keep moving if the next one is late, in multiples of the time between frames.
+pub const MAX_EXTRAPOLATION: f32 = 0.5;
+
+/// Shows the game streamed by the spectator server at `server_url`, e.g. `ws://localhost:9200`.
+pub struct ViewerPlugin {
+    pub server_url: String,
+}
+
+impl Plugin for ViewerPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_non_send_resource(ViewerConnection::default())
+            .insert_resource(ViewerState {
+                server_url: self.server_url.clone(),
+                cell_size: GRID_CELL_SIZE,
+                frame: None,
+                prev_frame: None,
+            })
+            .add_systems(Startup, (connect_viewer, stop_physics))
+            .add_systems(
+                Update,
+                (
+                    receive_spectator_messages.before(reset_level),
+                    mark_viewed_agents,
+                    apply_frame.after(move_agents),
+                ),
+            );
+    }
+}
+
+/// The connection to the spectator server, if one has been opened.
+/// This isn't `Send` in browsers, so it's stored as a non-send resource.
+#[derive(Default)]
+struct ViewerConnection {
+    socket: Option<Box<dyn Transport>>,
+}
+
+/// What the spectator server has sent so far.
+#[derive(Resource)]
+pub struct ViewerState {
+    pub server_url: String,
+    /// The width of a cell in the positions frames use.
+    pub cell_size: f32,
+    /// The latest state of the level.
+    pub frame: Option<ReceivedFrame>,
+    /// The state of the level before `frame`.
+    pub prev_frame: Option<ReceivedFrame>,
+}
+
+/// A frame, and when it arrived.
+pub struct ReceivedFrame {
+    pub frame: SpectatorFrame,
+    /// The real time since startup when the frame arrived.
+    pub received: Duration,
+}
+
+impl ViewerState {
+    /// Returns how far to draw things between the previous frame (0) and the latest one (1). Goes past 1 if the next
+    /// frame is late, up to `MAX_EXTRAPOLATION` more.
+    fn blend(&self, now: Duration) -> f32 {
+        let (Some(prev), Some(latest)) = (&self.prev_frame, &self.frame) else {
+            return 1.;
+        };
+        let interval = (latest.received - prev.received).as_secs_f32();
+        if interval <= 0. {
+            return 1.;
+        }
+        ((now - latest.received).as_secs_f32() / interval).min(1. + MAX_EXTRAPOLATION)
+    }
+}
+
+fn connect_viewer(mut conn: NonSendMut<ViewerConnection>, state: Res<ViewerState>) {
+    match transport::connect(&state.server_url) {
+        Ok(socket) => conn.socket = Some(socket),
+        Err(err) => error!("Couldn't connect to {}: {err}", state.server_url),
+    }
+}
+
+/// Stops physics, since everything is moved to where frames say it is.
+fn stop_physics(mut rapier_config: ResMut<RapierConfiguration>) {
+    rapier_config.physics_pipeline_active = false;
+}
+
+/// Starts the levels the spectator server sends, and keeps the latest frame.
+fn receive_spectator_messages(
+    mut conn: NonSendMut<ViewerConnection>,
+    mut state: ResMut<ViewerState>,
+    mut ev_reset: EventWriter<ResetEvent>,
+    time: Res<Time<Real>>,
+) {
+    while let Some(ev) = conn.socket.as_mut().and_then(|socket| socket.try_recv()) {
+        let msg = match ev {
+            TransportEvent::Opened => {
+                info!("Watching {}", state.server_url);
+                continue;
+            }
+            TransportEvent::Message(text) => match serde_json::from_str(&text) {
+                Ok(msg) => msg,
+                Err(err) => {
+                    warn!("Ignoring invalid message from the spectator server: {err}");
+                    continue;
+                }
+            },
+            TransportEvent::Error(err) => {
+                error!("Spectator connection error: {err}");
+                continue;
+            }
+            TransportEvent::Closed => {
+                warn!("The spectator server closed the connection");
+                conn.socket = None;
+                break;
+            }
+        };
+        match msg {
+            SpectatorMessage::Level { level, cell_size } => {
+                state.cell_size = cell_size;
+                // Frames from the last level don't apply to this one
+                state.frame = None;
+                state.prev_frame = None;
+                ev_reset.send(ResetEvent {
+                    level: Some(LevelLayout::from_data(&level)),
+                });
+            }
+            SpectatorMessage::Frame(frame) => {
+                state.prev_frame = state.frame.take();
+                state.frame = Some(ReceivedFrame {
+                    frame,
+                    received: time.elapsed(),
+                });
+            }
+        }
+    }
+}
+
+/// Marks both agents as controlled from somewhere else, so the keyboard doesn't move them.
+fn mark_viewed_agents(
+    mut commands: Commands,
+    agent_query: Query<
+        Entity,
+        (
+            Or<(With<PlayerAgent>, With<PursuerAgent>)>,
+            Without<RemoteControlled>,
+        ),
+    >,
+) {
+    for e in agent_query.iter() {
+        commands.entity(e).insert(RemoteControlled);
+    }
+}
+
+/// Moves the agents and objects between where the last two frames say they are.
+fn apply_frame(
+    state: Res<ViewerState>,
+    time: Res<Time<Real>>,
+    mut player_query: Query<(&mut Agent, &mut Transform), With<PlayerAgent>>,
+    mut pursuer_query: Query<
+        (&mut Agent, &mut Transform),
+        (With<PursuerAgent>, Without<PlayerAgent>),
+    >,
+    mut obj_query: Query<(&LevelObject, &mut Transform), Without<Agent>>,
+) {
+    let Some(latest) = &state.frame else {
+        return;
+    };
+    let frame = &latest.frame;
+    // Without an earlier frame, things are drawn where the latest one says
+    let prev = state.prev_frame.as_ref().map_or(frame, |prev| &prev.frame);
+    let blend = state.blend(time.elapsed());
+    let scale = GRID_CELL_SIZE / state.cell_size;
+    let place = |xform: &mut Transform, prev_pos: [f32; 2], pos: [f32; 2]| {
+        let pos = Vec2::from(prev_pos).lerp(pos.into(), blend) * scale;
+        xform.translation = pos.extend(xform.translation.z);
+    };
+    let update_agent = |viewed: Option<(Mut<Agent>, Mut<Transform>)>,
+                        prev: &Option<SpectatorAgent>,
+                        agent: &Option<SpectatorAgent>| {
+        let (Some((mut viewed_agent, mut xform)), Some(agent)) = (viewed, agent) else {
+            return;
+        };
+        // Agents that just appeared have nowhere to move from
+        let prev = prev.as_ref().unwrap_or(agent);
+        let dir = Vec2::from(prev.dir).lerp(agent.dir.into(), blend.min(1.));
+        viewed_agent.dir = dir.try_normalize().unwrap_or(agent.dir.into());
+        place(&mut xform, prev.pos, agent.pos);
+    };
+    update_agent(
+        player_query.get_single_mut().ok(),
+        &prev.player,
+        &frame.player,
+    );
+    update_agent(
+        pursuer_query.get_single_mut().ok(),
+        &prev.pursuer,
+        &frame.pursuer,
+    );
+
+    let find_obj = |frame: &SpectatorFrame, idx: usize| {
+        frame
+            .objects
+            .iter()
+            .find(|(obj_idx, _)| *obj_idx == idx)
+            .map(|(_, pos)| *pos)
+    };
+    for (obj, mut xform) in obj_query.iter_mut() {
+        if let Some(pos) = find_obj(frame, obj.0) {
+            place(&mut xform, find_obj(prev, obj.0).unwrap_or(pos), pos);
+        }
+    }
+}
@@ -0,0 +1,390 @@
+//! Computes which cells observers can see, either by rasterizing their vision meshes or directly on the grid.
+
+use std::str::FromStr;
+
+use bevy::prelude::*;
+use bevy_rapier2d::geometry::Collider;
+use thiserror::Error;
+
+use crate::{
+    bitgrid::BitGrid,
+    gridworld::{Agent, GridTransform, LevelLayout, PursuerAgent, GRID_CELL_SIZE},
+    observer::{update_observers, Fog, Observer, OccludesVision, Wall},
+    world_objs::StationaryCamera,
+};
+
+/// Plugin for visibility shared between teammates.
+pub struct VisibilityPlugin;
+
+impl Plugin for VisibilityPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TeamVisibility>().add_systems(
+            Update,
+            update_team_visibility
+                .after(update_observers)
+                .run_if(resource_exists::<LevelLayout>),
+        );
+    }
+}
+
+/// How the cells an agent can see are worked out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VisibilityBackend {
+    /// Clips the observer's vision mesh to every cell, measuring exactly how much of each cell it covers.
+    #[default]
+    Mesh,
+    /// Casts a ray from the observer to the center of every cell. Faster, and exactly aligned to the grid.
+    Grid,
+}
+
+#[derive(Debug, Error)]
+#[error("Unknown visibility backend \"{0}\", expected \"mesh\" or \"grid\"")]
+pub struct UnknownVisibilityBackendError(pub String);
+
+impl FromStr for VisibilityBackend {
+    type Err = UnknownVisibilityBackendError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mesh" => Ok(Self::Mesh),
+            "grid" => Ok(Self::Grid),
+            _ => Err(UnknownVisibilityBackendError(s.into())),
+        }
+    }
+}
+
+/// How much of a cell a vision mesh has to cover for the cell to count as visible.
+pub const VISIBLE_COVERAGE: f32 = 0.5;
+
+/// Returns how much of each cell a vision mesh covers, from 0 to 1, indexed the same way as `LevelLayout::walls`.
+///
+/// Triangles are clipped exactly, first to each row of cells they overlap and then to each cell in that row, so cone
+/// edges don't alias the way sampling would.
+pub fn mesh_coverage(grid: GridTransform, vis_mesh: &[[Vec2; 3]]) -> Vec<f32> {
+    let half = GRID_CELL_SIZE / 2.;
+    let mut coverage = vec![0.; grid.width * grid.height];
+    for tri in vis_mesh {
+        let (min_x, min_y) = grid.world_to_cell_clamped(tri[0].min(tri[1]).min(tri[2]));
+        let (max_x, max_y) = grid.world_to_cell_clamped(tri[0].max(tri[1]).max(tri[2]));
+        for y in min_y..=max_y {
+            let row_y = grid.cell_to_world((0, y)).y;
+            let row = clip_polygon(&clip_polygon(tri, |p| p.y - (row_y - half)), |p| {
+                row_y + half - p.y
+            });
+            if row.len() < 3 {
+                continue;
+            }
+            for x in min_x..=max_x {
+                let cell_x = grid.cell_to_world((x, y)).x;
+                let cell = clip_polygon(&clip_polygon(&row, |p| p.x - (cell_x - half)), |p| {
+                    cell_x + half - p.x
+                });
+                let idx = grid.cell_idx((x, y)).unwrap();
+                coverage[idx] += polygon_area(&cell) / (GRID_CELL_SIZE * GRID_CELL_SIZE);
+            }
+        }
+    }
+    // Triangles in a vision mesh shouldn't overlap, but rounding can push shared cells slightly over
+    for cell in &mut coverage {
+        *cell = cell.min(1.);
+    }
+    coverage
+}
+
+/// Clips a polygon to the half plane where `dist` isn't negative, with one pass of Sutherland-Hodgman.
+/// `dist` should be linear, like the signed distance to an edge.
+fn clip_polygon(poly: &[Vec2], dist: impl Fn(Vec2) -> f32) -> Vec<Vec2> {
+    let mut clipped = Vec::with_capacity(poly.len() + 1);
+    for (i, &p) in poly.iter().enumerate() {
+        let q = poly[(i + 1) % poly.len()];
+        let (dist_p, dist_q) = (dist(p), dist(q));
+        if dist_p >= 0. {
+            clipped.push(p);
+        }
+        if (dist_p >= 0.) != (dist_q >= 0.) {
+            clipped.push(p + (q - p) * (dist_p / (dist_p - dist_q)));
+        }
+    }
+    clipped
+}
+
+/// Returns the area of a simple polygon, using the shoelace formula.
+fn polygon_area(poly: &[Vec2]) -> f32 {
+    let twice_area: f32 = poly
+        .iter()
+        .zip(poly.iter().cycle().skip(1))
+        .map(|(p, q)| p.perp_dot(*q))
+        .sum();
+    twice_area.abs() / 2.
+}
+
+/// Returns the cells whose centers lie inside any of these areas, which are given in world space.
+///
+/// Pass the areas of walls and occluders to get the cells that block vision.
+pub fn occluded_cells(grid: GridTransform, areas: impl IntoIterator<Item = Rect>) -> BitGrid {
+    let mut occluded = BitGrid::new(grid.width, grid.height);
+    for area in areas {
+        let min = grid.world_to_cell_clamped(area.min);
+        let max = grid.world_to_cell_clamped(area.max);
+        for y in min.1..=max.1 {
+            for x in min.0..=max.0 {
+                if area.contains(grid.cell_to_world((x, y))) {
+                    occluded.set(grid.cell_idx((x, y)).unwrap(), true);
+                }
+            }
+        }
+    }
+    occluded
+}
+
+/// Returns the cells an observer at `pos` facing `facing` can see.
+///
+/// A cell is visible if its center is within the observer's field of view and range, and nothing in `occluded` lies
+/// between the observer and the center. Occluded cells are never visible, except for the observer's own cell, which
+/// is ignored like cover the observer is standing in. Fog cuts rays short the same way it does for vision meshes.
+pub fn visible_cells(
+    occluded: &BitGrid,
+    observer: &Observer,
+    pos: Vec2,
+    facing: Vec2,
+    fog: &[&Fog],
+) -> BitGrid {
+    let grid = GridTransform::new(occluded.width(), occluded.height());
+    let mut occluded = occluded.clone();
+    let own_cell = grid.world_to_cell(pos);
+    if let Some(idx) = own_cell.and_then(|cell| grid.cell_idx(cell)) {
+        occluded.set(idx, false);
+    }
+
+    let half_fov = observer.fov_degrees.to_radians() / 2.;
+    let mut visible = BitGrid::new(grid.width, grid.height);
+    for i in 0..occluded.len() {
+        let cell = grid.idx_cell(i);
+        let center = grid.cell_to_world(cell);
+        let offset = center - pos;
+        let dist = offset.length();
+        let in_view = if Some(cell) == own_cell || dist < f32::EPSILON {
+            true
+        } else {
+            let dir = offset / dist;
+            let max_dist = fog
+                .iter()
+                .filter_map(|fog| fog.ray_limit(pos, dir))
+                .fold(observer.max_range.unwrap_or(f32::MAX), f32::min);
+            // Clamp, since rounding can push the dot product of parallel vectors past 1
+            dir.dot(facing).clamp(-1., 1.).acos() <= half_fov + 0.01
+                && dist <= max_dist
+                && occluded.line_of_sight(pos / GRID_CELL_SIZE, center / GRID_CELL_SIZE)
+        };
+        visible.set(i, in_view);
+    }
+    visible
+}
+
+/// Returns the world space area of a rect collider, or `None` if the collider isn't a rect.
+fn collider_rect(xform: &GlobalTransform, collider: &Collider) -> Option<Rect> {
+    let half = collider.as_cuboid()?.raw.half_extents.xy();
+    Some(Rect::from_center_half_size(xform.translation().xy(), half))
+}
+
+/// Returns the cells the agent with the provided component can see, given the current walls, occluders, and fog.
+pub fn agent_visible_cells<T: Component>(world: &mut World) -> BitGrid {
+    let mut occluder_query = world
+        .query_filtered::<(&GlobalTransform, &Collider), Or<(With<Wall>, With<OccludesVision>)>>();
+    let mut fog_query = world.query::<&Fog>();
+    let mut agent_query = world.query_filtered::<(&Agent, &GlobalTransform, &Observer), With<T>>();
+    let world = &*world;
+
+    let occluded = occluded_cells(
+        world.resource::<LevelLayout>().grid(),
+        occluder_query
+            .iter(world)
+            .filter_map(|(xform, collider)| collider_rect(xform, collider)),
+    );
+    let fog = fog_query.iter(world).collect::<Vec<_>>();
+    let (agent, xform, observer) = agent_query.single(world);
+    visible_cells(
+        &occluded,
+        observer,
+        xform.translation().xy(),
+        agent.dir,
+        &fog,
+    )
+}
+
+/// What the pursuer's team can see together this frame. The team is every pursuer and stationary camera.
+///
+/// Cells are computed with the grid backend, so they match `agent_visible_cells`.
+#[derive(Resource, Clone, Default)]
+pub struct TeamVisibility {
+    /// Cells any teammate can see, indexed the same way as `LevelLayout::walls`.
+    pub visible: BitGrid,
+    /// Entities any teammate can see, other than teammates themselves.
+    pub observing: Vec<Entity>,
+}
+
+/// Unions the visible cells and observed entities of every teammate.
+fn update_team_visibility(
+    level: Res<LevelLayout>,
+    occluder_query: Query<(&GlobalTransform, &Collider), Or<(With<Wall>, With<OccludesVision>)>>,
+    fog_query: Query<&Fog>,
+    team_query: Query<
+        (
+            Entity,
+            &GlobalTransform,
+            &Observer,
+            Option<&Agent>,
+            Option<&StationaryCamera>,
+        ),
+        Or<(With<PursuerAgent>, With<StationaryCamera>)>,
+    >,
+    mut team_vis: ResMut<TeamVisibility>,
+) {
+    let grid = level.grid();
+    let occluded = occluded_cells(
+        grid,
+        occluder_query
+            .iter()
+            .filter_map(|(xform, collider)| collider_rect(xform, collider)),
+    );
+    let fog = fog_query.iter().collect::<Vec<_>>();
+
+    let mut visible = BitGrid::new(grid.width, grid.height);
+    let mut observing = Vec::new();
+    for (_, xform, observer, agent, camera) in team_query.iter() {
+        let facing = agent
+            .map(|agent| agent.dir)
+            .or(camera.map(|camera| camera.facing))
+            .unwrap_or(Vec2::X);
+        visible.union_with(&visible_cells(
+            &occluded,
+            observer,
+            xform.translation().xy(),
+            facing,
+            &fog,
+        ));
+        observing.extend_from_slice(&observer.observing);
+    }
+    observing.sort_unstable();
+    observing.dedup();
+    observing.retain(|&e| !team_query.contains(e));
+    *team_vis = TeamVisibility { visible, observing };
+}
+
+/// Remembers when an observer last saw each cell, indexed the same way as `LevelLayout::walls`.
+#[derive(Component, Clone, Default)]
+pub struct SeenCells {
+    /// When each cell was last visible (time since startup), or `None` if it never has been.
+    pub last_seen: Vec<Option<f32>>,
+}
+
+impl SeenCells {
+    /// Marks the visible cells as seen at time `now`.
+    pub fn update(&mut self, visible: &[bool], now: f32) {
+        self.last_seen.resize(visible.len(), None);
+        for (last_seen, &visible) in self.last_seen.iter_mut().zip(visible) {
+            if visible {
+                *last_seen = Some(now);
+            }
+        }
+    }
+
+    /// Returns how stale each cell is at time `now`, from 0 for cells seen just now to 1 for cells last seen at least
+    /// `horizon` seconds ago or never seen at all. `horizon` must be positive.
+    pub fn staleness(&self, now: f32, horizon: f32) -> Vec<f32> {
+        self.last_seen
+            .iter()
+            .map(|last_seen| match last_seen {
+                Some(t) => ((now - t) / horizon).clamp(0., 1.),
+                None => 1.,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CELL_AREA: f32 = GRID_CELL_SIZE * GRID_CELL_SIZE;
+
+    /// Estimates coverage by sampling `scale * scale` points in every cell, the way visible cells used to be computed.
+    fn sampled_coverage(grid: GridTransform, vis_mesh: &[[Vec2; 3]], scale: usize) -> Vec<f32> {
+        let inside = |tri: &[Vec2; 3], p: Vec2| {
+            let sides = [0, 1, 2].map(|i| (tri[(i + 1) % 3] - tri[i]).perp_dot(p - tri[i]));
+            sides.iter().all(|&s| s >= 0.) || sides.iter().all(|&s| s <= 0.)
+        };
+        (0..grid.width * grid.height)
+            .map(|i| {
+                let corner = grid.cell_to_world(grid.idx_cell(i)) - GRID_CELL_SIZE / 2.;
+                let step = GRID_CELL_SIZE / scale as f32;
+                let hits = (0..scale * scale)
+                    .filter(|j| {
+                        let offset = Vec2::new((j % scale) as f32 + 0.5, (j / scale) as f32 + 0.5);
+                        let p = corner + offset * step;
+                        vis_mesh.iter().any(|tri| inside(tri, p))
+                    })
+                    .count();
+                hits as f32 / (scale * scale) as f32
+            })
+            .collect()
+    }
+
+    /// A fan of thin triangles, like the cone of an observer's vision mesh.
+    fn cone() -> Vec<[Vec2; 3]> {
+        let origin = Vec2::new(12., 37.);
+        let rays: Vec<_> = (0..=16)
+            .map(|i| origin + Vec2::from_angle(0.1 + 0.05 * i as f32) * 160.)
+            .collect();
+        rays.windows(2).map(|r| [origin, r[0], r[1]]).collect()
+    }
+
+    #[test]
+    fn full_cell_is_fully_covered() {
+        let grid = GridTransform::new(3, 3);
+        let center = grid.cell_to_world((1, 1));
+        let (min, max) = (center - GRID_CELL_SIZE / 2., center + GRID_CELL_SIZE / 2.);
+        let mesh = [
+            [min, Vec2::new(max.x, min.y), max],
+            [min, max, Vec2::new(min.x, max.y)],
+        ];
+        let coverage = mesh_coverage(grid, &mesh);
+        for (i, &c) in coverage.iter().enumerate() {
+            let expected = if grid.idx_cell(i) == (1, 1) { 1. } else { 0. };
+            assert!((c - expected).abs() < 1e-4, "cell {i} had coverage {c}");
+        }
+    }
+
+    #[test]
+    fn diagonal_covers_half_a_cell() {
+        let grid = GridTransform::new(2, 2);
+        let center = grid.cell_to_world((0, 0));
+        let (min, max) = (center - GRID_CELL_SIZE / 2., center + GRID_CELL_SIZE / 2.);
+        let coverage = mesh_coverage(grid, &[[min, Vec2::new(max.x, min.y), max]]);
+        assert!((coverage[0] - 0.5).abs() < 1e-4);
+        assert!(coverage[1..].iter().all(|&c| c.abs() < 1e-4));
+    }
+
+    #[test]
+    fn coverage_adds_up_to_mesh_area() {
+        let grid = GridTransform::new(8, 8);
+        let mesh = cone();
+        let covered: f32 = mesh_coverage(grid, &mesh).iter().sum::<f32>() * CELL_AREA;
+        let area: f32 = mesh.iter().map(|tri| polygon_area(tri)).sum();
+        assert!((covered - area).abs() / area < 1e-3, "{covered} != {area}");
+    }
+
+    #[test]
+    fn sampling_converges_to_exact_coverage() {
+        let grid = GridTransform::new(8, 8);
+        let mesh = cone();
+        let exact = mesh_coverage(grid, &mesh);
+        let errors = [1, 4, 8].map(|scale| {
+            let sampled = sampled_coverage(grid, &mesh, scale);
+            let error: f32 = exact.iter().zip(&sampled).map(|(e, s)| (e - s).abs()).sum();
+            error / exact.len() as f32
+        });
+        assert!(errors[0] > errors[1] && errors[1] > errors[2], "{errors:?}");
+        assert!(errors[2] < 0.02, "{errors:?}");
+    }
+}
@@ -1,6 +1,12 @@
+use std::collections::HashMap;
+
 use crate::{
-    gridworld::{Agent, NextAction, GRID_CELL_SIZE},
-    observer::Wall,
+    gridworld::{
+        move_agents, Agent, LevelEntity, LevelLayout, NextAction, PlayerAgent, PursuerAgent,
+        ShouldRun, Terrain, AGENT_RADIUS, GRID_CELL_SIZE,
+    },
+    observer::{line_of_sight, update_observers, Observable, Observer, Wall},
+    pathfinding::distance_field,
 };
 use bevy::{prelude::*, sprite::Mesh2dHandle};
 use bevy_rapier2d::prelude::*;
@@ -10,16 +16,48 @@ pub struct WorldObjPlugin;
 
 impl Plugin for WorldObjPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(
-            Update,
-            (
-                update_door,
-                visualize_door,
-                update_noise_src,
-                // visualize_noise_src,
-                // visualize_visual_marker,
-            ),
-        );
+        app.add_event::<KeyPickedUp>()
+            .add_event::<DoorUnlocked>()
+            .add_event::<GameOutcome>()
+            .add_event::<CameraSighting>()
+            .init_resource::<HearingConfig>()
+            .add_systems(
+                Update,
+                (
+                    update_door,
+                    visualize_door,
+                    update_noise_src,
+                    (expire_terrain_noise, make_terrain_noise)
+                        .chain()
+                        .run_if(resource_exists::<ShouldRun>),
+                    (pick_up_key, unlock_exit, escape_through_exit, catch_player)
+                        .chain()
+                        .run_if(resource_exists::<ShouldRun>),
+                    toggle_dynamic_walls
+                        .after(move_agents)
+                        .before(update_observers)
+                        .run_if(resource_exists::<ShouldRun>),
+                    (enter_vents, travel_through_vents)
+                        .chain()
+                        .before(move_agents)
+                        .run_if(resource_exists::<ShouldRun>),
+                    move_patrols
+                        .before(update_observers)
+                        .run_if(resource_exists::<ShouldRun>),
+                    update_camera_feeds.after(update_observers),
+                    // visualize_noise_src,
+                    // visualize_visual_marker,
+                ),
+            );
+    }
+}
+
+/// Adds playable functionality for `WorldObjPlugin`.
+pub struct WorldObjPlayPlugin;
+
+impl Plugin for WorldObjPlayPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, show_outcome.after(escape_through_exit));
     }
 }
 
@@ -91,6 +129,455 @@ fn visualize_door(
     }
 }
 
+/// A wall segment that agents can open and close.
+///
+/// While open, the wall is a sensor, so agents can walk through it while it stays in Rapier's query pipeline. This
+/// lets observers see through it as soon as it's opened, and be blocked by it as soon as it's closed.
+#[derive(Component)]
+pub struct DynamicWall {
+    /// The index of this wall in `LevelLayout::walls`.
+    pub cell_idx: usize,
+    pub open: bool,
+}
+
+/// Opens and closes dynamic walls when a nearby agent toggles them, keeping the level's wall grid in sync.
+///
+/// Each wall toggles at most once per frame, even if several agents toggle it, and isn't closed while it
+/// overlaps any agent's collider.
+fn toggle_dynamic_walls(
+    mut commands: Commands,
+    agent_query: Query<(&GlobalTransform, &NextAction), With<Agent>>,
+    mut wall_query: Query<(Entity, &GlobalTransform, &mut DynamicWall)>,
+    mut level: ResMut<LevelLayout>,
+) {
+    let agent_positions: Vec<_> = agent_query
+        .iter()
+        .map(|(xform, _)| xform.translation().xy())
+        .collect();
+    let toggle_positions: Vec<_> = agent_query
+        .iter()
+        .filter(|(_, action)| action.toggle_objs)
+        .map(|(xform, _)| xform.translation().xy())
+        .collect();
+    if toggle_positions.is_empty() {
+        return;
+    }
+    for (e, wall_xform, mut wall) in wall_query.iter_mut() {
+        let wall_pos = wall_xform.translation().xy();
+        // An agent is in the wall if its collider overlaps any part of the wall's cell
+        let cell = Rect::from_center_half_size(wall_pos, Vec2::splat(GRID_CELL_SIZE / 2.));
+        let in_wall = |pos: &Vec2| {
+            pos.clamp(cell.min, cell.max).distance_squared(*pos) < AGENT_RADIUS.powi(2)
+        };
+        let toggled = toggle_positions
+            .iter()
+            .any(|pos| !in_wall(pos) && wall_pos.distance_squared(*pos) < TOGGLE_DIST.powi(2));
+        // Don't close walls on top of agents
+        if !toggled || (wall.open && agent_positions.iter().any(in_wall)) {
+            continue;
+        }
+        wall.open = !wall.open;
+        level.walls.set(wall.cell_idx, !wall.open);
+        if wall.open {
+            commands
+                .entity(e)
+                .remove::<Wall>()
+                .insert((Sensor, Visibility::Hidden));
+        } else {
+            commands
+                .entity(e)
+                .remove::<Sensor>()
+                .insert((Wall, Visibility::Inherited));
+        }
+    }
+}
+
+/// One end of a pair of connected vents.
+#[derive(Component)]
+pub struct Vent {
+    /// Where agents that enter this vent come out.
+    pub exit: Vec2,
+}
+
+/// Indicates an agent is travelling through a vent.
+/// Agents in vents can't move and can't be seen.
+#[derive(Component)]
+pub struct InVent {
+    pub exit: Vec2,
+    /// Seconds until the agent comes out.
+    pub remaining: f32,
+}
+
+/// How long it takes to travel through a vent, in seconds.
+const VENT_TRAVEL_TIME: f32 = 1.;
+
+/// Moves agents that toggle objects while standing on a vent into the vent.
+fn enter_vents(
+    mut commands: Commands,
+    agent_query: Query<(Entity, &GlobalTransform, &NextAction), (With<Agent>, Without<InVent>)>,
+    vent_query: Query<(&GlobalTransform, &Vent)>,
+) {
+    for (agent_e, agent_xform, action) in agent_query.iter() {
+        if !action.toggle_objs {
+            continue;
+        }
+        let agent_pos = agent_xform.translation().xy();
+        for (vent_xform, vent) in vent_query.iter() {
+            let dist_sq = (vent_xform.translation().xy() - agent_pos).length_squared();
+            if dist_sq < PICKUP_DIST.powi(2) {
+                commands
+                    .entity(agent_e)
+                    .remove::<Observable>()
+                    .insert(InVent {
+                        exit: vent.exit,
+                        remaining: VENT_TRAVEL_TIME,
+                    });
+                break;
+            }
+        }
+    }
+}
+
+/// Moves agents out of vents once they've finished travelling.
+fn travel_through_vents(
+    mut commands: Commands,
+    mut agent_query: Query<(Entity, &mut Transform, &mut InVent)>,
+    time: Res<Time>,
+) {
+    for (agent_e, mut xform, mut in_vent) in agent_query.iter_mut() {
+        in_vent.remaining -= time.delta_seconds();
+        if in_vent.remaining <= 0. {
+            xform.translation = in_vent.exit.extend(xform.translation.z);
+            commands
+                .entity(agent_e)
+                .remove::<InVent>()
+                .insert(Observable);
+        }
+    }
+}
+
+/// A neutral obstacle that walks a loop of waypoints.
+/// Patrols are walls that move, so they block both movement and vision.
+#[derive(Component)]
+pub struct Patrol {
+    /// World positions to walk to in order.
+    pub waypoints: Vec<Vec2>,
+    /// The index of the waypoint currently being walked to.
+    pub next: usize,
+    /// How fast the patrol walks, in world units per second.
+    pub speed: f32,
+}
+
+/// Patrols wait instead of moving closer than this to an agent.
+const PATROL_BLOCK_DIST: f32 = GRID_CELL_SIZE * 0.7;
+
+/// Walks patrols towards their next waypoint.
+fn move_patrols(
+    mut patrol_query: Query<(&mut Transform, &mut Patrol)>,
+    agent_query: Query<&GlobalTransform, With<Agent>>,
+    time: Res<Time>,
+) {
+    for (mut xform, mut patrol) in patrol_query.iter_mut() {
+        let pos = xform.translation.xy();
+        let target = patrol.waypoints[patrol.next];
+        let step = patrol.speed * time.delta_seconds();
+        let new_pos = if pos.distance(target) <= step {
+            target
+        } else {
+            pos + (target - pos).normalize() * step
+        };
+        let blocked = agent_query.iter().any(|agent_xform| {
+            let agent_pos = agent_xform.translation().xy();
+            agent_pos.distance_squared(new_pos) < PATROL_BLOCK_DIST.powi(2)
+                && agent_pos.distance_squared(new_pos) < agent_pos.distance_squared(pos)
+        });
+        if blocked {
+            continue;
+        }
+        xform.translation = new_pos.extend(xform.translation.z);
+        if new_pos == target {
+            patrol.next = (patrol.next + 1) % patrol.waypoints.len();
+        }
+    }
+}
+
+/// A camera fixed in place. Cameras see up to their observer's range, either in every direction or within a cone
+/// around `facing`.
+#[derive(Component)]
+pub struct StationaryCamera {
+    /// Which way the camera looks, as a unit vector.
+    pub facing: Vec2,
+}
+
+/// Where and when a camera feed last received a sighting of the player.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sighting {
+    /// The camera that saw the player.
+    pub camera: Entity,
+    pub pos: Vec2,
+    pub time: f32,
+}
+
+/// Lets an agent receive sightings from stationary cameras.
+/// Sightings are kept apart from what the agent sees itself, so they can be merged in or left out.
+#[derive(Component, Default)]
+pub struct CameraFeed {
+    /// Entities that any stationary camera can see, other than the agent itself.
+    pub observing: Vec<Entity>,
+    /// The most recent sighting of each player, kept after cameras lose sight of them.
+    pub sightings: HashMap<Entity, Sighting>,
+}
+
+/// Sent every update a stationary camera sees the player.
+#[derive(Event)]
+pub struct CameraSighting {
+    pub camera: Entity,
+    pub target: Entity,
+    pub pos: Vec2,
+    pub time: f32,
+}
+
+/// Collects what stationary cameras can see into every camera feed, and reports cameras that see the player.
+fn update_camera_feeds(
+    time: Res<Time>,
+    camera_query: Query<(Entity, &Observer), With<StationaryCamera>>,
+    player_query: Query<(Entity, &GlobalTransform), With<PlayerAgent>>,
+    mut feed_query: Query<(Entity, &mut CameraFeed)>,
+    mut sighting_events: EventWriter<CameraSighting>,
+) {
+    let now = time.elapsed_seconds_wrapped();
+    let mut observing = camera_query
+        .iter()
+        .flat_map(|(_, observer)| observer.observing.iter().copied())
+        .collect::<Vec<_>>();
+    observing.sort_unstable();
+    observing.dedup();
+
+    let mut sightings = Vec::new();
+    for (camera_e, observer) in camera_query.iter() {
+        for (player_e, player_xform) in player_query.iter() {
+            if observer.observing.contains(&player_e) {
+                let pos = player_xform.translation().xy();
+                sighting_events.send(CameraSighting {
+                    camera: camera_e,
+                    target: player_e,
+                    pos,
+                    time: now,
+                });
+                sightings.push((
+                    player_e,
+                    Sighting {
+                        camera: camera_e,
+                        pos,
+                        time: now,
+                    },
+                ));
+            }
+        }
+    }
+
+    for (feed_e, mut feed) in feed_query.iter_mut() {
+        feed.observing = observing.iter().copied().filter(|&e| e != feed_e).collect();
+        feed.sightings.retain(|&e, _| player_query.contains(e));
+        for &(player_e, sighting) in &sightings {
+            if player_e != feed_e {
+                feed.sightings.insert(player_e, sighting);
+            }
+        }
+    }
+}
+
+/// A key that the player can pick up to unlock the exit.
+#[derive(Component)]
+pub struct Key;
+
+/// Indicates an agent is carrying a key.
+#[derive(Component)]
+pub struct HasKey;
+
+/// The level's exit. Blocks movement and vision until the player unlocks it with a key.
+#[derive(Component, Default)]
+pub struct ExitDoor {
+    pub unlocked: bool,
+}
+
+/// Sent when an agent picks up a key.
+#[derive(Event)]
+pub struct KeyPickedUp {
+    pub agent: Entity,
+}
+
+/// Sent when an agent unlocks the exit.
+#[derive(Event)]
+pub struct DoorUnlocked {
+    pub agent: Entity,
+}
+
+/// An open exit cell. Unlike `ExitDoor`, this never blocks movement or vision.
+#[derive(Component)]
+pub struct Exit {
+    /// Whether the player has to be carrying the key to escape through this exit.
+    pub needs_key: bool,
+}
+
+/// How a level ended.
+#[derive(Event, Clone, Copy, Debug, PartialEq)]
+pub enum GameOutcome {
+    /// The player left through an exit at this position.
+    PlayerEscaped { exit_pos: Vec2 },
+    /// A pursuer caught the player at this position.
+    PlayerCaught { player_pos: Vec2 },
+    /// The episode ran out of steps before either. Never sent by the game itself, since only whatever's running the
+    /// episode knows how long it should last.
+    TimedOut,
+}
+
+/// Inserted when the level ends, along with how it ended.
+#[derive(Resource)]
+pub struct LevelComplete(pub GameOutcome);
+
+/// How close the player needs to be to pick up a key or walk through the exit.
+const PICKUP_DIST: f32 = GRID_CELL_SIZE / 2.;
+/// How close a pursuer needs to be to the player to catch it. A little further than where their colliders touch, since
+/// character controllers leave a gap.
+const CATCH_DIST: f32 = GRID_CELL_SIZE * 0.6;
+
+/// Gives the player the key if it's touching it.
+fn pick_up_key(
+    mut commands: Commands,
+    player_query: Query<(Entity, &GlobalTransform), (With<PlayerAgent>, Without<HasKey>)>,
+    key_query: Query<(Entity, &GlobalTransform), With<Key>>,
+    mut ev_picked_up: EventWriter<KeyPickedUp>,
+) {
+    for (player_e, player_xform) in player_query.iter() {
+        let player_pos = player_xform.translation().xy();
+        for (key_e, key_xform) in key_query.iter() {
+            let dist_sq = (key_xform.translation().xy() - player_pos).length_squared();
+            if dist_sq < PICKUP_DIST.powi(2) {
+                commands.entity(key_e).despawn_recursive();
+                commands.entity(player_e).insert(HasKey);
+                ev_picked_up.send(KeyPickedUp { agent: player_e });
+                break;
+            }
+        }
+    }
+}
+
+/// Unlocks the exit if a player carrying a key toggles it.
+fn unlock_exit(
+    mut commands: Commands,
+    player_query: Query<(Entity, &GlobalTransform, &NextAction), (With<PlayerAgent>, With<HasKey>)>,
+    mut door_query: Query<(Entity, &GlobalTransform, &mut ExitDoor)>,
+    mut ev_unlocked: EventWriter<DoorUnlocked>,
+) {
+    for (player_e, player_xform, action) in player_query.iter() {
+        if !action.toggle_objs {
+            continue;
+        }
+        let player_pos = player_xform.translation().xy();
+        for (door_e, door_xform, mut door) in door_query.iter_mut() {
+            let dist_sq = (door_xform.translation().xy() - player_pos).length_squared();
+            if !door.unlocked && dist_sq < TOGGLE_DIST.powi(2) {
+                door.unlocked = true;
+                commands
+                    .entity(door_e)
+                    .remove::<(Wall, Collider)>()
+                    .insert(Visibility::Hidden);
+                commands.entity(player_e).remove::<HasKey>();
+                ev_unlocked.send(DoorUnlocked { agent: player_e });
+                break;
+            }
+        }
+    }
+}
+
+/// Ends the level once the player walks through an unlocked door or an open exit.
+fn escape_through_exit(
+    mut commands: Commands,
+    player_query: Query<(&GlobalTransform, Has<HasKey>), With<PlayerAgent>>,
+    door_query: Query<(&GlobalTransform, &ExitDoor)>,
+    exit_query: Query<(&GlobalTransform, &Exit)>,
+    mut ev_outcome: EventWriter<GameOutcome>,
+) {
+    for (player_xform, has_key) in player_query.iter() {
+        let player_pos = player_xform.translation().xy();
+        let usable_exits = door_query
+            .iter()
+            .filter(|(_, door)| door.unlocked)
+            .map(|(xform, _)| xform)
+            .chain(
+                exit_query
+                    .iter()
+                    .filter(|(_, exit)| has_key || !exit.needs_key)
+                    .map(|(xform, _)| xform),
+            );
+        for exit_xform in usable_exits {
+            let exit_pos = exit_xform.translation().xy();
+            if (exit_pos - player_pos).length_squared() < PICKUP_DIST.powi(2) {
+                let outcome = GameOutcome::PlayerEscaped { exit_pos };
+                commands.insert_resource(LevelComplete(outcome));
+                commands.remove_resource::<ShouldRun>();
+                ev_outcome.send(outcome);
+                return;
+            }
+        }
+    }
+}
+
+/// Ends the level once a pursuer reaches the player, unless the player escaped first.
+fn catch_player(
+    mut commands: Commands,
+    player_query: Query<&GlobalTransform, With<PlayerAgent>>,
+    pursuer_query: Query<&GlobalTransform, With<PursuerAgent>>,
+    level_complete: Option<Res<LevelComplete>>,
+    mut ev_outcome: EventWriter<GameOutcome>,
+) {
+    if level_complete.is_some() {
+        return;
+    }
+    for player_xform in player_query.iter() {
+        let player_pos = player_xform.translation().xy();
+        let caught = pursuer_query.iter().any(|pursuer_xform| {
+            (pursuer_xform.translation().xy() - player_pos).length_squared() < CATCH_DIST.powi(2)
+        });
+        if caught {
+            let outcome = GameOutcome::PlayerCaught { player_pos };
+            commands.insert_resource(LevelComplete(outcome));
+            commands.remove_resource::<ShouldRun>();
+            ev_outcome.send(outcome);
+            return;
+        }
+    }
+}
+
+/// Shows a message when the level ends.
+fn show_outcome(mut commands: Commands, mut ev_outcome: EventReader<GameOutcome>) {
+    for outcome in ev_outcome.read() {
+        let message = match outcome {
+            GameOutcome::PlayerEscaped { .. } => "You escaped!",
+            GameOutcome::PlayerCaught { .. } => "You were caught!",
+            GameOutcome::TimedOut => "Out of time!",
+        };
+        commands.spawn((
+            LevelEntity,
+            TextBundle::from_section(
+                message,
+                TextStyle {
+                    font_size: 32.,
+                    color: Color::WHITE,
+                    ..default()
+                },
+            )
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                top: Val::Px(16.),
+                left: Val::Px(16.),
+                ..default()
+            }),
+        ));
+    }
+}
+
 /// A source of noise that alerts observers within a radius.
 #[derive(Component)]
 pub struct NoiseSource {
@@ -101,15 +588,111 @@ pub struct NoiseSource {
     pub activated_by: Option<Entity>,
 }
 
+impl NoiseSource {
+    /// Returns how loud this noise source at `pos` is to an agent at `listener_pos`, from 1 right on top of it down to 0
+    /// at `noise_radius` times `range_scale`, or `None` if the agent can't hear it at all.
+    ///
+    /// Noise that can't travel straight to the agent has to go around walls, so it only carries as far as the shortest
+    /// path through the grid. `listener_dists` is the distance field to the agent's cell (see `distance_field`).
+    pub fn loudness(
+        &self,
+        level: &LevelLayout,
+        listener_dists: &[Option<u32>],
+        listener_pos: Vec2,
+        pos: Vec2,
+        range_scale: f32,
+    ) -> Option<f32> {
+        let dist = if line_of_sight(level, pos, listener_pos) {
+            pos.distance(listener_pos)
+        } else {
+            let grid = level.grid();
+            let idx = grid.cell_idx(grid.world_to_cell(pos)?)?;
+            listener_dists.get(idx).copied().flatten()? as f32 * GRID_CELL_SIZE
+        };
+        let radius = self.noise_radius * range_scale;
+        (radius > 0. && dist <= radius).then(|| 1. - dist / radius)
+    }
+}
+
+/// Configures how far each agent can hear noises, as a multiple of each noise's radius.
+#[derive(Resource, Clone, Copy)]
+pub struct HearingConfig {
+    pub pursuer: f32,
+    pub player: f32,
+}
+
+impl Default for HearingConfig {
+    fn default() -> Self {
+        Self {
+            pursuer: 1.,
+            player: 1.,
+        }
+    }
+}
+
+impl HearingConfig {
+    /// Returns true if neither range is negative.
+    pub fn is_valid(&self) -> bool {
+        self.pursuer >= 0. && self.player >= 0.
+    }
+
+    /// Returns the range scale of the pursuer if `is_pursuer` is set, or the player's otherwise.
+    pub fn range_scale(&self, is_pursuer: bool) -> f32 {
+        if is_pursuer {
+            self.pursuer
+        } else {
+            self.player
+        }
+    }
+}
+
+/// Returns how loud each noise source at a world position is to a listener at `listener_pos` that hears `range_scale`
+/// times as far as usual, leaving out sources it can't hear.
+///
+/// Noise usually carries straight to the listener, so paths around walls are only searched for when it doesn't.
+pub fn audible_noises<'a, T>(
+    level: &LevelLayout,
+    listener_pos: Vec2,
+    range_scale: f32,
+    sources: impl IntoIterator<Item = (T, Vec2, &'a NoiseSource)>,
+) -> Vec<(T, f32)> {
+    let mut listener_dists = None;
+    sources
+        .into_iter()
+        .filter_map(|(key, pos, noise_src)| {
+            if listener_dists.is_none() && !line_of_sight(level, pos, listener_pos) {
+                listener_dists = Some(
+                    level
+                        .grid()
+                        .world_to_cell(listener_pos)
+                        .map(|cell| distance_field(level, cell))
+                        .unwrap_or_default(),
+                );
+            }
+            let dists = listener_dists.as_deref().unwrap_or_default();
+            let loudness = noise_src.loudness(level, dists, listener_pos, pos, range_scale)?;
+            Some((key, loudness))
+        })
+        .collect()
+}
+
 /// Broadcasts that an agent touched the noise source.
+/// Agents standing on carpet are muffled, and don't set off noise sources.
 fn update_noise_src(
     agent_query: Query<(Entity, &GlobalTransform), With<Agent>>,
-    mut noise_query: Query<(&GlobalTransform, &mut NoiseSource)>,
+    mut noise_query: Query<(&GlobalTransform, &mut NoiseSource), Without<TerrainNoise>>,
+    level: Option<Res<LevelLayout>>,
 ) {
     for (obj_xform, mut noise) in noise_query.iter_mut() {
         noise.activated_by = None;
         for (agent_e, agent_xform) in agent_query.iter() {
             let agent_pos = agent_xform.translation().xy();
+            if level
+                .as_ref()
+                .is_some_and(|level| level.terrain_at(agent_pos) == Terrain::Carpet)
+            {
+                continue;
+            }
             let obj_pos = obj_xform.translation().xy();
             let dist_sq = (obj_pos - agent_pos).length_squared();
             if dist_sq <= noise.active_radius.powi(2) {
@@ -119,6 +702,68 @@ fn update_noise_src(
     }
 }
 
+/// Marks a noise source made by an agent walking over noisy terrain.
+/// The noise source is attached to the agent, and is removed once `remaining` runs out.
+#[derive(Component)]
+pub struct TerrainNoise {
+    /// How many seconds until the noise stops.
+    pub remaining: f32,
+}
+
+/// How long the noise from a single step on gravel lasts.
+const TERRAIN_NOISE_TIME: f32 = 0.5;
+
+/// Makes agents walking on gravel into noise sources.
+fn make_terrain_noise(
+    mut commands: Commands,
+    mut agent_query: Query<
+        (
+            Entity,
+            &GlobalTransform,
+            &NextAction,
+            Option<&mut TerrainNoise>,
+        ),
+        (With<Agent>, Without<InVent>),
+    >,
+    level: Res<LevelLayout>,
+) {
+    for (agent_e, agent_xform, action, terrain_noise) in agent_query.iter_mut() {
+        let on_gravel = level.terrain_at(agent_xform.translation().xy()) == Terrain::Gravel;
+        if !on_gravel || action.dir.length_squared() <= 0.1 {
+            continue;
+        }
+        match terrain_noise {
+            Some(mut terrain_noise) => terrain_noise.remaining = TERRAIN_NOISE_TIME,
+            None => {
+                commands.entity(agent_e).insert((
+                    TerrainNoise {
+                        remaining: TERRAIN_NOISE_TIME,
+                    },
+                    NoiseSource {
+                        noise_radius: GRID_CELL_SIZE * 3.,
+                        active_radius: 0.,
+                        activated_by: Some(agent_e),
+                    },
+                ));
+            }
+        }
+    }
+}
+
+/// Silences terrain noise once it runs out.
+fn expire_terrain_noise(
+    mut commands: Commands,
+    mut noise_query: Query<(Entity, &mut TerrainNoise)>,
+    time: Res<Time>,
+) {
+    for (e, mut terrain_noise) in noise_query.iter_mut() {
+        terrain_noise.remaining -= time.delta_seconds();
+        if terrain_noise.remaining <= 0. {
+            commands.entity(e).remove::<(TerrainNoise, NoiseSource)>();
+        }
+    }
+}
+
 /// Visualizes a noise source.
 fn visualize_noise_src(mut gizmos: Gizmos, noise_query: Query<(&GlobalTransform, &NoiseSource)>) {
     for (obj_xform, noise) in noise_query.iter() {
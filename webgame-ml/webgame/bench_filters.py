@@ -31,7 +31,9 @@ if __name__ == "__main__":
 
     action_space = env.action_space("pursuer")  # Same for both agents
     if args.checkpoint:
-        model = MeasureModel(8, env.game_state.level_size, args.use_pos)
+        model = MeasureModel(
+            8, env.game_state.level_width, env.game_state.level_height, args.use_pos
+        )
         model.eval()
         load_model(model, args.checkpoint)
         update_fn = model_update(model)
@@ -43,7 +45,8 @@ if __name__ == "__main__":
         env.reset()
         assert env.game_state is not None
         b_filter = BayesFilter(
-            env.game_state.level_size,
+            env.game_state.level_width,
+            env.game_state.level_height,
             CELL_SIZE,
             update_fn,
             use_objs=args.use_objs,
@@ -67,7 +70,8 @@ if __name__ == "__main__":
                 args.use_objs,
                 game_state,
                 agent_state,
-                game_state.level_size,
+                game_state.level_width,
+                game_state.level_height,
                 CELL_SIZE,
                 True,
             )
@@ -76,9 +80,9 @@ if __name__ == "__main__":
 
             player_pos = env.game_state.player.pos
             gold_tile = pos_to_grid(
-                player_pos.x, player_pos.y, env.game_state.level_size, CELL_SIZE
+                player_pos.x, player_pos.y, env.game_state.level_width, CELL_SIZE
             )
-            gold_tile_idx = gold_tile[0] + gold_tile[1] * env.game_state.level_size
+            gold_tile_idx = gold_tile[0] + gold_tile[1] * env.game_state.level_width
 
             if probs_flattened.argmax() == gold_tile_idx:
                 correct_preds += 1
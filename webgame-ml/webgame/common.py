@@ -64,10 +64,10 @@ def explore_policy(game_state: GameState, is_pursuer: bool) -> int:
         return best_dir[2]
     
     # Otherwise, randomly move away from walls
-    level_size = game_state.level_size
-    tile = pos_to_grid(agent_state.pos.x, agent_state.pos.y, level_size, CELL_SIZE)
+    width = game_state.level_width
+    height = game_state.level_height
+    tile = pos_to_grid(agent_state.pos.x, agent_state.pos.y, width, CELL_SIZE)
     x, y = tile
-    walls = game_state.walls
     actions = [
         7,
         3,
@@ -75,10 +75,10 @@ def explore_policy(game_state: GameState, is_pursuer: bool) -> int:
         5
     ]
     mask = [
-        x > 0 and not walls[(x - 1) + y * level_size],
-        x < level_size - 1 and not walls[(x + 1) + y * level_size],
-        y < level_size - 1 and not walls[(x + (y + 1) * level_size)],
-        y > 0 and not walls[x + (y - 1) * level_size],
+        x > 0 and game_state.can_move(tile, (x - 1, y)),
+        x < width - 1 and game_state.can_move(tile, (x + 1, y)),
+        y < height - 1 and game_state.can_move(tile, (x, y + 1)),
+        y > 0 and game_state.can_move(tile, (x, y - 1)),
     ]
     valid_actions = []
     for action, mask_val in zip(actions, mask):
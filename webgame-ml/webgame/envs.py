@@ -7,6 +7,7 @@ from tqdm import tqdm
 from webgame_rust import AgentState, GameWrapper, GameState
 import numpy as np
 import functools
+import copy
 
 from webgame.common import process_obs
 from webgame.filter import BayesFilter
@@ -14,11 +15,39 @@ from webgame.filter import BayesFilter
 # The maximum number of object vectors supported by the environment.
 MAX_OBJS = 16
 # The dimension of each object vector.
-OBJ_DIM = 8
+OBJ_DIM = 9
 
 # The world space size of a grid cell
 CELL_SIZE = 25
 
+# The number of movement actions, see the `AgentAction` enum.
+NUM_MOVES = 10
+
+
+class RewardStats:
+    """
+    Running mean and variance of each agent's rewards, used to normalize them.
+    """
+
+    def __init__(self, agents: List[str]):
+        self.count = {agent: 0 for agent in agents}
+        self.mean = {agent: 0.0 for agent in agents}
+        self.m2 = {agent: 0.0 for agent in agents}
+
+    def update(self, agent: str, reward: float):
+        self.count[agent] += 1
+        delta = reward - self.mean[agent]
+        self.mean[agent] += delta / self.count[agent]
+        self.m2[agent] += delta * (reward - self.mean[agent])
+
+    def std(self, agent: str) -> float:
+        if self.count[agent] < 2:
+            return 1.0
+        return float(np.sqrt(self.m2[agent] / self.count[agent]))
+
+    def normalize(self, agent: str, reward: float) -> float:
+        return reward / (self.std(agent) + 1e-8)
+
 
 class GameEnv(pettingzoo.ParallelEnv):
     """
@@ -35,14 +64,49 @@ class GameEnv(pettingzoo.ParallelEnv):
         4: 1 if the other agent is visible, 0 if not
         5: If the other agent is visible, the other agent's x coordinate divided by map size
         6: If the other agent is visible, the other agent's y coordinate divided by map size
+        7: If `use_gadgets` is set, how much gadget energy this agent has, divided by the most it can store
+        8-11: If `use_gadgets` is set, 1 for the quadrant the player is in if this agent pinged this step (see
+            `AgentState.ping_quadrant`), 0 otherwise
+
+        If `use_radio` is set, three more items follow: 1 if a teammate has radioed in a sighting of the other agent
+        (see `AgentState.radio_alert`), and where it was seen, normalized like the other agent's coordinates.
 
         , the second item is a 2D map showing where walls are, the third item is a list of items detected by the agent,
-        and the fourth item is an attention mask for the previous item.
+        and the fourth item is an attention mask for the previous item. If `use_topology` is set, the 2D map has an
+        extra channel with each cell's topology (see `GameState`) divided by 4. If `use_terrain` is set, the 2D map has
+        an extra channel with each cell's terrain (see `GameState`) divided by 3. If `use_lighting` is set, the 2D map
+        has an extra channel with each cell's illumination (see `GameState`). If `use_staleness` is set, the 2D map has
+        an extra channel with how long ago the agent last saw each cell (see `AgentState`). If `use_team_visibility` is
+        set, the pursuer's 2D map has an extra channel with the cells any pursuer or fixed camera can see (the player's
+        is left empty). If `camera_size` is set, a fifth item is added: an egocentric RGB image from the agent's point
+        of view, with shape (3, camera_size, camera_size). If `use_game_belief` is set, the 2D map's second channel is
+        the agent's belief about where the other agent is, as tracked in the game (see `AgentState`), instead of the
+        output of `filters`.
 
-    Action Space: Discrete, check the `AgentAction` enum for a complete list.
+    Action Space: Discrete, check the `AgentAction` enum for a complete list. If `use_gadgets` is set, the pursuer has
+        another copy of every action that also sprints, and a third that also pings (see `GameWrapper.step`).
 
     Args:
         visualize: If we should log visuals to Rerun.
+        use_topology: If the grid observation should include cell topology.
+        use_terrain: If the grid observation should include cell terrain.
+        use_lighting: If the grid observation should include cell illumination.
+        use_staleness: If the grid observation should include how long ago each cell was seen.
+        use_team_visibility: If the grid observation should include the cells the pursuer's team can see.
+        hearing_bearing_noise: The most, in degrees, that the bearing to a heard noise can be off by.
+        use_awareness: If the pursuer should only see where the player is once it's alert. While suspicious, it only
+            learns that the player is nearby.
+        use_game_belief: If the grid observation's belief channel should come from the game's own filters, which both
+            agents have, instead of `filters`.
+        camera_size: If set, the width and height of camera images added to observations.
+        merge_camera_sightings: If objects seen by fixed cameras, and where they last saw the player, should be added to
+            the pursuer's observations.
+        level_width: How many cells wide levels are.
+        level_height: How many cells tall levels are.
+        use_gadgets: If the pursuer can use gadgets, and observations should include gadget energy and ping results.
+        use_radio: If observations should include the latest sighting radioed in by a teammate, such as a fixed camera.
+        normalize_rewards: If rewards should be divided by their running standard deviation. Environments created by
+            `fork` start from a copy of the statistics, and update them separately.
     """
 
     def __init__(
@@ -60,14 +124,39 @@ class GameEnv(pettingzoo.ParallelEnv):
                     GameState,
                     AgentState,
                     int,
+                    int,
                     float,
                     bool,
                 ],
                 np.ndarray,
             ]
         ] = None,
+        use_topology: bool = False,
+        use_terrain: bool = False,
+        camera_size: Optional[int] = None,
+        merge_camera_sightings: bool = True,
+        use_lighting: bool = False,
+        use_staleness: bool = False,
+        use_team_visibility: bool = False,
+        hearing_bearing_noise: float = 0.0,
+        use_awareness: bool = False,
+        use_game_belief: bool = False,
+        level_width: int = 8,
+        level_height: int = 8,
+        use_gadgets: bool = False,
+        use_radio: bool = False,
+        normalize_rewards: bool = False,
     ):
-        self.game = GameWrapper(use_objs, wall_prob, visualize, recording_id)
+        self.game = GameWrapper(
+            use_objs=use_objs,
+            wall_prob=wall_prob,
+            visualize=visualize,
+            recording_id=recording_id,
+            camera_size=camera_size,
+            hearing_bearing_noise=hearing_bearing_noise,
+            level_width=level_width,
+            level_height=level_height,
+        )
         self.game_state: Optional[GameState] = None
         self.possible_agents = ["player", "pursuer"]
         self.agents = self.possible_agents[:]
@@ -75,7 +164,35 @@ class GameEnv(pettingzoo.ParallelEnv):
         self.max_timer = max_timer
         self.use_objs = use_objs
         self.update_fn = update_fn
+        self.use_topology = use_topology
+        self.use_terrain = use_terrain
+        self.camera_size = camera_size
+        self.merge_camera_sightings = merge_camera_sightings
+        self.use_lighting = use_lighting
+        self.use_staleness = use_staleness
+        self.use_team_visibility = use_team_visibility
+        self.use_awareness = use_awareness
+        self.use_game_belief = use_game_belief
+        self.use_gadgets = use_gadgets
+        self.use_radio = use_radio
         self.filters: Optional[Dict[str, BayesFilter]] = None
+        self.reward_stats = RewardStats(self.possible_agents) if normalize_rewards else None
+
+    def fork(self) -> "GameEnv":
+        """
+        Creates a second environment with the same configuration, level sequence, and reward statistics so far, but
+        with independent game state. See `GameWrapper.fork`. Rewards in one environment don't affect how the other's
+        are normalized.
+
+        Useful for comparing reward shaping on matched levels. The fork starts on the current level, so call `reset`
+        on both environments together afterwards.
+        """
+        fork = copy.copy(self)
+        fork.game = self.game.fork()
+        fork.game_state = None
+        fork.filters = None
+        fork.reward_stats = copy.deepcopy(self.reward_stats)
+        return fork
 
     def step(self, actions: Mapping[str, int]) -> tuple[
         Mapping[str, tuple[np.ndarray, np.ndarray, np.ndarray, np.ndarray]],
@@ -84,10 +201,15 @@ class GameEnv(pettingzoo.ParallelEnv):
         Mapping[str, bool],
         Mapping[str, None],
     ]:
-        all_actions = []
-        for agent in ["player", "pursuer"]:
-            all_actions.append(actions[agent])
-        self.game_state = self.game.step(all_actions[0], all_actions[1])
+        pursuer_move = actions["pursuer"] % NUM_MOVES
+        pursuer_sprint = actions["pursuer"] // NUM_MOVES == 1
+        pursuer_ping = actions["pursuer"] // NUM_MOVES == 2
+        self.game_state = self.game.step(
+            actions["player"],
+            pursuer_move,
+            pursuer_sprint=pursuer_sprint,
+            pursuer_ping=pursuer_ping,
+        )
         assert self.game_state
         obs = self.game_state_to_obs(self.game_state)
 
@@ -97,16 +219,24 @@ class GameEnv(pettingzoo.ParallelEnv):
         )[0]
         seen_player = player_e in self.game_state.pursuer.observing
 
+        # The player wins if it escapes through the door, and the pursuer wins if it catches the player
+        escaped = self.game_state.player_escaped
+        caught = self.game_state.player_caught
+
         self.timer += 1
-        trunc = self.timer == self.max_timer
+        trunc = self.timer == self.episode_max_timer()
 
         rewards = {
-            "player": -float(seen_player),
-            "pursuer": float(seen_player),
+            "player": float(escaped) - float(seen_player) - float(caught),
+            "pursuer": float(seen_player) - float(escaped) + float(caught),
         }
+        if self.reward_stats:
+            for agent, reward in rewards.items():
+                self.reward_stats.update(agent, reward)
+                rewards[agent] = self.reward_stats.normalize(agent, reward)
         dones = {
-            "player": False,
-            "pursuer": False,
+            "player": escaped or caught,
+            "pursuer": escaped or caught,
         }
         truncs = {
             "player": trunc,
@@ -128,7 +258,8 @@ class GameEnv(pettingzoo.ParallelEnv):
         if self.update_fn:
             self.filters = {
                 agent: BayesFilter(
-                    self.game_state.level_size,
+                    self.game_state.level_width,
+                    self.game_state.level_height,
                     CELL_SIZE,
                     self.update_fn,
                     self.use_objs,
@@ -143,6 +274,14 @@ class GameEnv(pettingzoo.ParallelEnv):
         }
         return (obs, infos)
 
+    def episode_max_timer(self) -> Optional[int]:
+        """
+        Returns how many steps the current episode lasts. `max_timer` takes priority over the level's `max_steps`.
+        """
+        if self.max_timer is not None or self.game_state is None:
+            return self.max_timer
+        return self.game_state.meta.max_steps
+
     def game_state_to_obs(
         self,
         game_state: GameState,
@@ -156,19 +295,46 @@ class GameEnv(pettingzoo.ParallelEnv):
         }
 
     @functools.lru_cache(maxsize=None)
-    def action_space(self, _agent: str) -> gym.Space:
-        return gym.spaces.Discrete(10)
+    def action_space(self, agent: str) -> gym.Space:
+        if self.use_gadgets and agent == "pursuer":
+            return gym.spaces.Discrete(NUM_MOVES * 3)
+        return gym.spaces.Discrete(NUM_MOVES)
+
+    def scalar_obs_size(self) -> int:
+        """
+        Returns the size of the vector at the start of each observation.
+        """
+        return 7 + 5 * int(self.use_gadgets) + 3 * int(self.use_radio)
 
     @functools.lru_cache(maxsize=None)
     def observation_space(self, _: str) -> gym.Space:
-        return gym.spaces.Tuple(
-            (
-                gym.spaces.Box(0, 1, (7,)),
-                gym.spaces.Box(0, 1, (2, 8, 8)),
-                gym.spaces.Box(0, 1, (MAX_OBJS, OBJ_DIM)),
-                gym.spaces.Box(0, 1, (MAX_OBJS,)),
+        level_w, level_h = self.game.level_size()
+        spaces = [
+            gym.spaces.Box(0, 1, (self.scalar_obs_size(),)),
+            gym.spaces.Box(
+                0,
+                1,
+                (
+                    2
+                    + int(self.use_topology)
+                    + int(self.use_terrain)
+                    + int(self.use_lighting)
+                    + int(self.use_staleness)
+                    + int(self.use_team_visibility),
+                    level_h,
+                    level_w,
+                ),
+            ),
+            gym.spaces.Box(0, 1, (MAX_OBJS, OBJ_DIM)),
+            gym.spaces.Box(0, 1, (MAX_OBJS,)),
+        ]
+        if self.camera_size:
+            spaces.append(
+                gym.spaces.Box(
+                    0, 255, (3, self.camera_size, self.camera_size), dtype=np.uint8
+                )
             )
-        )
+        return gym.spaces.Tuple(spaces)
 
     def agent_state_to_obs(
         self, agent_state: AgentState, game_state: GameState, is_pursuer: bool
@@ -176,9 +342,17 @@ class GameEnv(pettingzoo.ParallelEnv):
         """
         Generates observations for an agent.
         """
-        obs_vec = np.zeros([7], dtype=float)
-        obs_vec[0] = 0.5 + agent_state.pos.x / (game_state.level_size * CELL_SIZE)
-        obs_vec[1] = 0.5 + agent_state.pos.y / (game_state.level_size * CELL_SIZE)
+        observing = agent_state.observing
+        if self.merge_camera_sightings:
+            observing = observing + [
+                e for e in agent_state.camera_observing if e not in observing
+            ]
+
+        obs_vec = np.zeros([self.scalar_obs_size()], dtype=float)
+        level_w = game_state.level_width * CELL_SIZE
+        level_h = game_state.level_height * CELL_SIZE
+        obs_vec[0] = 0.5 + agent_state.pos.x / level_w
+        obs_vec[1] = 0.5 + agent_state.pos.y / level_h
         obs_vec[2] = agent_state.dir.x
         obs_vec[3] = agent_state.dir.y
 
@@ -186,47 +360,70 @@ class GameEnv(pettingzoo.ParallelEnv):
         other_e, other_obs = list(
             filter(lambda t: t[1].obj_type == other_agent, game_state.objects.items())
         )[0]
-        if other_e in agent_state.observing:
+        awareness_state = agent_state.awareness_state if self.use_awareness else None
+        if awareness_state == "suspicious":
             obs_vec[4] = 1
-            obs_vec[5] = 0.5 + other_obs.pos.x / (game_state.level_size * CELL_SIZE)
-            obs_vec[6] = 0.5 + other_obs.pos.y / (game_state.level_size * CELL_SIZE)
+        elif other_e in observing and awareness_state in [None, "alert"]:
+            obs_vec[4] = 1
+            obs_vec[5] = 0.5 + other_obs.pos.x / level_w
+            obs_vec[6] = 0.5 + other_obs.pos.y / level_h
+        if self.use_gadgets and agent_state.gadget_energy is not None:
+            obs_vec[7] = agent_state.gadget_energy / agent_state.max_gadget_energy
+        if self.use_gadgets and agent_state.ping_quadrant is not None:
+            obs_vec[8 + agent_state.ping_quadrant] = 1
+        radio_offset = 7 + 5 * int(self.use_gadgets)
+        if self.use_radio and agent_state.radio_alert is not None:
+            obs_vec[radio_offset] = 1
+            obs_vec[radio_offset + 1] = 0.5 + agent_state.radio_alert.x / level_w
+            obs_vec[radio_offset + 2] = 0.5 + agent_state.radio_alert.y / level_h
 
         walls = np.array(game_state.walls, dtype=float).reshape(
-            (game_state.level_size, game_state.level_size)
+            (game_state.level_height, game_state.level_width)
         )
 
         obs_vecs = np.zeros([MAX_OBJS, OBJ_DIM], dtype=float)
-        for i, e in enumerate(agent_state.observing):
+        for i, e in enumerate(observing):
             if e in agent_state.vm_data:
                 obs_obj = game_state.objects[e]
                 obj_features = np.zeros([OBJ_DIM])
-                obj_features[0] = 0.5 + obs_obj.pos.x / (
-                    game_state.level_size * CELL_SIZE
-                )
-                obj_features[1] = 0.5 + obs_obj.pos.y / (
-                    game_state.level_size * CELL_SIZE
-                )
+                obj_features[0] = 0.5 + obs_obj.pos.x / level_w
+                obj_features[1] = 0.5 + obs_obj.pos.y / level_h
                 obj_features[2] = 1
                 vm_data = agent_state.vm_data[e]
                 obj_features[5] = vm_data.last_seen_elapsed / 10.0
                 obj_features[6] = obs_obj.pos.x - vm_data.last_pos.x
                 obj_features[7] = obs_obj.pos.y - vm_data.last_pos.y
                 obs_vecs[i] = obj_features
-        for i, e in enumerate(agent_state.listening):
-            obj_noise = game_state.noise_sources[e]
+        # Heard noises only give a rough bearing and how loud they are, not where they came from
+        for i, (e, loudness) in enumerate(agent_state.listening.items()):
+            bearing = agent_state.noise_bearings[e]
             obj_features = np.zeros([OBJ_DIM])
-            obj_features[0] = obj_noise.pos.x / (game_state.level_size * CELL_SIZE)
-            obj_features[1] = obj_noise.pos.y / (game_state.level_size * CELL_SIZE)
+            obj_features[0] = np.cos(bearing)
+            obj_features[1] = np.sin(bearing)
             obj_features[3] = 1
-            obj_features[4] = obj_noise.active_radius
-            obs_vecs[i + len(agent_state.observing)] = obj_features
+            obj_features[4] = loudness
+            obs_vecs[i + len(observing)] = obj_features
+        # Camera sightings are where the player was last seen, which may be out of date
+        sightings = agent_state.camera_sightings if self.merge_camera_sightings else []
+        obj_offset = len(observing) + len(agent_state.listening)
+        # Only as many sightings as there's room left for are kept
+        sightings = sightings[: max(MAX_OBJS - obj_offset, 0)]
+        for i, (_, pos, elapsed) in enumerate(sightings):
+            obj_features = np.zeros([OBJ_DIM])
+            obj_features[0] = 0.5 + pos.x / level_w
+            obj_features[1] = 0.5 + pos.y / level_h
+            obj_features[5] = elapsed / 10.0
+            obj_features[8] = 1
+            obs_vecs[i + obj_offset] = obj_features
 
         attn_mask = np.zeros([MAX_OBJS])
-        attn_mask[len(agent_state.observing) + len(agent_state.listening) :] = 1
+        attn_mask[min(obj_offset + len(sightings), MAX_OBJS) :] = 1
 
         agent_name = ["player", "pursuer"][int(is_pursuer)]
         filter_probs = np.zeros(walls.shape, dtype=float)
-        if self.filters:
+        if self.use_game_belief and agent_state.belief is not None:
+            filter_probs = np.array(agent_state.belief, dtype=float).reshape(walls.shape)
+        elif self.filters:
             filter_probs = self.filters[agent_name].localize(
                 process_obs(
                     (
@@ -239,8 +436,33 @@ class GameEnv(pettingzoo.ParallelEnv):
                 game_state,
                 agent_state,
             )
-        grid = np.stack([walls, filter_probs])
+        grid_channels = [walls, filter_probs]
+        if self.use_topology:
+            topology = np.array(game_state.cell_topology, dtype=float).reshape(walls.shape)
+            grid_channels.append(topology / 4.0)
+        if self.use_terrain:
+            terrain = np.array(game_state.terrain, dtype=float).reshape(walls.shape)
+            grid_channels.append(terrain / 3.0)
+        if self.use_lighting:
+            illumination = np.array(game_state.illumination, dtype=float).reshape(walls.shape)
+            grid_channels.append(illumination)
+        if self.use_staleness:
+            staleness = np.array(agent_state.staleness, dtype=float).reshape(walls.shape)
+            grid_channels.append(staleness)
+        if self.use_team_visibility:
+            team_visible = np.zeros(walls.shape, dtype=float)
+            if is_pursuer:
+                team_visible = np.array(game_state.team_visible_cells, dtype=float).reshape(
+                    walls.shape
+                )
+            grid_channels.append(team_visible)
+        grid = np.stack(grid_channels)
 
+        if self.camera_size:
+            camera = np.array(agent_state.camera, dtype=np.uint8).reshape(
+                self.camera_size, self.camera_size, 3
+            )
+            return (obs_vec, grid, obs_vecs, attn_mask, camera.transpose(2, 0, 1))
         return (obs_vec, grid, obs_vecs, attn_mask)
 
 
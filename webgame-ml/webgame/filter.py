@@ -21,7 +21,8 @@ class BayesFilter:
 
     def __init__(
         self,
-        size: int,
+        width: int,
+        height: int,
         cell_size: float,
         update_fn: Callable[
             [
@@ -30,6 +31,7 @@ class BayesFilter:
                 GameState,
                 AgentState,
                 int,
+                int,
                 float,
                 bool,
             ],
@@ -38,9 +40,10 @@ class BayesFilter:
         use_objs: bool,
         is_pursuer: bool,
     ):
-        self.size = size
+        self.width = width
+        self.height = height
         self.cell_size = cell_size
-        self.belief = np.ones([size, size]) / size**2
+        self.belief = np.ones([height, width]) / (width * height)
         self.update_fn = update_fn
         self.use_objs = use_objs
         self.is_pursuer = is_pursuer
@@ -60,11 +63,16 @@ class BayesFilter:
             self.use_objs,
             game_state,
             agent_state,
-            self.size,
+            self.width,
+            self.height,
             self.cell_size,
             self.is_pursuer,
         )
         self.belief = lkhd * self.belief
+        if agent_state.ping_quadrant is not None:
+            self.belief = self.belief * ping_lkhd(
+                agent_state.ping_quadrant, self.width, self.height
+            )
         self.belief = self.belief / self.belief.sum()
         return self.belief
 
@@ -75,12 +83,25 @@ class BayesFilter:
         return belief
 
 
+def ping_lkhd(quadrant: int, width: int, height: int) -> np.ndarray:
+    """
+    Returns the likelihood of the player being in each cell, given the quadrant revealed by a ping.
+    """
+    xs = np.arange(width)[np.newaxis, :]
+    ys = np.arange(height)[:, np.newaxis]
+    in_quadrant = ((xs * 2 >= width) == bool(quadrant & 1)) & (
+        (ys * 2 >= height) == bool(quadrant & 2)
+    )
+    return np.where(in_quadrant, 1.0, 0.01)
+
+
 def manual_update(
     obs: Tuple[np.ndarray, np.ndarray, np.ndarray],
     use_objs: bool,
     game_state: GameState,
     agent_state: AgentState,
-    size: int,
+    width: int,
+    height: int,
     cell_size: float,
     is_pursuer: bool,
 ) -> np.ndarray:
@@ -92,14 +113,12 @@ def manual_update(
     )[0]
     player_vis_grid = None
     if other_e in agent_state.observing:
-        player_vis_grid = pos_to_grid(other_obs.pos.x, other_obs.pos.y, size, cell_size)
+        player_vis_grid = pos_to_grid(other_obs.pos.x, other_obs.pos.y, width, cell_size)
 
-    obs_grid = np.array(game_state.walls).reshape(
-        [game_state.level_size, game_state.level_size]
-    )
-    lkhd = np.zeros([size, size])
-    for y in range(size):
-        for x in range(size):
+    obs_grid = np.array(game_state.walls).reshape([height, width])
+    lkhd = np.zeros([height, width])
+    for y in range(height):
+        for x in range(width):
             grid_lkhd = 1 - obs_grid[y][x]
             agent_lkhd = 1.0
             if player_vis_grid is not None:
@@ -108,11 +127,11 @@ def manual_update(
             else:
                 # Cells within vision have 0% chance of agent being there
                 agent_lkhd = 1 - int(
-                    agent_state.visible_cells[y * game_state.level_size + x]
+                    agent_state.visible_cells[y * width + x]
                 )
                 # All other cells are equally probable
                 agent_lkhd = agent_lkhd * (
-                    1.0 / (size**2 - sum(agent_state.visible_cells))
+                    1.0 / (width * height - sum(agent_state.visible_cells))
                 )
             lkhd[y][x] = grid_lkhd * agent_lkhd
     return lkhd
@@ -127,6 +146,7 @@ def model_update(
         GameState,
         AgentState,
         int,
+        int,
         float,
         bool,
     ],
@@ -138,7 +158,8 @@ def model_update(
         use_objs: bool,
         game_state: GameState,
         agent_state: AgentState,
-        size: int,
+        width: int,
+        height: int,
         cell_size: float,
         is_pursuer: bool,
     ) -> np.ndarray:
@@ -161,13 +182,14 @@ def gt_update(
     use_objs: bool,
     game_state: GameState,
     agent_state: AgentState,
-    size: int,
+    width: int,
+    height: int,
     cell_size: float,
     is_pursuer: bool,
 ) -> np.ndarray:
     player_pos = game_state.player.pos
-    grid_pos = pos_to_grid(player_pos.x, player_pos.y, game_state.level_size, CELL_SIZE)
-    lkhd = np.zeros([size, size])
+    grid_pos = pos_to_grid(player_pos.x, player_pos.y, width, cell_size)
+    lkhd = np.zeros([height, width])
     lkhd[grid_pos[1], grid_pos[0]] = 1
     kernel = np.array([[0.1, 0.1, 0.1], [0.1, 1, 0.1], [0.1, 0.1, 0.1]])
     lkhd = signal.convolve2d(lkhd, kernel, mode="same")
@@ -206,7 +228,7 @@ if __name__ == "__main__":
         use_objs: bool,
     ) -> Callable[[str, GameEnv, Tuple[torch.Tensor, torch.Tensor, torch.Tensor]], int]:
         p_net = PolicyNet(
-            9, 8, action_count, use_pos, (MAX_OBJS, OBJ_DIM) if use_objs else None
+            9, 8, 8, action_count, use_pos, (MAX_OBJS, OBJ_DIM) if use_objs else None
         )
         load_model(p_net, chkpt_path)
 
@@ -250,14 +272,23 @@ if __name__ == "__main__":
 
     # Set up filter
     if args.checkpoint:
-        model = MeasureModel(9, env.game_state.level_size, args.use_pos)
+        model = MeasureModel(
+            9, env.game_state.level_width, env.game_state.level_height, args.use_pos
+        )
         load_model(model, args.checkpoint)
         update_fn = model_update(model)
     elif args.use_gt:
         update_fn = gt_update
     else:
         update_fn = manual_update
-    b_filter = BayesFilter(env.game_state.level_size, CELL_SIZE, update_fn, False, True)
+    b_filter = BayesFilter(
+        env.game_state.level_width,
+        env.game_state.level_height,
+        CELL_SIZE,
+        update_fn,
+        False,
+        True,
+    )
 
     # Set up policies
     policies = {}
@@ -289,7 +320,8 @@ if __name__ == "__main__":
             False,
             game_state,
             agent_state,
-            game_state.level_size,
+            game_state.level_width,
+            game_state.level_height,
             CELL_SIZE,
             True,
         )
@@ -53,7 +53,7 @@ def main() -> None:
             processed_obs = process_obs(pursuer_obs)
             player_pos = env.game_state.player.pos
             gold_tile = pos_to_grid(
-                player_pos.x, player_pos.y, env.game_state.level_size, CELL_SIZE
+                player_pos.x, player_pos.y, env.game_state.level_width, CELL_SIZE
             )
             seq.append(processed_obs)
             tiles.append(gold_tile)
@@ -8,7 +8,8 @@ class Backbone(nn.Module):
         self,
         channels: int,
         out_channels: int,
-        size: int,
+        width: int,
+        height: int,
         use_pos: bool = False,
         objs_shape: Optional[Tuple[int, int]] = None,
         use_bn: bool = False,
@@ -46,11 +47,11 @@ class Backbone(nn.Module):
         )
 
         # Positional encoding
-        x_channel = torch.tensor([list(range(size))] * size, dtype=torch.float) / size
-        y_channel = x_channel.T
-        self.pos = torch.stack(
-            [x_channel, y_channel]
-        )  # Shape: (2, grid_size, grid_size)
+        x_channel = torch.tensor([list(range(width))] * height, dtype=torch.float) / width
+        y_channel = (
+            torch.tensor([list(range(height))] * width, dtype=torch.float).T / height
+        )
+        self.pos = torch.stack([x_channel, y_channel])  # Shape: (2, height, width)
         self.pos.requires_grad = False
         self.use_pos = use_pos
         self.use_bn = use_bn
@@ -76,10 +77,10 @@ class Backbone(nn.Module):
 
     def forward(
         self,
-        grid: Tensor,  # Shape: (batch_size, channels, size, size)
+        grid: Tensor,  # Shape: (batch_size, channels, height, width)
         objs: Optional[Tensor],  # Shape: (batch_size, max_obj_size, obj_dim)
         objs_attn_mask: Optional[Tensor],  # Shape: (batch_size, max_obj_size)
-    ) -> Tensor:  # Shape: (batch_size, grid_features_dim, grid_size, grid_size)
+    ) -> Tensor:  # Shape: (batch_size, grid_features_dim, height, width)
         # Concat pos encoding to grid
         device = grid.device
         if self.use_pos:
@@ -94,7 +95,7 @@ class Backbone(nn.Module):
             )
         grid_features = self.grid_net(
             grid
-        )  # Shape: (batch_size, grid_features_dim, grid_size, grid_size)
+        )  # Shape: (batch_size, grid_features_dim, height, width)
 
         if self.use_objs:
             assert objs is not None
@@ -104,11 +105,11 @@ class Backbone(nn.Module):
             )  # Shape: (batch_size, max_obj_size, proj_dim)
             grid_features = grid_features.permute(
                 0, 2, 3, 1
-            )  # Shape: (batch_size, grid_size, grid_size, grid_features_dim)
+            )  # Shape: (batch_size, height, width, grid_features_dim)
             orig_shape = grid_features.shape
             grid_features = grid_features.flatten(
                 1, 2
-            )  # Shape: (batch_size, grid_size * grid_size, grid_features_dim)
+            )  # Shape: (batch_size, height * width, grid_features_dim)
             attns = [self.attn1, self.attn2, self.attn3]
             bns = [self.bn1, self.bn2, self.bn3]
             for attn, bn in zip(attns, bns):
@@ -127,7 +128,7 @@ class Backbone(nn.Module):
                 grid_features = nn.functional.silu(grid_features)
             grid_features = grid_features.view(orig_shape).permute(
                 0, 3, 1, 2
-            )  # Shape: (batch_size, grid_features_dim, grid_size, grid_size)
+            )  # Shape: (batch_size, grid_features_dim, height, width)
 
         return grid_features
 
@@ -136,13 +137,16 @@ class MeasureModel(nn.Module):
     def __init__(
         self,
         channels: int,
-        size: int,
+        width: int,
+        height: int,
         use_pos: bool = False,
         objs_shape: Optional[Tuple[int, int]] = None,
     ):
         super().__init__()
         proj_dim = 32
-        self.backbone = Backbone(channels, proj_dim, size, use_pos, objs_shape, True)
+        self.backbone = Backbone(
+            channels, proj_dim, width, height, use_pos, objs_shape, True
+        )
 
         # Convert features into liklihood map
         self.out_net = nn.Sequential(
@@ -158,7 +162,7 @@ class MeasureModel(nn.Module):
 
     def forward(
         self,
-        grid: Tensor,  # Shape: (batch_size, channels, size, size)
+        grid: Tensor,  # Shape: (batch_size, channels, height, width)
         objs: Optional[Tensor],  # Shape: (batch_size, max_obj_size, obj_dim)
         objs_attn_mask: Optional[Tensor],  # Shape: (batch_size, max_obj_size)
     ) -> Tensor:
@@ -166,27 +170,28 @@ class MeasureModel(nn.Module):
 
         return self.out_net(grid_features).squeeze(
             1
-        )  # Shape: (batch_size, grid_size, grid_size)
+        )  # Shape: (batch_size, height, width)
 
 class PolicyNet(nn.Module):
     def __init__(
         self,
         channels: int,
-        size: int,
+        width: int,
+        height: int,
         action_count: int,
         use_pos: bool = False,
         objs_shape: Optional[Tuple[int, int]] = None,
     ):
         super().__init__()
         proj_dim = 32
-        self.backbone = Backbone(channels, proj_dim, size, use_pos, objs_shape)
+        self.backbone = Backbone(channels, proj_dim, width, height, use_pos, objs_shape)
         self.net = nn.Sequential(
             nn.Conv2d(proj_dim, 32, 3, padding="same", dtype=torch.float),
             nn.SiLU(),
             nn.Conv2d(32, 16, 3, padding="same", dtype=torch.float),
             nn.SiLU(),
             nn.Flatten(),
-            nn.Linear(size**2 * 16, 256),
+            nn.Linear(width * height * 16, 256),
             nn.SiLU(),
             nn.Linear(256, 256),
             nn.SiLU(),
@@ -195,10 +200,53 @@ class PolicyNet(nn.Module):
 
     def forward(
         self,
-        grid: Tensor,  # Shape: (batch_size, channels, size, size)
+        grid: Tensor,  # Shape: (batch_size, channels, height, width)
         objs: Optional[Tensor],  # Shape: (batch_size, max_obj_size, obj_dim)
         objs_attn_mask: Optional[Tensor],  # Shape: (batch_size, max_obj_size)
     ) -> Tensor:
         features = self.backbone(grid, objs, objs_attn_mask)
         values = self.net(features)
         return values
+
+class RecurrentPolicyNet(nn.Module):
+    """
+    A `PolicyNet` with a GRU or LSTM layer before its output, so it can remember what it saw on earlier steps.
+    """
+
+    def __init__(
+        self,
+        channels: int,
+        width: int,
+        height: int,
+        action_count: int,
+        cell: str = "gru",
+        use_pos: bool = False,
+        objs_shape: Optional[Tuple[int, int]] = None,
+    ):
+        super().__init__()
+        proj_dim = 32
+        hidden_dim = 256
+        self.backbone = Backbone(channels, proj_dim, width, height, use_pos, objs_shape)
+        self.net = nn.Sequential(
+            nn.Conv2d(proj_dim, 32, 3, padding="same", dtype=torch.float),
+            nn.SiLU(),
+            nn.Conv2d(32, 16, 3, padding="same", dtype=torch.float),
+            nn.SiLU(),
+            nn.Flatten(),
+            nn.Linear(width * height * 16, hidden_dim),
+            nn.SiLU(),
+        )
+        rnn_types = {"gru": nn.GRU, "lstm": nn.LSTM}
+        self.rnn = rnn_types[cell](hidden_dim, hidden_dim, batch_first=True)
+        self.out = nn.Linear(hidden_dim, action_count)
+
+    def forward(
+        self,
+        grid: Tensor,  # Shape: (batch_size, channels, height, width)
+        objs: Optional[Tensor],  # Shape: (batch_size, max_obj_size, obj_dim)
+        objs_attn_mask: Optional[Tensor],  # Shape: (batch_size, max_obj_size)
+        hidden: Optional[Any] = None,  # The hidden state returned by the last step, or None to start from zeros
+    ) -> Tuple[Tensor, Any]:
+        features = self.net(self.backbone(grid, objs, objs_attn_mask))
+        features, hidden = self.rnn(features.unsqueeze(1), hidden)
+        return self.out(features.squeeze(1)), hidden
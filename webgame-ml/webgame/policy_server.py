@@ -0,0 +1,52 @@
+"""
+Serves a policy checkpoint to the game over TCP, so agents can be controlled by models that don't run in-process.
+
+Point the game at this server with a `.remote` file (see `RemotePolicy` in `webgame-game/src/remote_policy.rs`), e.g.
+`{"addr": "127.0.0.1:9100"}`. Each tick, the game sends a line of JSON with the agent's observation, and this replies
+with a line of JSON containing the probability of each action.
+"""
+
+from argparse import ArgumentParser
+import json
+import socketserver
+
+import torch
+from safetensors.torch import load_model
+
+from webgame.models import PolicyNet
+
+ACTION_COUNT = 10
+
+
+def main() -> None:
+    parser = ArgumentParser()
+    parser.add_argument("checkpoint", type=str)
+    parser.add_argument("--host", type=str, default="127.0.0.1")
+    parser.add_argument("--port", type=int, default=9100)
+    parser.add_argument("--width", type=int, default=8)
+    parser.add_argument("--height", type=int, default=8)
+    args = parser.parse_args()
+
+    p_net = PolicyNet(9, args.width, args.height, ACTION_COUNT)
+    load_model(p_net, args.checkpoint)
+    p_net.eval()
+
+    class PolicyHandler(socketserver.StreamRequestHandler):
+        def handle(self) -> None:
+            for line in self.rfile:
+                obs = json.loads(line)
+                grid = torch.tensor(obs["grid"], dtype=torch.float).reshape(
+                    1, obs["channels"], obs["height"], obs["width"]
+                )
+                with torch.no_grad():
+                    probs = torch.softmax(p_net(grid, None, None).squeeze(0), 0)
+                reply = json.dumps({"probs": probs.tolist()}) + "\n"
+                self.wfile.write(reply.encode())
+
+    with socketserver.ThreadingTCPServer((args.host, args.port), PolicyHandler) as server:
+        print(f"Serving {args.checkpoint} on {args.host}:{args.port}")
+        server.serve_forever()
+
+
+if __name__ == "__main__":
+    main()
@@ -63,20 +63,21 @@ class ValueNet(nn.Module):
     def __init__(
         self,
         channels: int,
-        size: int,
+        width: int,
+        height: int,
         use_pos: bool = False,
         objs_shape: Optional[Tuple[int, int]] = None,
     ):
         super().__init__()
         proj_dim = 32
-        self.backbone = Backbone(channels, proj_dim, size, use_pos, objs_shape)
+        self.backbone = Backbone(channels, proj_dim, width, height, use_pos, objs_shape)
         self.net = nn.Sequential(
             nn.Conv2d(proj_dim, 32, 3, padding="same", dtype=torch.float),
             nn.SiLU(),
             nn.Conv2d(32, 16, 3, padding="same", dtype=torch.float),
             nn.SiLU(),
             nn.Flatten(),
-            nn.Linear(size**2 * 16, 256),
+            nn.Linear(width * height * 16, 256),
             nn.SiLU(),
             nn.Linear(256, 256),
             nn.SiLU(),
@@ -85,7 +86,7 @@ class ValueNet(nn.Module):
 
     def forward(
         self,
-        grid: Tensor,  # Shape: (batch_size, channels, size, size)
+        grid: Tensor,  # Shape: (batch_size, channels, height, width)
         objs: Optional[Tensor],  # Shape: (batch_size, max_obj_size, obj_dim)
         objs_attn_mask: Optional[Tensor],  # Shape: (batch_size, max_obj_size)
     ) -> Tensor:
@@ -106,12 +107,14 @@ class AgentData:
         self.v_net = ValueNet(
             channels,
             grid_size,
+            grid_size,
             cfg.use_pos,
             (max_objs, obj_dim) if cfg.use_objs else None,
         )
         self.p_net = PolicyNet(
             channels,
             grid_size,
+            grid_size,
             act_count,
             cfg.use_pos,
             (max_objs, obj_dim) if cfg.use_objs else None,
@@ -120,7 +123,7 @@ class AgentData:
         self.p_opt = torch.optim.Adam(self.p_net.parameters(), lr=cfg.p_lr)
         self.buffer = RolloutBuffer(
             [
-                (torch.Size((channels, grid_size, grid_size)), torch.float),
+                (torch.Size((channels, height, width)), torch.float),
                 (torch.Size((max_objs, obj_dim)), torch.float),
                 (torch.Size((max_objs,)), torch.bool),
             ],
@@ -150,7 +153,7 @@ if __name__ == "__main__":
     if cfg.update_chkpt:
         assert cfg.update_fn == "model"
     if cfg.update_fn == "model":
-        m_model = MeasureModel(9, 8, cfg.use_pos)
+        m_model = MeasureModel(9, 8, 8, cfg.use_pos)
         load_model(m_model, cfg.update_chkpt)
         update_fn = model_update(m_model)
     elif cfg.update_fn == "manual":
@@ -99,6 +99,7 @@ def main() -> None:
     model = MeasureModel(
         channels,
         grid_size,
+        grid_size,
         args.use_pos,
         (max_objs, obj_dim) if args.use_objs else None,
     )
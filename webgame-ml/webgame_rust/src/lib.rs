@@ -1,16 +1,53 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, path::Path};
 
-use bevy::{app::AppExit, prelude::*};
+use bevy::{app::AppExit, ecs::system::RunSystemOnce, prelude::*};
 use num_enum::{IntoPrimitive, TryFromPrimitive};
-use pyo3::{exceptions::PyValueError, prelude::*};
+use pyo3::{
+    exceptions::{PyIOError, PyKeyError, PyRuntimeError, PyTypeError, PyValueError},
+    prelude::*,
+    types::{PyBytes, PyDict},
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use webgame_game::{
+    awareness::{Awareness, AwarenessConfig, AwarenessState},
+    comms::{Radio, RadioConfig},
     configs::{LibCfgPlugin, VisualizerPlugin},
+    difficulty::Difficulty,
+    filter::{
+        Belief, Beliefs, ExternalSighting, FilterBackend, FilterConfig, FilterRng, FilterSnapshot,
+        MotionModel, Resampling,
+    },
+    gadgets::{Gadget, GadgetConfig, GadgetEnergy, PingResult},
     gridworld::{
-        Agent, LevelLayout, NextAction, PlayerAgent, PursuerAgent, DEFAULT_LEVEL_SIZE,
+        Agent, GameId, GridTransform, LevelLayout, LevelMeta, LevelObject, LevelTopology,
+        LoadedLevelData, NextAction, PlayerAgent, PursuerAgent, SpawnRng, DEFAULT_LEVEL_SIZE,
         GRID_CELL_SIZE,
     },
-    observer::{Observable, Observer},
-    world_objs::NoiseSource,
+    level_diff::LevelPatch,
+    level_gen::{ObstacleParams, ObstacleShape, Symmetry},
+    level_mutation,
+    level_set::{LevelSampling, LevelSet},
+    lighting::Illumination,
+    net::{
+        ComputeDevice, LoadableNN, MeasureNet, NNWrapper, NetPrecision, PolicyNet, SafeTensorsData,
+        POLICY_CHANNELS,
+    },
+    observer::{
+        DetectionConfig, DetectionRng, EnteredView, ExitedView, MarkerConfig, Observable, Observer,
+        VisionConfig, VisionParams, DEFAULT_FOV_DEGREES,
+    },
+    pursuer_ai::ScriptedPursuerPlugin,
+    sensors::{add_camera_sensors, render_camera_sensors, CameraSensor, CameraSensorConfig},
+    spectator::{SpectatorPlugin, SpectatorServer},
+    thumbnail::{level_thumbnail_png, DEFAULT_THUMBNAIL_CELL_PIXELS},
+    visibility::{
+        agent_visible_cells, mesh_coverage, SeenCells, TeamVisibility, VisibilityBackend,
+        VISIBLE_COVERAGE,
+    },
+    world_objs::{
+        audible_noises, CameraFeed, ExitDoor, GameOutcome, HasKey, HearingConfig, InVent, Key,
+        LevelComplete, NoiseSource, Patrol,
+    },
 };
 
 /// Describes an observable object.
@@ -62,6 +99,46 @@ pub struct VMData {
     pub last_seen_elapsed: f32,
     #[pyo3(get)]
     pub last_pos: PyVec2,
+    /// How far the marker moved the last time the agent saw it move, or zero if that was too long ago.
+    #[pyo3(get)]
+    pub displacement: PyVec2,
+    /// How fast the marker was moving when `displacement` was seen, if velocities are estimated.
+    #[pyo3(get)]
+    pub velocity: PyVec2,
+}
+
+/// Descriptive information about the current level. See `LevelMeta`.
+#[pyclass]
+#[derive(Debug, Clone)]
+pub struct PyLevelMeta {
+    #[pyo3(get)]
+    pub name: Option<String>,
+    #[pyo3(get)]
+    pub author: Option<String>,
+    #[pyo3(get)]
+    pub difficulty: Option<u32>,
+    /// How many steps an episode on this level should last before it's truncated.
+    #[pyo3(get)]
+    pub max_steps: Option<u32>,
+    /// The asset path of the policy the pursuer should use on this level, if it picks its own.
+    #[pyo3(get)]
+    pub pursuer_policy: Option<String>,
+    /// The asset path of the policy the player should use on this level, if it picks its own.
+    #[pyo3(get)]
+    pub player_policy: Option<String>,
+}
+
+impl From<LevelMeta> for PyLevelMeta {
+    fn from(value: LevelMeta) -> Self {
+        Self {
+            name: value.name,
+            author: value.author,
+            difficulty: value.difficulty,
+            max_steps: value.max_steps,
+            pursuer_policy: value.pursuer_policy,
+            player_policy: value.player_policy,
+        }
+    }
 }
 
 /// Contains the state of an agent for a single frame.
@@ -74,12 +151,96 @@ pub struct AgentState {
     pub dir: PyVec2,
     #[pyo3(get)]
     pub observing: Vec<u64>,
+    /// Entities that fixed cameras can see, for agents that receive camera sightings. These aren't included in
+    /// `observing`, so they can be merged in or left out.
+    #[pyo3(get)]
+    pub camera_observing: Vec<u64>,
+    /// Where fixed cameras last saw each player and how many seconds ago, as `(id, pos, elapsed)`, for agents that
+    /// receive camera sightings. Sightings are kept after cameras lose sight of the player.
     #[pyo3(get)]
-    pub listening: Vec<u64>,
+    pub camera_sightings: Vec<(u64, PyVec2, f32)>,
+    /// How loud each noise source the agent can hear is, from 0 to 1.
+    #[pyo3(get)]
+    pub listening: HashMap<u64, f32>,
+    /// Roughly which direction each noise source the agent can hear is in, as an angle in radians counterclockwise
+    /// from `+X`. Off by up to the wrapper's `hearing_bearing_noise`.
+    #[pyo3(get)]
+    pub noise_bearings: HashMap<u64, f32>,
     #[pyo3(get)]
     pub vm_data: HashMap<u64, VMData>,
+    /// The chance the agent had of noticing each object in its field of view this step, including ones it didn't
+    /// notice. Objects in `observing` were noticed.
+    #[pyo3(get)]
+    pub detection_probs: HashMap<u64, f32>,
     #[pyo3(get)]
     pub visible_cells: Vec<bool>,
+    /// How much of each cell the agent can see, from 0 to 1. Indexed the same way as `visible_cells`.
+    #[pyo3(get)]
+    pub visible_coverage: Vec<f32>,
+    /// How long ago the agent last saw each cell, from 0 for cells visible now to 1 for cells it hasn't seen within
+    /// the memory horizon. Indexed the same way as `visible_cells`.
+    #[pyo3(get)]
+    pub staleness: Vec<f32>,
+    /// Where a teammate, such as a fixed camera, last reported seeing the player, after radio delay.
+    #[pyo3(get)]
+    pub radio_alert: Option<PyVec2>,
+    /// Whether the agent is travelling through a vent.
+    #[pyo3(get)]
+    pub in_vent: bool,
+    /// An egocentric RGB image from the agent's point of view, if cameras are enabled.
+    #[pyo3(get)]
+    pub camera: Option<Vec<u8>>,
+    /// How much energy the agent has left for gadgets, if it can use them.
+    #[pyo3(get)]
+    pub gadget_energy: Option<f32>,
+    /// The most gadget energy the agent can store, if it can use gadgets.
+    #[pyo3(get)]
+    pub max_gadget_energy: Option<f32>,
+    /// If the agent pinged this step, the quadrant the player is in.
+    /// 0 is bottom left, 1 is bottom right, 2 is top left, and 3 is top right.
+    #[pyo3(get)]
+    pub ping_quadrant: Option<u8>,
+    /// How aware the agent is of the player, from 0 to 1, if it tracks awareness.
+    #[pyo3(get)]
+    pub awareness: Option<f32>,
+    /// Whether the agent is "unaware", "suspicious", or "alert", if it tracks awareness.
+    #[pyo3(get)]
+    pub awareness_state: Option<String>,
+    /// The chance the agent thinks the other agent has of being in each cell, if it tracks a belief. Indexed the same
+    /// way as `visible_cells`. If it tracks several agents, this is its belief about the one with the lowest ID.
+    #[pyo3(get)]
+    pub belief: Option<Vec<f32>>,
+    /// The agent's belief about each agent it tracks, keyed by the tracked agent's ID. Each is indexed the same way as
+    /// `visible_cells`. Empty if the agent doesn't track beliefs.
+    #[pyo3(get)]
+    pub beliefs: HashMap<u64, Vec<f32>>,
+}
+
+#[pymethods]
+impl AgentState {
+    /// Returns how spread out `belief` is, in nats, if the agent tracks a belief.
+    pub fn belief_entropy(&self) -> Option<f32> {
+        self.belief().map(|belief| belief.entropy())
+    }
+
+    /// Returns the index of the cell the agent thinks the other agent is most likely to be in, if it tracks a belief.
+    pub fn belief_map_cell(&self) -> Option<usize> {
+        self.belief().and_then(|belief| belief.map_cell())
+    }
+
+    /// Returns the `k` cells the agent thinks the other agent is most likely to be in, as indices paired with their
+    /// probabilities, most likely first. Empty if the agent doesn't track a belief.
+    pub fn belief_top_k(&self, k: usize) -> Vec<(usize, f32)> {
+        self.belief()
+            .map(|belief| belief.top_k(k))
+            .unwrap_or_default()
+    }
+}
+
+impl AgentState {
+    fn belief(&self) -> Option<Belief> {
+        self.belief.clone().map(|probs| Belief { probs })
+    }
 }
 
 /// Contains the state of the game for a single frame.
@@ -92,12 +253,260 @@ pub struct GameState {
     pub pursuer: AgentState,
     #[pyo3(get)]
     pub walls: Vec<bool>,
+    /// Stores `true` for cells with cover, which block vision but not movement. Indexed the same way as `walls`.
+    #[pyo3(get)]
+    pub cover: Vec<bool>,
     #[pyo3(get)]
-    pub level_size: usize,
+    pub level_width: usize,
+    #[pyo3(get)]
+    pub level_height: usize,
     #[pyo3(get)]
     pub objects: HashMap<u64, ObservableObject>,
     #[pyo3(get)]
     pub noise_sources: HashMap<u64, NoiseSourceObject>,
+    /// The cell the key is in, indexed the same way as `walls`.
+    #[pyo3(get)]
+    pub key_pos: Option<(usize, usize)>,
+    /// The cell the door is in, indexed the same way as `walls`.
+    #[pyo3(get)]
+    pub door_pos: Option<(usize, usize)>,
+    /// Cells with open exits, indexed the same way as `walls`.
+    #[pyo3(get)]
+    pub exits: Vec<(usize, usize)>,
+    /// The `CellTopology` of each cell as an integer, indexed the same way as `walls`.
+    #[pyo3(get)]
+    pub cell_topology: Vec<u8>,
+    /// The `Terrain` of each cell as an integer, indexed the same way as `walls`.
+    #[pyo3(get)]
+    pub terrain: Vec<u8>,
+    /// `(from, to)` pairs of neighboring cells, indexed the same way as `walls`, that agents can only move between in
+    /// that direction.
+    #[pyo3(get)]
+    pub one_way: Vec<((usize, usize), (usize, usize))>,
+    /// The direction each cell's conveyor pushes agents as a `(dx, dy)` step, or `None` if it doesn't have one.
+    /// Indexed the same way as `walls`.
+    #[pyo3(get)]
+    pub conveyors: Vec<Option<(i32, i32)>>,
+    /// How many cells into the fog agents can see for each cell, or `None` for cells without fog.
+    /// Indexed the same way as `walls`.
+    #[pyo3(get)]
+    pub fog: Vec<Option<f32>>,
+    /// The cell each fixed camera is in, indexed the same way as `walls`, and how many cells away it can see.
+    #[pyo3(get)]
+    pub cameras: Vec<((usize, usize), f32)>,
+    /// How brightly lit each cell is, from 0 to 1, indexed the same way as `walls`.
+    #[pyo3(get)]
+    pub illumination: Vec<f32>,
+    /// Cells that any pursuer or fixed camera can see, indexed the same way as `walls`.
+    #[pyo3(get)]
+    pub team_visible_cells: Vec<bool>,
+    /// Entities that any pursuer or fixed camera can see, other than pursuers and cameras.
+    #[pyo3(get)]
+    pub team_observing: Vec<u64>,
+    /// `(observer, observable, time)` for every entity an agent started observing this step.
+    #[pyo3(get)]
+    pub entered_view: Vec<(u64, u64, f32)>,
+    /// `(observer, observable, time)` for every entity an agent stopped observing this step.
+    #[pyo3(get)]
+    pub exited_view: Vec<(u64, u64, f32)>,
+    /// Whether the player is carrying the key.
+    #[pyo3(get)]
+    pub player_has_key: bool,
+    #[pyo3(get)]
+    pub door_unlocked: bool,
+    /// Whether the player has left through the unlocked door, ending the episode.
+    #[pyo3(get)]
+    pub player_escaped: bool,
+    /// Whether a pursuer has caught the player, ending the episode.
+    #[pyo3(get)]
+    pub player_caught: bool,
+    /// Metadata from the level file.
+    #[pyo3(get)]
+    pub meta: PyLevelMeta,
+    /// Maps raw entity bits to game IDs.
+    pub entity_ids: HashMap<u64, u64>,
+}
+
+#[pymethods]
+impl GameState {
+    /// The pursuer's in-engine belief about where the player is. Shorthand for `pursuer.belief`.
+    #[getter]
+    pub fn pursuer_belief(&self) -> Option<Vec<f32>> {
+        self.pursuer.belief.clone()
+    }
+
+    /// The player's in-engine belief about where the pursuer is. Shorthand for `player.belief`.
+    #[getter]
+    pub fn player_belief(&self) -> Option<Vec<f32>> {
+        self.player.belief.clone()
+    }
+
+    /// Converts raw entity bits into the game IDs used everywhere else in `GameState`.
+    pub fn resolve_ids(&self, entities: Vec<u64>) -> PyResult<Vec<u64>> {
+        entities
+            .into_iter()
+            .map(|e| {
+                self.entity_ids
+                    .get(&e)
+                    .copied()
+                    .ok_or_else(|| PyKeyError::new_err(format!("No game ID for entity {e}")))
+            })
+            .collect()
+    }
+
+    /// Returns the cell containing a world space position, if it's within the level.
+    /// Cells are indexed the same way as `walls`.
+    pub fn world_to_cell(&self, pos: PyVec2) -> Option<(usize, usize)> {
+        self.grid().world_to_cell(Vec2::new(pos.x, pos.y))
+    }
+
+    /// Returns the world space position of the center of a cell.
+    pub fn cell_to_world(&self, cell: (usize, usize)) -> PyVec2 {
+        self.grid().cell_to_world(cell).into()
+    }
+
+    /// Returns the index of a cell in `walls` and other per-cell lists, if it's within the level.
+    pub fn cell_idx(&self, cell: (usize, usize)) -> Option<usize> {
+        self.grid().cell_idx(cell)
+    }
+
+    /// Returns true if an agent can step from a cell to a neighboring one.
+    /// The neighbor must be inside the level and not a wall, no one-way edge can be in the way, and neither cell can
+    /// have a conveyor pushing back against the step.
+    pub fn can_move(&self, from_cell: (usize, usize), to_cell: (usize, usize)) -> bool {
+        let open = self.cell_idx(to_cell).is_some_and(|idx| !self.walls[idx]);
+        let backwards = Some((
+            from_cell.0 as i32 - to_cell.0 as i32,
+            from_cell.1 as i32 - to_cell.1 as i32,
+        ));
+        let against_conveyor = [from_cell, to_cell].into_iter().any(|cell| {
+            self.cell_idx(cell)
+                .is_some_and(|idx| self.conveyors[idx] == backwards)
+        });
+        open && !against_conveyor && !self.one_way.contains(&(to_cell, from_cell))
+    }
+
+    /// Draws the level as text, one character per cell, with the top row first.
+    ///
+    /// If `belief` is provided (indexed the same way as `walls`), empty cells show the belief's decile relative to
+    /// its maximum value instead of `.`.
+    #[pyo3(signature = (belief=None))]
+    pub fn to_ascii(&self, belief: Option<Vec<f32>>) -> String {
+        let width = self.level_width;
+        let mut cells: Vec<char> = self
+            .walls
+            .iter()
+            .map(|&wall| if wall { '#' } else { '.' })
+            .collect();
+        if let Some(belief) = belief {
+            let max = belief.iter().cloned().fold(f32::EPSILON, f32::max);
+            for (cell, p) in cells.iter_mut().zip(belief) {
+                if *cell == '.' {
+                    let decile = ((p / max) * 9.).round().clamp(0., 9.) as u32;
+                    *cell = char::from_digit(decile, 10).unwrap();
+                }
+            }
+        }
+        let mut mark = |pos: Option<(usize, usize)>, c: char| {
+            if let Some((x, y)) = pos {
+                cells[y * width + x] = c;
+            }
+        };
+        mark(self.door_pos, 'D');
+        mark(self.key_pos, 'K');
+        for noise_src in self.noise_sources.values() {
+            mark(self.world_to_cell(noise_src.pos), 'N');
+        }
+        mark(self.world_to_cell(self.player.pos), 'P');
+        mark(self.world_to_cell(self.pursuer.pos), 'E');
+
+        cells
+            .chunks(width)
+            .rev()
+            .map(|row| row.iter().collect::<String>())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+impl GameState {
+    fn grid(&self) -> GridTransform {
+        GridTransform::new(self.level_width, self.level_height)
+    }
+}
+
+/// Configures the obstacle level generator.
+#[pyclass]
+#[derive(Debug, Clone)]
+pub struct ObstacleConfig {
+    /// How many obstacles to try to place.
+    #[pyo3(get, set)]
+    pub count: usize,
+    /// The smallest width or height of rectangles and L-shapes.
+    #[pyo3(get, set)]
+    pub min_size: usize,
+    /// The largest width or height of rectangles and L-shapes.
+    #[pyo3(get, set)]
+    pub max_size: usize,
+    /// How many empty cells must separate obstacles.
+    #[pyo3(get, set)]
+    pub min_corridor_width: usize,
+    /// The shapes to pick from: "rect", "l_shape", or "pillar".
+    #[pyo3(get, set)]
+    pub shapes: Vec<String>,
+}
+
+#[pymethods]
+impl ObstacleConfig {
+    #[new]
+    #[pyo3(signature = (count=4, min_size=2, max_size=3, min_corridor_width=1, shapes=None))]
+    pub fn new(
+        count: usize,
+        min_size: usize,
+        max_size: usize,
+        min_corridor_width: usize,
+        shapes: Option<Vec<String>>,
+    ) -> Self {
+        Self {
+            count,
+            min_size,
+            max_size,
+            min_corridor_width,
+            shapes: shapes
+                .unwrap_or_else(|| vec!["rect".into(), "l_shape".into(), "pillar".into()]),
+        }
+    }
+}
+
+impl TryFrom<ObstacleConfig> for ObstacleParams {
+    type Error = PyErr;
+
+    fn try_from(value: ObstacleConfig) -> PyResult<Self> {
+        if value.min_size > value.max_size {
+            return Err(PyValueError::new_err(
+                "min_size must not be larger than max_size",
+            ));
+        }
+        let shapes = value
+            .shapes
+            .iter()
+            .map(|shape| match shape.as_str() {
+                "rect" => Ok(ObstacleShape::Rect),
+                "l_shape" => Ok(ObstacleShape::LShape),
+                "pillar" => Ok(ObstacleShape::Pillar),
+                other => Err(PyValueError::new_err(format!(
+                    "Unknown obstacle shape \"{other}\""
+                ))),
+            })
+            .collect::<PyResult<_>>()?;
+        Ok(Self {
+            count: value.count,
+            min_size: value.min_size,
+            max_size: value.max_size,
+            min_corridor_width: value.min_corridor_width,
+            shapes,
+        })
+    }
 }
 
 /// Indicates the kind of actions an agent can take.
@@ -123,55 +532,265 @@ impl<'source> FromPyObject<'source> for AgentAction {
     }
 }
 
+/// Configures a `GameWrapper`.
+///
+/// Each field matches the keyword argument of the same name that `GameWrapper` takes from Python, which documents what it
+/// does.
+#[derive(Debug, Clone)]
+pub struct EnvConfig {
+    pub use_objs: bool,
+    pub wall_prob: f64,
+    pub visualize: bool,
+    pub recording_id: Option<String>,
+    pub seed: Option<u64>,
+    pub level_path: Option<LevelPaths>,
+    pub radio_delay: u64,
+    pub camera_size: Option<usize>,
+    pub level_sampling: String,
+    pub level_weights: Option<Vec<f64>>,
+    pub obstacles: Option<ObstacleConfig>,
+    pub symmetry: Option<String>,
+    pub pursuer_fov: f32,
+    pub pursuer_range: Option<f32>,
+    pub player_fov: f32,
+    pub player_range: Option<f32>,
+    pub visibility: String,
+    pub detection_certain_dist: f32,
+    pub detection_dist_falloff: f32,
+    pub detection_peripheral_falloff: f32,
+    pub memory_horizon: f32,
+    pub hearing_bearing_noise: f32,
+    pub marker_move_threshold: f32,
+    pub marker_estimate_velocity: bool,
+    pub marker_evidence_duration: f32,
+    pub awareness_detection_gain: f32,
+    pub awareness_noise_gain: f32,
+    pub awareness_decay: f32,
+    pub awareness_suspicious_threshold: f32,
+    pub awareness_speed_scales: (f32, f32, f32),
+    pub filter_backend: String,
+    pub particle_count: usize,
+    pub particle_resampling: String,
+    pub particle_motion_noise: f32,
+    pub compute_device: String,
+    pub filter_motion_model: String,
+    pub filter_goal_bias: f32,
+    pub gaussian_count: usize,
+    pub measurement_model: Option<String>,
+    pub sparse_floor: f32,
+    pub scripted_pursuer: bool,
+    pub difficulty: String,
+    pub spectator_addr: Option<String>,
+    pub level_width: usize,
+    pub level_height: usize,
+}
+
+impl Default for EnvConfig {
+    fn default() -> Self {
+        Self {
+            use_objs: false,
+            wall_prob: 0.1,
+            visualize: false,
+            recording_id: None,
+            seed: None,
+            level_path: None,
+            radio_delay: 2,
+            camera_size: None,
+            level_sampling: "round_robin".into(),
+            level_weights: None,
+            obstacles: None,
+            symmetry: None,
+            pursuer_fov: DEFAULT_FOV_DEGREES,
+            pursuer_range: None,
+            player_fov: DEFAULT_FOV_DEGREES,
+            player_range: None,
+            visibility: "mesh".into(),
+            detection_certain_dist: 0.,
+            detection_dist_falloff: 0.,
+            detection_peripheral_falloff: 0.,
+            memory_horizon: 10.,
+            hearing_bearing_noise: 0.,
+            marker_move_threshold: 0.,
+            marker_estimate_velocity: false,
+            marker_evidence_duration: 0.,
+            awareness_detection_gain: 2.,
+            awareness_noise_gain: 1.,
+            awareness_decay: 0.1,
+            awareness_suspicious_threshold: 0.3,
+            awareness_speed_scales: (1., 1., 1.),
+            filter_backend: "grid".into(),
+            particle_count: 1000,
+            particle_resampling: "systematic".into(),
+            particle_motion_noise: 1.,
+            compute_device: "auto".into(),
+            filter_motion_model: "random_walk".into(),
+            filter_goal_bias: 0.5,
+            gaussian_count: 4,
+            measurement_model: None,
+            sparse_floor: 1e-4,
+            scripted_pursuer: false,
+            difficulty: "custom".into(),
+            spectator_addr: None,
+            level_width: DEFAULT_LEVEL_SIZE,
+            level_height: DEFAULT_LEVEL_SIZE,
+        }
+    }
+}
+
+impl EnvConfig {
+    /// Builds a config from Python keyword arguments, using the default for any that aren't given.
+    fn from_kwargs(kwargs: Option<&PyDict>) -> PyResult<Self> {
+        let mut config = Self::default();
+        for (key, value) in kwargs.into_iter().flatten() {
+            let key: &str = key.extract()?;
+            match key {
+                "use_objs" => config.use_objs = value.extract()?,
+                "wall_prob" => config.wall_prob = value.extract()?,
+                "visualize" => config.visualize = value.extract()?,
+                "recording_id" => config.recording_id = value.extract()?,
+                "seed" => config.seed = value.extract()?,
+                "level_path" => config.level_path = value.extract()?,
+                "radio_delay" => config.radio_delay = value.extract()?,
+                "camera_size" => config.camera_size = value.extract()?,
+                "level_sampling" => config.level_sampling = value.extract()?,
+                "level_weights" => config.level_weights = value.extract()?,
+                "obstacles" => config.obstacles = value.extract()?,
+                "symmetry" => config.symmetry = value.extract()?,
+                "pursuer_fov" => config.pursuer_fov = value.extract()?,
+                "pursuer_range" => config.pursuer_range = value.extract()?,
+                "player_fov" => config.player_fov = value.extract()?,
+                "player_range" => config.player_range = value.extract()?,
+                "visibility" => config.visibility = value.extract()?,
+                "detection_certain_dist" => config.detection_certain_dist = value.extract()?,
+                "detection_dist_falloff" => config.detection_dist_falloff = value.extract()?,
+                "detection_peripheral_falloff" => {
+                    config.detection_peripheral_falloff = value.extract()?
+                }
+                "memory_horizon" => config.memory_horizon = value.extract()?,
+                "hearing_bearing_noise" => config.hearing_bearing_noise = value.extract()?,
+                "marker_move_threshold" => config.marker_move_threshold = value.extract()?,
+                "marker_estimate_velocity" => config.marker_estimate_velocity = value.extract()?,
+                "marker_evidence_duration" => config.marker_evidence_duration = value.extract()?,
+                "awareness_detection_gain" => config.awareness_detection_gain = value.extract()?,
+                "awareness_noise_gain" => config.awareness_noise_gain = value.extract()?,
+                "awareness_decay" => config.awareness_decay = value.extract()?,
+                "awareness_suspicious_threshold" => {
+                    config.awareness_suspicious_threshold = value.extract()?
+                }
+                "awareness_speed_scales" => config.awareness_speed_scales = value.extract()?,
+                "filter_backend" => config.filter_backend = value.extract()?,
+                "particle_count" => config.particle_count = value.extract()?,
+                "particle_resampling" => config.particle_resampling = value.extract()?,
+                "particle_motion_noise" => config.particle_motion_noise = value.extract()?,
+                "compute_device" => config.compute_device = value.extract()?,
+                "filter_motion_model" => config.filter_motion_model = value.extract()?,
+                "filter_goal_bias" => config.filter_goal_bias = value.extract()?,
+                "gaussian_count" => config.gaussian_count = value.extract()?,
+                "measurement_model" => config.measurement_model = value.extract()?,
+                "sparse_floor" => config.sparse_floor = value.extract()?,
+                "scripted_pursuer" => config.scripted_pursuer = value.extract()?,
+                "difficulty" => config.difficulty = value.extract()?,
+                "spectator_addr" => config.spectator_addr = value.extract()?,
+                "level_width" => config.level_width = value.extract()?,
+                "level_height" => config.level_height = value.extract()?,
+                _ => {
+                    return Err(PyTypeError::new_err(format!(
+                        "GameWrapper got an unexpected keyword argument \"{key}\""
+                    )))
+                }
+            }
+        }
+        Ok(config)
+    }
+}
+
 /// Wraps our game in a gym-like interface.
 #[pyclass]
 pub struct GameWrapper {
     pub app: App,
     pub use_objs: bool,
     pub wall_prob: f64,
+    /// The width of randomly generated levels, in cells.
+    pub level_width: usize,
+    /// The height of randomly generated levels, in cells.
+    pub level_height: usize,
     pub visualize: bool,
     pub recording_id: Option<String>,
+    /// RNG used to generate levels. Forked wrappers receive a copy of this, so they see the same levels.
+    pub level_rng: StdRng,
+    /// If set, levels are picked from this set on every reset instead of being randomly generated.
+    pub level_set: Option<LevelSet>,
+    /// How many ticks it takes for a sighting to reach teammates.
+    pub radio_delay: u64,
+    /// How visible cells are computed.
+    pub visibility: VisibilityBackend,
+    /// How many seconds it takes for a cell an agent saw to become fully stale.
+    pub memory_horizon: f32,
+    /// The width and height of agent camera images. If not set, cameras aren't rendered.
+    pub camera_size: Option<usize>,
+    /// If set, random levels are built from obstacle shapes instead of independently random cells.
+    pub obstacle_params: Option<ObstacleParams>,
+    /// If set, random levels are made symmetric, with the agents spawning on opposite sides.
+    pub symmetry: Option<Symmetry>,
+    /// How agents see, unless a level overrides it.
+    pub vision: VisionConfig,
+    /// How likely agents are to notice objects they can see.
+    pub detection: DetectionConfig,
+    /// Decides whether agents notice objects. Carried over between episodes, and copied by forked wrappers.
+    pub detection_rng: StdRng,
+    /// The most, in degrees, that the bearing to a heard noise can be off by.
+    pub hearing_bearing_noise: f32,
+    /// Adds error to the bearings of heard noises. Copied by forked wrappers.
+    pub hearing_rng: StdRng,
+    /// How agents notice visual markers moving.
+    pub markers: MarkerConfig,
+    /// How quickly agents become aware of the player.
+    pub awareness: AwarenessConfig,
+    /// How each agent's belief about where the other agent is gets tracked.
+    pub filter: FilterConfig,
+    /// Drives the particle filter backend. Carried over between episodes, and copied by forked wrappers.
+    pub filter_rng: StdRng,
+    /// Picks where agents spawn. Carried over between episodes, and copied by forked wrappers.
+    pub spawn_rng: StdRng,
+    /// Which device neural networks run on.
+    pub compute_device: ComputeDevice,
+    /// If set, the weights of a `MeasureNet` that replaces the filter's hand-written likelihood.
+    pub measurement_weights: Option<SafeTensorsData>,
+    /// If set, the pursuer is controlled by a `ScriptedPursuer` instead of the actions passed to `step`.
+    pub scripted_pursuer: bool,
+    /// The difficulty preset applied to the pursuer's senses, speed, and policy.
+    pub difficulty: Difficulty,
+    /// If set, streams every episode to spectators. Forked wrappers don't stream.
+    pub spectators: Option<SpectatorServer>,
 }
 
 #[pymethods]
 impl GameWrapper {
+    /// Creates a wrapper from keyword arguments, see `EnvConfig`.
     #[new]
-    pub fn new(
-        use_objs: bool,
-        wall_prob: f64,
-        visualize: bool,
-        recording_id: Option<String>,
-    ) -> Self {
-        let mut app = App::new();
-        app.add_plugins(LibCfgPlugin);
-        app.insert_resource(LevelLayout::random(
-            DEFAULT_LEVEL_SIZE,
-            wall_prob,
-            if use_objs { DEFAULT_LEVEL_SIZE } else { 0 },
-        ));
-
-        if visualize {
-            app.add_plugins(VisualizerPlugin {
-                recording_id: recording_id.clone(),
-            });
-        }
-
-        app.finish();
-        app.cleanup();
-        app.update();
-
-        Self {
-            app,
-            visualize,
-            recording_id,
-            use_objs,
-            wall_prob,
-        }
+    #[pyo3(signature = (**kwargs))]
+    pub fn py_new(kwargs: Option<&PyDict>) -> PyResult<Self> {
+        Self::new(EnvConfig::from_kwargs(kwargs)?)
     }
 
-    pub fn step(&mut self, action_player: AgentAction, action_pursuer: AgentAction) -> GameState {
-        set_agent_action::<PlayerAgent>(&mut self.app.world, action_player);
-        set_agent_action::<PursuerAgent>(&mut self.app.world, action_pursuer);
+    #[pyo3(signature = (action_player, action_pursuer, pursuer_sprint=false, pursuer_ping=false))]
+    pub fn step(
+        &mut self,
+        action_player: AgentAction,
+        action_pursuer: AgentAction,
+        pursuer_sprint: bool,
+        pursuer_ping: bool,
+    ) -> GameState {
+        // Only one gadget can be used at a time, and pinging takes priority
+        let pursuer_gadget = if pursuer_ping {
+            Some(Gadget::Ping)
+        } else if pursuer_sprint {
+            Some(Gadget::Sprint)
+        } else {
+            None
+        };
+        set_agent_action::<PlayerAgent>(&mut self.app.world, action_player, None);
+        set_agent_action::<PursuerAgent>(&mut self.app.world, action_pursuer, pursuer_gadget);
 
         self.app.update();
 
@@ -179,20 +798,398 @@ impl GameWrapper {
     }
 
     pub fn reset(&mut self) -> GameState {
+        self.detection_rng = self.app.world.resource::<DetectionRng>().0.clone();
+        self.filter_rng = self.app.world.resource::<FilterRng>().0.clone();
+        self.spawn_rng = self.app.world.resource::<SpawnRng>().0.clone();
         self.app.world.send_event(AppExit);
         self.app.run();
-        *self = Self::new(
-            self.use_objs,
-            self.wall_prob,
-            self.visualize,
-            self.recording_id.clone(),
-        );
+        let level = self.next_level();
+        self.app = self.build_app(level);
         self.get_state()
     }
+
+    /// Draws the current state of the game as text. See `GameState::to_ascii`.
+    #[pyo3(signature = (belief=None))]
+    pub fn render_ascii(&mut self, belief: Option<Vec<f32>>) -> String {
+        self.get_state().to_ascii(belief)
+    }
+
+    /// Saves the current level as a level file, with objects at the cells they've been pushed to.
+    /// Useful for keeping random levels that produce interesting behavior.
+    pub fn save_level(&mut self, path: &str) -> PyResult<()> {
+        let world = &mut self.app.world;
+        let mut obj_query = world.query::<(&LevelObject, &GlobalTransform)>();
+        let data = world
+            .resource::<LevelLayout>()
+            .snapshot(obj_query.iter(world));
+        std::fs::write(path, data.to_json())
+            .map_err(|e| PyIOError::new_err(format!("Could not write level {path}: {e}")))
+    }
+
+    /// Saves every agent's beliefs (see `AgentState::beliefs`) as JSON, including particles with the particle backend.
+    /// Restoring them with `load_filter_state`, here or in a fork on the same level, reproduces the beliefs exactly.
+    pub fn save_filter_state(&mut self) -> String {
+        let world = &mut self.app.world;
+        let game_ids: HashMap<Entity, u64> = world
+            .query::<(Entity, &GameId)>()
+            .iter(world)
+            .map(|(e, id)| (e, id.0))
+            .collect();
+        let mut beliefs_query = world.query::<(&GameId, &Beliefs)>();
+        FilterSnapshot::capture(beliefs_query.iter(world), &game_ids).to_json()
+    }
+
+    /// Replaces agents' beliefs with ones saved by `save_filter_state`.
+    /// Agents that weren't saved keep their current beliefs.
+    pub fn load_filter_state(&mut self, state: &str) -> PyResult<()> {
+        let snapshot = FilterSnapshot::from_json(state)
+            .map_err(|e| PyValueError::new_err(format!("Invalid filter state: {e}")))?;
+        let world = &mut self.app.world;
+        let entities: HashMap<u64, Entity> = world
+            .query::<(Entity, &GameId)>()
+            .iter(world)
+            .map(|(e, id)| (id.0, e))
+            .collect();
+        let mut beliefs_query = world.query::<(&GameId, &mut Beliefs)>();
+        snapshot.restore(beliefs_query.iter_mut(world), &entities);
+        Ok(())
+    }
+
+    /// Reports a sighting of an agent from outside the game, such as by a camera or alarm. It's taken into account the
+    /// next time beliefs are updated. See `ExternalSighting`.
+    #[pyo3(signature = (target, cell, confidence, radius=1.0, tracker=None))]
+    pub fn report_sighting(
+        &mut self,
+        target: u64,
+        cell: (usize, usize),
+        confidence: f32,
+        radius: f32,
+        tracker: Option<u64>,
+    ) -> PyResult<()> {
+        if !(0. ..=1.).contains(&confidence) || radius < 0. {
+            return Err(PyValueError::new_err(
+                "confidence must be between 0 and 1, and radius must not be negative",
+            ));
+        }
+        let world = &mut self.app.world;
+        let entities: HashMap<u64, Entity> = world
+            .query::<(Entity, &GameId)>()
+            .iter(world)
+            .map(|(e, id)| (id.0, e))
+            .collect();
+        let entity = |id: u64| {
+            entities
+                .get(&id)
+                .copied()
+                .ok_or_else(|| PyKeyError::new_err(format!("No agent with game ID {id}")))
+        };
+        let grid = world.resource::<LevelLayout>().grid();
+        if grid.cell_idx(cell).is_none() {
+            return Err(PyValueError::new_err(format!(
+                "Cell {cell:?} is outside the level"
+            )));
+        }
+        let sighting = ExternalSighting {
+            target: entity(target)?,
+            tracker: tracker.map(entity).transpose()?,
+            pos: grid.cell_to_world(cell),
+            radius: radius * GRID_CELL_SIZE,
+            confidence,
+        };
+        world.send_event(sighting);
+        Ok(())
+    }
+
+    /// Returns the width and height of the current level, in cells.
+    pub fn level_size(&self) -> (usize, usize) {
+        let level = self.app.world.resource::<LevelLayout>();
+        (level.width, level.height)
+    }
+
+    /// Draws the current level as a PNG image, with each cell `cell_pixels` wide. See `level_thumbnail`.
+    #[pyo3(signature = (cell_pixels=DEFAULT_THUMBNAIL_CELL_PIXELS))]
+    pub fn render_thumbnail(&self, py: Python, cell_pixels: usize) -> PyResult<PyObject> {
+        thumbnail_bytes(py, self.app.world.resource::<LevelLayout>(), cell_pixels)
+    }
+
+    /// Creates a second environment with the same configuration, current level, and level RNG state, but with
+    /// independent game state.
+    ///
+    /// Since both wrappers generate levels from identical RNG streams, resetting them the same number of times
+    /// yields matched level sequences.
+    pub fn fork(&self) -> Self {
+        let level = self.app.world.resource::<LevelLayout>().clone();
+        let mut fork = Self {
+            app: App::empty(),
+            visualize: self.visualize,
+            recording_id: self.recording_id.clone(),
+            use_objs: self.use_objs,
+            wall_prob: self.wall_prob,
+            level_width: self.level_width,
+            level_height: self.level_height,
+            level_rng: self.level_rng.clone(),
+            level_set: self.level_set.clone(),
+            radio_delay: self.radio_delay,
+            visibility: self.visibility,
+            memory_horizon: self.memory_horizon,
+            camera_size: self.camera_size,
+            obstacle_params: self.obstacle_params.clone(),
+            symmetry: self.symmetry,
+            vision: self.vision,
+            detection: self.detection,
+            detection_rng: self.detection_rng.clone(),
+            hearing_bearing_noise: self.hearing_bearing_noise,
+            hearing_rng: self.hearing_rng.clone(),
+            markers: self.markers,
+            awareness: self.awareness,
+            filter: self.filter,
+            filter_rng: self.filter_rng.clone(),
+            spawn_rng: self.spawn_rng.clone(),
+            compute_device: self.compute_device,
+            measurement_weights: self.measurement_weights.clone(),
+            scripted_pursuer: self.scripted_pursuer,
+            difficulty: self.difficulty,
+            spectators: None,
+        };
+        fork.app = fork.build_app(level);
+        fork
+    }
+}
+
+/// One or more level paths passed from Python.
+#[derive(Debug, Clone, FromPyObject)]
+pub enum LevelPaths {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// Loads a level set from level files and directories of level files, raising a Python exception if any are
+/// malformed. Files in directories are loaded in alphabetical order.
+fn load_level_set(
+    paths: LevelPaths,
+    sampling: &str,
+    weights: Option<Vec<f64>>,
+) -> PyResult<LevelSet> {
+    let paths = match paths {
+        LevelPaths::One(path) => vec![path],
+        LevelPaths::Many(paths) => paths,
+    };
+    let mut levels = Vec::new();
+    for path in paths {
+        if !Path::new(&path).is_dir() {
+            levels.push(load_level_file(&path)?);
+            continue;
+        }
+        let entries = std::fs::read_dir(&path)
+            .map_err(|e| PyIOError::new_err(format!("Could not read directory {path}: {e}")))?;
+        let mut files = Vec::new();
+        for entry in entries {
+            let entry = entry
+                .map_err(|e| PyIOError::new_err(format!("Could not read directory {path}: {e}")))?;
+            if entry.path().extension().is_some_and(|ext| ext == "json") {
+                files.push(entry.path());
+            }
+        }
+        files.sort();
+        for file in files {
+            levels.push(load_level_file(&file.to_string_lossy())?);
+        }
+    }
+
+    let sampling = match (sampling, weights) {
+        ("round_robin", None) => LevelSampling::RoundRobin,
+        ("uniform", None) => LevelSampling::Uniform,
+        ("weighted", Some(weights)) => LevelSampling::Weighted(weights),
+        ("weighted", None) => {
+            return Err(PyValueError::new_err(
+                "level_weights must be provided for weighted sampling",
+            ))
+        }
+        ("round_robin" | "uniform", Some(_)) => {
+            return Err(PyValueError::new_err(
+                "level_weights can only be used with weighted sampling",
+            ))
+        }
+        (other, _) => {
+            return Err(PyValueError::new_err(format!(
+                "Unknown level sampling strategy \"{other}\""
+            )))
+        }
+    };
+    LevelSet::new(levels, sampling).map_err(|e| PyValueError::new_err(e.to_string()))
+}
+
+/// Reads and parses a level file, raising a Python exception if it's malformed.
+fn load_level_file(path: &str) -> PyResult<LevelLayout> {
+    let json = std::fs::read_to_string(path)
+        .map_err(|e| PyIOError::new_err(format!("Could not read level {path}: {e}")))?;
+    let data = LoadedLevelData::from_json(&json)
+        .map_err(|e| PyValueError::new_err(format!("Invalid level {path}: {e}")))?;
+    Ok(LevelLayout::from_data(&data))
+}
+
+/// Reads the weights of a `MeasureNet`, raising a Python exception if they can't be loaded.
+fn load_measurement_model(path: &str, compute_device: ComputeDevice) -> PyResult<SafeTensorsData> {
+    let (weights, _) =
+        read_net::<MeasureNet>(path, "measurement model", compute_device, NetPrecision::F32)?;
+    Ok(weights)
+}
+
+/// Reads a network's weights and loads them, raising a Python exception if they can't be. `name` describes the network
+/// in error messages.
+fn read_net<T: LoadableNN>(
+    path: &str,
+    name: &str,
+    compute_device: ComputeDevice,
+    precision: NetPrecision,
+) -> PyResult<(SafeTensorsData, T)> {
+    let weights = std::fs::read(path)
+        .map(SafeTensorsData)
+        .map_err(|e| PyIOError::new_err(format!("Could not read {name} {path}: {e}")))?;
+    let net = weights
+        .load_net::<T>(compute_device, precision)
+        .map_err(|e| PyValueError::new_err(format!("Invalid {name} {path}: {e}")))?;
+    Ok((weights, net))
+}
+
+/// Parses the device and precision to run a network with, raising a Python exception if either is invalid.
+fn parse_net_options(
+    compute_device: &str,
+    precision: &str,
+) -> PyResult<(ComputeDevice, NetPrecision)> {
+    let compute_device = compute_device
+        .parse::<ComputeDevice>()
+        .map_err(|e| PyValueError::new_err(e.to_string()))?;
+    let precision = precision
+        .parse::<NetPrecision>()
+        .map_err(|e| PyValueError::new_err(e.to_string()))?;
+    Ok((compute_device, precision))
+}
+
+/// Runs the game's port of `PolicyNet` with the weights at `path` on a batch of flattened input grids, returning each
+/// grid's action logits. Lets training code check the port matches the PyTorch model.
+#[pyfunction]
+#[pyo3(signature = (path, grids, width, height, compute_device="auto", precision="f32"))]
+fn policy_logits(
+    path: &str,
+    grids: Vec<f32>,
+    width: usize,
+    height: usize,
+    compute_device: &str,
+    precision: &str,
+) -> PyResult<Vec<Vec<f32>>> {
+    let (compute_device, precision) = parse_net_options(compute_device, precision)?;
+    check_grids_len(&grids, width, height)?;
+    let (_, net) = read_net::<PolicyNet>(path, "policy", compute_device, precision)?;
+    net.logits(grids, width, height)
+        .map_err(|e| PyRuntimeError::new_err(format!("Could not run policy: {e}")))
+}
+
+/// Runs the game's port of `MeasureModel` with the weights at `path` on a batch of flattened input grids, returning
+/// each grid's likelihood of the opponent being in each cell.
+#[pyfunction]
+#[pyo3(signature = (path, grids, width, height, compute_device="auto", precision="f32"))]
+fn measurement_likelihoods(
+    path: &str,
+    grids: Vec<f32>,
+    width: usize,
+    height: usize,
+    compute_device: &str,
+    precision: &str,
+) -> PyResult<Vec<Vec<f32>>> {
+    let (compute_device, precision) = parse_net_options(compute_device, precision)?;
+    check_grids_len(&grids, width, height)?;
+    let (_, net) = read_net::<MeasureNet>(path, "measurement model", compute_device, precision)?;
+    net.likelihoods(grids, width, height)
+        .map_err(|e| PyRuntimeError::new_err(format!("Could not run measurement model: {e}")))
+}
+
+/// Raises a Python exception unless `grids` holds a whole number of input grids for a level of this size.
+fn check_grids_len(grids: &[f32], width: usize, height: usize) -> PyResult<()> {
+    let grid_len = POLICY_CHANNELS * width * height;
+    if grid_len == 0 || grids.is_empty() || grids.len() % grid_len != 0 {
+        return Err(PyValueError::new_err(format!(
+            "grids must hold a multiple of {grid_len} values, one grid of shape ({POLICY_CHANNELS}, {height}, {width}) after another"
+        )));
+    }
+    Ok(())
+}
+
+/// Parses level JSON, raising a Python exception if it's malformed.
+fn parse_level(json: &str) -> PyResult<LoadedLevelData> {
+    LoadedLevelData::from_json(json)
+        .map_err(|e| PyValueError::new_err(format!("Invalid level: {e}")))
+}
+
+/// Computes a patch that turns one level into another, returned as JSON.
+#[pyfunction]
+fn diff_levels(old_json: &str, new_json: &str) -> PyResult<String> {
+    let patch = LevelPatch::diff(&parse_level(old_json)?, &parse_level(new_json)?)
+        .map_err(|e| PyValueError::new_err(e.to_string()))?;
+    Ok(patch.to_json())
+}
+
+/// Applies a patch created by `diff_levels` to a level, returning the patched level as JSON.
+#[pyfunction]
+fn apply_level_patch(level_json: &str, patch_json: &str) -> PyResult<String> {
+    let patch =
+        LevelPatch::from_json(patch_json).map_err(|e| PyValueError::new_err(e.to_string()))?;
+    let level = patch
+        .apply(&parse_level(level_json)?)
+        .map_err(|e| PyValueError::new_err(e.to_string()))?;
+    Ok(level.to_json())
+}
+
+/// Draws a level as a PNG image, with each cell `cell_pixels` wide, without running the game.
+#[pyfunction]
+#[pyo3(signature = (level_json, cell_pixels=DEFAULT_THUMBNAIL_CELL_PIXELS))]
+fn level_thumbnail(py: Python, level_json: &str, cell_pixels: usize) -> PyResult<PyObject> {
+    let level = LevelLayout::from_data(&parse_level(level_json)?);
+    thumbnail_bytes(py, &level, cell_pixels)
+}
+
+/// Encodes a level's thumbnail as Python bytes.
+fn thumbnail_bytes(py: Python, level: &LevelLayout, cell_pixels: usize) -> PyResult<PyObject> {
+    let png = level_thumbnail_png(level, cell_pixels)
+        .map_err(|e| PyRuntimeError::new_err(e.to_string()))?;
+    Ok(PyBytes::new(py, &png).into())
+}
+
+/// Returns a random playable mutation of a level as JSON, or `None` if none could be found.
+#[pyfunction]
+#[pyo3(signature = (level_json, seed=None))]
+fn mutate_level(level_json: &str, seed: Option<u64>) -> PyResult<Option<String>> {
+    let level = parse_level(level_json)?;
+    let mut rng = match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    Ok(level_mutation::mutate_level(&level, &mut rng).map(|level| level.to_json()))
+}
+
+/// Mutates a level to maximize a score computed by `score_fn`, returning the best level as JSON and its score.
+#[pyfunction]
+#[pyo3(signature = (level_json, score_fn, iterations, seed=None))]
+fn search_levels(
+    level_json: &str,
+    score_fn: &PyAny,
+    iterations: usize,
+    seed: Option<u64>,
+) -> PyResult<(String, f32)> {
+    let level = parse_level(level_json)?;
+    let mut rng = match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let (best, best_score) =
+        level_mutation::search_levels(level, iterations, &mut rng, |candidate| {
+            score_fn.call1((candidate.to_json(),))?.extract::<f32>()
+        })?;
+    Ok((best.to_json(), best_score))
 }
 
 /// Queries the world for an agent with the provided component and sets the next action.
-fn set_agent_action<T: Component>(world: &mut World, action: AgentAction) {
+fn set_agent_action<T: Component>(world: &mut World, action: AgentAction, gadget: Option<Gadget>) {
     let mut next_action = world
         .query_filtered::<&mut NextAction, With<T>>()
         .single_mut(world);
@@ -208,136 +1205,539 @@ fn set_agent_action<T: Component>(world: &mut World, action: AgentAction) {
         _ => Vec2::ZERO,
     };
     next_action.toggle_objs = action == AgentAction::ToggleObj;
+    next_action.gadget = gadget;
 }
 
 /// Queries the world for an agent with the provided component and returns an `AgentState`.
-fn get_agent_state<T: Component>(world: &mut World) -> AgentState {
-    let (agent, &xform, observer) = world
-        .query_filtered::<(&Agent, &GlobalTransform, &Observer), With<T>>()
+fn get_agent_state<T: Component>(
+    world: &mut World,
+    game_ids: &HashMap<Entity, u64>,
+    visibility: VisibilityBackend,
+    memory_horizon: f32,
+    hearing_bearing_noise: f32,
+    hearing_rng: &mut StdRng,
+) -> AgentState {
+    let (
+        agent_e,
+        agent,
+        &xform,
+        observer,
+        camera_feed,
+        radio,
+        in_vent,
+        camera,
+        energy,
+        ping_result,
+        awareness,
+        beliefs,
+    ) = world
+        .query_filtered::<(
+            Entity,
+            &Agent,
+            &GlobalTransform,
+            &Observer,
+            Option<&CameraFeed>,
+            Option<&Radio>,
+            Has<InVent>,
+            Option<&CameraSensor>,
+            Option<&GadgetEnergy>,
+            Option<&PingResult>,
+            Option<&Awareness>,
+            Option<&Beliefs>,
+        ), With<T>>()
         .single(world);
+    let camera = camera.map(|camera| camera.image.clone());
+    let beliefs = beliefs
+        .iter()
+        .flat_map(|beliefs| &beliefs.targets)
+        .map(|(e, target_belief)| (game_id(game_ids, e), target_belief.belief.probs.clone()))
+        .collect::<HashMap<_, _>>();
+    let belief = beliefs
+        .iter()
+        .min_by_key(|(&id, _)| id)
+        .map(|(_, probs)| probs.clone());
+    let awareness_state = awareness.map(|awareness| {
+        match awareness.state {
+            AwarenessState::Unaware => "unaware",
+            AwarenessState::Suspicious => "suspicious",
+            AwarenessState::Alert => "alert",
+        }
+        .to_string()
+    });
+    let awareness = awareness.map(|awareness| awareness.level);
+    let max_gadget_energy = energy.map(|_| world.resource::<GadgetConfig>().max_energy);
+    let radio_alert = radio
+        .and_then(|radio| radio.last_alert)
+        .map(|alert| alert.pos.into());
     let vis_mesh = observer.vis_mesh.clone();
     let pos = xform.translation().xy().into();
     let dir = agent.dir.into();
-    let observing = observer.observing.iter().map(|e| e.to_bits()).collect();
+    let observing = observer
+        .observing
+        .iter()
+        .map(|e| game_id(game_ids, e))
+        .collect();
+    let camera_observing = camera_feed
+        .iter()
+        .flat_map(|feed| &feed.observing)
+        .map(|e| game_id(game_ids, e))
+        .collect();
+    let now = world.resource::<Time>().elapsed_seconds_wrapped();
+    let mut camera_sightings = camera_feed
+        .iter()
+        .flat_map(|feed| &feed.sightings)
+        .map(|(e, sighting)| {
+            (
+                game_id(game_ids, e),
+                sighting.pos.into(),
+                now - sighting.time,
+            )
+        })
+        .collect::<Vec<_>>();
+    camera_sightings.sort_unstable_by_key(|&(id, _, _)| id);
+    let detection_probs = observer
+        .detection_probs
+        .iter()
+        .map(|(e, &prob)| (game_id(game_ids, e), prob))
+        .collect();
     let vm_data = observer
         .seen_markers
         .iter()
         .map(|(e, vm_data)| {
             (
-                e.to_bits(),
+                game_id(game_ids, e),
                 VMData {
                     last_seen: vm_data.last_seen,
                     last_seen_elapsed: vm_data.last_seen_elapsed,
                     last_pos: vm_data.last_pos.into(),
+                    displacement: vm_data.displacement.into(),
+                    velocity: vm_data.velocity.into(),
                 },
             )
         })
         .collect();
 
-    let listening = world
-        .query::<(Entity, &GlobalTransform, &NoiseSource)>()
-        .iter(world)
-        .filter(|(_, noise_xform, noise_src)| {
-            (xform.translation().xy() - noise_xform.translation().xy()).length_squared()
-                <= noise_src.noise_radius
+    // Noise has to travel around walls to reach the agent
+    let range_scale = world
+        .resource::<HearingConfig>()
+        .range_scale(world.entity(agent_e).contains::<PursuerAgent>());
+    let mut noise_query = world.query::<(Entity, &GlobalTransform, &NoiseSource)>();
+    let listening = audible_noises(
+        world.resource::<LevelLayout>(),
+        xform.translation().xy(),
+        range_scale,
+        noise_query
+            .iter(world)
+            // Agents making noise (e.g. on gravel) don't listen to themselves
+            .filter(|(noise_e, _, _)| *noise_e != agent_e)
+            .map(|(e, noise_xform, noise_src)| {
+                let pos = noise_xform.translation().xy();
+                ((game_id(game_ids, &e), pos), pos, noise_src)
+            }),
+    );
+    let noise_bearings = listening
+        .iter()
+        .map(|&((id, noise_pos), _)| {
+            let offset = noise_pos - xform.translation().xy();
+            let error = if hearing_bearing_noise > 0. {
+                hearing_rng.gen_range(-hearing_bearing_noise..=hearing_bearing_noise)
+            } else {
+                0.
+            };
+            (id, offset.y.atan2(offset.x) + error.to_radians())
         })
-        .map(|(e, _, _)| e.to_bits())
+        .collect();
+    let listening = listening
+        .into_iter()
+        .map(|((id, _), loudness)| (id, loudness))
         .collect();
 
-    // Compute intersection of agent visible area with grid
-    let mut visible_cells = vec![false; DEFAULT_LEVEL_SIZE * DEFAULT_LEVEL_SIZE];
-    for tri in &vis_mesh {
-        let mut points = tri.to_vec();
-        points.sort_by(|p1, p2| p1.y.total_cmp(&p2.y)); // 2 is top, 0 is bottom
-        let slope = (points[2].x - points[0].x) / (points[2].y - points[0].y);
-        let mid_point = Vec2::new(
-            points[0].x + slope * (points[1].y - points[0].y),
-            points[1].y,
-        );
-
-        let mut mid_points = [points[1], mid_point];
-        mid_points.sort_by(|p1, p2| p1.x.total_cmp(&p2.x));
+    let (visible_cells, visible_coverage) = match visibility {
+        VisibilityBackend::Mesh => {
+            let coverage = mesh_coverage(world.resource::<LevelLayout>().grid(), &vis_mesh);
+            let visible = coverage.iter().map(|&c| c >= VISIBLE_COVERAGE).collect();
+            (visible, coverage)
+        }
+        // The grid backend only knows whether each cell is visible, so cells are either fully covered or not at all
+        VisibilityBackend::Grid => {
+            let visible = agent_visible_cells::<T>(world).to_vec();
+            let coverage = visible.iter().map(|&v| if v { 1. } else { 0. }).collect();
+            (visible, coverage)
+        }
+    };
 
-        fill_tri_half(
-            &mut visible_cells,
-            mid_points[0],
-            mid_points[1],
-            points[2],
-            true,
-            DEFAULT_LEVEL_SIZE,
-        );
-        fill_tri_half(
-            &mut visible_cells,
-            mid_points[0],
-            mid_points[1],
-            points[0],
-            false,
-            DEFAULT_LEVEL_SIZE,
-        );
+    // Remember when each cell was last seen, so agents can tell where they haven't looked recently
+    let mut agent_e = world.entity_mut(agent_e);
+    if !agent_e.contains::<SeenCells>() {
+        agent_e.insert(SeenCells::default());
     }
+    let mut seen_cells = agent_e.get_mut::<SeenCells>().unwrap();
+    seen_cells.update(&visible_cells, now);
+    let staleness = seen_cells.staleness(now, memory_horizon);
 
     AgentState {
         pos,
         dir,
         observing,
+        camera_observing,
+        camera_sightings,
         listening,
+        noise_bearings,
         vm_data,
+        detection_probs,
         visible_cells,
+        visible_coverage,
+        staleness,
+        radio_alert,
+        in_vent,
+        camera,
+        gadget_energy: energy.map(|energy| energy.current),
+        max_gadget_energy,
+        ping_quadrant: ping_result
+            .filter(|ping_result| ping_result.fresh)
+            .map(|ping_result| ping_result.quadrant),
+        awareness,
+        awareness_state,
+        belief,
+        beliefs,
     }
 }
 
-/// Fills in half a triangle.
-fn fill_tri_half(
-    visible_cells: &mut [bool],
-    mid1: Vec2,
-    mid2: Vec2,
-    other: Vec2,
-    is_top: bool,
-    size: usize,
-) {
-    let slope1 = (other.x - mid1.x) / (other.y - mid1.y);
-    let slope2 = (other.x - mid2.x) / (other.y - mid2.y);
-    let dy = GRID_CELL_SIZE;
-    let (mut last1, mut last2) = if is_top { (mid1, mid2) } else { (other, other) };
-    for _ in 0..((if is_top {
-        other.y - mid1.y
-    } else {
-        mid1.y - other.y
-    } / dy)
-        .ceil() as u32)
-    {
-        let y = ((last1.y / GRID_CELL_SIZE).round() as usize).clamp(0, size - 1);
-        for x in ((last1.x / GRID_CELL_SIZE).floor() as usize)
-            ..((last2.x / GRID_CELL_SIZE).ceil() as usize)
-        {
-            visible_cells[y * DEFAULT_LEVEL_SIZE + x.clamp(0, size - 1)] = true;
-        }
-
-        last1.x += slope1 * dy;
-        last1.y += dy;
-        last2.x += slope2 * dy;
-        last2.y += dy;
-    }
+/// Returns the game ID of an entity. All observable entities and noise sources should have one.
+fn game_id(game_ids: &HashMap<Entity, u64>, e: &Entity) -> u64 {
+    *game_ids
+        .get(e)
+        .expect("observable entities and noise sources should have a GameId")
 }
 
 impl GameWrapper {
+    /// Creates a wrapper, raising a Python exception if the config is invalid.
+    pub fn new(config: EnvConfig) -> PyResult<Self> {
+        let EnvConfig {
+            use_objs,
+            wall_prob,
+            visualize,
+            recording_id,
+            seed,
+            level_path,
+            radio_delay,
+            camera_size,
+            level_sampling,
+            level_weights,
+            obstacles,
+            symmetry,
+            pursuer_fov,
+            pursuer_range,
+            player_fov,
+            player_range,
+            visibility,
+            detection_certain_dist,
+            detection_dist_falloff,
+            detection_peripheral_falloff,
+            memory_horizon,
+            hearing_bearing_noise,
+            marker_move_threshold,
+            marker_estimate_velocity,
+            marker_evidence_duration,
+            awareness_detection_gain,
+            awareness_noise_gain,
+            awareness_decay,
+            awareness_suspicious_threshold,
+            awareness_speed_scales,
+            filter_backend,
+            particle_count,
+            particle_resampling,
+            particle_motion_noise,
+            compute_device,
+            filter_motion_model,
+            filter_goal_bias,
+            gaussian_count,
+            measurement_model,
+            sparse_floor,
+            scripted_pursuer,
+            difficulty,
+            spectator_addr,
+            level_width,
+            level_height,
+        } = config;
+        if camera_size == Some(0) {
+            return Err(PyValueError::new_err("camera_size must be at least 1"));
+        }
+        if level_width == 0 || level_height == 0 {
+            return Err(PyValueError::new_err(
+                "level_width and level_height must be at least 1",
+            ));
+        }
+        if memory_horizon <= 0. {
+            return Err(PyValueError::new_err("memory_horizon must be positive"));
+        }
+        if !(0. ..=180.).contains(&hearing_bearing_noise) {
+            return Err(PyValueError::new_err(
+                "hearing_bearing_noise must be between 0 and 180 degrees",
+            ));
+        }
+        let vision = VisionConfig {
+            pursuer: VisionParams {
+                fov_degrees: pursuer_fov,
+                max_range: pursuer_range,
+            },
+            player: VisionParams {
+                fov_degrees: player_fov,
+                max_range: player_range,
+            },
+        };
+        if !vision.pursuer.is_valid() || !vision.player.is_valid() {
+            return Err(PyValueError::new_err(
+                "Fields of view must be between 0 and 360 degrees, and ranges must not be negative",
+            ));
+        }
+        let detection = DetectionConfig {
+            certain_dist: detection_certain_dist,
+            dist_falloff: detection_dist_falloff,
+            peripheral_falloff: detection_peripheral_falloff,
+        };
+        if !detection.is_valid() {
+            return Err(PyValueError::new_err(
+                "Detection settings must not be negative, and the peripheral falloff must be at most 1",
+            ));
+        }
+        let markers = MarkerConfig {
+            move_threshold: marker_move_threshold,
+            estimate_velocity: marker_estimate_velocity,
+            evidence_duration: marker_evidence_duration,
+        };
+        if !markers.is_valid() {
+            return Err(PyValueError::new_err(
+                "marker_move_threshold and marker_evidence_duration must not be negative",
+            ));
+        }
+        let awareness = AwarenessConfig {
+            detection_gain: awareness_detection_gain,
+            noise_gain: awareness_noise_gain,
+            decay: awareness_decay,
+            suspicious_threshold: awareness_suspicious_threshold,
+            speed_scales: awareness_speed_scales.into(),
+        };
+        if !awareness.is_valid() {
+            return Err(PyValueError::new_err(
+                "Awareness settings must not be negative, the suspicious threshold must be at most 1, and speed scales must be positive",
+            ));
+        }
+        let filter = FilterConfig {
+            backend: filter_backend
+                .parse::<FilterBackend>()
+                .map_err(|e| PyValueError::new_err(e.to_string()))?,
+            particle_count,
+            resampling: particle_resampling
+                .parse::<Resampling>()
+                .map_err(|e| PyValueError::new_err(e.to_string()))?,
+            motion_noise: particle_motion_noise,
+            motion_model: filter_motion_model
+                .parse::<MotionModel>()
+                .map_err(|e| PyValueError::new_err(e.to_string()))?,
+            goal_bias: filter_goal_bias,
+            gaussian_count,
+            sparse_floor,
+        };
+        if !filter.is_valid() {
+            return Err(PyValueError::new_err(
+                "particle_count and gaussian_count must be at least 1, particle_motion_noise must not be negative, and filter_goal_bias and sparse_floor must be between 0 and 1",
+            ));
+        }
+        let compute_device = compute_device
+            .parse::<ComputeDevice>()
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        let measurement_weights = measurement_model
+            .map(|path| load_measurement_model(&path, compute_device))
+            .transpose()?;
+        let difficulty = difficulty
+            .parse::<Difficulty>()
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        let spectators = spectator_addr
+            .map(|addr| {
+                SpectatorServer::bind(&addr).map_err(|e| {
+                    PyIOError::new_err(format!("Could not stream to spectators on {addr}: {e}"))
+                })
+            })
+            .transpose()?;
+        // Every stream gets its own seed from one master RNG, so they don't all draw the same numbers
+        let mut master_rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        let mut stream_rng = || StdRng::seed_from_u64(master_rng.gen());
+        let level_rng = stream_rng();
+        let detection_rng = stream_rng();
+        let hearing_rng = stream_rng();
+        let filter_rng = stream_rng();
+        let spawn_rng = stream_rng();
+        let level_set = level_path
+            .map(|paths| load_level_set(paths, &level_sampling, level_weights))
+            .transpose()?;
+        let obstacle_params = obstacles.map(ObstacleParams::try_from).transpose()?;
+        let symmetry = symmetry
+            .map(|symmetry| symmetry.parse::<Symmetry>())
+            .transpose()
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        let visibility = visibility
+            .parse::<VisibilityBackend>()
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        let mut wrapper = Self {
+            app: App::empty(),
+            visualize,
+            recording_id,
+            use_objs,
+            wall_prob,
+            level_width,
+            level_height,
+            level_rng,
+            level_set,
+            radio_delay,
+            visibility,
+            memory_horizon,
+            camera_size,
+            obstacle_params,
+            symmetry,
+            vision,
+            detection,
+            detection_rng,
+            hearing_bearing_noise,
+            hearing_rng,
+            markers,
+            awareness,
+            filter,
+            filter_rng,
+            spawn_rng,
+            compute_device,
+            measurement_weights,
+            scripted_pursuer,
+            difficulty,
+            spectators,
+        };
+        let level = wrapper.next_level();
+        wrapper.app = wrapper.build_app(level);
+        Ok(wrapper)
+    }
+
+    /// Creates a headless app that plays the given level.
+    fn build_app(&self, level: LevelLayout) -> App {
+        let mut app = App::new();
+        app.add_plugins(LibCfgPlugin);
+        app.insert_resource(level);
+        app.insert_resource(RadioConfig {
+            delay_ticks: self.radio_delay,
+        });
+        app.insert_resource(self.vision);
+        app.insert_resource(self.detection);
+        app.insert_resource(self.markers);
+        app.insert_resource(self.awareness);
+        app.insert_resource(DetectionRng(self.detection_rng.clone()));
+        app.insert_resource(self.filter);
+        app.insert_resource(FilterRng(self.filter_rng.clone()));
+        app.insert_resource(SpawnRng(self.spawn_rng.clone()));
+        app.insert_resource(self.compute_device);
+        app.insert_resource(self.difficulty);
+        if let Some(server) = &self.spectators {
+            app.add_plugins(SpectatorPlugin {
+                server: server.clone(),
+            });
+        }
+        if let Some(weights) = &self.measurement_weights {
+            let weights = app
+                .world
+                .resource_mut::<Assets<SafeTensorsData>>()
+                .add(weights.clone());
+            app.world
+                .spawn(NNWrapper::<MeasureNet>::with_sftensors(weights));
+        }
+        if self.scripted_pursuer {
+            app.add_plugins(ScriptedPursuerPlugin::default());
+        }
+        if let Some(size) = self.camera_size {
+            app.insert_resource(CameraSensorConfig { size, ..default() });
+        }
+
+        if self.visualize {
+            app.add_plugins(VisualizerPlugin {
+                recording_id: self.recording_id.clone(),
+            });
+        }
+
+        app.finish();
+        app.cleanup();
+        app.update();
+
+        // Agents are spawned at the end of the first update, so render their first images now
+        if self.camera_size.is_some() {
+            app.world.run_system_once(add_camera_sensors);
+            app.world.run_system_once(render_camera_sensors);
+        }
+        app
+    }
+
+    /// Returns the level to use for the next episode.
+    fn next_level(&mut self) -> LevelLayout {
+        let max_items = if self.use_objs { DEFAULT_LEVEL_SIZE } else { 0 };
+        let mut level = match (&mut self.level_set, &self.obstacle_params) {
+            (Some(level_set), _) => return level_set.next_level(&mut self.level_rng),
+            (None, Some(params)) => LevelLayout::random_obstacles(
+                self.level_width,
+                self.level_height,
+                params,
+                max_items,
+                &mut self.level_rng,
+            ),
+            (None, None) => LevelLayout::random(
+                self.level_width,
+                self.level_height,
+                self.wall_prob,
+                max_items,
+                &mut self.level_rng,
+            ),
+        };
+        if let Some(symmetry) = self.symmetry {
+            level.make_symmetric(symmetry, &mut self.level_rng);
+        }
+        level
+    }
+
     fn get_state(&mut self) -> GameState {
         let world = &mut self.app.world;
-        let player = get_agent_state::<PlayerAgent>(world);
-        let pursuer = get_agent_state::<PursuerAgent>(world);
+        let game_ids: HashMap<Entity, u64> = world
+            .query::<(Entity, &GameId)>()
+            .iter(world)
+            .map(|(e, id)| (e, id.0))
+            .collect();
+        let player = get_agent_state::<PlayerAgent>(
+            world,
+            &game_ids,
+            self.visibility,
+            self.memory_horizon,
+            self.hearing_bearing_noise,
+            &mut self.hearing_rng,
+        );
+        let pursuer = get_agent_state::<PursuerAgent>(
+            world,
+            &game_ids,
+            self.visibility,
+            self.memory_horizon,
+            self.hearing_bearing_noise,
+            &mut self.hearing_rng,
+        );
 
-        // Record all observable items
+        // Record all observable items. Agents in vents can't be seen, but are still listed, so both agents are always
+        // in `objects`
         let mut observables = world.query_filtered::<(
             Entity,
             &GlobalTransform,
             Option<&PlayerAgent>,
             Option<&PursuerAgent>,
-        ), With<Observable>>();
+        ), Or<(With<Observable>, With<Agent>)>>();
         let mut objects = HashMap::new();
         for (e, xform, player, pursuer) in observables.iter(world) {
             if player.is_some() {
                 objects.insert(
-                    e.to_bits(),
+                    game_id(&game_ids, &e),
                     ObservableObject {
                         pos: xform.translation().xy().into(),
                         obj_type: "player".into(),
@@ -345,7 +1745,7 @@ impl GameWrapper {
                 );
             } else if pursuer.is_some() {
                 objects.insert(
-                    e.to_bits(),
+                    game_id(&game_ids, &e),
                     ObservableObject {
                         pos: xform.translation().xy().into(),
                         obj_type: "pursuer".into(),
@@ -353,7 +1753,7 @@ impl GameWrapper {
                 );
             } else {
                 objects.insert(
-                    e.to_bits(),
+                    game_id(&game_ids, &e),
                     ObservableObject {
                         pos: xform.translation().xy().into(),
                         obj_type: "visual".into(),
@@ -362,12 +1762,24 @@ impl GameWrapper {
             }
         }
 
+        // Record patrols, which aren't observable since they block vision themselves
+        let mut patrols = world.query_filtered::<(Entity, &GlobalTransform), With<Patrol>>();
+        for (e, xform) in patrols.iter(world) {
+            objects.insert(
+                game_id(&game_ids, &e),
+                ObservableObject {
+                    pos: xform.translation().xy().into(),
+                    obj_type: "patrol".into(),
+                },
+            );
+        }
+
         // Record all noise sources
         let mut noise_srcs = world.query::<(Entity, &GlobalTransform, &NoiseSource)>();
         let mut noise_sources = HashMap::new();
         for (e, xform, noise_src) in noise_srcs.iter(world) {
             noise_sources.insert(
-                e.to_bits(),
+                game_id(&game_ids, &e),
                 NoiseSourceObject {
                     pos: xform.translation().xy().into(),
                     active_radius: noise_src.active_radius,
@@ -375,21 +1787,108 @@ impl GameWrapper {
             );
         }
 
+        // The key is removed from the level once it's picked up
+        let key_spawned = world
+            .query_filtered::<(), With<Key>>()
+            .iter(world)
+            .next()
+            .is_some();
+        let player_has_key = world
+            .query_filtered::<(), (With<PlayerAgent>, With<HasKey>)>()
+            .iter(world)
+            .next()
+            .is_some();
+        let door_unlocked = world
+            .query::<&ExitDoor>()
+            .iter(world)
+            .any(|door| door.unlocked);
+        let player_escaped = matches!(
+            world.get_resource::<LevelComplete>(),
+            Some(LevelComplete(GameOutcome::PlayerEscaped { .. }))
+        );
+        let player_caught = matches!(
+            world.get_resource::<LevelComplete>(),
+            Some(LevelComplete(GameOutcome::PlayerCaught { .. }))
+        );
+        let cell_topology = world
+            .resource::<LevelTopology>()
+            .0
+            .iter()
+            .map(|&cell| cell as u8)
+            .collect();
+        let illumination = world.resource::<Illumination>().levels.clone();
+        let team_vis = world.resource::<TeamVisibility>();
+        let team_visible_cells = team_vis.visible.to_vec();
+        let team_observing = team_vis
+            .observing
+            .iter()
+            .map(|e| game_id(&game_ids, e))
+            .collect();
+        // Fixed cameras and despawned entities don't have game IDs, so their events are left out
+        let view_event = |observer: Entity, observable: Entity, time: f32| {
+            Some((*game_ids.get(&observer)?, *game_ids.get(&observable)?, time))
+        };
+        let entered_view = world
+            .resource::<Events<EnteredView>>()
+            .iter_current_update_events()
+            .filter_map(|ev| view_event(ev.observer, ev.observable, ev.time))
+            .collect();
+        let exited_view = world
+            .resource::<Events<ExitedView>>()
+            .iter_current_update_events()
+            .filter_map(|ev| view_event(ev.observer, ev.observable, ev.time))
+            .collect();
+
         let level = world.get_resource::<LevelLayout>().unwrap();
+        let grid = level.grid();
+        let flip_y = |cell| grid.flip_y(cell);
         GameState {
             player,
             pursuer,
-            walls: level.walls.clone(),
-            level_size: level.size,
+            walls: level.walls.to_vec(),
+            cover: level.cover.clone(),
+            level_width: level.width,
+            level_height: level.height,
             objects,
             noise_sources,
+            key_pos: level.key_pos.filter(|_| key_spawned).map(flip_y),
+            door_pos: level.door_pos.map(flip_y),
+            exits: level.exits.iter().copied().map(flip_y).collect(),
+            cell_topology,
+            terrain: level.terrain.iter().map(|&cell| cell as u8).collect(),
+            one_way: level.one_way_moves().collect(),
+            conveyors: level
+                .conveyors
+                .iter()
+                .map(|dir| dir.map(|dir| dir.to_world().as_ivec2().into()))
+                .collect(),
+            fog: level.fog_view_dists(),
+            cameras: level
+                .cameras
+                .iter()
+                .map(|camera| (flip_y(camera.pos), camera.range))
+                .collect(),
+            illumination,
+            team_visible_cells,
+            team_observing,
+            entered_view,
+            exited_view,
+            player_has_key,
+            door_unlocked,
+            player_escaped,
+            player_caught,
+            meta: level.meta.clone().into(),
+            entity_ids: game_ids
+                .into_iter()
+                .map(|(e, id)| (e.to_bits(), id))
+                .collect(),
         }
     }
 }
 
 impl Default for GameWrapper {
     fn default() -> Self {
-        Self::new(false, 0.1, false, None)
+        Self::new(EnvConfig::default()).unwrap()
     }
 }
 
@@ -400,5 +1899,14 @@ fn webgame_rust(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add_class::<GameState>()?;
     m.add_class::<AgentState>()?;
     m.add_class::<PyVec2>()?;
+    m.add_class::<PyLevelMeta>()?;
+    m.add_class::<ObstacleConfig>()?;
+    m.add_function(wrap_pyfunction!(diff_levels, m)?)?;
+    m.add_function(wrap_pyfunction!(apply_level_patch, m)?)?;
+    m.add_function(wrap_pyfunction!(mutate_level, m)?)?;
+    m.add_function(wrap_pyfunction!(search_levels, m)?)?;
+    m.add_function(wrap_pyfunction!(level_thumbnail, m)?)?;
+    m.add_function(wrap_pyfunction!(policy_logits, m)?)?;
+    m.add_function(wrap_pyfunction!(measurement_likelihoods, m)?)?;
     Ok(())
 }
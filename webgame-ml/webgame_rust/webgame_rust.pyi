@@ -3,6 +3,12 @@ from typing import *
 class ObservableObj:
     """
     Describes an observable object.
+
+    `obj_type` is "player", "pursuer", "visual" (movable objects), or "patrol". Patrols are neutral obstacles that walk
+    loops of waypoints from the level file. They block vision like walls, so they never appear in
+    `AgentState.observing`. Movable objects also block vision, so agents can hide behind them, but they can still be
+    observed themselves. Both agents are always listed, even while they're in a vent and can't be seen (see
+    `AgentState.in_vent`).
     """
     pos: PyVec2
     obj_type: str
@@ -24,48 +30,523 @@ class PyVec2:
 class VMData:
     """
     Data on visual markers.
+
+    `displacement` is how far the marker moved the last time the agent saw it move further than the wrapper's
+    `marker_move_threshold`, and `velocity` is how fast it was going then, if `marker_estimate_velocity` is set. Both
+    are cleared once the evidence is older than `marker_evidence_duration`.
     """
     last_seen: float
     last_seen_elapsed: float
     last_pos: PyVec2
+    displacement: PyVec2
+    velocity: PyVec2
 
 class AgentState:
     """
     Contains the state of an agent for a single frame.
+
+    `camera_observing` lists what fixed cameras can see, and is only filled in for the pursuer. It's kept apart from
+    `observing` so it can be merged in or left out. `camera_sightings` gives where cameras last saw the player and how
+    many seconds ago, as `(id, pos, elapsed)`. Sightings stay after the cameras lose sight of the player.
+
+    `detection_probs` gives the chance the agent had of noticing each object in its field of view this step. Objects
+    are only added to `observing` if the agent noticed them.
+
+    `listening` gives how loud each noise source the agent can hear is, from 1 right next to it down to 0 at the edge
+    of its radius. Noise that can't travel straight to the agent has to go around walls, so it's quieter, and sources
+    behind thick walls may not be heard at all. `noise_bearings` gives roughly which direction each of them is in, as an
+    angle in radians counterclockwise from +X, off by up to `hearing_bearing_noise` degrees.
+
+    `visible_coverage` gives how much of each cell the agent can see, from 0 to 1, indexed the same way as
+    `visible_cells`. Cells count as visible if at least half of them is covered. With the "grid" visibility backend,
+    every cell is either 0 or 1.
+
+    `staleness` gives how long ago the agent last saw each cell, indexed the same way as `visible_cells`. Cells visible
+    now are 0, and cells the agent hasn't seen within the wrapper's `memory_horizon` (or ever) are 1.
+
+    `awareness` gives how aware the pursuer is of the player, from 0 to 1, and `awareness_state` is "unaware",
+    "suspicious", or "alert" depending on how high it is. Both are only filled in for the pursuer.
+
+    `belief` is the agent's own estimate of where the other agent is, as the chance of the other agent being in each
+    cell, indexed the same way as `visible_cells`. It's updated in the game like `BayesFilter` updates its belief, from
+    what the agent sees, noises the other agent sets off that it hears, visual markers it sees move (unless it could
+    have moved them itself), and pings. The pursuer tracks the player, and the player tracks the pursuer.
+
+    `beliefs` holds a separate belief for every agent this one tracks, keyed by ID, for when there are several. Noises
+    and moved markers don't say who caused them, so each goes to the agent most likely to be responsible. `belief` is
+    the entry with the lowest ID.
     """
     pos: PyVec2
     dir: PyVec2
     observing: list[int]
-    listening: list[int]
+    camera_observing: list[int]
+    camera_sightings: list[Tuple[int, PyVec2, float]]
+    listening: Mapping[int, float]
+    noise_bearings: Mapping[int, float]
     vm_data: Mapping[int, VMData]
+    detection_probs: Mapping[int, float]
     visible_cells: list[bool]
+    visible_coverage: list[float]
+    staleness: list[float]
+    radio_alert: Optional[PyVec2]
+    in_vent: bool
+    camera: Optional[list[int]]
+    gadget_energy: Optional[float]
+    max_gadget_energy: Optional[float]
+    ping_quadrant: Optional[int]
+    awareness: Optional[float]
+    awareness_state: Optional[str]
+    belief: Optional[list[float]]
+    beliefs: dict[int, list[float]]
+
+    def belief_entropy(self) -> Optional[float]:
+        """
+        Returns how spread out `belief` is, in nats, or None if the agent doesn't track a belief.
+        """
+        ...
+
+    def belief_map_cell(self) -> Optional[int]:
+        """
+        Returns the index of the cell the agent thinks the other agent is most likely to be in, or None if the agent
+        doesn't track a belief. Ties go to the lowest index.
+        """
+        ...
+
+    def belief_top_k(self, k: int) -> list[Tuple[int, float]]:
+        """
+        Returns the `k` cells the agent thinks the other agent is most likely to be in, as indices paired with their
+        probabilities, most likely first. Empty if the agent doesn't track a belief.
+        """
+        ...
+
+class PyLevelMeta:
+    """
+    Descriptive information about the current level. Every field is optional in level files.
+    """
+    name: Optional[str]
+    author: Optional[str]
+    difficulty: Optional[int]
+    max_steps: Optional[int]
+    pursuer_policy: Optional[str]
+    player_policy: Optional[str]
 
 class GameState:
     """
     Contains the state of the game for a single frame.
+
+    `cell_topology` classifies each cell by its open neighbors: 0 is a wall, 1 a dead end, 2 a corridor, 3 a corner,
+    and 4 an open area.
+
+    `terrain` gives the ground in each cell: 0 is normal, 1 is mud (slows agents down), 2 is gravel (makes noise when
+    walked on), and 3 is carpet (stops agents from setting off noise sources).
+
+    `one_way` lists `(from, to)` pairs of neighboring cells that agents can only move between in that direction.
+    `conveyors` gives the `(dx, dy)` step each cell's conveyor pushes agents by, or `None`. Conveyors push as fast as
+    agents walk, so agents can't walk against them. Use `can_move` to mask out moves that walls, one-way edges, or
+    conveyors would block.
+
+    `fog` gives how many cells into the fog agents can see for each cell, or `None` for cells without fog. Agents in
+    fog, or looking through it, can't see any further into it than that.
+
+    `cameras` lists the cell each fixed camera is in and how many cells away it can see. Cameras see in every
+    direction unless the level gives them a cone, and walls and fog block them like they block agents.
+
+    `illumination` gives how brightly lit each cell is, from 0 to 1. Agents can only notice things in dimly lit cells
+    from a fraction of their usual range, but can always notice things in neighboring cells.
+
+    `team_visible_cells` and `team_observing` merge what every pursuer and fixed camera can see. Cells are computed
+    by casting rays to cell centers, like the "grid" visibility backend.
+
+    `entered_view` and `exited_view` list `(observer, observable, time)` for every object an agent started or stopped
+    observing this step, where `time` is in seconds since the episode started. Objects that are removed from the level
+    are left out.
+
+    `pursuer_belief` and `player_belief` are shorthand for `pursuer.belief` and `player.belief`: the in-engine filter's
+    belief about where the other agent is, indexed the same way as `walls`, or `None` if the agent doesn't track one.
+
+    All IDs (keys of `objects`, `noise_sources`, `AgentState.listening`, and `AgentState.vm_data`, and entries of
+    `AgentState.observing`, `AgentState.camera_observing`, `team_observing`, `entered_view`, and `exited_view`) are
+    game IDs, which are assigned in spawn order and are the same every time a level is played.
     """
     player: AgentState
     pursuer: AgentState
     walls: list[bool]
-    level_size: int
+    cover: list[bool]
+    level_width: int
+    level_height: int
     objects: Mapping[int, ObservableObj]
     noise_sources: Mapping[int, NoiseSourceObj]
+    key_pos: Optional[Tuple[int, int]]
+    door_pos: Optional[Tuple[int, int]]
+    exits: list[Tuple[int, int]]
+    cell_topology: list[int]
+    terrain: list[int]
+    one_way: list[Tuple[Tuple[int, int], Tuple[int, int]]]
+    conveyors: list[Optional[Tuple[int, int]]]
+    fog: list[Optional[float]]
+    cameras: list[Tuple[Tuple[int, int], float]]
+    illumination: list[float]
+    team_visible_cells: list[bool]
+    team_observing: list[int]
+    entered_view: list[Tuple[int, int, float]]
+    exited_view: list[Tuple[int, int, float]]
+    player_has_key: bool
+    door_unlocked: bool
+    player_escaped: bool
+    player_caught: bool
+    meta: PyLevelMeta
+    pursuer_belief: Optional[list[float]]
+    player_belief: Optional[list[float]]
+
+    def resolve_ids(self, entities: list[int]) -> list[int]:
+        """
+        Converts raw entity bits into game IDs.
+
+        Raises:
+            KeyError: If an entity has no game ID.
+        """
+        ...
+
+    def world_to_cell(self, pos: PyVec2) -> Optional[tuple[int, int]]:
+        """
+        Returns the cell containing a world space position, indexed the same way as `walls`, or `None` if it's
+        outside the level.
+        """
+        ...
+
+    def cell_to_world(self, cell: tuple[int, int]) -> PyVec2:
+        """Returns the world space position of the center of a cell."""
+        ...
+
+    def cell_idx(self, cell: tuple[int, int]) -> Optional[int]:
+        """Returns the index of a cell in `walls` and other per-cell lists, or `None` if it's outside the level."""
+        ...
+
+    def can_move(self, from_cell: tuple[int, int], to_cell: tuple[int, int]) -> bool:
+        """
+        Returns true if an agent can step from a cell to a neighboring one. The neighbor must be inside the level and
+        not a wall, no one-way edge can be in the way, and neither cell can have a conveyor pushing back against the
+        step.
+        """
+        ...
+
+    def to_ascii(self, belief: Optional[list[float]] = None) -> str:
+        """
+        Draws the level as text, one character per cell, with the top row first.
+
+        `#` is a wall, `P` the player, `E` the pursuer, `N` a noise source, `K` the key, and `D` the door.
+
+        Args:
+            belief: Optional probabilities indexed the same way as `walls`. If provided, empty cells show the
+                belief's decile relative to its maximum value instead of `.`.
+        """
+        ...
+
+def diff_levels(old_json: str, new_json: str) -> str:
+    """
+    Computes a patch that turns one level into another. The patch is returned as JSON and lists toggled walls,
+    moved, added, and removed objects, and changed spawn, key, and door positions.
+
+    Raises:
+        ValueError: If either level is malformed or the levels have different sizes.
+    """
+    ...
+
+def apply_level_patch(level_json: str, patch_json: str) -> str:
+    """
+    Applies a patch created by `diff_levels` to a level, returning the patched level as JSON.
+
+    Raises:
+        ValueError: If the level or patch is malformed, or the patch does not match the level.
+    """
+    ...
+
+def mutate_level(level_json: str, seed: Optional[int] = None) -> Optional[str]:
+    """
+    Applies a random edit to a level (flipping a wall, or moving a spawn, the key, or an object), returning the new
+    level as JSON. Edits are retried until the level is playable: everything sits on open cells and all open cells
+    are connected. Returns `None` if no playable edit was found.
+
+    Raises:
+        ValueError: If the level is malformed.
+    """
+    ...
+
+def level_thumbnail(level_json: str, cell_pixels: int = 4) -> bytes:
+    """
+    Draws a level as a PNG image, with each cell `cell_pixels` wide, without running the game. Walls, cover, terrain,
+    exits, and the door fill their cells. The key, vents, patrol waypoints (orange), objects (blue if they make noise
+    when pushed), and fixed spawn points (red for the pursuer, green for the player) are drawn as smaller squares.
+
+    Raises:
+        ValueError: If the level is malformed.
+    """
+    ...
+
+def search_levels(
+    level_json: str,
+    score_fn: Callable[[str], float],
+    iterations: int,
+    seed: Optional[int] = None,
+) -> Tuple[str, float]:
+    """
+    Searches for a level that maximizes `score_fn` (e.g. a policy's failure rate), starting from `level_json`. Each
+    iteration mutates the best level found so far with `mutate_level`, and keeps the result if it scores at least as
+    well. Returns the best level as JSON and its score.
+
+    Raises:
+        ValueError: If the level is malformed.
+        Any exception raised by `score_fn`.
+    """
+    ...
+
+def policy_logits(
+    path: str,
+    grids: list[float],
+    width: int,
+    height: int,
+    compute_device: str = "auto",
+    precision: str = "f32",
+) -> list[list[float]]:
+    """
+    Runs the game's port of `PolicyNet` with the weights at `path` on a batch of input grids, returning each grid's
+    action logits. Use this to check the port matches the PyTorch model before shipping weights into the game.
+
+    Args:
+        path: A safetensors checkpoint of `PolicyNet`.
+        grids: Grids of shape (9, height, width), flattened and concatenated, as built by `GameEnv` without a filter.
+        compute_device: Where the network runs, like `GameWrapper`'s `compute_device`.
+        precision: What the weights are converted to: "f32", "f16", or "bf16".
+
+    Raises:
+        IOError: If the checkpoint could not be read.
+        ValueError: If the checkpoint isn't a valid `PolicyNet`, `grids` doesn't hold a whole number of grids, or
+            `compute_device` or `precision` is invalid.
+        RuntimeError: If the network fails to run, e.g. because it was trained on a different level size.
+    """
+    ...
+
+def measurement_likelihoods(
+    path: str,
+    grids: list[float],
+    width: int,
+    height: int,
+    compute_device: str = "auto",
+    precision: str = "f32",
+) -> list[list[float]]:
+    """
+    Runs the game's port of `MeasureModel` with the weights at `path` on a batch of input grids, returning the
+    likelihood of each cell for each grid. Takes the same arguments as `policy_logits`.
+
+    Raises:
+        IOError: If the checkpoint could not be read.
+        ValueError: If the checkpoint isn't a valid `MeasureModel`, `grids` doesn't hold a whole number of grids, or
+            `compute_device` or `precision` is invalid.
+        RuntimeError: If the network fails to run.
+    """
+    ...
+
+class ObstacleConfig:
+    """
+    Configures the obstacle level generator, which builds walls from rectangles, L-shapes, and pillars instead of
+    independently random cells. Obstacles never cut off part of the level.
+    """
+    count: int
+    min_size: int
+    max_size: int
+    min_corridor_width: int
+    shapes: list[str]
+
+    def __init__(
+        self,
+        count: int = 4,
+        min_size: int = 2,
+        max_size: int = 3,
+        min_corridor_width: int = 1,
+        shapes: Optional[list[str]] = None,
+    ) -> None:
+        """
+        Args:
+            count: How many obstacles to try to place. Fewer may be placed if the level runs out of room.
+            min_size: The smallest width or height of rectangles and L-shapes.
+            max_size: The largest width or height of rectangles and L-shapes.
+            min_corridor_width: How many empty cells must separate obstacles from each other, and from the sides of
+                the level unless they touch them.
+            shapes: The shapes to pick from: "rect", "l_shape", or "pillar". Defaults to all of them.
+        """
+        ...
 
 class GameWrapper:
-    def __init__(self, use_objs: bool, wall_prob: float, visualize: bool, recording_id: Optional[str]) -> None:
+    def __init__(
+        self,
+        *,
+        use_objs: bool = False,
+        wall_prob: float = 0.1,
+        visualize: bool = False,
+        recording_id: Optional[str] = None,
+        seed: Optional[int] = None,
+        level_path: Optional[Union[str, list[str]]] = None,
+        radio_delay: int = 2,
+        camera_size: Optional[int] = None,
+        level_sampling: str = "round_robin",
+        level_weights: Optional[list[float]] = None,
+        obstacles: Optional[ObstacleConfig] = None,
+        symmetry: Optional[str] = None,
+        pursuer_fov: float = 60.0,
+        pursuer_range: Optional[float] = None,
+        player_fov: float = 60.0,
+        player_range: Optional[float] = None,
+        visibility: str = "mesh",
+        detection_certain_dist: float = 0.0,
+        detection_dist_falloff: float = 0.0,
+        detection_peripheral_falloff: float = 0.0,
+        memory_horizon: float = 10.0,
+        hearing_bearing_noise: float = 0.0,
+        marker_move_threshold: float = 0.0,
+        marker_estimate_velocity: bool = False,
+        marker_evidence_duration: float = 0.0,
+        awareness_detection_gain: float = 2.0,
+        awareness_noise_gain: float = 1.0,
+        awareness_decay: float = 0.1,
+        awareness_suspicious_threshold: float = 0.3,
+        awareness_speed_scales: Tuple[float, float, float] = (1.0, 1.0, 1.0),
+        filter_backend: str = "grid",
+        particle_count: int = 1000,
+        particle_resampling: str = "systematic",
+        particle_motion_noise: float = 1.0,
+        compute_device: str = "auto",
+        filter_motion_model: str = "random_walk",
+        filter_goal_bias: float = 0.5,
+        gaussian_count: int = 4,
+        measurement_model: Optional[str] = None,
+        sparse_floor: float = 1e-4,
+        scripted_pursuer: bool = False,
+        difficulty: str = "custom",
+        spectator_addr: Optional[str] = None,
+        level_width: int = 8,
+        level_height: int = 8,
+    ) -> None:
         """
         Args:
             use_objs: Whether the environment should add objects to the scene.
             wall_prob: Probability of each tile being a wall.
             visualize: If we should log visuals to Rerun.
             recording_id: Recording ID used by Rerun. Useful for syncing data between Python and Rust.
+            seed: Seed for level generation and where agents spawn. If not provided, both are picked from entropy.
+            level_path: Level files, or directories of level files, to play instead of random levels. A level is picked
+                from them on every reset. Older versions of the level format are migrated automatically.
+            radio_delay: How many steps it takes for a pursuer's or fixed camera's sighting of the player to reach its
+                teammates.
+            camera_size: If set, each agent's `camera` holds a `camera_size` x `camera_size` RGB image from its point of
+                view, stored row by row from the top with 3 bytes per pixel.
+            level_sampling: How levels are picked from `level_path`. "round_robin" plays them in order (files in a
+                directory are sorted by name), "uniform" picks one at random, and "weighted" picks one with probability
+                proportional to its entry in `level_weights`.
+            level_weights: One weight per level, used with "weighted" sampling.
+            obstacles: If set, random levels are built from obstacles instead of using `wall_prob`.
+            symmetry: If set, random levels are made symmetric and the agents spawn on opposite sides, so neither
+                has an easier position. One of "mirror_x", "mirror_y", or "rotate_180".
+            pursuer_fov: How wide the pursuer's vision cone is, in degrees.
+            pursuer_range: How many cells away the pursuer can see. If not set, only walls limit its view.
+            player_fov: How wide the player's vision cone is, in degrees.
+            player_range: How many cells away the player can see. If not set, only walls limit its view.
+
+            visibility: How `visible_cells` is computed. "mesh" measures exactly how much of each cell each agent's
+                vision mesh covers, and "grid" casts a ray to the center of every cell instead, which is faster.
+
+            detection_certain_dist: How many cells away objects can be before agents might not notice them.
+            detection_dist_falloff: How much the chance of noticing an object drops for every cell past
+                `detection_certain_dist`.
+            detection_peripheral_falloff: How much lower the chance of noticing an object is at the edge of an agent's
+                vision cone than at its center, from 0 to 1.
+
+            Levels can override the vision settings for either agent with their `vision` field. With the default
+            detection settings, agents notice everything they can see. Otherwise, whether they notice each object is
+            rolled every step, from an RNG seeded with `seed`.
+
+            memory_horizon: How many seconds of game time it takes for a cell an agent saw to become fully stale in
+                `AgentState.staleness`. Each step is half a second.
+            hearing_bearing_noise: The most, in degrees, that the bearing to a heard noise in
+                `AgentState.noise_bearings` can be off by. Errors are drawn uniformly from an RNG seeded with `seed`.
+            marker_move_threshold: How many cells a visual marker has to move between sightings to count as moved.
+            marker_estimate_velocity: Whether to estimate how fast visual markers that moved were going.
+            marker_evidence_duration: How many seconds evidence that a visual marker moved is kept for.
+
+            awareness_detection_gain: How much the pursuer's awareness rises per second, multiplied by its chance of
+                noticing the player. Actually noticing the player makes it alert straight away.
+            awareness_noise_gain: How much the pursuer's awareness rises per second, multiplied by how loud noises the
+                player makes are.
+            awareness_decay: How much the pursuer's awareness falls per second when nothing raises it.
+            awareness_suspicious_threshold: The awareness at which the pursuer becomes suspicious. It becomes alert
+                at 1.
+            awareness_speed_scales: How fast the pursuer moves when unaware, suspicious, and alert, as fractions of its
+                usual speed.
+
+            filter_backend: How `AgentState.belief` is tracked. "grid" keeps a probability for every cell, "sparse"
+                only keeps cells above `sparse_floor`, "particles" keeps a set of weighted particles instead, and
+                "gaussians" keeps a small mixture of Gaussians in continuous coordinates, for agents that don't move
+                cell by cell. "sparse" and "particles" scale better to large levels. All of them are rasterized to the
+                same grid every step.
+            particle_count: How many particles the "particles" backend uses.
+            particle_resampling: How particles are resampled after every step. One of "multinomial", "systematic", or
+                "stratified". Particles are moved and resampled with an RNG seeded with `seed`.
+            particle_motion_noise: The furthest, in cells, particles can drift along each axis every step. With
+                "gaussians", the standard deviation, in cells, each Gaussian widens by every step.
+            compute_device: Where neural networks in the game run. One of "auto", "cpu", "cuda", "cuda:<index>",
+                "metal", or "metal:<index>". "auto" picks a GPU if one is available. Falls back to the CPU if the
+                device isn't available, including when the game was built without the `cuda` or `metal` feature.
+            filter_motion_model: How the filter assumes tracked agents move between steps. "stationary" assumes they
+                stay put, "random_walk" that they step to a random open neighbor or stay put, and "goal_directed" that
+                they head for the nearest exit or door some of the time and take a random walk step otherwise.
+            filter_goal_bias: With "goal_directed", the chance of a tracked agent heading for the nearest exit on each
+                step.
+            gaussian_count: How many Gaussians the "gaussians" backend uses. They're seeded with an RNG seeded with
+                `seed`.
+            measurement_model: A safetensors checkpoint of `MeasureModel`, as saved by `webgame/train_filter.py`. If
+                set, it replaces the filter's hand-written likelihood of what agents see and hear, like
+                `model_update` in `webgame/filter.py`. Pings and reported sightings still apply on top. Only
+                checkpoints trained without objects or position encodings are supported.
+            sparse_floor: The "sparse" backend drops cells less likely than this after every step.
+            scripted_pursuer: Whether the pursuer follows a built-in script instead of `action_pursuer`. It patrols the
+                level, chases the player on sight, and searches where it last saw or heard them. Useful as a baseline
+                opponent for the player. The pursuer's gadgets still work.
+            difficulty: One of "easy", "normal", "hard", or "custom". Every difficulty but "custom" overrides the
+                pursuer's speed, field of view, and hearing range, how quickly gadget energy regenerates and what
+                gadgets cost, `particle_count`, and `filter_motion_model`.
+            spectator_addr: If set, an address like "127.0.0.1:9200" to stream episodes to spectators on over
+                WebSocket, e.g. for a dashboard watching training. Forked environments don't stream.
+            level_width: How many cells wide random levels are. Levels from `level_path` keep their own size.
+            level_height: How many cells tall random levels are.
+
+        Raises:
+            IOError: If the level file or `measurement_model` could not be read, or `spectator_addr` can't be listened
+                on.
+            ValueError: If a level file is malformed, `camera_size` is 0, or the level sampling
+                options are invalid, or `obstacles`, `symmetry`, or `visibility` is invalid, or a field of view isn't
+                between 0 and 360 degrees, or a range or detection setting is negative, or
+                `detection_peripheral_falloff` is greater than 1, or `memory_horizon` isn't positive, or
+                `hearing_bearing_noise` isn't between 0 and 180 degrees, or `marker_move_threshold` or
+                `marker_evidence_duration` is negative, or an awareness setting is negative, or
+                `awareness_suspicious_threshold` is greater than 1, or a speed scale isn't positive, or
+                `filter_backend` or `particle_resampling` is invalid, or `particle_count` or `gaussian_count` is 0, or
+                `particle_motion_noise` is negative, or `compute_device` or `filter_motion_model` is invalid, or
+                `filter_goal_bias` or `sparse_floor` isn't between 0 and 1, or `measurement_model` isn't a valid
+                checkpoint, or `difficulty` is invalid, or `level_width` or `level_height` is 0.
         """
         ...
     def step(
-        self, action_player: int, action_pursuer: int
+        self,
+        action_player: int,
+        action_pursuer: int,
+        pursuer_sprint: bool = False,
+        pursuer_ping: bool = False,
     ) -> GameState:
         """
         Runs one step of the game, and returns the next state of the game.
+
+        If `pursuer_sprint` is set, the pursuer moves faster this step, as long as it has enough gadget energy.
+        If `pursuer_ping` is set, the pursuer spends gadget energy to learn which quadrant the player is in (see
+        `AgentState.ping_quadrant`). Only one gadget can be used per step, and pinging takes priority.
         """
         ...
     def reset(self) -> GameState: 
@@ -73,3 +554,79 @@ class GameWrapper:
         Resets the game, returning the next state of the game.
         """
         ...
+    def render_ascii(self, belief: Optional[list[float]] = None) -> str:
+        """
+        Draws the current state of the game as text. See `GameState.to_ascii`.
+        """
+        ...
+    def save_level(self, path: str):
+        """
+        Saves the current level as a level file, with objects at the cells they've been pushed to. Useful for keeping
+        random levels that produce interesting behavior.
+
+        Raises:
+            IOError: If the file could not be written.
+        """
+        ...
+
+    def save_filter_state(self) -> str:
+        """
+        Saves every agent's beliefs (see `AgentState.beliefs`) as JSON, including particles with the "particles"
+        backend. Restoring them with `load_filter_state`, here or in a fork on the same level, reproduces the beliefs
+        exactly. Doesn't include the filter's random number generator.
+        """
+        ...
+
+    def load_filter_state(self, state: str):
+        """
+        Replaces agents' beliefs with ones saved by `save_filter_state`. Agents that weren't saved keep their current
+        beliefs.
+
+        Raises:
+            ValueError: If `state` isn't valid filter state.
+        """
+        ...
+    def report_sighting(
+        self,
+        target: int,
+        cell: Tuple[int, int],
+        confidence: float,
+        radius: float = 1.0,
+        tracker: Optional[int] = None,
+    ):
+        """
+        Reports a sighting of an agent from outside the game, such as by a camera or alarm. It's taken into account the
+        next time beliefs are updated.
+
+        Args:
+            target: The game ID of the agent that was seen.
+            cell: The cell it was seen near, indexed the same way as `GameState.walls`.
+            confidence: How sure the source is, from 0 to 1. Cells further than `radius` from `cell` are down-weighted
+                more the higher this is.
+            radius: How many cells away from `cell` the agent could be.
+            tracker: If set, only this agent's beliefs take the sighting into account. Otherwise, every agent tracking
+                `target` does.
+
+        Raises:
+            KeyError: If `target` or `tracker` isn't an agent's game ID.
+            ValueError: If `cell` is outside the level, `confidence` isn't between 0 and 1, or `radius` is negative.
+        """
+        ...
+    def level_size(self) -> Tuple[int, int]:
+        """
+        Returns the width and height of the current level, in cells.
+        """
+        ...
+    def render_thumbnail(self, cell_pixels: int = 4) -> bytes:
+        """
+        Draws the current level as a PNG image, with each cell `cell_pixels` wide. See `level_thumbnail`.
+        """
+        ...
+    def fork(self) -> "GameWrapper":
+        """
+        Creates a second environment with the same configuration, current level, and level RNG state, but with
+        independent game state. Both environments will see the same sequence of levels across resets, which makes
+        them useful for A/B comparisons. Agents also spawn in the same places. Normalization statistics live on the
+        Python side, and `GameEnv.fork` gives the fork its own copy.
+        """
+        ...